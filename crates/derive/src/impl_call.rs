@@ -102,7 +102,7 @@ pub(crate) fn impl_call_req(args: TokenStream, input: TokenStream) -> TokenStrea
     }
 }
 
-fn naive_snake_case(name: &str) -> String {
+pub(crate) fn naive_snake_case(name: &str) -> String {
     let mut s = String::new();
     let mut it = name.chars().peekable();
 
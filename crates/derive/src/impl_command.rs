@@ -0,0 +1,255 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{Attribute, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta, Type};
+
+use crate::{impl_call::naive_snake_case, impl_self_builder::extract_type_from_option};
+
+/// Values of a `#[command(...)]` attribute.
+#[derive(Default)]
+struct CommandAttrs {
+    name: Option<String>,
+    description: Option<String>,
+    permission: Option<String>,
+}
+
+impl CommandAttrs {
+    fn from_attrs(attrs: &[Attribute]) -> Self {
+        let mut parsed = Self::default();
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("command")) {
+            let list = match attr.parse_meta() {
+                Ok(Meta::List(list)) => list,
+                _ => panic!("expected `#[command(key = \"value\", ...)]`"),
+            };
+            for nested in list.nested {
+                let (key, value) = match nested {
+                    NestedMeta::Meta(Meta::NameValue(pair)) => {
+                        match (pair.path.get_ident(), pair.lit) {
+                            (Some(key), Lit::Str(value)) => (key.to_string(), value.value()),
+                            _ => panic!("expected `key = \"value\"` in `#[command(...)]`"),
+                        }
+                    }
+                    _ => panic!("expected `key = \"value\"` in `#[command(...)]`"),
+                };
+                match key.as_str() {
+                    "name" => parsed.name = Some(value),
+                    "description" => parsed.description = Some(value),
+                    "permission" => parsed.permission = Some(value),
+                    _ => panic!("unknown `#[command(...)]` key `{}`", key),
+                }
+            }
+        }
+        parsed
+    }
+}
+
+struct ArgField {
+    ident: Ident,
+    name: String,
+    ty: Type,
+    optional: bool,
+}
+
+impl ArgField {
+    /// Whether this is a `Rest` argument. `FromArg::KIND` can't be read while
+    /// expanding, so this goes by the name of the type.
+    fn is_rest(&self) -> bool {
+        match &self.ty {
+            Type::Path(path) => {
+                matches!(path.path.segments.last(), Some(segment) if segment.ident == "Rest")
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Checks that `Rest` arguments come last and that required arguments don't
+/// come after optional ones, since they couldn't be parsed otherwise.
+fn check_arg_order(fields: &[ArgField]) -> syn::Result<()> {
+    for pair in fields.windows(2) {
+        let (last, field) = (&pair[0], &pair[1]);
+        if last.is_rest() {
+            return Err(syn::Error::new_spanned(
+                &last.ident,
+                "`Rest` arguments must be the last argument",
+            ));
+        }
+        if last.optional && !field.optional {
+            return Err(syn::Error::new_spanned(
+                &field.ident,
+                "required arguments can't come after optional arguments",
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn arg_fields(fields: &Fields) -> Vec<ArgField> {
+    match fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| {
+                let ident = field.ident.clone().expect("named field");
+                let attrs = CommandAttrs::from_attrs(&field.attrs);
+                let name = attrs.name.unwrap_or_else(|| ident.to_string());
+                let (ty, optional) = match &field.ty {
+                    ty @ Type::Path(_) => match extract_type_from_option(ty) {
+                        Some(inner) => (inner, true),
+                        None => (ty.clone(), false),
+                    },
+                    ty => (ty.clone(), false),
+                };
+                ArgField {
+                    ident,
+                    name,
+                    ty,
+                    optional,
+                }
+            })
+            .collect(),
+        Fields::Unit => Vec::new(),
+        Fields::Unnamed(_) => panic!("`Command` can't be derived for tuple structs or variants"),
+    }
+}
+
+/// Generates the `CommandSpec` of a command and the expression constructing
+/// it from parsed arguments.
+fn command(
+    ident: &Ident,
+    attrs: &[Attribute],
+    fields: &Fields,
+    constructor: TokenStream2,
+) -> syn::Result<(TokenStream2, TokenStream2, String)> {
+    let bot = quote! { ::harmony_rust_sdk::client::bot };
+    let attrs = CommandAttrs::from_attrs(attrs);
+    let name = attrs
+        .name
+        .unwrap_or_else(|| naive_snake_case(&ident.to_string()));
+    let description = attrs.description.unwrap_or_default();
+    let permission = match attrs.permission {
+        Some(permission) => quote! { Some(#permission.to_string()) },
+        None => quote! { None },
+    };
+
+    let fields = arg_fields(fields);
+    check_arg_order(&fields)?;
+    let specs = fields.iter().map(|field| {
+        let ArgField {
+            name, ty, optional, ..
+        } = field;
+        quote! {
+            #bot::ArgSpec {
+                name: #name.to_string(),
+                kind: <#ty as #bot::FromArg>::KIND,
+                optional: #optional,
+            }
+        }
+    });
+    let spec = quote! {
+        #bot::CommandSpec {
+            name: #name.to_string(),
+            description: #description.to_string(),
+            permission: #permission,
+            args: vec![#(#specs),*],
+        }
+    };
+
+    let values = fields.iter().map(|field| {
+        let ArgField {
+            ident,
+            name,
+            ty,
+            optional,
+        } = field;
+        if *optional {
+            quote! { #ident: args.parse_optional::<#ty>(#name)? }
+        } else {
+            quote! { #ident: args.parse::<#ty>(#name)? }
+        }
+    });
+    // Braces also work for unit structs and variants
+    let construct = quote! { #constructor { #(#values),* } };
+
+    Ok((spec, construct, name))
+}
+
+pub(crate) fn impl_command(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    let bot = quote! { ::harmony_rust_sdk::client::bot };
+    let name = &input.ident;
+
+    let commands = match &input.data {
+        Data::Struct(data) => vec![command(name, &input.attrs, &data.fields, quote! { Self })],
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .map(|variant| {
+                let ident = &variant.ident;
+                command(
+                    ident,
+                    &variant.attrs,
+                    &variant.fields,
+                    quote! { Self::#ident },
+                )
+            })
+            .collect(),
+        Data::Union(_) => panic!("`Command` can't be derived for unions"),
+    };
+    let commands = match commands.into_iter().collect::<syn::Result<Vec<_>>>() {
+        Ok(commands) => commands,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let specs = commands.iter().map(|(spec, _, _)| spec);
+    let arms = commands.iter().map(|(_, construct, command_name)| {
+        quote! { #command_name => Ok(#construct), }
+    });
+
+    (quote! {
+        impl #bot::CommandArgs for #name {
+            fn specs() -> Vec<#bot::CommandSpec> {
+                vec![#(#specs),*]
+            }
+
+            #[allow(unused_variables)]
+            fn from_args(command: &str, args: &#bot::Args) -> Result<Self, #bot::ParseError> {
+                match command {
+                    #(#arms)*
+                    _ => Err(#bot::ParseError::UnknownCommand(command.to_string())),
+                }
+            }
+        }
+    })
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    fn check(fields: syn::FieldsNamed) -> Result<(), String> {
+        check_arg_order(&arg_fields(&Fields::Named(fields))).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn checks_arg_order() {
+        assert!(
+            check(parse_quote!({ user: UserId, count: Option<i64>, reason: Option<Rest> })).is_ok()
+        );
+        assert!(check(parse_quote!({ user: UserId, reason: bot::Rest })).is_ok());
+        assert_eq!(
+            check(parse_quote!({ reason: Rest, user: UserId })),
+            Err("`Rest` arguments must be the last argument".to_string())
+        );
+        assert_eq!(
+            check(parse_quote!({ reason: Option<Rest>, user: Option<UserId> })),
+            Err("`Rest` arguments must be the last argument".to_string())
+        );
+        assert_eq!(
+            check(parse_quote!({ count: Option<i64>, user: UserId })),
+            Err("required arguments can't come after optional arguments".to_string())
+        );
+    }
+}
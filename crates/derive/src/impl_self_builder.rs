@@ -104,9 +104,11 @@ fn construct_impl_sign(
     } = field_info;
 
     let method_name = quote::format_ident!("with_{}", name);
-    let vis = for_self
-        .then(|| TokenStream2::from_str("pub").unwrap())
-        .unwrap_or_else(TokenStream2::new);
+    let vis = if for_self {
+        TokenStream2::from_str("pub").unwrap()
+    } else {
+        TokenStream2::new()
+    };
 
     if !skip_setter {
         let doc_msg = format!("Set the {} field of this struct.", name);
@@ -146,7 +148,7 @@ fn construct_impl_sign(
     }
 }
 
-fn extract_type_from_option(ty: &Type) -> Option<Type> {
+pub(crate) fn extract_type_from_option(ty: &Type) -> Option<Type> {
     fn path_is_option(path: &Path) -> bool {
         path.segments.last().unwrap().ident == "Option"
     }
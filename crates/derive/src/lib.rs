@@ -1,6 +1,7 @@
 use proc_macro::TokenStream;
 
 mod impl_call;
+mod impl_command;
 mod impl_into_request;
 mod impl_self_builder;
 
@@ -9,6 +10,11 @@ pub fn into_request(args: TokenStream, input: TokenStream) -> TokenStream {
     impl_into_request::impl_into_request(args, input)
 }
 
+#[proc_macro_derive(Command, attributes(command))]
+pub fn command(input: TokenStream) -> TokenStream {
+    impl_command::impl_command(input)
+}
+
 #[proc_macro_derive(builder, attributes(builder))]
 pub fn self_builder(input: TokenStream) -> TokenStream {
     let input = syn::parse_macro_input!(input as syn::DeriveInput);
@@ -19,6 +19,10 @@ pub mod v1 {
 }
 pub use v1::*;
 
+/// Functions for creating and displaying [`Photo`] content.
+#[cfg(feature = "image")]
+pub mod photo;
+
 impl From<String> for FormattedText {
     fn from(text: String) -> Self {
         FormattedText::new(text, Vec::new())
@@ -193,6 +197,23 @@ pub enum EventSource {
     Unsubscribe,
 }
 
+impl EventSource {
+    /// Get the source a [`StreamEventsRequest`] subscribes to, or `None` if
+    /// the request is empty.
+    pub fn from_request(request: &StreamEventsRequest) -> Option<Self> {
+        Some(match request.request.as_ref()? {
+            stream_events_request::Request::SubscribeToGuild(sub) => {
+                EventSource::Guild(sub.guild_id)
+            }
+            stream_events_request::Request::SubscribeToHomeserverEvents(_) => {
+                EventSource::Homeserver
+            }
+            stream_events_request::Request::SubscribeToActions(_) => EventSource::Action,
+            stream_events_request::Request::UnsubscribeFromAll(_) => EventSource::Unsubscribe,
+        })
+    }
+}
+
 impl From<EventSource> for StreamEventsRequest {
     fn from(o: EventSource) -> StreamEventsRequest {
         StreamEventsRequest {
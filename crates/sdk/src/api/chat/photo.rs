@@ -0,0 +1,222 @@
+use super::{Minithumbnail, Photo};
+
+use image::{
+    codecs::jpeg::JpegEncoder, ColorType, DynamicImage, ImageFormat, ImageResult, RgbaImage,
+};
+use std::convert::TryFrom;
+
+/// Maximum width / height of a generated minithumbnail.
+pub const MINITHUMBNAIL_SIZE: u32 = 32;
+/// JPEG quality used when encoding minithumbnails.
+const MINITHUMBNAIL_QUALITY: u8 = 40;
+
+/// Create a [`Photo`] from image data, filling the width, height, file
+/// size and minithumbnail fields.
+///
+/// The `hmc`, `name` and `caption` fields are left empty, since they depend
+/// on where and how the image is uploaded.
+pub fn make_photo(data: &[u8]) -> ImageResult<Photo> {
+    let image = image::load_from_memory(data)?;
+
+    Ok(Photo {
+        file_size: u32::try_from(data.len()).unwrap_or(u32::MAX),
+        width: image.width(),
+        height: image.height(),
+        minithumbnail: Some(make_minithumbnail(&image)?),
+        ..Default::default()
+    })
+}
+
+/// Create a tiny JPEG thumbnail of an image, suitable to be sent inline with
+/// a [`Photo`].
+///
+/// The thumbnail keeps the aspect ratio of the image and fits in a
+/// [`MINITHUMBNAIL_SIZE`] square.
+pub fn make_minithumbnail(image: &DynamicImage) -> ImageResult<Minithumbnail> {
+    let thumbnail = image
+        .thumbnail(MINITHUMBNAIL_SIZE, MINITHUMBNAIL_SIZE)
+        .to_rgb8();
+
+    let mut data = Vec::new();
+    JpegEncoder::new_with_quality(&mut data, MINITHUMBNAIL_QUALITY).encode(
+        thumbnail.as_raw(),
+        thumbnail.width(),
+        thumbnail.height(),
+        ColorType::Rgb8,
+    )?;
+
+    Ok(Minithumbnail {
+        width: thumbnail.width(),
+        height: thumbnail.height(),
+        data,
+    })
+}
+
+/// Decode a received minithumbnail, so that it can be displayed while the
+/// full photo is loading.
+pub fn decode_minithumbnail(minithumbnail: &Minithumbnail) -> ImageResult<RgbaImage> {
+    image::load_from_memory_with_format(&minithumbnail.data, ImageFormat::Jpeg)
+        .map(|image| image.to_rgba8())
+}
+
+/// Encode an image as a [BlurHash](https://blurha.sh) string.
+///
+/// `components_x` and `components_y` control the amount of detail, and must
+/// be between `1` and `9`. `4` and `3` are good defaults for most images.
+///
+/// # Panics
+/// Panics if component counts are out of range.
+pub fn blurhash(image: &DynamicImage, components_x: u32, components_y: u32) -> String {
+    // Computing the hash is expensive for big images, and a thumbnail
+    // produces practically the same result.
+    let image = image.thumbnail(64, 64).to_rgb8();
+    blurhash_rgb(
+        image.width(),
+        image.height(),
+        image.as_raw(),
+        components_x,
+        components_y,
+    )
+}
+
+fn blurhash_rgb(
+    width: u32,
+    height: u32,
+    rgb: &[u8],
+    components_x: u32,
+    components_y: u32,
+) -> String {
+    use std::f32::consts::PI;
+
+    assert!(
+        (1..=9).contains(&components_x) && (1..=9).contains(&components_y),
+        "blurhash components must be between 1 and 9"
+    );
+
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0_f32; 3];
+            for y in 0..height {
+                for x in 0..width {
+                    let basis = normalisation
+                        * (PI * i as f32 * x as f32 / width as f32).cos()
+                        * (PI * j as f32 * y as f32 / height as f32).cos();
+                    let at = 3 * (y * width + x) as usize;
+                    for (channel, value) in factor.iter_mut().zip(&rgb[at..at + 3]) {
+                        *channel += basis * srgb_to_linear(*value);
+                    }
+                }
+            }
+            let scale = 1.0 / (width * height) as f32;
+            factors.push(factor.map(|c| c * scale));
+        }
+    }
+
+    let (dc, ac) = factors
+        .split_first()
+        .expect("there is at least one component");
+
+    let mut hash = String::with_capacity(4 + 2 * factors.len());
+    encode_base83((components_x - 1) + (components_y - 1) * 9, 1, &mut hash);
+
+    let maximum_value = if ac.is_empty() {
+        encode_base83(0, 1, &mut hash);
+        1.0
+    } else {
+        let actual_max = ac
+            .iter()
+            .flat_map(|f| f.iter())
+            .fold(0.0_f32, |max, c| max.max(c.abs()));
+        let quantised_max = ((actual_max * 166.0 - 0.5).floor() as i32).clamp(0, 82) as u32;
+        encode_base83(quantised_max, 1, &mut hash);
+        (quantised_max + 1) as f32 / 166.0
+    };
+
+    let [r, g, b] = dc.map(linear_to_srgb);
+    encode_base83((r << 16) + (g << 8) + b, 4, &mut hash);
+
+    for factor in ac {
+        let [r, g, b] = factor.map(|c| {
+            let quantised = (sign_pow(c / maximum_value, 0.5) * 9.0 + 9.5).floor();
+            quantised.clamp(0.0, 18.0) as u32
+        });
+        encode_base83(r * 19 * 19 + g * 19 + b, 2, &mut hash);
+    }
+
+    hash
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    let srgb = if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+fn sign_pow(value: f32, exp: f32) -> f32 {
+    value.abs().powf(exp).copysign(value)
+}
+
+const BASE83: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+fn encode_base83(value: u32, length: u32, out: &mut String) {
+    for i in 1..=length {
+        let digit = (value / 83_u32.pow(length - i)) % 83;
+        out.push(BASE83[digit as usize] as char);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_base83(s: &str) -> u32 {
+        s.bytes().fold(0, |value, c| {
+            value * 83 + BASE83.iter().position(|b| *b == c).unwrap() as u32
+        })
+    }
+
+    #[test]
+    fn blurhash_length() {
+        let rgb = vec![128; 4 * 4 * 3];
+        assert_eq!(blurhash_rgb(4, 4, &rgb, 4, 3).len(), 4 + 2 * 4 * 3);
+        assert_eq!(blurhash_rgb(4, 4, &rgb, 1, 1).len(), 6);
+    }
+
+    #[test]
+    fn blurhash_solid_color() {
+        let rgb = [255, 0, 0].repeat(2 * 2);
+        let hash = blurhash_rgb(2, 2, &rgb, 1, 1);
+        assert_eq!(&hash[0..2], "00");
+        assert_eq!(decode_base83(&hash[2..6]), 0xFF0000);
+    }
+
+    #[test]
+    fn minithumbnail_roundtrip() {
+        let image = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            128,
+            64,
+            image::Rgb([10, 200, 30]),
+        ));
+        let minithumbnail = make_minithumbnail(&image).unwrap();
+        assert_eq!((minithumbnail.width, minithumbnail.height), (32, 16));
+
+        let decoded = decode_minithumbnail(&minithumbnail).unwrap();
+        assert_eq!(decoded.dimensions(), (32, 16));
+    }
+}
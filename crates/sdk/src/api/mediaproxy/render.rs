@@ -0,0 +1,882 @@
+//! Renders [`InstantViewResponse`]s to sanitized HTML, plain text and
+//! [`FormattedText`].
+//!
+//! [`FormattedText`]: crate::api::chat::FormattedText
+
+use super::InstantViewResponse;
+
+use std::fmt::Write;
+
+/// Render an instant view article as sanitized HTML.
+///
+/// The page title (if there is one) is rendered as a heading and the
+/// article content is sanitized with [`sanitize_html()`]. The result is
+/// wrapped in an `<article>` element.
+pub fn to_html(response: &InstantViewResponse) -> String {
+    let mut html = String::from("<article>");
+    if let Some(title) = title_of(response) {
+        html.push_str("<h1>");
+        escape_into(title, &mut html);
+        html.push_str("</h1>");
+    }
+    html.push_str(&sanitize_html(&response.content));
+    html.push_str("</article>");
+    html
+}
+
+/// Render an instant view article as plain text, wrapping lines at `width`
+/// characters.
+///
+/// Links are written as `text <url>` and images as `[image: alt]`.
+pub fn to_plain_text(response: &InstantViewResponse, width: usize) -> String {
+    let width = width.max(8);
+    let mut text = String::new();
+    if let Some(title) = title_of(response) {
+        for line in wrap(title, width, "", "") {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text.push_str(&"=".repeat(title.chars().count().min(width)));
+        text.push_str("\n\n");
+    }
+
+    let blocks = parse_blocks(&response.content);
+    for (index, block) in blocks.iter().enumerate() {
+        let quote = "> ".repeat(block.quote);
+        let content = block.plain_text();
+        match &block.kind {
+            BlockKind::Preformatted => {
+                for line in content.lines() {
+                    let _ = writeln!(text, "{}    {}", quote, line);
+                }
+            }
+            BlockKind::Rule => {
+                let _ = writeln!(
+                    text,
+                    "{}{}",
+                    quote,
+                    "-".repeat(width.saturating_sub(quote.len()))
+                );
+            }
+            BlockKind::Heading(level) => {
+                let lines = wrap(&content, width, &quote, &quote);
+                let underline = if *level <= 1 { "=" } else { "-" };
+                let underline_len = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+                for line in lines {
+                    text.push_str(&line);
+                    text.push('\n');
+                }
+                text.push_str(&quote);
+                text.push_str(&underline.repeat(underline_len.saturating_sub(quote.len())));
+                text.push('\n');
+            }
+            BlockKind::ListItem { depth, marker } => {
+                let indent = "  ".repeat(depth.saturating_sub(1));
+                let first = format!("{}{}{} ", quote, indent, marker);
+                let rest = format!(
+                    "{}{}{}",
+                    quote,
+                    indent,
+                    " ".repeat(marker.chars().count() + 1)
+                );
+                for line in wrap(&content, width, &first, &rest) {
+                    text.push_str(&line);
+                    text.push('\n');
+                }
+            }
+            BlockKind::Paragraph | BlockKind::Image { .. } => {
+                for line in wrap(&content, width, &quote, &quote) {
+                    text.push_str(&line);
+                    text.push('\n');
+                }
+            }
+        }
+
+        // Keep consecutive list items together
+        let next_is_item = matches!(
+            blocks.get(index + 1).map(|b| &b.kind),
+            Some(BlockKind::ListItem { .. })
+        );
+        if !(matches!(block.kind, BlockKind::ListItem { .. }) && next_is_item) {
+            text.push('\n');
+        }
+    }
+
+    let trimmed_len = text.trim_end_matches('\n').len();
+    text.truncate(trimmed_len);
+    text
+}
+
+/// Render an instant view article as [`FormattedText`], so it can be
+/// displayed with the same code that displays messages.
+///
+/// Bold, italic and code text is mapped to the respective [`Format`]s.
+/// Format offsets are counted in characters (Unicode scalar values).
+///
+/// [`FormattedText`]: crate::api::chat::FormattedText
+/// [`Format`]: crate::api::chat::Format
+#[cfg(feature = "gen_chat")]
+pub fn to_formatted_text(response: &InstantViewResponse) -> crate::api::chat::FormattedText {
+    use crate::api::chat::{format, Format, FormattedText};
+
+    let mut text = String::new();
+    let mut formats = Vec::new();
+    let mut len = 0_u32;
+    // Appends to the text, returning the range of the appended string
+    fn push(text: &mut String, len: &mut u32, s: &str) -> (u32, u32) {
+        let start = *len;
+        text.push_str(s);
+        *len += s.chars().count() as u32;
+        (start, *len - start)
+    }
+    let make = |(start, length): (u32, u32), format: format::Format| Format {
+        start,
+        length,
+        format: Some(format),
+    };
+
+    if let Some(title) = title_of(response) {
+        let range = push(&mut text, &mut len, title);
+        formats.push(make(range, format::Format::Bold(format::Bold {})));
+    }
+
+    let mut previous_was_item = false;
+    for block in parse_blocks(&response.content) {
+        let is_item = matches!(block.kind, BlockKind::ListItem { .. });
+        if !text.is_empty() {
+            let separator = if is_item && previous_was_item {
+                "\n"
+            } else {
+                "\n\n"
+            };
+            push(&mut text, &mut len, separator);
+        }
+        previous_was_item = is_item;
+        push(&mut text, &mut len, &"> ".repeat(block.quote));
+
+        match &block.kind {
+            BlockKind::Rule => {
+                push(&mut text, &mut len, "---");
+                continue;
+            }
+            BlockKind::Image { .. } => {
+                push(&mut text, &mut len, &block.plain_text());
+                continue;
+            }
+            BlockKind::ListItem { depth, marker } => {
+                let indent = "  ".repeat(depth.saturating_sub(1));
+                push(&mut text, &mut len, &format!("{}{} ", indent, marker));
+            }
+            _ => {}
+        }
+
+        let block_start = len;
+        for span in &block.spans {
+            let range = push(&mut text, &mut len, &span.text);
+            if span.style.bold {
+                formats.push(make(range, format::Format::Bold(format::Bold {})));
+            }
+            if span.style.italic {
+                formats.push(make(range, format::Format::Italic(format::Italic {})));
+            }
+            if span.style.code && !matches!(block.kind, BlockKind::Preformatted) {
+                formats.push(make(range, format::Format::Monospace(format::Monospace {})));
+            }
+            if let Some(href) = span.style.link.as_ref().filter(|href| **href != span.text) {
+                push(&mut text, &mut len, &format!(" <{}>", href));
+            }
+        }
+        let block_range = (block_start, len - block_start);
+        match &block.kind {
+            BlockKind::Heading(_) => {
+                formats.push(make(block_range, format::Format::Bold(format::Bold {})))
+            }
+            BlockKind::Preformatted => formats.push(make(
+                block_range,
+                format::Format::CodeBlock(format::CodeBlock::default()),
+            )),
+            _ => {}
+        }
+    }
+
+    FormattedText::new(text, formats)
+}
+
+/// Sanitize HTML, keeping only a safe subset of text formatting elements.
+///
+/// - Allowed elements are kept with only their safe attributes (`href` on
+///   links, `src` and `alt` on images, `http(s)` URLs only).
+/// - Scripts, styles and embedded content are removed along with their
+///   contents.
+/// - Other elements are removed, but their contents are kept.
+/// - Text is escaped, and unclosed elements are closed at the end.
+///
+/// # Example
+/// ```
+/// # use harmony_rust_sdk::api::mediaproxy::render::sanitize_html;
+/// let html = r#"<p onclick="evil()">Hi <script>evil()</script><a href="javascript:evil()">there</a>"#;
+/// assert_eq!(sanitize_html(html), "<p>Hi <a>there</a></p>");
+/// ```
+pub fn sanitize_html(html: &str) -> String {
+    const ALLOWED: &[&str] = &[
+        "a",
+        "b",
+        "blockquote",
+        "br",
+        "code",
+        "del",
+        "em",
+        "figcaption",
+        "figure",
+        "h1",
+        "h2",
+        "h3",
+        "h4",
+        "h5",
+        "h6",
+        "hr",
+        "i",
+        "img",
+        "li",
+        "ol",
+        "p",
+        "pre",
+        "s",
+        "strong",
+        "sub",
+        "sup",
+        "u",
+        "ul",
+    ];
+
+    let mut out = String::with_capacity(html.len());
+    let mut open: Vec<String> = Vec::new();
+    for token in tokenize(html) {
+        match token {
+            Token::Text(text) => escape_into(&text, &mut out),
+            Token::Start { name, attrs } => {
+                if !ALLOWED.contains(&name.as_str()) {
+                    continue;
+                }
+                out.push('<');
+                out.push_str(&name);
+                for (key, value) in attrs {
+                    let allowed = matches!(
+                        (name.as_str(), key.as_str()),
+                        ("a", "href") | ("img", "src") | ("img", "alt")
+                    );
+                    if !allowed || (key != "alt" && !is_safe_url(&value)) {
+                        continue;
+                    }
+                    let _ = write!(out, " {}=\"", key);
+                    escape_into(&value, &mut out);
+                    out.push('"');
+                }
+                out.push('>');
+                if !is_void(&name) {
+                    open.push(name);
+                }
+            }
+            Token::End(name) => {
+                if let Some(pos) = open.iter().rposition(|open| *open == name) {
+                    for name in open.drain(pos..).rev() {
+                        let _ = write!(out, "</{}>", name);
+                    }
+                }
+            }
+        }
+    }
+    for name in open.into_iter().rev() {
+        let _ = write!(out, "</{}>", name);
+    }
+
+    out
+}
+
+fn title_of(response: &InstantViewResponse) -> Option<&str> {
+    response
+        .metadata
+        .as_ref()
+        .map(|m| m.page_title.trim())
+        .filter(|title| !title.is_empty())
+}
+
+fn is_safe_url(url: &str) -> bool {
+    let url = url.trim().to_ascii_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+fn is_void(name: &str) -> bool {
+    matches!(
+        name,
+        "br" | "hr" | "img" | "wbr" | "meta" | "link" | "input"
+    )
+}
+
+fn escape_into(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| entity.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Start {
+        name: String,
+        attrs: Vec<(String, String)>,
+    },
+    End(String),
+}
+
+/// A forgiving HTML tokenizer. Drops comments, doctypes and the contents of
+/// elements that can't contain text.
+fn tokenize(html: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let lt = match rest.find('<') {
+            Some(lt) => lt,
+            None => {
+                tokens.push(Token::Text(decode_entities(rest)));
+                break;
+            }
+        };
+        if lt > 0 {
+            tokens.push(Token::Text(decode_entities(&rest[..lt])));
+        }
+        rest = &rest[lt..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map_or("", |end| &rest[end + 1..]);
+            continue;
+        }
+
+        let (is_end, tag) = match rest[1..].strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, &rest[1..]),
+        };
+        let name_len = tag
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(tag.len());
+        if name_len == 0 {
+            // Not a tag, just a stray `<`
+            tokens.push(Token::Text("<".to_string()));
+            rest = &rest[1..];
+            continue;
+        }
+        let name = tag[..name_len].to_ascii_lowercase();
+        let (attrs, after) = parse_attrs(&tag[name_len..]);
+        rest = after;
+
+        if is_end {
+            tokens.push(Token::End(name));
+        } else if matches!(
+            name.as_str(),
+            "script" | "style" | "iframe" | "object" | "embed" | "noscript" | "template" | "svg"
+        ) {
+            // Skip everything until the closing tag
+            let close = format!("</{}", name);
+            rest = rest.to_ascii_lowercase().find(&close).map_or("", |end| {
+                let after_close = &rest[end..];
+                after_close
+                    .find('>')
+                    .map_or("", |gt| &after_close[gt + 1..])
+            });
+        } else {
+            tokens.push(Token::Start { name, attrs });
+        }
+    }
+
+    tokens
+}
+
+/// Parses attributes until the end of the tag, returning them and the rest
+/// of the input after the tag.
+fn parse_attrs(mut input: &str) -> (Vec<(String, String)>, &str) {
+    let mut attrs = Vec::new();
+    loop {
+        input = input.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if input.is_empty() {
+            return (attrs, input);
+        }
+        if let Some(after) = input.strip_prefix('>') {
+            return (attrs, after);
+        }
+
+        let key_len = input
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(input.len())
+            .max(1);
+        let key = input[..key_len].to_ascii_lowercase();
+        input = input[key_len..].trim_start();
+
+        let value = match input.strip_prefix('=') {
+            Some(after_eq) => {
+                let after_eq = after_eq.trim_start();
+                let (value, after) = match after_eq.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let inner = &after_eq[1..];
+                        let end = inner.find(quote).unwrap_or(inner.len());
+                        (&inner[..end], inner.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => {
+                        let end = after_eq
+                            .find(|c: char| c.is_whitespace() || c == '>')
+                            .unwrap_or(after_eq.len());
+                        (&after_eq[..end], &after_eq[end..])
+                    }
+                };
+                input = after;
+                decode_entities(value)
+            }
+            None => String::new(),
+        };
+        attrs.push((key, value));
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    code: bool,
+    link: Option<String>,
+}
+
+#[derive(Debug)]
+struct Span {
+    text: String,
+    style: Style,
+}
+
+#[derive(Debug, PartialEq)]
+enum BlockKind {
+    Paragraph,
+    Heading(u8),
+    ListItem { depth: usize, marker: String },
+    Preformatted,
+    Rule,
+    Image { src: String, alt: String },
+}
+
+#[derive(Debug)]
+struct Block {
+    kind: BlockKind,
+    quote: usize,
+    spans: Vec<Span>,
+}
+
+impl Block {
+    fn plain_text(&self) -> String {
+        if let BlockKind::Image { alt, .. } = &self.kind {
+            return if alt.is_empty() {
+                "[image]".to_string()
+            } else {
+                format!("[image: {}]", alt)
+            };
+        }
+        let mut text = String::new();
+        for span in &self.spans {
+            text.push_str(&span.text);
+            if let Some(href) = span.style.link.as_ref().filter(|href| **href != span.text) {
+                let _ = write!(text, " <{}>", href);
+            }
+        }
+        text
+    }
+}
+
+#[derive(Default)]
+struct BlockBuilder {
+    blocks: Vec<Block>,
+    current: Vec<Span>,
+    kind: Option<BlockKind>,
+    style: Style,
+    bold: usize,
+    italic: usize,
+    code: usize,
+    links: Vec<Option<String>>,
+    pre: usize,
+    quote: usize,
+    lists: Vec<Option<u32>>,
+}
+
+impl BlockBuilder {
+    fn finish_block(&mut self) {
+        let kind = self.kind.take().unwrap_or(BlockKind::Paragraph);
+        let mut spans = std::mem::take(&mut self.current);
+        if !matches!(kind, BlockKind::Preformatted) {
+            // Trim whitespace around the block
+            if let Some(first) = spans.first_mut() {
+                first.text = first.text.trim_start().to_string();
+            }
+            if let Some(last) = spans.last_mut() {
+                last.text = last.text.trim_end().to_string();
+            }
+            spans.retain(|s| !s.text.is_empty());
+        }
+        let is_empty = spans.iter().all(|s| s.text.trim().is_empty());
+        if !is_empty || matches!(kind, BlockKind::ListItem { .. }) {
+            self.blocks.push(Block {
+                kind,
+                quote: self.quote,
+                spans,
+            });
+        }
+    }
+
+    fn start_block(&mut self, kind: BlockKind) {
+        self.finish_block();
+        self.kind = Some(kind);
+    }
+
+    fn push_text(&mut self, text: &str) {
+        let text = if self.pre > 0 {
+            text.to_string()
+        } else {
+            let mut collapsed = String::with_capacity(text.len());
+            let ends_with_space = !matches!(self.current.last(), Some(s) if !s.text.ends_with(' '));
+            let mut last_space = ends_with_space;
+            for c in text.chars() {
+                if c.is_whitespace() && c != '\u{a0}' {
+                    if !last_space {
+                        collapsed.push(' ');
+                    }
+                    last_space = true;
+                } else {
+                    collapsed.push(c);
+                    last_space = false;
+                }
+            }
+            collapsed
+        };
+        if text.is_empty() {
+            return;
+        }
+
+        self.style.bold = self.bold > 0;
+        self.style.italic = self.italic > 0;
+        self.style.code = self.code > 0 || self.pre > 0;
+        self.style.link = self.links.iter().rev().find_map(Clone::clone);
+        match self.current.last_mut() {
+            Some(last) if last.style == self.style => last.text.push_str(&text),
+            _ => self.current.push(Span {
+                text,
+                style: self.style.clone(),
+            }),
+        }
+    }
+
+    fn start(&mut self, name: &str, attrs: &[(String, String)]) {
+        let attr = |key: &str| attrs.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+        match name {
+            "b" | "strong" => self.bold += 1,
+            "i" | "em" => self.italic += 1,
+            "code" | "kbd" | "samp" => self.code += 1,
+            "a" => self
+                .links
+                .push(attr("href").filter(|href| is_safe_url(href))),
+            "br" => self.push_line_break(),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.start_block(BlockKind::Heading(name.as_bytes()[1] - b'0'))
+            }
+            "pre" => {
+                self.start_block(BlockKind::Preformatted);
+                self.pre += 1;
+            }
+            "blockquote" => {
+                self.finish_block();
+                self.quote += 1;
+            }
+            "ul" => {
+                self.finish_block();
+                self.lists.push(None);
+            }
+            "ol" => {
+                self.finish_block();
+                let start = attr("start").and_then(|s| s.parse().ok()).unwrap_or(1);
+                self.lists.push(Some(start));
+            }
+            "li" => {
+                let depth = self.lists.len().max(1);
+                let marker = match self.lists.last_mut() {
+                    Some(Some(counter)) => {
+                        let marker = format!("{}.", counter);
+                        *counter = counter.saturating_add(1);
+                        marker
+                    }
+                    _ => "•".to_string(),
+                };
+                self.start_block(BlockKind::ListItem { depth, marker });
+            }
+            "hr" => {
+                self.start_block(BlockKind::Rule);
+                self.finish_block_always();
+            }
+            "img" => {
+                if let Some(src) = attr("src").filter(|src| is_safe_url(src)) {
+                    let alt = attr("alt").unwrap_or_default();
+                    self.start_block(BlockKind::Image { src, alt });
+                    self.finish_block_always();
+                }
+            }
+            "p" | "div" | "section" | "article" | "header" | "footer" | "figure" | "figcaption"
+            | "table" | "tr" | "dd" | "dt" | "main" | "aside" => self.finish_block(),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        match name {
+            "b" | "strong" => self.bold = self.bold.saturating_sub(1),
+            "i" | "em" => self.italic = self.italic.saturating_sub(1),
+            "code" | "kbd" | "samp" => self.code = self.code.saturating_sub(1),
+            "a" => {
+                self.links.pop();
+            }
+            "pre" => {
+                self.pre = self.pre.saturating_sub(1);
+                self.finish_block();
+            }
+            "blockquote" => {
+                self.finish_block();
+                self.quote = self.quote.saturating_sub(1);
+            }
+            "ul" | "ol" => {
+                self.finish_block();
+                self.lists.pop();
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "li" | "p" | "div" | "section"
+            | "article" | "header" | "footer" | "figure" | "figcaption" | "table" | "tr" | "dd"
+            | "dt" | "main" | "aside" => self.finish_block(),
+            _ => {}
+        }
+    }
+
+    fn push_line_break(&mut self) {
+        // Line breaks only make sense inside preformatted text when reflowing,
+        // so treat them as paragraph breaks otherwise.
+        if self.pre > 0 {
+            self.push_text("\n");
+        } else {
+            // Continue list items without a marker
+            let continuation = match &self.kind {
+                Some(BlockKind::ListItem { depth, marker }) => Some(BlockKind::ListItem {
+                    depth: *depth,
+                    marker: " ".repeat(marker.chars().count()),
+                }),
+                _ => None,
+            };
+            self.finish_block();
+            self.kind = continuation;
+        }
+    }
+
+    fn finish_block_always(&mut self) {
+        let kind = self.kind.take().unwrap_or(BlockKind::Paragraph);
+        self.blocks.push(Block {
+            kind,
+            quote: self.quote,
+            spans: Vec::new(),
+        });
+    }
+}
+
+fn parse_blocks(html: &str) -> Vec<Block> {
+    let mut builder = BlockBuilder::default();
+    for token in tokenize(html) {
+        match token {
+            Token::Text(text) => builder.push_text(&text),
+            Token::Start { name, attrs } => builder.start(&name, &attrs),
+            Token::End(name) => builder.end(&name),
+        }
+    }
+    builder.finish_block();
+    builder.blocks
+}
+
+/// Wraps text into lines of at most `width` characters (including indents).
+/// Words longer than a line are split.
+fn wrap(text: &str, width: usize, first_indent: &str, rest_indent: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = first_indent.to_string();
+    let mut line_len = first_indent.chars().count();
+    let mut indent_len = line_len;
+
+    for word in text.split_whitespace() {
+        let mut word = word;
+        loop {
+            let word_len = word.chars().count();
+            let space = usize::from(line_len > indent_len);
+            if line_len + space + word_len <= width {
+                if space == 1 {
+                    line.push(' ');
+                }
+                line.push_str(word);
+                line_len += space + word_len;
+                break;
+            }
+            if line_len > indent_len {
+                lines.push(std::mem::replace(&mut line, rest_indent.to_string()));
+                line_len = rest_indent.chars().count();
+                indent_len = line_len;
+                continue;
+            }
+            // The word doesn't fit in an empty line, split it
+            let fits = width.saturating_sub(line_len).max(1);
+            let split_at = word.char_indices().nth(fits).map_or(word.len(), |(i, _)| i);
+            line.push_str(&word[..split_at]);
+            lines.push(std::mem::replace(&mut line, rest_indent.to_string()));
+            line_len = rest_indent.chars().count();
+            indent_len = line_len;
+            word = &word[split_at..];
+            if word.is_empty() {
+                break;
+            }
+        }
+    }
+    if line_len > indent_len || lines.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(content: &str) -> InstantViewResponse {
+        InstantViewResponse {
+            content: content.to_string(),
+            is_valid: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sanitize_removes_unsafe_content() {
+        let html = r#"<div><p style="color: red">Hello <b>world</b><script>alert(1)</script></p><img src="javascript:x" alt="a"><img src="https://example.org/a.png" onerror="x"></div>"#;
+        assert_eq!(
+            sanitize_html(html),
+            r#"<p>Hello <b>world</b></p><img alt="a"><img src="https://example.org/a.png">"#
+        );
+    }
+
+    #[test]
+    fn sanitize_balances_tags() {
+        assert_eq!(
+            sanitize_html("<p><b>bold</p> &lt;text</i>"),
+            "<p><b>bold</b></p> &lt;text"
+        );
+    }
+
+    #[test]
+    fn decodes_entities() {
+        assert_eq!(
+            decode_entities("a &amp; b &#x41;&#66; &unknown; &"),
+            "a & b AB &unknown; &"
+        );
+    }
+
+    #[test]
+    fn wraps_text() {
+        assert_eq!(
+            wrap("the quick brown fox jumps", 10, "", ""),
+            vec!["the quick", "brown fox", "jumps"]
+        );
+        assert_eq!(
+            wrap("aaaaaaaaaaaa b", 5, "- ", "  "),
+            vec!["- aaa", "  aaa", "  aaa", "  aaa", "  b"]
+        );
+    }
+
+    #[test]
+    fn plain_text_rendering() {
+        let html = "<h2>Intro</h2><p>Some <a href=\"https://example.org\">link</a>\n text.</p><ul><li>one</li><li>two</li></ul><pre>let x =\n  1;</pre>";
+        assert_eq!(
+            to_plain_text(&response(html), 40),
+            "Intro\n-----\n\nSome link <https://example.org> text.\n\n• one\n• two\n\n    let x =\n      1;"
+        );
+    }
+
+    #[test]
+    fn plain_text_ordered_list_and_quote() {
+        let html = "<blockquote><p>quoted words here</p></blockquote><ol start=\"3\"><li>a</li><li>b</li></ol>";
+        assert_eq!(
+            to_plain_text(&response(html), 12),
+            "> quoted\n> words here\n\n3. a\n4. b"
+        );
+    }
+
+    #[test]
+    fn plain_text_ordered_list_at_max_start() {
+        let html = "<ol start=\"4294967295\"><li>a</li><li>b</li></ol>";
+        assert_eq!(
+            to_plain_text(&response(html), 20),
+            "4294967295. a\n4294967295. b"
+        );
+    }
+
+    #[test]
+    fn plain_text_deeply_nested_quotes() {
+        let html = "<blockquote><blockquote><blockquote><blockquote><blockquote><hr><h1></h1><p>deep</p></blockquote></blockquote></blockquote></blockquote></blockquote>";
+        assert_eq!(
+            to_plain_text(&response(html), 8),
+            "> > > > > \n\n> > > > > d\n> > > > > e\n> > > > > e\n> > > > > p"
+        );
+    }
+}
@@ -35,6 +35,9 @@ pub mod mediaproxy {
         hrpc::include_proto!("protocol.mediaproxy.v1");
     }
     pub use v1::*;
+
+    /// Rendering of instant view articles.
+    pub mod render;
 }
 
 /// Sync service API.
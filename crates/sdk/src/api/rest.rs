@@ -168,6 +168,135 @@ pub fn extract_file_info_from_download_response<'a>(
     Ok((name, mimetype, kind))
 }
 
+/// Guesses the mimetype of a file, first by looking at its contents and then
+/// by looking at the extension of its name.
+///
+/// Returns `application/octet-stream` if the mimetype couldn't be guessed.
+///
+/// # Example
+/// ```
+/// # use harmony_rust_sdk::api::rest::guess_mimetype;
+/// assert_eq!(guess_mimetype("notes.txt", b"hello"), "text/plain");
+/// assert_eq!(guess_mimetype("unknown", b"\x89PNG\r\n\x1a\n"), "image/png");
+/// ```
+pub fn guess_mimetype(name: &str, data: &[u8]) -> &'static str {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xFF\xD8\xFF", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"BM", "image/bmp"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"ID3", "audio/mpeg"),
+        (b"\x1A\x45\xDF\xA3", "video/webm"),
+    ];
+
+    if let Some((_, mimetype)) = MAGIC.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return mimetype;
+    }
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return "video/mp4";
+    }
+
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "svg" => "image/svg+xml",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Reads the dimensions of an image from its header, without decoding it.
+///
+/// Supports PNG, GIF, BMP, JPEG and WebP images. Returns `(width, height)`, or
+/// `None` if the format isn't supported or the header is malformed.
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let u16_be = |at: usize| -> Option<u32> {
+        data.get(at..at + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)
+    };
+    let u16_le = |at: usize| -> Option<u32> {
+        data.get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as u32)
+    };
+    let u32_be = |at: usize| -> Option<u32> {
+        data.get(at..at + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let u24_le = |at: usize| -> Option<u32> {
+        data.get(at..at + 3)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], 0]))
+    };
+
+    match guess_mimetype("", data) {
+        "image/png" => Some((u32_be(16)?, u32_be(20)?)),
+        "image/gif" => Some((u16_le(6)?, u16_le(8)?)),
+        "image/bmp" => {
+            let width = data.get(18..22)?;
+            let height = data.get(22..26)?;
+            let width = i32::from_le_bytes([width[0], width[1], width[2], width[3]]);
+            let height = i32::from_le_bytes([height[0], height[1], height[2], height[3]]);
+            Some((width.unsigned_abs(), height.unsigned_abs()))
+        }
+        "image/webp" => match data.get(12..16)? {
+            b"VP8 " => Some((u16_le(26)? & 0x3FFF, u16_le(28)? & 0x3FFF)),
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+                Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+            }
+            b"VP8X" => Some((u24_le(24)? + 1, u24_le(27)? + 1)),
+            _ => None,
+        },
+        "image/jpeg" => {
+            // Walk the segments until we find a start of frame marker
+            let mut at = 2;
+            loop {
+                if *data.get(at)? != 0xFF {
+                    return None;
+                }
+                let marker = *data.get(at + 1)?;
+                match marker {
+                    // Padding
+                    0xFF => at += 1,
+                    // Markers without a length
+                    0x01 | 0xD0..=0xD7 => at += 2,
+                    // SOF markers, excluding DHT, JPG and DAC
+                    0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                        return Some((u16_be(at + 7)?, u16_be(at + 5)?));
+                    }
+                    _ => at += 2 + u16_be(at + 2)? as usize,
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
 /// Struct that implements `serde` `Deserialize` / `Serialize` and can be used for
 /// the [`/_harmony/about`](https://github.com/harmony-development/protocol/blob/main/stable/rest/rest.md#get-_harmonyabout) endpoint.
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Deserialize, Serialize)]
@@ -219,6 +348,48 @@ mod tests {
         assert_eq!(URI.to_string(), file_id.to_string());
     }
 
+    #[test]
+    fn guess_mimetype_by_extension() {
+        assert_eq!(guess_mimetype("photo.JPG", b""), "image/jpeg");
+        assert_eq!(guess_mimetype("archive", b""), "application/octet-stream");
+    }
+
+    #[test]
+    fn guess_mimetype_by_content() {
+        assert_eq!(guess_mimetype("photo.txt", b"GIF89a"), "image/gif");
+        assert_eq!(guess_mimetype("", b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+    }
+
+    #[test]
+    fn png_dimensions() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&640_u32.to_be_bytes());
+        png.extend_from_slice(&480_u32.to_be_bytes());
+        assert_eq!(image_dimensions(&png), Some((640, 480)));
+    }
+
+    #[test]
+    fn gif_dimensions() {
+        let gif = b"GIF89a\x20\x00\x10\x00";
+        assert_eq!(image_dimensions(gif), Some((32, 16)));
+    }
+
+    #[test]
+    fn jpeg_dimensions() {
+        let jpeg = [
+            0xFF, 0xD8, // SOI
+            0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, // APP0 with 2 bytes of data
+            0xFF, 0xC0, 0x00, 0x11, 0x08, 0x00, 0x78, 0x00, 0xA0, // SOF0, 160x120
+        ];
+        assert_eq!(image_dimensions(&jpeg), Some((160, 120)));
+    }
+
+    #[test]
+    fn truncated_image_dimensions() {
+        assert_eq!(image_dimensions(b"\x89PNG\r\n\x1a\n"), None);
+        assert_eq!(image_dimensions(b"not an image"), None);
+    }
+
     #[test]
     #[should_panic(expected = "InvalidFileId")]
     fn parse_empty() {
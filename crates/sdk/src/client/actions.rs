@@ -0,0 +1,559 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use hrpc::exports::futures_util::future::BoxFuture;
+
+use super::{error::ClientResult, Client};
+use crate::api::chat::{
+    action, action_payload, embed, stream_event, Action, ActionPayload, Embed, Event,
+    FormattedText, SendMessageRequest, TriggerActionRequest,
+};
+
+/// Default number of sent messages an [`ActionRouter`] keeps the callbacks of.
+pub const DEFAULT_MAX_MESSAGES: usize = 1024;
+
+type MessageKey = (u64, u64, u64);
+type Handler =
+    Arc<dyn Fn(ActionContext, ActionInput) -> BoxFuture<'static, ClientResult<()>> + Send + Sync>;
+
+/// Input of a performed action.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionInput {
+    /// A button was clicked.
+    Button,
+    /// A dropdown entry was chosen. Contains the value of the entry.
+    Dropdown(String),
+    /// Text was submitted in an input.
+    Input(String),
+}
+
+impl ActionInput {
+    /// Get the ID of the performed action and its input from an action
+    /// payload of a message sent with an [`ActionRouter`]. Returns `None` if
+    /// the payload is empty or its data wasn't set by an [`ActionMessage`].
+    pub fn from_payload(payload: &ActionPayload) -> Option<(u32, Self)> {
+        match payload.payload.as_ref()? {
+            action_payload::Payload::Button(button) => {
+                Some((parse_action_id(&button.data)?, ActionInput::Button))
+            }
+            action_payload::Payload::Dropdown(dropdown) => {
+                let choice = std::str::from_utf8(&dropdown.choice).ok()?;
+                let (action_id, value) = choice.split_once(':')?;
+                let action_id = action_id.parse().ok()?;
+                Some((action_id, ActionInput::Dropdown(value.to_string())))
+            }
+            action_payload::Payload::Input(input) => Some((
+                parse_action_id(&input.data)?,
+                ActionInput::Input(input.input.clone()),
+            )),
+        }
+    }
+
+    /// Create the payload sent when performing the action with the given ID
+    /// with this input.
+    pub fn into_payload(self, action_id: u32) -> ActionPayload {
+        let payload = match self {
+            ActionInput::Button => action_payload::Payload::Button(action_payload::Button {
+                data: action_data(action_id),
+            }),
+            ActionInput::Dropdown(value) => {
+                action_payload::Payload::Dropdown(action_payload::Dropdown {
+                    choice: entry_data(action_id, &value),
+                })
+            }
+            ActionInput::Input(input) => action_payload::Payload::Input(action_payload::Input {
+                input,
+                data: action_data(action_id),
+            }),
+        };
+        ActionPayload {
+            payload: Some(payload),
+        }
+    }
+
+    /// Whether this is a valid input for the action with the given ID. The
+    /// value of a dropdown must be one of its entries.
+    fn matches(&self, action_id: u32, action: &Action) -> bool {
+        match (self, action.kind.as_ref()) {
+            (ActionInput::Button, Some(action::Kind::Button(_)))
+            | (ActionInput::Input(_), Some(action::Kind::Input(_))) => true,
+            (ActionInput::Dropdown(value), Some(action::Kind::Dropdown(dropdown))) => {
+                let data = entry_data(action_id, value);
+                dropdown.entries.iter().any(|entry| entry.data == data)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Actions are identified by their position in the message, which is stored
+/// in their data.
+fn action_data(action_id: u32) -> Vec<u8> {
+    action_id.to_string().into_bytes()
+}
+
+/// Dropdown entries store the ID of their dropdown along with their value.
+fn entry_data(action_id: u32, value: &str) -> Vec<u8> {
+    format!("{}:{}", action_id, value).into_bytes()
+}
+
+fn parse_action_id(data: &[u8]) -> Option<u32> {
+    std::str::from_utf8(data).ok()?.parse().ok()
+}
+
+/// Context an action was performed in.
+#[derive(Debug, Clone)]
+pub struct ActionContext {
+    /// Client that sent the message, to make further requests.
+    pub client: Client,
+    /// Guild of the message.
+    pub guild_id: u64,
+    /// Channel of the message.
+    pub channel_id: u64,
+    /// ID of the message containing the action.
+    pub message_id: u64,
+    /// User that performed the action.
+    pub user_id: u64,
+}
+
+/// Builder for a message with interactive actions.
+///
+/// Actions are added to an embed, and each action with a callback registers
+/// it to be called when the action is performed. Send the message with
+/// [`ActionRouter::send()`] so that callbacks get called.
+pub struct ActionMessage {
+    guild_id: u64,
+    channel_id: u64,
+    embed: Embed,
+    actions: Vec<Action>,
+    handlers: Vec<Option<Handler>>,
+}
+
+impl Debug for ActionMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ActionMessage")
+            .field("guild_id", &self.guild_id)
+            .field("channel_id", &self.channel_id)
+            .field("embed", &self.embed)
+            .field("actions", &self.actions)
+            .finish()
+    }
+}
+
+impl ActionMessage {
+    /// Create a new message with an embed that has the given title.
+    pub fn new(guild_id: u64, channel_id: u64, title: impl Into<String>) -> Self {
+        Self {
+            guild_id,
+            channel_id,
+            embed: Embed {
+                title: title.into(),
+                ..Default::default()
+            },
+            actions: Vec::new(),
+            handlers: Vec::new(),
+        }
+    }
+
+    /// Set the body of the embed.
+    pub fn with_body(mut self, body: impl Into<FormattedText>) -> Self {
+        self.embed.body = Some(body.into());
+        self
+    }
+
+    fn next_action_id(&self) -> u32 {
+        self.actions.len() as u32
+    }
+
+    fn with_action(mut self, kind: action::Kind, handler: Option<Handler>) -> Self {
+        self.actions.push(Action {
+            kind: Some(kind),
+            ..Default::default()
+        });
+        self.handlers.push(handler);
+        self
+    }
+
+    /// Add a button that calls the given callback when clicked.
+    pub fn with_button<F, Fut>(self, text: impl Into<String>, callback: F) -> Self
+    where
+        F: Fn(ActionContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ClientResult<()>> + Send + 'static,
+    {
+        let button = action::Button {
+            text: text.into(),
+            data: action_data(self.next_action_id()),
+            url: None,
+        };
+        let handler: Handler = Arc::new(move |context, _| Box::pin(callback(context)));
+        self.with_action(action::Kind::Button(button), Some(handler))
+    }
+
+    /// Add a button that opens an URL. It doesn't trigger any callback.
+    pub fn with_link_button(self, text: impl Into<String>, url: impl Into<String>) -> Self {
+        let button = action::Button {
+            text: text.into(),
+            data: action_data(self.next_action_id()),
+            url: Some(url.into()),
+        };
+        self.with_action(action::Kind::Button(button), None)
+    }
+
+    /// Add a dropdown with `(label, value)` entries. The callback gets the
+    /// value of the chosen entry, and isn't called for values that aren't one
+    /// of the entries.
+    pub fn with_dropdown<I, L, V, F, Fut>(
+        self,
+        label: impl Into<String>,
+        entries: I,
+        callback: F,
+    ) -> Self
+    where
+        I: IntoIterator<Item = (L, V)>,
+        L: Into<String>,
+        V: Into<String>,
+        F: Fn(ActionContext, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ClientResult<()>> + Send + 'static,
+    {
+        let action_id = self.next_action_id();
+        let dropdown = action::Dropdown {
+            label: label.into(),
+            entries: entries
+                .into_iter()
+                .map(|(label, value)| action::dropdown::Entry {
+                    label: label.into(),
+                    data: entry_data(action_id, &value.into()),
+                })
+                .collect(),
+        };
+        let handler: Handler = Arc::new(move |context, input| match input {
+            ActionInput::Dropdown(choice) => Box::pin(callback(context, choice)),
+            _ => Box::pin(async { Ok(()) }),
+        });
+        self.with_action(action::Kind::Dropdown(dropdown), Some(handler))
+    }
+
+    /// Add a text input. The callback gets the submitted text.
+    pub fn with_input<F, Fut>(self, label: impl Into<String>, multiline: bool, callback: F) -> Self
+    where
+        F: Fn(ActionContext, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ClientResult<()>> + Send + 'static,
+    {
+        let input = action::Input {
+            label: label.into(),
+            multiline,
+            data: action_data(self.next_action_id()),
+        };
+        let handler: Handler = Arc::new(move |context, input| match input {
+            ActionInput::Input(text) => Box::pin(callback(context, text)),
+            _ => Box::pin(async { Ok(()) }),
+        });
+        self.with_action(action::Kind::Input(input), Some(handler))
+    }
+
+    /// Build the request sending this message. Actions are put in a single
+    /// embed field, and their IDs are their positions in it.
+    fn into_parts(self) -> (SendMessageRequest, Vec<Action>, Vec<Option<Handler>>) {
+        let mut embed = self.embed;
+        embed.fields.push(embed::EmbedField {
+            actions: self.actions.clone(),
+            ..Default::default()
+        });
+        let request = SendMessageRequest {
+            guild_id: self.guild_id,
+            channel_id: self.channel_id,
+            ..Default::default()
+        }
+        .with_embed_content(vec![embed]);
+        (request, self.actions, self.handlers)
+    }
+}
+
+/// Callbacks of the actions of a sent message.
+struct MessageActions {
+    actions: Vec<Action>,
+    handlers: Vec<Option<Handler>>,
+}
+
+/// Callbacks of sent messages, by message. Only the last `max_messages`
+/// sent messages are kept.
+struct ActionTable {
+    messages: HashMap<MessageKey, MessageActions>,
+    order: VecDeque<MessageKey>,
+    max_messages: usize,
+}
+
+impl ActionTable {
+    fn new(max_messages: usize) -> Self {
+        Self {
+            messages: HashMap::new(),
+            order: VecDeque::new(),
+            max_messages,
+        }
+    }
+
+    fn insert(&mut self, key: MessageKey, actions: MessageActions) {
+        if self.messages.insert(key, actions).is_none() {
+            self.order.push_back(key);
+        }
+        while self.messages.len() > self.max_messages {
+            match self.order.pop_front() {
+                Some(oldest) => self.messages.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    fn remove(&mut self, key: &MessageKey) {
+        if self.messages.remove(key).is_some() {
+            self.order.retain(|other| other != key);
+        }
+    }
+
+    /// Get the callback of an action, if the input is valid for the action.
+    fn handler(&self, key: MessageKey, action_id: u32, input: &ActionInput) -> Option<Handler> {
+        let message = self.messages.get(&key)?;
+        let index = action_id as usize;
+        if !input.matches(action_id, message.actions.get(index)?) {
+            return None;
+        }
+        message.handlers.get(index)?.clone()
+    }
+}
+
+/// Sends messages with actions and calls the callbacks registered with them
+/// when `ActionPerformed` events are received.
+///
+/// Callbacks are kept until their message is deleted or forgotten with
+/// [`ActionRouter::forget()`]. Only the callbacks of the last
+/// [`DEFAULT_MAX_MESSAGES`] sent messages are kept, see
+/// [`ActionRouter::with_max_messages()`].
+///
+/// The events socket must be subscribed to [`EventSource::Action`] for
+/// action events to be received.
+///
+/// [`EventSource::Action`]: crate::api::chat::EventSource::Action
+pub struct ActionRouter {
+    client: Client,
+    table: Mutex<ActionTable>,
+}
+
+impl Debug for ActionRouter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ActionRouter")
+            .field("client", &self.client)
+            .field(
+                "messages",
+                &self.table.lock().expect("poisoned").messages.len(),
+            )
+            .finish()
+    }
+}
+
+impl ActionRouter {
+    /// Create a new action router that uses the given client.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            table: Mutex::new(ActionTable::new(DEFAULT_MAX_MESSAGES)),
+        }
+    }
+
+    /// Set how many sent messages to keep the callbacks of. Callbacks of the
+    /// oldest messages are forgotten first. Defaults to
+    /// [`DEFAULT_MAX_MESSAGES`].
+    pub fn with_max_messages(self, max_messages: usize) -> Self {
+        self.table.lock().expect("poisoned").max_messages = max_messages;
+        self
+    }
+
+    /// Send a message with actions and register its callbacks. Returns the
+    /// ID of the sent message.
+    pub async fn send(&self, message: ActionMessage) -> ClientResult<u64> {
+        let (guild_id, channel_id) = (message.guild_id, message.channel_id);
+        let (request, actions, handlers) = message.into_parts();
+        let message_id = self.client.call(request).await?.message_id;
+
+        self.table.lock().expect("poisoned").insert(
+            (guild_id, channel_id, message_id),
+            MessageActions { actions, handlers },
+        );
+        Ok(message_id)
+    }
+
+    /// Stop calling the callbacks of a message.
+    pub fn forget(&self, guild_id: u64, channel_id: u64, message_id: u64) {
+        self.table
+            .lock()
+            .expect("poisoned")
+            .remove(&(guild_id, channel_id, message_id));
+    }
+
+    /// Handle an event, calling the callback of the performed action if it
+    /// is an `ActionPerformed` event for one of the sent messages. Returns
+    /// `true` if a callback was called.
+    ///
+    /// Callbacks of deleted messages are forgotten.
+    pub async fn handle_event(&self, event: &Event) -> ClientResult<bool> {
+        let performed = match event {
+            Event::Chat(stream_event::Event::ActionPerformed(performed)) => performed,
+            Event::Chat(stream_event::Event::DeletedMessage(deleted)) => {
+                self.forget(deleted.guild_id, deleted.channel_id, deleted.message_id);
+                return Ok(false);
+            }
+            _ => return Ok(false),
+        };
+        let (action_id, input) = match performed
+            .payload
+            .as_ref()
+            .and_then(ActionInput::from_payload)
+        {
+            Some(input) => input,
+            None => return Ok(false),
+        };
+
+        let key = (
+            performed.guild_id,
+            performed.channel_id,
+            performed.message_id,
+        );
+        let handler = self
+            .table
+            .lock()
+            .expect("poisoned")
+            .handler(key, action_id, &input);
+        let handler = match handler {
+            Some(handler) => handler,
+            None => return Ok(false),
+        };
+
+        let context = ActionContext {
+            client: self.client.clone(),
+            guild_id: performed.guild_id,
+            channel_id: performed.channel_id,
+            message_id: performed.message_id,
+            user_id: performed.user_id,
+        };
+        handler(context, input).await?;
+        Ok(true)
+    }
+}
+
+/// Create a request that performs an action of a message.
+pub fn trigger_action(
+    guild_id: u64,
+    channel_id: u64,
+    message_id: u64,
+    action_id: u32,
+    input: ActionInput,
+) -> TriggerActionRequest {
+    TriggerActionRequest {
+        guild_id,
+        channel_id,
+        message_id,
+        payload: Some(input.into_payload(action_id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> ActionMessage {
+        ActionMessage::new(1, 2, "Poll")
+            .with_body("Pick one".to_string())
+            .with_button("Vote", |_| async { Ok(()) })
+            .with_link_button("Results", "https://example.org")
+            .with_dropdown("Color", [("Red", "red"), ("Blue", "blue")], |_, _| async {
+                Ok(())
+            })
+            .with_input("Comment", true, |_, _| async { Ok(()) })
+    }
+
+    #[test]
+    fn builds_embed() {
+        let (request, actions, handlers) = message().into_parts();
+        assert_eq!((request.guild_id, request.channel_id), (1, 2));
+        assert_eq!(actions.len(), 4);
+        assert_eq!(handlers.len(), 4);
+        assert!(handlers[1].is_none());
+
+        let embed = match request.content.and_then(|c| c.content) {
+            Some(crate::api::chat::content::Content::EmbedMessage(content)) => {
+                content.embeds[0].clone()
+            }
+            _ => panic!("expected embed content"),
+        };
+        assert_eq!(embed.title, "Poll");
+        assert_eq!(embed.fields.len(), 1);
+        assert_eq!(embed.fields[0].actions, actions);
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        for input in [
+            ActionInput::Button,
+            ActionInput::Dropdown("red".to_string()),
+            ActionInput::Input("hello".to_string()),
+        ] {
+            let payload = input.clone().into_payload(3);
+            assert_eq!(ActionInput::from_payload(&payload), Some((3, input)));
+        }
+        assert_eq!(ActionInput::from_payload(&ActionPayload::default()), None);
+
+        let foreign = action_payload::Button {
+            data: b"not an ID".to_vec(),
+        };
+        let payload = ActionPayload {
+            payload: Some(action_payload::Payload::Button(foreign)),
+        };
+        assert_eq!(ActionInput::from_payload(&payload), None);
+    }
+
+    #[test]
+    fn finds_handlers() {
+        let (_, actions, handlers) = message().into_parts();
+        let mut table = ActionTable::new(DEFAULT_MAX_MESSAGES);
+        table.insert((1, 2, 3), MessageActions { actions, handlers });
+
+        let dropdown = ActionInput::Dropdown("red".to_string());
+        assert!(table.handler((1, 2, 3), 0, &ActionInput::Button).is_some());
+        assert!(table.handler((1, 2, 3), 2, &dropdown).is_some());
+        // Link buttons have no callback
+        assert!(table.handler((1, 2, 3), 1, &ActionInput::Button).is_none());
+        // Input doesn't match the kind of the action
+        assert!(table.handler((1, 2, 3), 0, &dropdown).is_none());
+        assert!(table.handler((1, 2, 3), 9, &ActionInput::Button).is_none());
+        assert!(table.handler((1, 2, 4), 0, &ActionInput::Button).is_none());
+    }
+
+    #[test]
+    fn rejects_unknown_dropdown_choices() {
+        let (_, actions, handlers) = message().into_parts();
+        let mut table = ActionTable::new(DEFAULT_MAX_MESSAGES);
+        table.insert((1, 2, 3), MessageActions { actions, handlers });
+
+        let choice = |value: &str| ActionInput::Dropdown(value.to_string());
+        assert!(table.handler((1, 2, 3), 2, &choice("blue")).is_some());
+        assert!(table.handler((1, 2, 3), 2, &choice("green")).is_none());
+        assert!(table.handler((1, 2, 3), 2, &choice("")).is_none());
+    }
+
+    #[test]
+    fn forgets_oldest_messages() {
+        let mut table = ActionTable::new(2);
+        for message_id in 1..=3 {
+            let (_, actions, handlers) = message().into_parts();
+            table.insert((1, 2, message_id), MessageActions { actions, handlers });
+        }
+        assert!(table.handler((1, 2, 1), 0, &ActionInput::Button).is_none());
+        assert!(table.handler((1, 2, 3), 0, &ActionInput::Button).is_some());
+
+        table.remove(&(1, 2, 3));
+        assert_eq!(table.order, [(1, 2, 2)]);
+    }
+}
@@ -0,0 +1,546 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    marker::PhantomData,
+    sync::Arc,
+};
+
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{
+    error::{ClientError, ClientResult, InvalidDataKind},
+    Client,
+};
+use crate::api::profile::{GetAppDataRequest, SetAppDataRequest};
+
+/// How many times [`AppData::set()`] writes the value again if another
+/// client's write raced it.
+const MAX_WRITE_ATTEMPTS: usize = 3;
+
+/// Schema version used by [`AppData`] if none is set.
+pub const DEFAULT_VERSION: u32 = 1;
+
+/// Stored app data: an encoded value along with its schema version and a
+/// revision number that is incremented on every write.
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Envelope {
+    #[prost(uint32, tag = "1")]
+    version: u32,
+    #[prost(uint64, tag = "2")]
+    revision: u64,
+    #[prost(bytes = "vec", tag = "3")]
+    payload: Vec<u8>,
+}
+
+impl Envelope {
+    /// Parses stored app data. Returns `None` if there is no data.
+    ///
+    /// Data that wasn't written by [`AppData`] is treated as having schema
+    /// version `0`, so it can be migrated with [`AppData::with_migration()`].
+    fn parse(raw: &[u8]) -> Option<Self> {
+        if raw.is_empty() {
+            return None;
+        }
+        let envelope = Self::decode(raw)
+            .ok()
+            .filter(|envelope| envelope.revision > 0)
+            .unwrap_or_else(|| Self {
+                version: 0,
+                revision: 0,
+                payload: raw.to_vec(),
+            });
+        Some(envelope)
+    }
+}
+
+/// Encoding used to store app data values.
+pub trait Codec<T> {
+    /// Encode a value.
+    fn encode(value: &T) -> ClientResult<Vec<u8>>;
+    /// Decode a value.
+    fn decode(data: &[u8]) -> ClientResult<T>;
+}
+
+/// Stores app data values as JSON.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for Json {
+    fn encode(value: &T) -> ClientResult<Vec<u8>> {
+        serde_json::to_vec(value)
+            .map_err(|err| ClientError::invalid_data(InvalidDataKind::AppData, err))
+    }
+
+    fn decode(data: &[u8]) -> ClientResult<T> {
+        serde_json::from_slice(data)
+            .map_err(|err| ClientError::invalid_data(InvalidDataKind::AppData, err))
+    }
+}
+
+/// Stores app data values as protobuf messages.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Protobuf;
+
+impl<T: Message + Default> Codec<T> for Protobuf {
+    fn encode(value: &T) -> ClientResult<Vec<u8>> {
+        Ok(value.encode_to_vec())
+    }
+
+    fn decode(data: &[u8]) -> ClientResult<T> {
+        T::decode(data).map_err(|err| ClientError::invalid_data(InvalidDataKind::AppData, err))
+    }
+}
+
+type Migration<T> = Arc<dyn Fn(&[u8]) -> ClientResult<T> + Send + Sync>;
+type Merge<T> = Arc<dyn Fn(Option<&T>, T, T) -> T + Send + Sync>;
+
+/// Schema version and migrations of an app data value.
+struct Schema<T, C> {
+    version: u32,
+    migrations: HashMap<u32, Migration<T>>,
+    _codec: PhantomData<fn() -> C>,
+}
+
+impl<T, C: Codec<T>> Schema<T, C> {
+    fn decode(&self, envelope: &Envelope) -> ClientResult<T> {
+        if envelope.version == self.version {
+            return C::decode(&envelope.payload);
+        }
+        match self.migrations.get(&envelope.version) {
+            Some(migrate) => migrate(&envelope.payload),
+            None => Err(ClientError::invalid_data(
+                InvalidDataKind::AppData,
+                format!(
+                    "can't migrate from schema version {} to {}",
+                    envelope.version, self.version
+                ),
+            )),
+        }
+    }
+
+    fn encode(&self, value: &T, revision: u64) -> ClientResult<Envelope> {
+        Ok(Envelope {
+            version: self.version,
+            revision,
+            payload: C::encode(value)?,
+        })
+    }
+}
+
+/// Typed app data stored with the profile service's `GetAppData` and
+/// `SetAppData` endpoints, to sync app settings between devices.
+///
+/// Values are encoded with a [`Codec`] ([`Json`] by default) and stored
+/// along with a schema version. Values stored with an older schema version
+/// are upgraded using the migrations added with [`AppData::with_migration()`].
+///
+/// Fetched values are cached in memory by the [`Client`], and the cache is
+/// shared between all `AppData` handles for the same app ID.
+pub struct AppData<T, C = Json> {
+    client: Client,
+    app_id: String,
+    schema: Schema<T, C>,
+    merge: Option<Merge<T>>,
+}
+
+impl<T, C> Debug for AppData<T, C> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppData")
+            .field("app_id", &self.app_id)
+            .field("version", &self.schema.version)
+            .finish()
+    }
+}
+
+impl<T, C: Codec<T>> AppData<T, C> {
+    /// Create a new app data handle for the given app ID.
+    ///
+    /// Also see [`Client::app_data()`] and [`Client::protobuf_app_data()`].
+    pub fn new(client: Client, app_id: impl Into<String>) -> Self {
+        Self {
+            client,
+            app_id: app_id.into(),
+            schema: Schema {
+                version: DEFAULT_VERSION,
+                migrations: HashMap::new(),
+                _codec: PhantomData,
+            },
+            merge: None,
+        }
+    }
+
+    /// Set the schema version values are written with. Defaults to
+    /// [`DEFAULT_VERSION`].
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.schema.version = version;
+        self
+    }
+
+    /// Add a migration that decodes values stored with schema version
+    /// `from_version`.
+    ///
+    /// Data that wasn't written by [`AppData`] has schema version `0`.
+    pub fn with_migration(
+        mut self,
+        from_version: u32,
+        migrate: impl Fn(&[u8]) -> ClientResult<T> + Send + Sync + 'static,
+    ) -> Self {
+        self.schema
+            .migrations
+            .insert(from_version, Arc::new(migrate));
+        self
+    }
+
+    /// Set the function used to merge values when another client changed the
+    /// stored value since it was last fetched.
+    ///
+    /// The function is called with the last fetched value (if any), the value
+    /// being written and the value currently stored, and returns the value to
+    /// write. If no merge function is set, the value being written wins.
+    pub fn with_merge(
+        mut self,
+        merge: impl Fn(Option<&T>, T, T) -> T + Send + Sync + 'static,
+    ) -> Self {
+        self.merge = Some(Arc::new(merge));
+        self
+    }
+
+    /// Get the app ID of this app data.
+    #[inline(always)]
+    pub fn app_id(&self) -> &str {
+        self.app_id.as_str()
+    }
+
+    /// Get the cached value, without fetching it.
+    pub fn cached(&self) -> ClientResult<Option<T>> {
+        self.cached_envelope()
+            .map(|envelope| self.schema.decode(&envelope))
+            .transpose()
+    }
+
+    /// Get the value, fetching it only if it isn't cached.
+    pub async fn get(&self) -> ClientResult<Option<T>> {
+        match self.cached_envelope() {
+            Some(envelope) => self.schema.decode(&envelope).map(Some),
+            None => self.fetch().await,
+        }
+    }
+
+    /// Fetch the value, updating the cache.
+    pub async fn fetch(&self) -> ClientResult<Option<T>> {
+        self.fetch_envelope()
+            .await?
+            .map(|envelope| self.schema.decode(&envelope))
+            .transpose()
+    }
+
+    /// Store a value, returning the value that was actually stored.
+    ///
+    /// If the stored value changed since it was last fetched, the values are
+    /// merged using the function set with [`AppData::with_merge()`]. Since the
+    /// protocol has no atomic writes, the value is read back after writing,
+    /// and the write is retried if another client's write raced it.
+    pub async fn set(&self, value: T) -> ClientResult<T> {
+        write_merged(
+            &self.schema,
+            self.merge.as_ref(),
+            &self.app_id,
+            self.cached_envelope(),
+            value,
+            || self.fetch_envelope(),
+            |envelope| {
+                self.client.call(SetAppDataRequest {
+                    app_id: self.app_id.clone(),
+                    app_data: envelope.encode_to_vec(),
+                })
+            },
+        )
+        .await
+    }
+
+    /// Fetch the value, update it with the given function and store it.
+    ///
+    /// See [`AppData::set()`] for how concurrent writes are handled.
+    pub async fn update(&self, update: impl FnOnce(Option<T>) -> T) -> ClientResult<T> {
+        let current = self.fetch().await?;
+        self.set(update(current)).await
+    }
+
+    /// Remove the cached value, so it is fetched again on the next
+    /// [`AppData::get()`].
+    pub fn invalidate(&self) {
+        self.client
+            .data
+            .app_data
+            .lock()
+            .expect("poisoned")
+            .remove(&self.app_id);
+    }
+
+    fn cached_envelope(&self) -> Option<Envelope> {
+        self.client
+            .data
+            .app_data
+            .lock()
+            .expect("poisoned")
+            .get(&self.app_id)
+            .cloned()
+    }
+
+    async fn fetch_envelope(&self) -> ClientResult<Option<Envelope>> {
+        let response = self
+            .client
+            .call(GetAppDataRequest {
+                app_id: self.app_id.clone(),
+            })
+            .await?;
+        let envelope = Envelope::parse(&response.app_data);
+
+        let mut cache = self.client.data.app_data.lock().expect("poisoned");
+        match &envelope {
+            Some(envelope) => cache.insert(self.app_id.clone(), envelope.clone()),
+            None => cache.remove(&self.app_id),
+        };
+
+        Ok(envelope)
+    }
+}
+
+/// Writes `value` over the stored envelope, merging it with the stored value
+/// if that isn't `base` anymore, until reading back returns what was written.
+///
+/// Every attempt merges the value being written with the stored value using
+/// the same `base`, so values written by racing clients are merged as
+/// changes made since `base` was fetched.
+async fn write_merged<T, C, F, FFut, W, WFut, R>(
+    schema: &Schema<T, C>,
+    merge: Option<&Merge<T>>,
+    app_id: &str,
+    base: Option<Envelope>,
+    value: T,
+    fetch: F,
+    write: W,
+) -> ClientResult<T>
+where
+    C: Codec<T>,
+    F: Fn() -> FFut,
+    FFut: Future<Output = ClientResult<Option<Envelope>>>,
+    W: Fn(&Envelope) -> WFut,
+    WFut: Future<Output = ClientResult<R>>,
+{
+    let local = C::encode(&value)?;
+    let mut first = Some(value);
+
+    for _ in 0..MAX_WRITE_ATTEMPTS {
+        let mut value = match first.take() {
+            Some(value) => value,
+            None => C::decode(&local)?,
+        };
+        let current = fetch().await?;
+
+        if let Some(current) = current
+            .as_ref()
+            .filter(|current| Some(*current) != base.as_ref())
+        {
+            if current.version > schema.version {
+                return Err(ClientError::invalid_data(
+                    InvalidDataKind::AppData,
+                    format!(
+                        "stored value has newer schema version {} (expected {})",
+                        current.version, schema.version
+                    ),
+                ));
+            }
+            if let Some(merge) = merge {
+                let base_value = base
+                    .as_ref()
+                    .map(|envelope| schema.decode(envelope))
+                    .transpose()?;
+                let current_value = schema.decode(current)?;
+                value = merge(base_value.as_ref(), value, current_value);
+            }
+        }
+
+        let revision = current.map_or(0, |envelope| envelope.revision) + 1;
+        let envelope = schema.encode(&value, revision)?;
+        write(&envelope).await?;
+
+        let stored = fetch().await?;
+        if stored.as_ref() == Some(&envelope) {
+            return Ok(value);
+        }
+        tracing::debug!("concurrent write to app data {} detected", app_id);
+    }
+
+    Err(ClientError::invalid_data(
+        InvalidDataKind::AppData,
+        format!("too many concurrent writes to app data {}", app_id),
+    ))
+}
+
+impl Client {
+    /// Get a handle to app data stored as JSON.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use harmony_rust_sdk::client::{Client, error::ClientResult};
+    /// # async fn run(client: Client) -> ClientResult<()> {
+    /// let theme = client.app_data::<String>("com.example.app.theme");
+    /// theme.set("dark".to_string()).await?;
+    /// assert_eq!(theme.get().await?.as_deref(), Some("dark"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn app_data<T: Serialize + DeserializeOwned>(
+        &self,
+        app_id: impl Into<String>,
+    ) -> AppData<T, Json> {
+        AppData::new(self.clone(), app_id)
+    }
+
+    /// Get a handle to app data stored as a protobuf message.
+    pub fn protobuf_app_data<T: Message + Default>(
+        &self,
+        app_id: impl Into<String>,
+    ) -> AppData<T, Protobuf> {
+        AppData::new(self.clone(), app_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hrpc::exports::futures_util::FutureExt;
+    use std::sync::Mutex;
+
+    fn schema<C>(version: u32) -> Schema<Vec<String>, C> {
+        Schema {
+            version,
+            migrations: HashMap::new(),
+            _codec: PhantomData,
+        }
+    }
+
+    #[test]
+    fn parses_foreign_data_as_version_zero() {
+        assert_eq!(Envelope::parse(&[]), None);
+
+        let raw = b"not an envelope".to_vec();
+        let envelope = Envelope::parse(&raw).unwrap();
+        assert_eq!(envelope.version, 0);
+        assert_eq!(envelope.payload, raw);
+
+        let written = schema::<Json>(2).encode(&vec!["a".to_string()], 5).unwrap();
+        assert_eq!(Envelope::parse(&written.encode_to_vec()), Some(written));
+    }
+
+    #[test]
+    fn migrates_old_versions() {
+        let mut schema = schema::<Json>(2);
+        schema.migrations.insert(
+            1,
+            Arc::new(|data: &[u8]| {
+                let value: String = serde_json::from_slice(data).unwrap();
+                Ok(vec![value])
+            }),
+        );
+
+        let old = Envelope {
+            version: 1,
+            revision: 1,
+            payload: b"\"dark\"".to_vec(),
+        };
+        assert_eq!(schema.decode(&old).unwrap(), vec!["dark".to_string()]);
+
+        let newer = Envelope { version: 3, ..old };
+        assert!(matches!(
+            schema.decode(&newer),
+            Err(ClientError::InvalidData {
+                kind: InvalidDataKind::AppData,
+                ..
+            })
+        ));
+    }
+
+    fn envelope(revision: u64, value: &[&str]) -> Envelope {
+        let value = value.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        schema::<Json>(1).encode(&value, revision).unwrap()
+    }
+
+    /// Keeps the values of both sides that were added since `base`.
+    fn union() -> Merge<Vec<String>> {
+        Arc::new(|base, ours, mut theirs| {
+            let base = base.cloned().unwrap_or_default();
+            theirs.retain(|v| !base.contains(v) || ours.contains(v));
+            theirs.extend(ours.into_iter().filter(|v| !base.contains(v)));
+            theirs.dedup();
+            theirs
+        })
+    }
+
+    /// Stores an envelope in memory. The first write is overwritten by
+    /// `racer`, if set, as if another client wrote right after it.
+    #[derive(Default)]
+    struct Store {
+        stored: Mutex<Option<Envelope>>,
+        racer: Mutex<Option<Envelope>>,
+    }
+
+    impl Store {
+        fn set(&self, base: Envelope, value: &[&str]) -> ClientResult<Vec<String>> {
+            let value = value.iter().map(|v| v.to_string()).collect();
+            let fetch = || {
+                let stored = self.stored.lock().unwrap().clone();
+                async move { Ok(stored) }
+            };
+            let write = |envelope: &Envelope| {
+                let racer = self.racer.lock().unwrap().take();
+                *self.stored.lock().unwrap() = Some(racer.unwrap_or_else(|| envelope.clone()));
+                async { Ok(()) }
+            };
+            let schema = schema::<Json>(1);
+            let merge = union();
+            let fut = write_merged(
+                &schema,
+                Some(&merge),
+                "test",
+                Some(base),
+                value,
+                fetch,
+                write,
+            );
+            fut.now_or_never().expect("future is ready")
+        }
+    }
+
+    #[test]
+    fn set_merges_changes_since_fetch() {
+        let store = Store::default();
+        *store.stored.lock().unwrap() = Some(envelope(2, &["a", "b"]));
+
+        let stored = store.set(envelope(1, &["a"]), &["a", "c"]).unwrap();
+        assert_eq!(stored, ["a", "b", "c"]);
+        assert_eq!(
+            *store.stored.lock().unwrap(),
+            Some(envelope(3, &["a", "b", "c"]))
+        );
+    }
+
+    #[test]
+    fn set_merges_raced_writes_with_fetched_base() {
+        let store = Store::default();
+        *store.stored.lock().unwrap() = Some(envelope(1, &["a"]));
+        // The racer read the same revision, so it writes the same revision
+        // as the first attempt with a different payload
+        *store.racer.lock().unwrap() = Some(envelope(2, &["a", "b"]));
+
+        let stored = store.set(envelope(1, &["a"]), &["a", "c"]).unwrap();
+        assert_eq!(stored, ["a", "b", "c"]);
+        assert_eq!(
+            *store.stored.lock().unwrap(),
+            Some(envelope(3, &["a", "b", "c"]))
+        );
+    }
+}
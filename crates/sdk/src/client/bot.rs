@@ -0,0 +1,1037 @@
+use std::{
+    error::Error as StdError,
+    fmt::{self, Debug, Display, Formatter},
+    future::Future,
+    str::FromStr,
+    sync::Arc,
+};
+
+pub use harmony_derive::Command;
+use hrpc::exports::futures_util::future::BoxFuture;
+
+use super::{error::ClientResult, Client, EventsSocket};
+use crate::api::chat::{
+    format, stream_event, Event, FormattedText, InviteId, Message, QueryHasPermissionRequest,
+    SendMessageRequest,
+};
+
+/// Default prefix of commands.
+pub const DEFAULT_PREFIX: &str = "!";
+/// Name of the command that is added to list available commands.
+pub const HELP_COMMAND: &str = "help";
+
+/// Kind of a command argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// A user, either mentioned or given by ID.
+    User,
+    /// A channel, either mentioned or given by ID.
+    Channel,
+    /// A signed integer.
+    Integer,
+    /// A single word, or a quoted string.
+    Text,
+    /// An invite ID.
+    Invite,
+    /// All of the remaining text. Must be the last argument.
+    Rest,
+}
+
+impl ArgKind {
+    fn describe(self) -> &'static str {
+        match self {
+            ArgKind::User => "a user",
+            ArgKind::Channel => "a channel",
+            ArgKind::Integer => "an integer",
+            ArgKind::Text => "some text",
+            ArgKind::Invite => "an invite ID",
+            ArgKind::Rest => "some text",
+        }
+    }
+}
+
+/// Description of a command argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgSpec {
+    /// Name of the argument, used in usage strings and to get its value.
+    pub name: String,
+    /// Kind of the argument.
+    pub kind: ArgKind,
+    /// Whether the argument can be left out. Only trailing arguments can be
+    /// optional.
+    pub optional: bool,
+}
+
+impl ArgSpec {
+    /// Create a required argument.
+    pub fn new(name: impl Into<String>, kind: ArgKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            optional: false,
+        }
+    }
+
+    /// Create an optional argument.
+    pub fn optional(name: impl Into<String>, kind: ArgKind) -> Self {
+        Self {
+            optional: true,
+            ..Self::new(name, kind)
+        }
+    }
+}
+
+/// Value of a parsed command argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Arg {
+    /// A user ID.
+    User(u64),
+    /// A channel ID.
+    Channel(u64),
+    /// An integer.
+    Integer(i64),
+    /// Text, with quotes removed.
+    Text(String),
+    /// An invite ID.
+    Invite(InviteId),
+}
+
+impl Display for Arg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Arg::User(user_id) => write!(f, "<@{}>", user_id),
+            Arg::Channel(channel_id) => write!(f, "<#{}>", channel_id),
+            Arg::Integer(value) => write!(f, "{}", value),
+            Arg::Text(text) => f.write_str(text),
+            Arg::Invite(invite) => write!(f, "{}", invite),
+        }
+    }
+}
+
+/// Types that can be parsed from a command argument, used by
+/// [`derive(Command)`](macro@Command) to get the kind and value of fields.
+pub trait FromArg: Sized {
+    /// Kind of argument this type is parsed from.
+    const KIND: ArgKind;
+
+    /// Convert a parsed argument. Returns `None` if the argument has the
+    /// wrong kind.
+    fn from_arg(arg: Arg) -> Option<Self>;
+}
+
+/// A user ID given as a command argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UserId(pub u64);
+
+/// A channel ID given as a command argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChannelId(pub u64);
+
+/// All of the remaining text of a command.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Rest(pub String);
+
+impl FromArg for UserId {
+    const KIND: ArgKind = ArgKind::User;
+
+    fn from_arg(arg: Arg) -> Option<Self> {
+        match arg {
+            Arg::User(user_id) => Some(Self(user_id)),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for ChannelId {
+    const KIND: ArgKind = ArgKind::Channel;
+
+    fn from_arg(arg: Arg) -> Option<Self> {
+        match arg {
+            Arg::Channel(channel_id) => Some(Self(channel_id)),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for i64 {
+    const KIND: ArgKind = ArgKind::Integer;
+
+    fn from_arg(arg: Arg) -> Option<Self> {
+        match arg {
+            Arg::Integer(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for String {
+    const KIND: ArgKind = ArgKind::Text;
+
+    fn from_arg(arg: Arg) -> Option<Self> {
+        match arg {
+            Arg::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for Rest {
+    const KIND: ArgKind = ArgKind::Rest;
+
+    fn from_arg(arg: Arg) -> Option<Self> {
+        String::from_arg(arg).map(Self)
+    }
+}
+
+impl FromArg for InviteId {
+    const KIND: ArgKind = ArgKind::Invite;
+
+    fn from_arg(arg: Arg) -> Option<Self> {
+        match arg {
+            Arg::Invite(invite) => Some(invite),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned when the arguments of a command can't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A required argument wasn't given.
+    MissingArgument(String),
+    /// An argument had the wrong kind of value.
+    InvalidArgument {
+        /// Name of the argument.
+        name: String,
+        /// The value that was given.
+        value: String,
+        /// Kind of value that was expected.
+        expected: ArgKind,
+    },
+    /// More arguments were given than the command takes.
+    TooManyArguments,
+    /// A quoted string wasn't closed.
+    UnterminatedQuote,
+    /// The command isn't one of the commands of a [`CommandArgs`] type.
+    UnknownCommand(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MissingArgument(name) => write!(f, "missing argument `{}`", name),
+            ParseError::InvalidArgument {
+                name,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid value `{}` for argument `{}`, expected {}",
+                value,
+                name,
+                expected.describe()
+            ),
+            ParseError::TooManyArguments => f.write_str("too many arguments"),
+            ParseError::UnterminatedQuote => f.write_str("unterminated quote"),
+            ParseError::UnknownCommand(name) => write!(f, "unknown command `{}`", name),
+        }
+    }
+}
+
+impl StdError for ParseError {}
+
+/// Parsed arguments of a command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Args {
+    values: Vec<(String, Arg)>,
+}
+
+impl Args {
+    /// Get the value of an argument. Returns `None` if an optional argument
+    /// wasn't given.
+    pub fn get(&self, name: &str) -> Option<&Arg> {
+        self.values
+            .iter()
+            .find(|(arg_name, _)| arg_name == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Get the value of a user argument.
+    pub fn user(&self, name: &str) -> Option<u64> {
+        match self.get(name) {
+            Some(Arg::User(user_id)) => Some(*user_id),
+            _ => None,
+        }
+    }
+
+    /// Get the value of a channel argument.
+    pub fn channel(&self, name: &str) -> Option<u64> {
+        match self.get(name) {
+            Some(Arg::Channel(channel_id)) => Some(*channel_id),
+            _ => None,
+        }
+    }
+
+    /// Get the value of an integer argument.
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        match self.get(name) {
+            Some(Arg::Integer(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get the value of a text or rest argument.
+    pub fn text(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(Arg::Text(text)) => Some(text.as_str()),
+            _ => None,
+        }
+    }
+
+    /// Get the value of an invite argument.
+    pub fn invite(&self, name: &str) -> Option<&InviteId> {
+        match self.get(name) {
+            Some(Arg::Invite(invite)) => Some(invite),
+            _ => None,
+        }
+    }
+
+    /// Convert the value of a required argument.
+    pub fn parse<T: FromArg>(&self, name: &str) -> Result<T, ParseError> {
+        self.parse_optional(name)?
+            .ok_or_else(|| ParseError::MissingArgument(name.to_string()))
+    }
+
+    /// Convert the value of an optional argument.
+    pub fn parse_optional<T: FromArg>(&self, name: &str) -> Result<Option<T>, ParseError> {
+        let arg = match self.get(name) {
+            Some(arg) => arg.clone(),
+            None => return Ok(None),
+        };
+        let value = arg.to_string();
+        T::from_arg(arg)
+            .map(Some)
+            .ok_or_else(|| ParseError::InvalidArgument {
+                name: name.to_string(),
+                value,
+                expected: T::KIND,
+            })
+    }
+}
+
+/// Description of a command parsed by a [`CommandArgs`] type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandSpec {
+    /// Name of the command.
+    pub name: String,
+    /// Description of the command, shown in the help.
+    pub description: String,
+    /// Permission required to use the command.
+    pub permission: Option<String>,
+    /// Arguments of the command.
+    pub args: Vec<ArgSpec>,
+}
+
+impl CommandSpec {
+    /// Usage string of this command, see [`Command::usage()`].
+    pub fn usage(&self) -> String {
+        usage(&self.name, &self.args)
+    }
+}
+
+/// Types that commands can be parsed into, usually implemented with
+/// [`derive(Command)`](macro@Command) and registered with
+/// [`CommandRouter::with_commands()`].
+///
+/// # Example
+/// ```
+/// use harmony_rust_sdk::client::bot::{Command, Rest, UserId};
+///
+/// #[derive(Command)]
+/// enum Moderation {
+///     #[command(description = "Ban a user", permission = "user.manage.ban")]
+///     Ban { user: UserId, reason: Option<Rest> },
+///     #[command(name = "unban")]
+///     Pardon { user: UserId },
+/// }
+/// ```
+pub trait CommandArgs: Sized {
+    /// Descriptions of the commands parsed by this type.
+    fn specs() -> Vec<CommandSpec>;
+
+    /// Build a value from the parsed arguments of one of the commands.
+    fn from_args(command: &str, args: &Args) -> Result<Self, ParseError>;
+}
+
+/// A word of a command, with its position in characters in the message text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    text: String,
+    start: usize,
+    end: usize,
+    quoted: bool,
+}
+
+/// Split text into words. Words can be quoted with `"` to include spaces,
+/// and `\"` can be used to include a quote in a quoted word.
+fn tokenize(text: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().enumerate().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let mut token = Token {
+            text: String::new(),
+            start,
+            end: start + 1,
+            quoted: c == '"',
+        };
+        if token.quoted {
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) if matches!(chars.peek(), Some((_, '"'))) => {
+                        token.text.push('"');
+                        chars.next();
+                    }
+                    Some((end, '"')) => {
+                        token.end = end + 1;
+                        break;
+                    }
+                    Some((_, c)) => token.text.push(c),
+                    None => return Err(ParseError::UnterminatedQuote),
+                }
+            }
+        } else {
+            token.text.push(c);
+            while let Some((end, c)) = chars.peek().copied() {
+                if c.is_whitespace() {
+                    break;
+                }
+                token.text.push(c);
+                token.end = end + 1;
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// Get the ID of a mention format covering a token, if there is one.
+fn mention(formats: &[crate::api::chat::Format], token: &Token, kind: ArgKind) -> Option<u64> {
+    formats.iter().find_map(|f| {
+        let start = f.start as usize;
+        let end = start + f.length as usize;
+        if start > token.start || end < token.end {
+            return None;
+        }
+        match (kind, f.format.as_ref()?) {
+            (ArgKind::User, format::Format::UserMention(m)) => Some(m.user_id),
+            (ArgKind::Channel, format::Format::ChannelMention(m)) => Some(m.channel_id),
+            _ => None,
+        }
+    })
+}
+
+/// Parse an ID given as text, optionally wrapped like `<@123>` or `<#123>`.
+fn parse_id(text: &str, sigil: char) -> Option<u64> {
+    let id = text
+        .strip_prefix('<')
+        .and_then(|text| text.strip_suffix('>'))
+        .and_then(|text| text.strip_prefix(sigil))
+        .unwrap_or(text);
+    u64::from_str(id).ok()
+}
+
+/// Parse the arguments of a command from the text after its name.
+///
+/// `text` is the whole message text and `offset` the position in characters
+/// where the arguments start, so that mention formats of the message can be
+/// used for user and channel arguments.
+fn parse_args(specs: &[ArgSpec], text: &FormattedText, offset: usize) -> Result<Args, ParseError> {
+    let rest: String = text.text.chars().skip(offset).collect();
+    let mut tokens = tokenize(&rest)?.into_iter().map(|mut token| {
+        token.start += offset;
+        token.end += offset;
+        token
+    });
+    let mut args = Args::default();
+
+    for spec in specs {
+        let token = match tokens.next() {
+            Some(token) => token,
+            None if spec.optional => break,
+            None => return Err(ParseError::MissingArgument(spec.name.clone())),
+        };
+
+        let invalid = || ParseError::InvalidArgument {
+            name: spec.name.clone(),
+            value: token.text.clone(),
+            expected: spec.kind,
+        };
+        let value = match spec.kind {
+            ArgKind::User => mention(&text.format, &token, spec.kind)
+                .or_else(|| parse_id(&token.text, '@'))
+                .map(Arg::User)
+                .ok_or_else(invalid)?,
+            ArgKind::Channel => mention(&text.format, &token, spec.kind)
+                .or_else(|| parse_id(&token.text, '#'))
+                .map(Arg::Channel)
+                .ok_or_else(invalid)?,
+            ArgKind::Integer => i64::from_str(&token.text)
+                .map(Arg::Integer)
+                .map_err(|_| invalid())?,
+            ArgKind::Text => Arg::Text(token.text.clone()),
+            ArgKind::Invite => InviteId::new(&token.text)
+                .map(Arg::Invite)
+                .ok_or_else(invalid)?,
+            ArgKind::Rest => {
+                let remaining = text
+                    .text
+                    .chars()
+                    .skip(token.start)
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string();
+                args.values.push((spec.name.clone(), Arg::Text(remaining)));
+                return Ok(args);
+            }
+        };
+        args.values.push((spec.name.clone(), value));
+    }
+
+    match tokens.next() {
+        Some(_) => Err(ParseError::TooManyArguments),
+        None => Ok(args),
+    }
+}
+
+/// Context a command is invoked in.
+#[derive(Debug, Clone)]
+pub struct CommandContext {
+    /// Client of the bot, to make further requests.
+    pub client: Client,
+    /// Guild the command was sent in.
+    pub guild_id: u64,
+    /// Channel the command was sent in.
+    pub channel_id: u64,
+    /// ID of the message containing the command.
+    pub message_id: u64,
+    /// The message containing the command.
+    pub message: Message,
+    /// Parsed arguments of the command.
+    pub args: Args,
+}
+
+impl CommandContext {
+    /// ID of the user that sent the command.
+    pub fn author_id(&self) -> u64 {
+        self.message.author_id
+    }
+}
+
+fn usage(name: &str, args: &[ArgSpec]) -> String {
+    let mut usage = name.to_string();
+    for arg in args {
+        let name = match arg.kind {
+            ArgKind::Rest => format!("{}...", arg.name),
+            _ => arg.name.clone(),
+        };
+        if arg.optional {
+            usage.push_str(&format!(" [{}]", name));
+        } else {
+            usage.push_str(&format!(" <{}>", name));
+        }
+    }
+    usage
+}
+
+/// Result of a command handler: an optional reply.
+pub type CommandResult = ClientResult<Option<FormattedText>>;
+
+type Handler = Box<dyn Fn(CommandContext) -> BoxFuture<'static, CommandResult> + Send + Sync>;
+
+/// A command that can be registered with a [`CommandRouter`].
+pub struct Command {
+    name: String,
+    aliases: Vec<String>,
+    description: String,
+    args: Vec<ArgSpec>,
+    permission: Option<String>,
+    handler: Handler,
+}
+
+impl Debug for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Command")
+            .field("name", &self.name)
+            .field("aliases", &self.aliases)
+            .field("description", &self.description)
+            .field("args", &self.args)
+            .field("permission", &self.permission)
+            .finish()
+    }
+}
+
+impl Command {
+    /// Create a new command with a name and a handler. If the handler
+    /// returns a reply, it is sent as a reply to the command message.
+    pub fn new<F, Fut>(name: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(CommandContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = CommandResult> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            aliases: Vec::new(),
+            description: String::new(),
+            args: Vec::new(),
+            permission: None,
+            handler: Box::new(move |ctx| Box::pin(handler(ctx))),
+        }
+    }
+
+    /// Set the description of this command, shown in the help.
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    /// Add an alias for this command.
+    pub fn with_alias(mut self, alias: impl Into<String>) -> Self {
+        self.aliases.push(alias.into());
+        self
+    }
+
+    /// Add an argument to this command.
+    ///
+    /// # Panics
+    /// Panics if an argument is added after a [`ArgKind::Rest`] argument, or
+    /// if a required argument is added after an optional one.
+    pub fn with_arg(mut self, arg: ArgSpec) -> Self {
+        if let Some(last) = self.args.last() {
+            assert!(
+                last.kind != ArgKind::Rest,
+                "rest arguments must be the last argument"
+            );
+            assert!(
+                arg.optional || !last.optional,
+                "required arguments can't come after optional arguments"
+            );
+        }
+        self.args.push(arg);
+        self
+    }
+
+    /// Require a permission to use this command. Permissions are checked in
+    /// the channel the command is sent in.
+    pub fn with_permission(mut self, permission: impl Into<String>) -> Self {
+        self.permission = Some(permission.into());
+        self
+    }
+
+    /// Name of this command.
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Arguments of this command.
+    pub fn args(&self) -> &[ArgSpec] {
+        self.args.as_slice()
+    }
+
+    /// Usage string of this command, for example `ban <user> [reason...]`.
+    pub fn usage(&self) -> String {
+        usage(&self.name, &self.args)
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+}
+
+/// Routes command messages received on the events socket to command
+/// handlers, and sends their replies.
+///
+/// Messages starting with one of the prefixes (by default
+/// [`DEFAULT_PREFIX`]) followed by a command name invoke that command.
+/// A [`HELP_COMMAND`] command listing all commands is always available.
+#[derive(Debug)]
+pub struct CommandRouter {
+    client: Client,
+    prefixes: Vec<String>,
+    commands: Vec<Command>,
+}
+
+impl CommandRouter {
+    /// Create a new router that uses the given client.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            prefixes: vec![DEFAULT_PREFIX.to_string()],
+            commands: Vec::new(),
+        }
+    }
+
+    /// Set the prefixes of commands, replacing the default prefix.
+    pub fn with_prefixes<I, P>(mut self, prefixes: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        self.prefixes = prefixes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Register a command.
+    ///
+    /// # Panics
+    /// Panics if a command with the same name or alias is already
+    /// registered.
+    pub fn with_command(mut self, command: Command) -> Self {
+        let taken = std::iter::once(&command.name)
+            .chain(&command.aliases)
+            .find(|name| *name == HELP_COMMAND || self.find(name).is_some());
+        if let Some(name) = taken {
+            panic!("command name `{}` is already registered", name);
+        }
+        self.commands.push(command);
+        self
+    }
+
+    /// Register the commands of a [`CommandArgs`] type, usually implemented
+    /// with [`derive(Command)`](macro@Command). The handler gets the parsed
+    /// command and the context it was invoked in.
+    ///
+    /// # Panics
+    /// Panics if one of the commands is already registered.
+    pub fn with_commands<T, F, Fut>(mut self, handler: F) -> Self
+    where
+        T: CommandArgs + Send + 'static,
+        F: Fn(T, CommandContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = CommandResult> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        for spec in T::specs() {
+            let name = spec.name.clone();
+            let handler = handler.clone();
+            let mut command = Command::new(spec.name, move |context: CommandContext| {
+                let parsed = T::from_args(&name, &context.args);
+                let handler = handler.clone();
+                async move {
+                    match parsed {
+                        Ok(parsed) => handler(parsed, context).await,
+                        Err(err) => Ok(Some(err.to_string().into())),
+                    }
+                }
+            })
+            .with_description(spec.description);
+            if let Some(permission) = spec.permission {
+                command = command.with_permission(permission);
+            }
+            self = self.with_command(spec.args.into_iter().fold(command, Command::with_arg));
+        }
+        self
+    }
+
+    /// Get the registered commands.
+    pub fn commands(&self) -> &[Command] {
+        self.commands.as_slice()
+    }
+
+    /// Help text listing all registered commands.
+    pub fn help(&self) -> String {
+        let prefix = self.prefixes.first().map_or("", String::as_str);
+        let mut help = String::from("Available commands:");
+        for command in &self.commands {
+            help.push_str(&format!("\n{}{}", prefix, command.usage()));
+            if !command.description.is_empty() {
+                help.push_str(&format!(": {}", command.description));
+            }
+        }
+        help
+    }
+
+    fn find(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|command| command.matches(name))
+    }
+
+    /// Split a message into the invoked command name and the position in
+    /// characters where its arguments start. Returns `None` if the message
+    /// isn't a command.
+    fn split_command<'a>(&self, text: &'a str) -> Option<(&'a str, usize)> {
+        let rest = self
+            .prefixes
+            .iter()
+            .find_map(|prefix| text.strip_prefix(prefix.as_str()))?;
+        let name = rest.split_whitespace().next()?;
+        if !rest.starts_with(name) {
+            // Whitespace between the prefix and the name
+            return None;
+        }
+        let name_end = text.len() - rest.len() + name.len();
+        Some((name, text[..name_end].chars().count()))
+    }
+
+    /// Handle an event, invoking a command if it is a message containing one.
+    /// Returns `true` if a command was invoked or its usage was replied with.
+    ///
+    /// Messages sent by the current user are ignored. Commands that fail to
+    /// parse or that the author doesn't have permission for are replied to
+    /// with an explanation.
+    pub async fn handle_event(&self, event: &Event) -> ClientResult<bool> {
+        let sent = match event {
+            Event::Chat(stream_event::Event::SentMessage(sent)) => sent,
+            _ => return Ok(false),
+        };
+        let message = match &sent.message {
+            Some(message) if Some(message.author_id) != self.client.user_id() => message,
+            _ => return Ok(false),
+        };
+        let text = match message.get_text_content() {
+            Some(text) => text,
+            None => return Ok(false),
+        };
+        let (name, offset) = match self.split_command(&text.text) {
+            Some(command) => command,
+            None => return Ok(false),
+        };
+
+        let reply = |content: FormattedText| {
+            SendMessageRequest {
+                guild_id: sent.guild_id,
+                channel_id: sent.channel_id,
+                in_reply_to: Some(sent.message_id),
+                ..Default::default()
+            }
+            .with_text_content(content)
+        };
+
+        if name == HELP_COMMAND {
+            self.client.call(reply(self.help().into())).await?;
+            return Ok(true);
+        }
+        let command = match self.find(name) {
+            Some(command) => command,
+            None => return Ok(false),
+        };
+
+        let args = match parse_args(&command.args, text, offset) {
+            Ok(args) => args,
+            Err(err) => {
+                let prefix = self.prefixes.first().map_or("", String::as_str);
+                let content = format!("{}\nUsage: {}{}", err, prefix, command.usage());
+                self.client.call(reply(content.into())).await?;
+                return Ok(true);
+            }
+        };
+
+        if let Some(permission) = &command.permission {
+            let response = self
+                .client
+                .call(QueryHasPermissionRequest {
+                    guild_id: sent.guild_id,
+                    channel_id: Some(sent.channel_id),
+                    r#as: Some(message.author_id),
+                    check_for: permission.clone(),
+                })
+                .await?;
+            if !response.ok {
+                let content = format!("You need the `{}` permission to do that.", permission);
+                self.client.call(reply(content.into())).await?;
+                return Ok(true);
+            }
+        }
+
+        let context = CommandContext {
+            client: self.client.clone(),
+            guild_id: sent.guild_id,
+            channel_id: sent.channel_id,
+            message_id: sent.message_id,
+            message: message.clone(),
+            args,
+        };
+        if let Some(content) = (command.handler)(context).await? {
+            self.client.call(reply(content)).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Handle events from a socket until it is closed.
+    ///
+    /// Errors returned by command handlers are logged and don't stop the
+    /// router, errors of the socket itself are returned.
+    pub async fn run(&self, socket: &mut EventsSocket) -> ClientResult<()> {
+        while let Some(event) = socket.get_event().await? {
+            if let Err(err) = self.handle_event(&event).await {
+                tracing::error!("error while handling command: {}", err);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::chat::Format;
+
+    fn text(text: &str) -> FormattedText {
+        FormattedText::new(text.to_string(), Vec::new())
+    }
+
+    fn specs() -> Vec<ArgSpec> {
+        vec![
+            ArgSpec::new("user", ArgKind::User),
+            ArgSpec::new("count", ArgKind::Integer),
+            ArgSpec::optional("reason", ArgKind::Rest),
+        ]
+    }
+
+    #[test]
+    fn tokenize_quotes() {
+        let tokens = tokenize(r#"ban "some user" now \"x "say \"hi\"""#).unwrap();
+        let texts = tokens.iter().map(|t| t.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(texts, ["ban", "some user", "now", "\\\"x", "say \"hi\""]);
+        assert_eq!((tokens[1].start, tokens[1].end), (4, 15));
+        assert_eq!(tokenize("\"open"), Err(ParseError::UnterminatedQuote));
+    }
+
+    #[test]
+    fn parse_typed_args() {
+        let args = parse_args(&specs(), &text("!ban <@12> 3  spamming  a lot "), 4).unwrap();
+        assert_eq!(args.user("user"), Some(12));
+        assert_eq!(args.integer("count"), Some(3));
+        assert_eq!(args.text("reason"), Some("spamming  a lot"));
+
+        let args = parse_args(&specs(), &text("!ban 12 -1"), 4).unwrap();
+        assert_eq!(args.integer("count"), Some(-1));
+        assert_eq!(args.get("reason"), None);
+    }
+
+    #[test]
+    fn parse_mentions() {
+        let message = FormattedText::new(
+            "!ban @someone 1".to_string(),
+            vec![Format {
+                start: 5,
+                length: 8,
+                format: Some(format::Format::UserMention(format::UserMention {
+                    user_id: 42,
+                })),
+            }],
+        );
+        let args = parse_args(&specs(), &message, 4).unwrap();
+        assert_eq!(args.user("user"), Some(42));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse_args(&specs(), &text("!ban 12"), 4),
+            Err(ParseError::MissingArgument("count".to_string()))
+        );
+        assert_eq!(
+            parse_args(&specs(), &text("!ban someone 1"), 4),
+            Err(ParseError::InvalidArgument {
+                name: "user".to_string(),
+                value: "someone".to_string(),
+                expected: ArgKind::User,
+            })
+        );
+
+        let specs = [
+            ArgSpec::new("channel", ArgKind::Channel),
+            ArgSpec::new("invite", ArgKind::Invite),
+        ];
+        let args = parse_args(&specs, &text("!x <#5> \"harmony\""), 2).unwrap();
+        assert_eq!(args.channel("channel"), Some(5));
+        assert_eq!(args.invite("invite"), InviteId::new("harmony").as_ref());
+        assert_eq!(
+            parse_args(&specs, &text("!x 5 a b"), 2),
+            Err(ParseError::TooManyArguments)
+        );
+        assert!(parse_args(&specs, &text("!x 5 \"\""), 2).is_err());
+    }
+
+    #[test]
+    fn usage() {
+        let command = specs().into_iter().fold(
+            Command::new("ban", |_| async { Ok(None) }),
+            Command::with_arg,
+        );
+        assert_eq!(command.usage(), "ban <user> <count> [reason...]");
+    }
+
+    #[derive(Debug, PartialEq, Command)]
+    enum Moderation {
+        #[command(description = "Ban a user", permission = "user.manage.ban")]
+        Ban {
+            user: UserId,
+            #[command(name = "why")]
+            reason: Option<Rest>,
+        },
+        #[command(name = "unban")]
+        Pardon {
+            user: UserId,
+        },
+        PingAll,
+    }
+
+    #[test]
+    fn derived_specs() {
+        let specs = Moderation::specs();
+        let names = specs.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["ban", "unban", "ping_all"]);
+        assert_eq!(specs[0].usage(), "ban <user> [why...]");
+        assert_eq!(specs[0].description, "Ban a user");
+        assert_eq!(specs[0].permission.as_deref(), Some("user.manage.ban"));
+        assert_eq!(specs[1].permission, None);
+    }
+
+    #[test]
+    fn derived_from_args() {
+        let specs = Moderation::specs();
+        let args = parse_args(&specs[0].args, &text("!ban 5 being rude"), 4).unwrap();
+        assert_eq!(
+            Moderation::from_args("ban", &args),
+            Ok(Moderation::Ban {
+                user: UserId(5),
+                reason: Some(Rest("being rude".to_string())),
+            })
+        );
+        assert_eq!(
+            Moderation::from_args("ping_all", &Args::default()),
+            Ok(Moderation::PingAll)
+        );
+        assert_eq!(
+            Moderation::from_args("unban", &Args::default()),
+            Err(ParseError::MissingArgument("user".to_string()))
+        );
+        assert_eq!(
+            Moderation::from_args("kick", &Args::default()),
+            Err(ParseError::UnknownCommand("kick".to_string()))
+        );
+
+        let args = Args {
+            values: vec![("user".to_string(), Arg::Integer(5))],
+        };
+        assert!(matches!(
+            Moderation::from_args("unban", &args),
+            Err(ParseError::InvalidArgument { .. })
+        ));
+    }
+
+    #[test]
+    #[should_panic]
+    fn required_after_optional() {
+        Command::new("x", |_| async { Ok(None) })
+            .with_arg(ArgSpec::optional("a", ArgKind::Text))
+            .with_arg(ArgSpec::new("b", ArgKind::Text));
+    }
+}
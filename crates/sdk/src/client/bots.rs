@@ -0,0 +1,139 @@
+use http::Uri;
+
+use super::{error::ClientResult, Client};
+use crate::api::{auth::Session, bots::*};
+
+/// Manages the bots owned by the current user using the bots service:
+/// creating, listing, editing and deleting them, and adding them to guilds.
+#[derive(Debug, Clone)]
+pub struct BotManager {
+    client: Client,
+}
+
+impl BotManager {
+    /// Create a new bot manager that uses the given client.
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Get the bots the current user owns.
+    pub async fn list(&self) -> ClientResult<Vec<Bot>> {
+        let response = self.client.call(MyBotsRequest::default()).await?;
+        Ok(response.bots)
+    }
+
+    /// Get a bot, returning `None` if the homeserver didn't send it back.
+    pub async fn get(&self, bot_id: u64) -> ClientResult<Option<Bot>> {
+        let response = self
+            .client
+            .call(GetBotRequest {
+                bot_id,
+                ..Default::default()
+            })
+            .await?;
+        Ok(response.bot)
+    }
+
+    /// Create a new bot, returning its ID.
+    ///
+    /// If `invite` is set, the bot can be added to guilds using it with
+    /// [`BotManager::add_to_guild`].
+    pub async fn create(
+        &self,
+        display_name: impl Into<String>,
+        avatar_url: Option<String>,
+        invite: Option<String>,
+    ) -> ClientResult<u64> {
+        let response = self
+            .client
+            .call(CreateBotRequest {
+                display_name: display_name.into(),
+                avatar_url,
+                invite,
+            })
+            .await?;
+        Ok(response.bot_id)
+    }
+
+    /// Edit a bot. Fields that are `None` are left unchanged.
+    pub async fn edit(
+        &self,
+        bot_id: u64,
+        new_display_name: Option<String>,
+        new_avatar_url: Option<String>,
+        invite: Option<String>,
+    ) -> ClientResult<()> {
+        self.client
+            .call(EditBotRequest {
+                bot_id,
+                new_display_name,
+                new_avatar_url,
+                invite,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a bot.
+    pub async fn delete(&self, bot_id: u64) -> ClientResult<()> {
+        self.client
+            .call(DeleteBotRequest {
+                bot_id,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Add a bot to a guild using the bot's invite code.
+    pub async fn add_to_guild(
+        &self,
+        guild_id: u64,
+        invite_code: impl Into<String>,
+    ) -> ClientResult<()> {
+        self.client
+            .call(AddBotRequest {
+                guild_id,
+                invite_code: invite_code.into(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Get the maximum number of bots a user can own on this homeserver.
+    pub async fn max_bots(&self) -> ClientResult<u32> {
+        let response = self.client.call(PoliciesRequest::default()).await?;
+        Ok(response.max_bots)
+    }
+}
+
+impl Client {
+    /// Create a new [`Client`] that is logged in as a bot, using the bot's ID
+    /// and token.
+    ///
+    /// See [`Client::new`] for how `homeserver_url` is resolved.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use harmony_rust_sdk::client::*;
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> error::ClientResult<()> {
+    /// let token = std::env::var("BOT_TOKEN").unwrap();
+    /// let client = Client::new_bot("chat.harmonyapp.io".parse().unwrap(), 1234, token).await?;
+    /// assert!(client.auth_status().is_authenticated());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn new_bot(
+        homeserver_url: Uri,
+        bot_id: u64,
+        token: impl Into<String>,
+    ) -> ClientResult<Self> {
+        let session = Session {
+            user_id: bot_id,
+            session_token: token.into(),
+            ..Default::default()
+        };
+        Self::new(homeserver_url, Some(session)).await
+    }
+}
@@ -0,0 +1,1474 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{self, Debug, Formatter},
+    hash::Hash,
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+};
+
+use hrpc::exports::futures_util::future::BoxFuture;
+
+use super::{
+    error::{ClientError, ClientResult, ExternalKind},
+    Client,
+};
+use crate::api::{
+    chat::{
+        overrides, stream_event, AddReactionRequest, DeleteMessageRequest, Event, Message,
+        Overrides, RemoveReactionRequest, SendMessageRequest, UpdateMessageTextRequest,
+    },
+    emote::Emote,
+    harmonytypes::Empty,
+    profile::GetProfileRequest,
+};
+
+/// A user on the remote network.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteUser {
+    /// ID of the user on the remote network.
+    pub id: String,
+    /// Display name of the user.
+    pub name: String,
+    /// Avatar of the user, as a Harmony file ID or an URL.
+    pub avatar: Option<String>,
+}
+
+/// A message on the remote network.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RemoteMessage {
+    /// ID of the channel the message is in.
+    pub channel_id: String,
+    /// ID of the message.
+    pub message_id: String,
+    /// Author of the message.
+    pub author: RemoteUser,
+    /// Text of the message.
+    pub text: String,
+}
+
+/// An event on the remote network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteEvent {
+    /// A message was sent.
+    Message(RemoteMessage),
+    /// A message was edited.
+    Edited {
+        /// ID of the channel the message is in.
+        channel_id: String,
+        /// ID of the message.
+        message_id: String,
+        /// New text of the message.
+        text: String,
+    },
+    /// A message was deleted.
+    Deleted {
+        /// ID of the channel the message was in.
+        channel_id: String,
+        /// ID of the message.
+        message_id: String,
+    },
+    /// A reaction was added to or removed from a message.
+    Reaction {
+        /// ID of the channel the message is in.
+        channel_id: String,
+        /// ID of the message.
+        message_id: String,
+        /// ID of the user who reacted.
+        user_id: String,
+        /// Name of the emote.
+        emote: String,
+        /// Whether the reaction was added or removed.
+        added: bool,
+    },
+}
+
+/// A Harmony message to send to the remote network.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutgoingMessage {
+    /// ID of the Harmony user who sent the message.
+    pub author_id: u64,
+    /// Display name of the author.
+    pub author_name: String,
+    /// ID of the remote user the author is linked to, if any.
+    pub remote_author_id: Option<String>,
+    /// Text of the message.
+    pub text: String,
+}
+
+/// A network that is bridged to Harmony.
+///
+/// Most networks send a bridge its own messages and changes back as events;
+/// [`Bridge`] ignores these, so implementations don't need to filter them.
+pub trait RemoteNetwork: Send {
+    /// Wait for the next event, returning `None` if the network is closed.
+    fn next_event(&mut self) -> BoxFuture<'_, ClientResult<Option<RemoteEvent>>>;
+    /// Send a message to a channel, returning the ID of the sent message.
+    fn send<'a>(
+        &'a mut self,
+        channel_id: &'a str,
+        message: OutgoingMessage,
+    ) -> BoxFuture<'a, ClientResult<String>>;
+    /// Edit the text of a message.
+    fn edit<'a>(
+        &'a mut self,
+        channel_id: &'a str,
+        message_id: &'a str,
+        text: String,
+    ) -> BoxFuture<'a, ClientResult<()>>;
+    /// Delete a message.
+    fn delete<'a>(
+        &'a mut self,
+        channel_id: &'a str,
+        message_id: &'a str,
+    ) -> BoxFuture<'a, ClientResult<()>>;
+    /// Add or remove a reaction of the bridge to a message.
+    fn react<'a>(
+        &'a mut self,
+        channel_id: &'a str,
+        message_id: &'a str,
+        emote: &'a str,
+        added: bool,
+    ) -> BoxFuture<'a, ClientResult<()>>;
+}
+
+/// A link between a Harmony message and a remote message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MessageLink {
+    /// Guild ID of the Harmony message.
+    pub guild_id: u64,
+    /// Channel ID of the Harmony message.
+    pub channel_id: u64,
+    /// ID of the Harmony message.
+    pub message_id: u64,
+    /// Channel ID of the remote message.
+    pub remote_channel_id: String,
+    /// ID of the remote message.
+    pub remote_message_id: String,
+}
+
+/// Stores the mapping between Harmony and remote channels, users and
+/// messages of a [`Bridge`].
+pub trait MappingStore: Send {
+    /// Link a Harmony channel to a remote channel.
+    fn link_channel(&mut self, guild_id: u64, channel_id: u64, remote_channel_id: String);
+    /// Get the Harmony channel linked to a remote channel.
+    fn harmony_channel(&self, remote_channel_id: &str) -> Option<(u64, u64)>;
+    /// Get the remote channel linked to a Harmony channel.
+    fn remote_channel(&self, guild_id: u64, channel_id: u64) -> Option<String>;
+    /// Link a Harmony user to a remote user.
+    fn link_user(&mut self, user_id: u64, remote_user_id: String);
+    /// Get the Harmony user linked to a remote user.
+    fn harmony_user(&self, remote_user_id: &str) -> Option<u64>;
+    /// Get the remote user linked to a Harmony user.
+    fn remote_user(&self, user_id: u64) -> Option<String>;
+    /// Link a Harmony message to a remote message.
+    fn link_message(&mut self, link: MessageLink);
+    /// Get the link of a remote message.
+    fn remote_message(
+        &self,
+        remote_channel_id: &str,
+        remote_message_id: &str,
+    ) -> Option<MessageLink>;
+    /// Get the link of a Harmony message.
+    fn harmony_message(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> Option<MessageLink>;
+    /// Remove the link of a message.
+    fn unlink_message(&mut self, link: &MessageLink);
+}
+
+/// A [`MappingStore`] that keeps the mapping in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryMappingStore {
+    channels: HashMap<(u64, u64), String>,
+    remote_channels: HashMap<String, (u64, u64)>,
+    users: HashMap<u64, String>,
+    remote_users: HashMap<String, u64>,
+    messages: HashMap<(u64, u64, u64), MessageLink>,
+    remote_messages: HashMap<(String, String), (u64, u64, u64)>,
+}
+
+impl MemoryMappingStore {
+    /// Create a new, empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MappingStore for MemoryMappingStore {
+    fn link_channel(&mut self, guild_id: u64, channel_id: u64, remote_channel_id: String) {
+        self.remote_channels
+            .insert(remote_channel_id.clone(), (guild_id, channel_id));
+        self.channels
+            .insert((guild_id, channel_id), remote_channel_id);
+    }
+
+    fn harmony_channel(&self, remote_channel_id: &str) -> Option<(u64, u64)> {
+        self.remote_channels.get(remote_channel_id).copied()
+    }
+
+    fn remote_channel(&self, guild_id: u64, channel_id: u64) -> Option<String> {
+        self.channels.get(&(guild_id, channel_id)).cloned()
+    }
+
+    fn link_user(&mut self, user_id: u64, remote_user_id: String) {
+        self.remote_users.insert(remote_user_id.clone(), user_id);
+        self.users.insert(user_id, remote_user_id);
+    }
+
+    fn harmony_user(&self, remote_user_id: &str) -> Option<u64> {
+        self.remote_users.get(remote_user_id).copied()
+    }
+
+    fn remote_user(&self, user_id: u64) -> Option<String> {
+        self.users.get(&user_id).cloned()
+    }
+
+    fn link_message(&mut self, link: MessageLink) {
+        let key = (link.guild_id, link.channel_id, link.message_id);
+        self.remote_messages.insert(
+            (
+                link.remote_channel_id.clone(),
+                link.remote_message_id.clone(),
+            ),
+            key,
+        );
+        self.messages.insert(key, link);
+    }
+
+    fn remote_message(
+        &self,
+        remote_channel_id: &str,
+        remote_message_id: &str,
+    ) -> Option<MessageLink> {
+        let key = (remote_channel_id.to_string(), remote_message_id.to_string());
+        self.remote_messages
+            .get(&key)
+            .and_then(|key| self.messages.get(key))
+            .cloned()
+    }
+
+    fn harmony_message(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        message_id: u64,
+    ) -> Option<MessageLink> {
+        self.messages
+            .get(&(guild_id, channel_id, message_id))
+            .cloned()
+    }
+
+    fn unlink_message(&mut self, link: &MessageLink) {
+        self.messages
+            .remove(&(link.guild_id, link.channel_id, link.message_id));
+        self.remote_messages.remove(&(
+            link.remote_channel_id.clone(),
+            link.remote_message_id.clone(),
+        ));
+    }
+}
+
+/// Create a request that sends a message as a remote user, using message
+/// overrides for their name and avatar.
+///
+/// # Example
+/// ```
+/// # use harmony_rust_sdk::client::bridge::*;
+/// let author = RemoteUser {
+///     id: "alice@irc".to_string(),
+///     name: "alice".to_string(),
+///     avatar: None,
+/// };
+/// let request = bridged_message(1, 2, &author, "hello");
+/// assert_eq!(request.overrides.unwrap().username.as_deref(), Some("alice"));
+/// ```
+pub fn bridged_message(
+    guild_id: u64,
+    channel_id: u64,
+    author: &RemoteUser,
+    text: impl Into<String>,
+) -> SendMessageRequest {
+    SendMessageRequest {
+        guild_id,
+        channel_id,
+        overrides: Some(Overrides {
+            username: Some(author.name.clone()),
+            avatar: author.avatar.clone(),
+            reason: Some(overrides::Reason::Bridge(Empty {})),
+        }),
+        ..Default::default()
+    }
+    .with_text_content(text.into())
+}
+
+/// Whether a message was sent by a bridge running as `bridge_user_id`.
+pub fn is_bridged(message: &Message, bridge_user_id: u64) -> bool {
+    message.author_id == bridge_user_id
+        && matches!(
+            message.overrides.as_ref().and_then(|o| o.reason.as_ref()),
+            Some(overrides::Reason::Bridge(_))
+        )
+}
+
+/// The chat calls a [`Bridge`] makes, so they can be faked in tests.
+trait Chat: Sync {
+    fn send_message(&self, request: SendMessageRequest) -> BoxFuture<'_, ClientResult<u64>>;
+    fn edit_message(&self, request: UpdateMessageTextRequest) -> BoxFuture<'_, ClientResult<()>>;
+    fn delete_message(&self, request: DeleteMessageRequest) -> BoxFuture<'_, ClientResult<()>>;
+    fn add_reaction(&self, request: AddReactionRequest) -> BoxFuture<'_, ClientResult<()>>;
+    fn remove_reaction(&self, request: RemoveReactionRequest) -> BoxFuture<'_, ClientResult<()>>;
+    fn user_name(&self, user_id: u64) -> BoxFuture<'_, ClientResult<String>>;
+}
+
+impl Chat for Client {
+    fn send_message(&self, request: SendMessageRequest) -> BoxFuture<'_, ClientResult<u64>> {
+        Box::pin(async move { Ok(self.call(request).await?.message_id) })
+    }
+
+    fn edit_message(&self, request: UpdateMessageTextRequest) -> BoxFuture<'_, ClientResult<()>> {
+        Box::pin(async move { self.call(request).await.map(|_| ()) })
+    }
+
+    fn delete_message(&self, request: DeleteMessageRequest) -> BoxFuture<'_, ClientResult<()>> {
+        Box::pin(async move { self.call(request).await.map(|_| ()) })
+    }
+
+    fn add_reaction(&self, request: AddReactionRequest) -> BoxFuture<'_, ClientResult<()>> {
+        Box::pin(async move { self.call(request).await.map(|_| ()) })
+    }
+
+    fn remove_reaction(&self, request: RemoveReactionRequest) -> BoxFuture<'_, ClientResult<()>> {
+        Box::pin(async move { self.call(request).await.map(|_| ()) })
+    }
+
+    fn user_name(&self, user_id: u64) -> BoxFuture<'_, ClientResult<String>> {
+        Box::pin(async move {
+            let response = self.call(GetProfileRequest { user_id }).await?;
+            Ok(response
+                .profile
+                .map_or_else(|| user_id.to_string(), |p| p.user_name))
+        })
+    }
+}
+
+/// A change the bridge made on the remote network, and will get back as an
+/// event.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RemoteEcho {
+    Edit(String, String),
+    Reaction(String, String, String, bool),
+}
+
+type ReactionKey = (u64, u64, u64, String);
+
+/// How many events an echo marker is kept for. The echo of a change usually
+/// comes back within a few events; markers of echoes that never come back
+/// are dropped after this many, so they can't swallow an unrelated change
+/// later.
+const ECHO_LIFETIME: u64 = 256;
+
+/// Markers of changes the bridge made, with the event count at which they
+/// were made.
+struct EchoMarkers<K> {
+    markers: HashMap<K, u64>,
+}
+
+impl<K: Eq + Hash> EchoMarkers<K> {
+    fn new() -> Self {
+        Self {
+            markers: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, key: K, now: u64) {
+        self.markers.insert(key, now);
+    }
+
+    /// Removes a marker, returning whether it was there.
+    fn remove(&mut self, key: &K) -> bool {
+        self.markers.remove(key).is_some()
+    }
+
+    /// Drops markers made more than [`ECHO_LIFETIME`] events before `now`.
+    fn expire(&mut self, now: u64) {
+        self.markers
+            .retain(|_, made_at| now.saturating_sub(*made_at) <= ECHO_LIFETIME);
+    }
+}
+
+/// Bridges channels of a [`RemoteNetwork`] to Harmony channels.
+///
+/// Remote messages are sent to Harmony with overrides carrying their
+/// author's name and avatar, and Harmony messages are sent to the remote
+/// network with their author's name. Edits, deletes and reactions are
+/// mirrored both ways, and the echoes of the bridge's own changes are
+/// ignored. Only text messages are bridged.
+///
+/// Harmony reactions are per user, so reactions of several remote users
+/// with the same emote count once, and are only removed once none of them
+/// react with it anymore.
+///
+/// # Example
+/// ```no_run
+/// # use harmony_rust_sdk::client::{*, bridge::*};
+/// # async fn run(client: Client, network: LoopbackHandle) -> error::ClientResult<()> {
+/// let user_id = client.user_id().expect("logged in");
+/// let mut bridge = Bridge::new(user_id, network, MemoryMappingStore::new());
+/// bridge
+///     .mappings_mut()
+///     .link_channel(1234, 5678, "#harmony".to_string());
+///
+/// while let Some(event) = bridge.network_mut().next_event().await? {
+///     bridge.handle_remote_event(&client, event).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Bridge<N, M = MemoryMappingStore> {
+    user_id: u64,
+    network: N,
+    mappings: M,
+    names: HashMap<u64, String>,
+    reactions: HashMap<ReactionKey, u32>,
+    /// Remote users whose reactions the bridge's reactions stand for.
+    bridged_reactions: HashMap<ReactionKey, HashSet<String>>,
+    harmony_edits: EchoMarkers<(u64, u64, u64)>,
+    remote_echoes: EchoMarkers<RemoteEcho>,
+    events: u64,
+}
+
+impl<N: RemoteNetwork, M: MappingStore> Bridge<N, M> {
+    /// Create a new bridge. `user_id` is the ID of the Harmony user the
+    /// bridge sends messages as, usually [`Client::user_id`].
+    pub fn new(user_id: u64, network: N, mappings: M) -> Self {
+        Self {
+            user_id,
+            network,
+            mappings,
+            names: HashMap::new(),
+            reactions: HashMap::new(),
+            bridged_reactions: HashMap::new(),
+            harmony_edits: EchoMarkers::new(),
+            remote_echoes: EchoMarkers::new(),
+            events: 0,
+        }
+    }
+
+    /// Get the remote network.
+    pub fn network(&self) -> &N {
+        &self.network
+    }
+
+    /// Get the remote network mutably, for example to wait for its events.
+    pub fn network_mut(&mut self) -> &mut N {
+        &mut self.network
+    }
+
+    /// Get the mapping store.
+    pub fn mappings(&self) -> &M {
+        &self.mappings
+    }
+
+    /// Get the mapping store mutably, for example to link channels.
+    pub fn mappings_mut(&mut self) -> &mut M {
+        &mut self.mappings
+    }
+
+    /// Mirror an event of the remote network to Harmony.
+    pub async fn handle_remote_event(
+        &mut self,
+        client: &Client,
+        event: RemoteEvent,
+    ) -> ClientResult<()> {
+        self.handle_remote_event_with(client, event).await
+    }
+
+    /// Mirror a Harmony event to the remote network.
+    pub async fn handle_event(&mut self, client: &Client, event: &Event) -> ClientResult<()> {
+        self.handle_event_with(client, event).await
+    }
+
+    async fn handle_remote_event_with<C: Chat>(
+        &mut self,
+        chat: &C,
+        event: RemoteEvent,
+    ) -> ClientResult<()> {
+        self.tick();
+        match event {
+            RemoteEvent::Message(message) => {
+                if self
+                    .mappings
+                    .remote_message(&message.channel_id, &message.message_id)
+                    .is_some()
+                {
+                    return Ok(());
+                }
+                let (guild_id, channel_id) =
+                    match self.mappings.harmony_channel(&message.channel_id) {
+                        Some(channel) => channel,
+                        None => return Ok(()),
+                    };
+                let request = bridged_message(guild_id, channel_id, &message.author, message.text);
+                let message_id = chat.send_message(request).await?;
+                self.mappings.link_message(MessageLink {
+                    guild_id,
+                    channel_id,
+                    message_id,
+                    remote_channel_id: message.channel_id,
+                    remote_message_id: message.message_id,
+                });
+            }
+            RemoteEvent::Edited {
+                channel_id,
+                message_id,
+                text,
+            } => {
+                if self
+                    .remote_echoes
+                    .remove(&RemoteEcho::Edit(channel_id.clone(), message_id.clone()))
+                {
+                    return Ok(());
+                }
+                let link = match self.mappings.remote_message(&channel_id, &message_id) {
+                    Some(link) => link,
+                    None => return Ok(()),
+                };
+                chat.edit_message(UpdateMessageTextRequest {
+                    guild_id: link.guild_id,
+                    channel_id: link.channel_id,
+                    message_id: link.message_id,
+                    new_content: Some(text.into()),
+                })
+                .await?;
+                self.harmony_edits.insert(
+                    (link.guild_id, link.channel_id, link.message_id),
+                    self.events,
+                );
+            }
+            RemoteEvent::Deleted {
+                channel_id,
+                message_id,
+            } => {
+                let link = match self.mappings.remote_message(&channel_id, &message_id) {
+                    Some(link) => link,
+                    None => return Ok(()),
+                };
+                self.mappings.unlink_message(&link);
+                chat.delete_message(DeleteMessageRequest {
+                    guild_id: link.guild_id,
+                    channel_id: link.channel_id,
+                    message_id: link.message_id,
+                })
+                .await?;
+            }
+            RemoteEvent::Reaction {
+                channel_id,
+                message_id,
+                user_id,
+                emote,
+                added,
+            } => {
+                let echo = RemoteEcho::Reaction(
+                    channel_id.clone(),
+                    message_id.clone(),
+                    emote.clone(),
+                    added,
+                );
+                if self.remote_echoes.remove(&echo) {
+                    return Ok(());
+                }
+                let link = match self.mappings.remote_message(&channel_id, &message_id) {
+                    Some(link) => link,
+                    None => return Ok(()),
+                };
+                let key = (link.guild_id, link.channel_id, link.message_id, emote);
+                let users = self.bridged_reactions.get(&key);
+                let reacting = matches!(users, Some(users) if users.contains(&user_id));
+                let others = users.map_or(0, HashSet::len) - usize::from(reacting);
+                let emote = Some(Emote {
+                    name: key.3.clone(),
+                    ..Default::default()
+                });
+
+                if added && !reacting {
+                    if others == 0 {
+                        chat.add_reaction(AddReactionRequest {
+                            guild_id: link.guild_id,
+                            channel_id: link.channel_id,
+                            message_id: link.message_id,
+                            emote,
+                        })
+                        .await?;
+                        *self.reactions.entry(key.clone()).or_default() += 1;
+                    }
+                    self.bridged_reactions
+                        .entry(key)
+                        .or_default()
+                        .insert(user_id);
+                } else if !added && reacting {
+                    if others == 0 {
+                        chat.remove_reaction(RemoveReactionRequest {
+                            guild_id: link.guild_id,
+                            channel_id: link.channel_id,
+                            message_id: link.message_id,
+                            emote,
+                        })
+                        .await?;
+                        let count = self.reactions.entry(key.clone()).or_default();
+                        *count = count.saturating_sub(1);
+                        self.bridged_reactions.remove(&key);
+                    } else if let Some(users) = self.bridged_reactions.get_mut(&key) {
+                        users.remove(&user_id);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn author_name<C: Chat>(&mut self, chat: &C, message: &Message) -> ClientResult<String> {
+        if let Some(username) = message.overrides.as_ref().and_then(|o| o.username.clone()) {
+            return Ok(username);
+        }
+        if let Some(name) = self.names.get(&message.author_id) {
+            return Ok(name.clone());
+        }
+        let name = chat.user_name(message.author_id).await?;
+        self.names.insert(message.author_id, name.clone());
+        Ok(name)
+    }
+
+    /// Count an event, and drop the echo markers that expired.
+    fn tick(&mut self) {
+        self.events += 1;
+        self.harmony_edits.expire(self.events);
+        self.remote_echoes.expire(self.events);
+    }
+
+    async fn handle_event_with<C: Chat>(&mut self, chat: &C, event: &Event) -> ClientResult<()> {
+        self.tick();
+        let event = match event {
+            Event::Chat(event) => event,
+            _ => return Ok(()),
+        };
+
+        match event {
+            stream_event::Event::SentMessage(stream_event::MessageSent {
+                guild_id,
+                channel_id,
+                message_id,
+                message: Some(message),
+                ..
+            }) => {
+                if is_bridged(message, self.user_id)
+                    || self
+                        .mappings
+                        .harmony_message(*guild_id, *channel_id, *message_id)
+                        .is_some()
+                {
+                    return Ok(());
+                }
+                let remote_channel_id = match self.mappings.remote_channel(*guild_id, *channel_id) {
+                    Some(remote_channel_id) => remote_channel_id,
+                    None => return Ok(()),
+                };
+                let text = match message.get_text_content() {
+                    Some(text) => text.text.clone(),
+                    None => {
+                        tracing::debug!("not bridging non-text message {}", message_id);
+                        return Ok(());
+                    }
+                };
+
+                let outgoing = OutgoingMessage {
+                    author_id: message.author_id,
+                    author_name: self.author_name(chat, message).await?,
+                    remote_author_id: self.mappings.remote_user(message.author_id),
+                    text,
+                };
+                let remote_message_id = self.network.send(&remote_channel_id, outgoing).await?;
+                self.mappings.link_message(MessageLink {
+                    guild_id: *guild_id,
+                    channel_id: *channel_id,
+                    message_id: *message_id,
+                    remote_channel_id,
+                    remote_message_id,
+                });
+            }
+            stream_event::Event::EditedMessage(stream_event::MessageUpdated {
+                guild_id,
+                channel_id,
+                message_id,
+                new_content,
+                ..
+            }) => {
+                let key = (*guild_id, *channel_id, *message_id);
+                if self.harmony_edits.remove(&key) {
+                    return Ok(());
+                }
+                let link = match self.mappings.harmony_message(key.0, key.1, key.2) {
+                    Some(link) => link,
+                    None => return Ok(()),
+                };
+                let text = new_content
+                    .as_ref()
+                    .map_or_else(String::new, |c| c.text.clone());
+                self.network
+                    .edit(&link.remote_channel_id, &link.remote_message_id, text)
+                    .await?;
+                self.remote_echoes.insert(
+                    RemoteEcho::Edit(link.remote_channel_id, link.remote_message_id),
+                    self.events,
+                );
+            }
+            stream_event::Event::DeletedMessage(stream_event::MessageDeleted {
+                guild_id,
+                channel_id,
+                message_id,
+            }) => {
+                let link = match self
+                    .mappings
+                    .harmony_message(*guild_id, *channel_id, *message_id)
+                {
+                    Some(link) => link,
+                    None => return Ok(()),
+                };
+                self.mappings.unlink_message(&link);
+                self.network
+                    .delete(&link.remote_channel_id, &link.remote_message_id)
+                    .await?;
+            }
+            stream_event::Event::ReactionUpdated(stream_event::ReactionUpdated {
+                guild_id,
+                channel_id,
+                message_id,
+                reaction: Some(reaction),
+            }) => {
+                let link = match self
+                    .mappings
+                    .harmony_message(*guild_id, *channel_id, *message_id)
+                {
+                    Some(link) => link,
+                    None => return Ok(()),
+                };
+                let emote = match &reaction.emote {
+                    Some(emote) => emote.name.clone(),
+                    None => return Ok(()),
+                };
+                let key = (*guild_id, *channel_id, *message_id, emote);
+                let known = self.reactions.insert(key.clone(), reaction.count);
+                let added = match known.unwrap_or(0) {
+                    known if reaction.count > known => true,
+                    known if reaction.count < known => false,
+                    _ => return Ok(()),
+                };
+                self.network
+                    .react(
+                        &link.remote_channel_id,
+                        &link.remote_message_id,
+                        &key.3,
+                        added,
+                    )
+                    .await?;
+                self.remote_echoes.insert(
+                    RemoteEcho::Reaction(
+                        link.remote_channel_id,
+                        link.remote_message_id,
+                        key.3,
+                        added,
+                    ),
+                    self.events,
+                );
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl<N, M> Debug for Bridge<N, M> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bridge")
+            .field("user_id", &self.user_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Default)]
+struct LoopbackConnection {
+    queue: VecDeque<RemoteEvent>,
+    waker: Option<Waker>,
+}
+
+#[derive(Default)]
+struct LoopbackState {
+    connections: Vec<LoopbackConnection>,
+    next_message_id: u64,
+    closed: bool,
+}
+
+impl LoopbackState {
+    fn broadcast(&mut self, event: RemoteEvent) {
+        for conn in &mut self.connections {
+            conn.queue.push_back(event.clone());
+            if let Some(waker) = conn.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// An in-process remote network for testing bridges.
+///
+/// Every handle connected to the network gets every event, including the
+/// ones caused by itself, like a real network echoes a bridge's own
+/// messages back to it.
+#[derive(Clone, Default)]
+pub struct LoopbackNetwork {
+    state: Arc<Mutex<LoopbackState>>,
+}
+
+impl LoopbackNetwork {
+    /// Create a new, empty network.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connect to the network as a user.
+    pub fn connect(&self, user: RemoteUser) -> LoopbackHandle {
+        let mut state = self.state.lock().expect("poisoned");
+        state.connections.push(LoopbackConnection::default());
+        LoopbackHandle {
+            network: self.clone(),
+            index: state.connections.len() - 1,
+            user,
+        }
+    }
+
+    /// Close the network. Handles return `None` from
+    /// [`RemoteNetwork::next_event`] once they got all pending events.
+    pub fn close(&self) {
+        let mut state = self.state.lock().expect("poisoned");
+        state.closed = true;
+        for conn in &mut state.connections {
+            if let Some(waker) = conn.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn broadcast(&self, event: RemoteEvent) {
+        self.state.lock().expect("poisoned").broadcast(event);
+    }
+}
+
+impl Debug for LoopbackNetwork {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("LoopbackNetwork")
+    }
+}
+
+/// A connection to a [`LoopbackNetwork`].
+#[derive(Debug)]
+pub struct LoopbackHandle {
+    network: LoopbackNetwork,
+    index: usize,
+    user: RemoteUser,
+}
+
+impl LoopbackHandle {
+    /// Get the user this handle is connected as.
+    pub fn user(&self) -> &RemoteUser {
+        &self.user
+    }
+
+    /// Get an event without waiting, if there is one.
+    pub fn try_next_event(&mut self) -> Option<RemoteEvent> {
+        let mut state = self.network.state.lock().expect("poisoned");
+        state.connections[self.index].queue.pop_front()
+    }
+}
+
+impl RemoteNetwork for LoopbackHandle {
+    fn next_event(&mut self) -> BoxFuture<'_, ClientResult<Option<RemoteEvent>>> {
+        Box::pin(hrpc::exports::futures_util::future::poll_fn(move |cx| {
+            let mut state = self.network.state.lock().expect("poisoned");
+            let closed = state.closed;
+            let conn = &mut state.connections[self.index];
+            if let Some(event) = conn.queue.pop_front() {
+                Poll::Ready(Ok(Some(event)))
+            } else if closed {
+                Poll::Ready(Ok(None))
+            } else {
+                conn.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }))
+    }
+
+    fn send<'a>(
+        &'a mut self,
+        channel_id: &'a str,
+        message: OutgoingMessage,
+    ) -> BoxFuture<'a, ClientResult<String>> {
+        let mut state = self.network.state.lock().expect("poisoned");
+        let result = if state.closed {
+            Err(ClientError::external(
+                ExternalKind::Bridge,
+                "network is closed",
+            ))
+        } else {
+            state.next_message_id += 1;
+            let message_id = state.next_message_id.to_string();
+            state.broadcast(RemoteEvent::Message(RemoteMessage {
+                channel_id: channel_id.to_string(),
+                message_id: message_id.clone(),
+                author: RemoteUser {
+                    name: message.author_name,
+                    ..self.user.clone()
+                },
+                text: message.text,
+            }));
+            Ok(message_id)
+        };
+        Box::pin(async move { result })
+    }
+
+    fn edit<'a>(
+        &'a mut self,
+        channel_id: &'a str,
+        message_id: &'a str,
+        text: String,
+    ) -> BoxFuture<'a, ClientResult<()>> {
+        self.network.broadcast(RemoteEvent::Edited {
+            channel_id: channel_id.to_string(),
+            message_id: message_id.to_string(),
+            text,
+        });
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete<'a>(
+        &'a mut self,
+        channel_id: &'a str,
+        message_id: &'a str,
+    ) -> BoxFuture<'a, ClientResult<()>> {
+        self.network.broadcast(RemoteEvent::Deleted {
+            channel_id: channel_id.to_string(),
+            message_id: message_id.to_string(),
+        });
+        Box::pin(async { Ok(()) })
+    }
+
+    fn react<'a>(
+        &'a mut self,
+        channel_id: &'a str,
+        message_id: &'a str,
+        emote: &'a str,
+        added: bool,
+    ) -> BoxFuture<'a, ClientResult<()>> {
+        self.network.broadcast(RemoteEvent::Reaction {
+            channel_id: channel_id.to_string(),
+            message_id: message_id.to_string(),
+            user_id: self.user.id.clone(),
+            emote: emote.to_string(),
+            added,
+        });
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use hrpc::exports::futures_util::FutureExt;
+
+    use super::*;
+    use crate::api::chat::Reaction;
+
+    const BRIDGE_USER: u64 = 10;
+
+    #[derive(Default)]
+    struct FakeChat {
+        calls: Mutex<Vec<String>>,
+        sent: Mutex<Vec<SendMessageRequest>>,
+        fail_edits: AtomicBool,
+        fail_reactions: AtomicBool,
+    }
+
+    impl FakeChat {
+        fn log(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn calls(&self) -> Vec<String> {
+            std::mem::take(&mut *self.calls.lock().unwrap())
+        }
+    }
+
+    impl Chat for FakeChat {
+        fn send_message(&self, request: SendMessageRequest) -> BoxFuture<'_, ClientResult<u64>> {
+            let mut sent = self.sent.lock().unwrap();
+            sent.push(request);
+            let message_id = 100 + sent.len() as u64;
+            self.log(format!("send {}", message_id));
+            Box::pin(async move { Ok(message_id) })
+        }
+
+        fn edit_message(
+            &self,
+            request: UpdateMessageTextRequest,
+        ) -> BoxFuture<'_, ClientResult<()>> {
+            let text = request.new_content.unwrap().text;
+            self.log(format!("edit {} {}", request.message_id, text));
+            let result = if self.fail_edits.load(Ordering::SeqCst) {
+                Err(ClientError::external(ExternalKind::Bridge, "edit failed"))
+            } else {
+                Ok(())
+            };
+            Box::pin(async move { result })
+        }
+
+        fn delete_message(&self, request: DeleteMessageRequest) -> BoxFuture<'_, ClientResult<()>> {
+            self.log(format!("delete {}", request.message_id));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn add_reaction(&self, request: AddReactionRequest) -> BoxFuture<'_, ClientResult<()>> {
+            let emote = request.emote.unwrap().name;
+            self.log(format!("react {} {}", request.message_id, emote));
+            let result = if self.fail_reactions.load(Ordering::SeqCst) {
+                Err(ClientError::external(ExternalKind::Bridge, "react failed"))
+            } else {
+                Ok(())
+            };
+            Box::pin(async move { result })
+        }
+
+        fn remove_reaction(
+            &self,
+            request: RemoveReactionRequest,
+        ) -> BoxFuture<'_, ClientResult<()>> {
+            let emote = request.emote.unwrap().name;
+            self.log(format!("unreact {} {}", request.message_id, emote));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn user_name(&self, user_id: u64) -> BoxFuture<'_, ClientResult<String>> {
+            self.log(format!("profile {}", user_id));
+            Box::pin(async move { Ok(format!("user{}", user_id)) })
+        }
+    }
+
+    fn user(id: &str) -> RemoteUser {
+        RemoteUser {
+            id: id.to_string(),
+            name: id.to_string(),
+            avatar: None,
+        }
+    }
+
+    fn setup() -> (Bridge<LoopbackHandle>, LoopbackHandle, FakeChat) {
+        let network = LoopbackNetwork::new();
+        let mut bridge = Bridge::new(
+            BRIDGE_USER,
+            network.connect(user("bridge")),
+            MemoryMappingStore::new(),
+        );
+        bridge
+            .mappings_mut()
+            .link_channel(1, 2, "#chan".to_string());
+        (bridge, network.connect(user("alice")), FakeChat::default())
+    }
+
+    /// Feed all pending remote events to the bridge.
+    fn pump(bridge: &mut Bridge<LoopbackHandle>, chat: &FakeChat) {
+        while let Some(event) = bridge.network_mut().try_next_event() {
+            let result = bridge.handle_remote_event_with(chat, event).now_or_never();
+            result.unwrap().unwrap();
+        }
+    }
+
+    /// Feed a Harmony event to the bridge.
+    fn harmony(bridge: &mut Bridge<LoopbackHandle>, chat: &FakeChat, event: Event) {
+        let result = bridge.handle_event_with(chat, &event).now_or_never();
+        result.unwrap().unwrap();
+    }
+
+    fn chat_event(event: stream_event::Event) -> Event {
+        Event::Chat(event)
+    }
+
+    fn sent(message_id: u64, message: Message) -> Event {
+        chat_event(stream_event::Event::SentMessage(
+            stream_event::MessageSent {
+                guild_id: 1,
+                channel_id: 2,
+                message_id,
+                message: Some(message),
+                ..Default::default()
+            },
+        ))
+    }
+
+    fn text_message(author_id: u64, text: &str) -> Message {
+        Message {
+            author_id,
+            content: SendMessageRequest::default()
+                .with_text_content(text.to_string())
+                .content,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn remote_message_is_sent_with_overrides() {
+        let (mut bridge, mut alice, chat) = setup();
+        let outgoing = OutgoingMessage {
+            author_name: "alice".to_string(),
+            text: "hi".to_string(),
+            ..Default::default()
+        };
+        alice
+            .send("#chan", outgoing)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        pump(&mut bridge, &chat);
+
+        assert_eq!(chat.calls(), ["send 101"]);
+        let request = chat.sent.lock().unwrap().pop().unwrap();
+        let overrides = request.overrides.clone().unwrap();
+        assert_eq!(overrides.username.as_deref(), Some("alice"));
+
+        // The Harmony echo of the bridged message isn't sent back
+        let message = Message {
+            author_id: BRIDGE_USER,
+            content: request.content,
+            overrides: Some(overrides),
+            ..Default::default()
+        };
+        harmony(&mut bridge, &chat, sent(101, message));
+        alice.try_next_event().unwrap();
+        assert_eq!(alice.try_next_event(), None);
+    }
+
+    #[test]
+    fn harmony_message_is_sent_once() {
+        let (mut bridge, mut alice, chat) = setup();
+        let event = sent(5, text_message(3, "hello"));
+        harmony(&mut bridge, &chat, event);
+
+        let message = match alice.try_next_event() {
+            Some(RemoteEvent::Message(message)) => message,
+            event => panic!("unexpected event {:?}", event),
+        };
+        assert_eq!(message.author.name, "user3");
+        assert_eq!(message.text, "hello");
+
+        // The remote echo isn't sent back to Harmony
+        pump(&mut bridge, &chat);
+        assert_eq!(chat.calls(), ["profile 3"]);
+    }
+
+    #[test]
+    fn edits_and_deletes_are_mirrored_without_loops() {
+        let (mut bridge, mut alice, chat) = setup();
+        let outgoing = OutgoingMessage {
+            text: "hi".to_string(),
+            ..Default::default()
+        };
+        let remote_id = alice
+            .send("#chan", outgoing)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        pump(&mut bridge, &chat);
+        chat.calls();
+
+        // Remote edit goes to Harmony, and its Harmony echo is ignored
+        alice
+            .edit("#chan", &remote_id, "hey".to_string())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        pump(&mut bridge, &chat);
+        assert_eq!(chat.calls(), ["edit 101 hey"]);
+        let edited = chat_event(stream_event::Event::EditedMessage(
+            stream_event::MessageUpdated {
+                guild_id: 1,
+                channel_id: 2,
+                message_id: 101,
+                new_content: Some("hey".to_string().into()),
+                ..Default::default()
+            },
+        ));
+        harmony(&mut bridge, &chat, edited.clone());
+        while alice.try_next_event().is_some() {}
+
+        // Harmony edit goes to the remote network, and its echo is ignored
+        harmony(&mut bridge, &chat, edited);
+        assert!(matches!(
+            alice.try_next_event(),
+            Some(RemoteEvent::Edited { text, .. }) if text == "hey"
+        ));
+        pump(&mut bridge, &chat);
+        assert!(chat.calls().is_empty());
+
+        // Harmony delete goes to the remote network, and its echo is ignored
+        let deleted = chat_event(stream_event::Event::DeletedMessage(
+            stream_event::MessageDeleted {
+                guild_id: 1,
+                channel_id: 2,
+                message_id: 101,
+            },
+        ));
+        harmony(&mut bridge, &chat, deleted);
+        assert!(matches!(
+            alice.try_next_event(),
+            Some(RemoteEvent::Deleted { .. })
+        ));
+        pump(&mut bridge, &chat);
+        assert!(chat.calls().is_empty());
+        assert_eq!(bridge.mappings().harmony_message(1, 2, 101), None);
+    }
+
+    fn edited(message_id: u64, text: &str) -> Event {
+        chat_event(stream_event::Event::EditedMessage(
+            stream_event::MessageUpdated {
+                guild_id: 1,
+                channel_id: 2,
+                message_id,
+                new_content: Some(text.to_string().into()),
+                ..Default::default()
+            },
+        ))
+    }
+
+    /// Send a remote message through the bridge, returning its remote ID.
+    fn bridged_remote_message(
+        bridge: &mut Bridge<LoopbackHandle>,
+        alice: &mut LoopbackHandle,
+        chat: &FakeChat,
+    ) -> String {
+        let outgoing = OutgoingMessage {
+            text: "hi".to_string(),
+            ..Default::default()
+        };
+        let remote_id = alice
+            .send("#chan", outgoing)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        pump(bridge, chat);
+        chat.calls();
+        remote_id
+    }
+
+    #[test]
+    fn failed_edit_leaves_no_echo_marker() {
+        let (mut bridge, mut alice, chat) = setup();
+        let remote_id = bridged_remote_message(&mut bridge, &mut alice, &chat);
+
+        chat.fail_edits.store(true, Ordering::SeqCst);
+        alice
+            .edit("#chan", &remote_id, "hey".to_string())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let event = bridge.network_mut().try_next_event().unwrap();
+        let result = bridge.handle_remote_event_with(&chat, event).now_or_never();
+        assert!(result.unwrap().is_err());
+        while alice.try_next_event().is_some() {}
+
+        // The next Harmony edit isn't mistaken for an echo
+        harmony(&mut bridge, &chat, edited(101, "hello"));
+        assert!(matches!(
+            alice.try_next_event(),
+            Some(RemoteEvent::Edited { text, .. }) if text == "hello"
+        ));
+    }
+
+    #[test]
+    fn echo_markers_expire() {
+        let (mut bridge, mut alice, chat) = setup();
+        let remote_id = bridged_remote_message(&mut bridge, &mut alice, &chat);
+
+        alice
+            .edit("#chan", &remote_id, "hey".to_string())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        pump(&mut bridge, &chat);
+        assert_eq!(chat.calls(), ["edit 101 hey"]);
+        while alice.try_next_event().is_some() {}
+
+        // The echo of the edit never comes back
+        let unrelated = chat_event(stream_event::Event::DeletedMessage(
+            stream_event::MessageDeleted {
+                guild_id: 1,
+                channel_id: 2,
+                message_id: 999,
+            },
+        ));
+        for _ in 0..ECHO_LIFETIME {
+            harmony(&mut bridge, &chat, unrelated.clone());
+        }
+
+        harmony(&mut bridge, &chat, edited(101, "hello"));
+        assert!(matches!(
+            alice.try_next_event(),
+            Some(RemoteEvent::Edited { text, .. }) if text == "hello"
+        ));
+    }
+
+    #[test]
+    fn reactions_are_mirrored_without_loops() {
+        let (mut bridge, mut alice, chat) = setup();
+        let outgoing = OutgoingMessage {
+            text: "hi".to_string(),
+            ..Default::default()
+        };
+        let remote_id = alice
+            .send("#chan", outgoing)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        pump(&mut bridge, &chat);
+        chat.calls();
+
+        let reaction_updated = |count| {
+            chat_event(stream_event::Event::ReactionUpdated(
+                stream_event::ReactionUpdated {
+                    guild_id: 1,
+                    channel_id: 2,
+                    message_id: 101,
+                    reaction: Some(Reaction {
+                        emote: Some(Emote {
+                            name: "+1".to_string(),
+                            ..Default::default()
+                        }),
+                        count,
+                    }),
+                },
+            ))
+        };
+
+        alice
+            .react("#chan", &remote_id, "+1", true)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        pump(&mut bridge, &chat);
+        assert_eq!(chat.calls(), ["react 101 +1"]);
+        while alice.try_next_event().is_some() {}
+
+        // The Harmony echo has the count the bridge expects
+        harmony(&mut bridge, &chat, reaction_updated(1));
+        assert_eq!(alice.try_next_event(), None);
+
+        // Someone on Harmony reacts too
+        harmony(&mut bridge, &chat, reaction_updated(2));
+        assert!(matches!(
+            alice.try_next_event(),
+            Some(RemoteEvent::Reaction { added: true, .. })
+        ));
+        pump(&mut bridge, &chat);
+        assert!(chat.calls().is_empty());
+    }
+
+    #[test]
+    fn failed_reactions_are_retried() {
+        let (mut bridge, mut alice, chat) = setup();
+        let remote_id = alice
+            .send("#chan", OutgoingMessage::default())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        pump(&mut bridge, &chat);
+        chat.calls();
+
+        let react = |alice: &mut LoopbackHandle| {
+            alice
+                .react("#chan", &remote_id, "+1", true)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        };
+        chat.fail_reactions.store(true, Ordering::SeqCst);
+        react(&mut alice);
+        let event = bridge.network_mut().try_next_event().unwrap();
+        let result = bridge.handle_remote_event_with(&chat, event).now_or_never();
+        assert!(result.unwrap().is_err());
+        assert_eq!(chat.calls(), ["react 101 +1"]);
+
+        // Nothing was recorded, so the next attempt reacts again
+        chat.fail_reactions.store(false, Ordering::SeqCst);
+        react(&mut alice);
+        pump(&mut bridge, &chat);
+        assert_eq!(chat.calls(), ["react 101 +1"]);
+    }
+
+    #[test]
+    fn reactions_are_tracked_per_remote_user() {
+        let (mut bridge, mut alice, chat) = setup();
+        let mut bob = alice.network.connect(user("bob"));
+        let remote_id = alice
+            .send("#chan", OutgoingMessage::default())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        pump(&mut bridge, &chat);
+        chat.calls();
+
+        let react = |handle: &mut LoopbackHandle, added| {
+            handle
+                .react("#chan", &remote_id, "+1", added)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+        };
+        react(&mut alice, true);
+        react(&mut bob, true);
+        pump(&mut bridge, &chat);
+        assert_eq!(chat.calls(), ["react 101 +1"]);
+
+        // Alice's removal leaves the reaction bridged for Bob
+        react(&mut alice, false);
+        react(&mut alice, false);
+        pump(&mut bridge, &chat);
+        assert!(chat.calls().is_empty());
+
+        react(&mut bob, false);
+        pump(&mut bridge, &chat);
+        assert_eq!(chat.calls(), ["unreact 101 +1"]);
+    }
+
+    #[test]
+    fn loopback_echoes_to_every_handle() {
+        let network = LoopbackNetwork::new();
+        let mut a = network.connect(user("a"));
+        let mut b = network.connect(user("b"));
+
+        let id = a
+            .send("#chan", OutgoingMessage::default())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        for handle in [&mut a, &mut b] {
+            let event = handle.next_event().now_or_never().unwrap().unwrap();
+            assert!(matches!(event, Some(RemoteEvent::Message(m)) if m.message_id == id));
+        }
+
+        assert!(a.next_event().now_or_never().is_none());
+        network.close();
+        assert_eq!(a.next_event().now_or_never().unwrap().unwrap(), None);
+    }
+}
@@ -0,0 +1,716 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Cursor, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::{result::ZipError, write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use super::{
+    error::{ClientError, ClientResult, InvalidDataKind},
+    Client,
+};
+use crate::api::{
+    emote::*,
+    rest::{guess_mimetype, FileId},
+};
+
+/// Name of the manifest file in exported emote packs.
+pub const MANIFEST_NAME: &str = "manifest.json";
+/// Maximum decompressed size of a file in a zip archive read by
+/// [`LocalPack::from_zip()`], 16 MiB.
+pub const MAX_ZIP_ENTRY_SIZE: u64 = 16 * 1024 * 1024;
+/// Maximum decompressed size of all files in a zip archive read by
+/// [`LocalPack::from_zip()`], 256 MiB.
+pub const MAX_ZIP_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Manifest of an exported emote pack.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Name of the emote pack.
+    pub name: String,
+    /// Emotes in the emote pack.
+    pub emotes: Vec<ManifestEmote>,
+}
+
+/// An emote in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEmote {
+    /// Name of the emote.
+    pub name: String,
+    /// File ID of the emote image on the homeserver, if it was uploaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_id: Option<String>,
+    /// File name of the emote image, relative to the manifest.
+    pub file: String,
+    /// Hex encoded SHA-256 hash of the emote image that was uploaded as
+    /// `image_id`, to detect images that changed since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// An emote pack stored locally, as a directory or a zip archive containing
+/// the emote images and a [`Manifest`].
+#[derive(Debug, Clone, Default)]
+pub struct LocalPack {
+    /// Manifest of this emote pack.
+    pub manifest: Manifest,
+    /// Emote images, keyed by [`ManifestEmote::file`].
+    pub images: HashMap<String, Vec<u8>>,
+}
+
+impl LocalPack {
+    /// Read an emote pack from a directory.
+    ///
+    /// Images in the directory that aren't in the manifest are added to the
+    /// pack, named after their file name. If there is no manifest, the pack is
+    /// named after the directory.
+    pub async fn read_dir(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut files = HashMap::new();
+        let mut read_dir = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                files.insert(name.to_string(), tokio::fs::read(entry.path()).await?);
+            }
+        }
+
+        Self::from_files(files, &file_stem(dir))
+    }
+
+    /// Write this emote pack to a directory, creating it if it doesn't exist.
+    pub async fn write_dir(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let dir = dir.as_ref();
+        tokio::fs::create_dir_all(dir).await?;
+        for emote in &self.manifest.emotes {
+            if let Some(data) = self.images.get(&emote.file) {
+                tokio::fs::write(dir.join(&emote.file), data).await?;
+            }
+        }
+        tokio::fs::write(dir.join(MANIFEST_NAME), self.manifest_json()?).await
+    }
+
+    /// Read an emote pack from a zip archive.
+    ///
+    /// See [`LocalPack::read_dir()`] for how files are handled.
+    pub async fn read_zip(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let data = tokio::fs::read(path).await?;
+        Self::from_zip(&data, &file_stem(path))
+    }
+
+    /// Write this emote pack to a zip archive.
+    pub async fn write_zip(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        tokio::fs::write(path, self.to_zip()?).await
+    }
+
+    /// Read an emote pack from zip archive data. `name` is used as the pack
+    /// name if the archive has no manifest.
+    ///
+    /// Fails if a file is larger than [`MAX_ZIP_ENTRY_SIZE`] or all files
+    /// together are larger than [`MAX_ZIP_SIZE`] once decompressed.
+    pub fn from_zip(data: &[u8], name: &str) -> io::Result<Self> {
+        Self::from_zip_with_limits(data, name, MAX_ZIP_ENTRY_SIZE, MAX_ZIP_SIZE)
+    }
+
+    fn from_zip_with_limits(
+        data: &[u8],
+        name: &str,
+        max_entry_size: u64,
+        max_size: u64,
+    ) -> io::Result<Self> {
+        let mut archive = ZipArchive::new(Cursor::new(data)).map_err(zip_error)?;
+        let mut files = HashMap::new();
+        let mut total = 0;
+        for index in 0..archive.len() {
+            let file = archive.by_index(index).map_err(zip_error)?;
+            // Only files at the root of the archive are part of the pack
+            if file.is_dir() || !is_plain_file_name(file.name()) {
+                continue;
+            }
+            let name = file.name().to_string();
+            // Don't trust the size in the header, read one byte more than
+            // allowed to find out if the file is too large
+            let mut data = Vec::new();
+            file.take(max_entry_size + 1).read_to_end(&mut data)?;
+            let size = data.len() as u64;
+            total += size;
+            if size > max_entry_size || total > max_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is too large to be part of an emote pack", name),
+                ));
+            }
+            files.insert(name, data);
+        }
+
+        Self::from_files(files, name)
+    }
+
+    /// Create zip archive data containing this emote pack.
+    pub fn to_zip(&self) -> io::Result<Vec<u8>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+        writer
+            .start_file(MANIFEST_NAME, FileOptions::default())
+            .map_err(zip_error)?;
+        writer.write_all(&self.manifest_json()?)?;
+
+        // Images are already compressed
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        for emote in &self.manifest.emotes {
+            if let Some(data) = self.images.get(&emote.file) {
+                writer
+                    .start_file(emote.file.as_str(), options)
+                    .map_err(zip_error)?;
+                writer.write_all(data)?;
+            }
+        }
+
+        Ok(writer.finish().map_err(zip_error)?.into_inner())
+    }
+
+    fn from_files(mut files: HashMap<String, Vec<u8>>, name: &str) -> io::Result<Self> {
+        let mut manifest = match files.remove(MANIFEST_NAME) {
+            Some(raw) => serde_json::from_slice(&raw)?,
+            None => Manifest {
+                name: name.to_string(),
+                emotes: Vec::new(),
+            },
+        };
+
+        for emote in &manifest.emotes {
+            if !is_plain_file_name(&emote.file) || !files.contains_key(&emote.file) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("image {} of emote {} not found", emote.file, emote.name),
+                ));
+            }
+        }
+
+        let listed = manifest
+            .emotes
+            .iter()
+            .map(|emote| emote.file.clone())
+            .collect::<HashSet<_>>();
+        let mut unlisted = files
+            .iter()
+            .filter(|(file, data)| {
+                !listed.contains(file.as_str()) && guess_mimetype(file, data).starts_with("image/")
+            })
+            .map(|(file, _)| file.clone())
+            .collect::<Vec<_>>();
+        unlisted.sort();
+        manifest
+            .emotes
+            .extend(unlisted.into_iter().map(|file| ManifestEmote {
+                name: file_stem(Path::new(&file)),
+                image_id: None,
+                file,
+                sha256: None,
+            }));
+
+        let images = manifest
+            .emotes
+            .iter()
+            .filter_map(|emote| Some((emote.file.clone(), files.get(&emote.file)?.clone())))
+            .collect();
+
+        Ok(Self { manifest, images })
+    }
+
+    fn manifest_json(&self) -> io::Result<Vec<u8>> {
+        serde_json::to_vec_pretty(&self.manifest).map_err(io::Error::from)
+    }
+}
+
+/// Differences between a local and a remote emote pack.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackDiff {
+    /// Names of emotes that are only in the local pack.
+    pub added: Vec<String>,
+    /// Names of emotes that are only in the remote pack.
+    pub removed: Vec<String>,
+    /// Names of emotes whose image differs between the local and remote pack.
+    pub changed: Vec<String>,
+}
+
+impl PackDiff {
+    /// Returns `true` if the packs are the same.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compute the differences between a local emote pack and the emotes of a
+/// remote pack.
+///
+/// Local emotes are considered changed if their image ID differs from the
+/// remote one, or if their image doesn't match the hash in the manifest (for
+/// example because the image was replaced after it was uploaded, or there is
+/// no hash).
+pub fn diff(local: &LocalPack, remote: &[Emote]) -> PackDiff {
+    let remote_by_name = remote
+        .iter()
+        .map(|emote| (emote.name.as_str(), emote.image_id.as_str()))
+        .collect::<HashMap<_, _>>();
+    let local_names = local
+        .manifest
+        .emotes
+        .iter()
+        .map(|emote| emote.name.as_str())
+        .collect::<HashSet<_>>();
+
+    let mut diff = PackDiff::default();
+    for emote in &local.manifest.emotes {
+        let uploaded = |image_id: &str| {
+            let hash = local.images.get(&emote.file).map(|data| sha256_hex(data));
+            emote.image_id.as_deref() == Some(image_id) && hash.is_some() && emote.sha256 == hash
+        };
+        match remote_by_name.get(emote.name.as_str()) {
+            None => diff.added.push(emote.name.clone()),
+            Some(image_id) if !uploaded(image_id) => diff.changed.push(emote.name.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = remote
+        .iter()
+        .filter(|emote| !local_names.contains(emote.name.as_str()))
+        .map(|emote| emote.name.clone())
+        .collect();
+
+    diff
+}
+
+/// Manages emote packs using the emote service: exporting them, importing
+/// them and syncing them with local directories.
+#[derive(Debug, Clone)]
+pub struct EmotePackManager {
+    client: Client,
+}
+
+impl EmotePackManager {
+    /// Create a new emote pack manager that uses the given client.
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Get the emote packs the current user has equipped.
+    pub async fn packs(&self) -> ClientResult<Vec<EmotePack>> {
+        let response = self.client.call(GetEmotePacksRequest::default()).await?;
+        Ok(response.packs)
+    }
+
+    /// Get the emotes of an emote pack.
+    pub async fn emotes(&self, pack_id: u64) -> ClientResult<Vec<Emote>> {
+        let response = self
+            .client
+            .call(GetEmotePackEmotesRequest { pack_id })
+            .await?;
+        Ok(response.emotes)
+    }
+
+    /// Equip an emote pack.
+    pub async fn equip(&self, pack_id: u64) -> ClientResult<()> {
+        self.client.call(EquipEmotePackRequest { pack_id }).await?;
+        Ok(())
+    }
+
+    /// Export an emote pack, downloading all of its emote images.
+    ///
+    /// The emote pack must be equipped.
+    pub async fn export(&self, pack_id: u64) -> ClientResult<LocalPack> {
+        let pack = self
+            .packs()
+            .await?
+            .into_iter()
+            .find(|pack| pack.pack_id == pack_id)
+            .ok_or_else(|| ClientError::unexpected(format!("no emote pack with ID {}", pack_id)))?;
+
+        let mut local = LocalPack {
+            manifest: Manifest {
+                name: pack.pack_name,
+                emotes: Vec::new(),
+            },
+            images: HashMap::new(),
+        };
+        for emote in self.emotes(pack_id).await? {
+            let file_id = FileId::from_str(&emote.image_id).map_err(ClientError::unexpected)?;
+            let image = self.client.download_extract_file(file_id).await?;
+            let file = unique_file_name(&emote.name, &image.mimetype, &local.images);
+            local.images.insert(file.clone(), image.data.to_vec());
+            local.manifest.emotes.push(ManifestEmote {
+                name: emote.name,
+                image_id: Some(emote.image_id),
+                sha256: Some(sha256_hex(&image.data)),
+                file,
+            });
+        }
+
+        Ok(local)
+    }
+
+    /// Export an emote pack to a directory. See [`EmotePackManager::export()`].
+    pub async fn export_to_dir(&self, pack_id: u64, dir: impl AsRef<Path>) -> ClientResult<()> {
+        let pack = self.export(pack_id).await?;
+        pack.write_dir(dir).await.map_err(Into::into)
+    }
+
+    /// Export an emote pack to a zip archive. See [`EmotePackManager::export()`].
+    pub async fn export_to_zip(&self, pack_id: u64, path: impl AsRef<Path>) -> ClientResult<()> {
+        let pack = self.export(pack_id).await?;
+        pack.write_zip(path).await.map_err(Into::into)
+    }
+
+    /// Import an emote pack, uploading all of its emote images and creating
+    /// a new pack with them. Returns the ID of the created pack.
+    pub async fn import(&self, pack: &LocalPack) -> ClientResult<u64> {
+        let response = self
+            .client
+            .call(CreateEmotePackRequest {
+                pack_name: pack.manifest.name.clone(),
+            })
+            .await?;
+        let pack_id = response.pack_id;
+
+        for emote in &pack.manifest.emotes {
+            let image_id = self.upload_image(pack, emote).await?;
+            self.add_emote(pack_id, emote.name.clone(), image_id)
+                .await?;
+        }
+
+        Ok(pack_id)
+    }
+
+    /// Import an emote pack from a directory. See [`EmotePackManager::import()`].
+    pub async fn import_dir(&self, dir: impl AsRef<Path>) -> ClientResult<u64> {
+        let pack = LocalPack::read_dir(dir).await?;
+        self.import(&pack).await
+    }
+
+    /// Import an emote pack from a zip archive. See [`EmotePackManager::import()`].
+    pub async fn import_zip(&self, path: impl AsRef<Path>) -> ClientResult<u64> {
+        let pack = LocalPack::read_zip(path).await?;
+        self.import(&pack).await
+    }
+
+    /// Compute the differences between an emote pack stored in a directory
+    /// and a remote emote pack.
+    pub async fn diff_dir(&self, pack_id: u64, dir: impl AsRef<Path>) -> ClientResult<PackDiff> {
+        let local = LocalPack::read_dir(dir).await?;
+        let remote = self.emotes(pack_id).await?;
+        Ok(diff(&local, &remote))
+    }
+
+    /// Update a remote emote pack so that it matches an emote pack stored in a
+    /// directory, returning the applied differences.
+    ///
+    /// Added and changed emote images are uploaded and the manifest in the
+    /// directory is updated with their new image IDs and hashes. Emotes that aren't in
+    /// the directory are removed from the remote pack. All images are uploaded
+    /// before the remote pack is changed, and removed emotes are deleted last.
+    pub async fn sync_dir(&self, pack_id: u64, dir: impl AsRef<Path>) -> ClientResult<PackDiff> {
+        let dir = dir.as_ref();
+        let mut local = LocalPack::read_dir(dir).await?;
+        let remote = self.emotes(pack_id).await?;
+        let diff = diff(&local, &remote);
+        if diff.is_empty() {
+            return Ok(diff);
+        }
+
+        // Upload every image first, so a failed upload leaves the remote
+        // pack untouched
+        let mut uploaded = Vec::new();
+        for (index, emote) in local.manifest.emotes.iter().enumerate() {
+            if diff.added.contains(&emote.name) || diff.changed.contains(&emote.name) {
+                uploaded.push((index, self.upload_image(&local, emote).await?));
+            }
+        }
+
+        for (index, image_id) in uploaded {
+            let name = local.manifest.emotes[index].name.clone();
+            if diff.changed.contains(&name) {
+                self.delete_emote(pack_id, name.clone()).await?;
+            }
+            self.add_emote(pack_id, name, image_id.clone()).await?;
+            let emote = &mut local.manifest.emotes[index];
+            emote.sha256 = local.images.get(&emote.file).map(|data| sha256_hex(data));
+            emote.image_id = Some(image_id);
+        }
+
+        for name in &diff.removed {
+            self.delete_emote(pack_id, name.clone()).await?;
+        }
+
+        tokio::fs::write(dir.join(MANIFEST_NAME), local.manifest_json()?).await?;
+
+        Ok(diff)
+    }
+
+    async fn upload_image(&self, pack: &LocalPack, emote: &ManifestEmote) -> ClientResult<String> {
+        let data = pack.images.get(&emote.file).ok_or_else(|| {
+            ClientError::invalid_data(
+                InvalidDataKind::Upload,
+                format!("image {} not found in pack", emote.file),
+            )
+        })?;
+        let mimetype = guess_mimetype(&emote.file, data);
+        self.client
+            .upload_extract_id(emote.file.clone(), mimetype.to_string(), data.clone())
+            .await
+    }
+
+    async fn delete_emote(&self, pack_id: u64, name: String) -> ClientResult<()> {
+        self.client
+            .call(DeleteEmoteFromPackRequest { pack_id, name })
+            .await?;
+        Ok(())
+    }
+
+    async fn add_emote(&self, pack_id: u64, name: String, image_id: String) -> ClientResult<()> {
+        self.client
+            .call(AddEmoteToPackRequest {
+                pack_id,
+                emote: Some(Emote { image_id, name }),
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+fn zip_error(err: ZipError) -> io::Error {
+    match err {
+        ZipError::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Returns `true` if the name doesn't point outside of the pack directory.
+fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(&['/', '\\'][..]) && name != "." && name != ".."
+}
+
+/// Creates a file name for an emote image that isn't taken yet.
+fn unique_file_name(emote_name: &str, mimetype: &str, taken: &HashMap<String, Vec<u8>>) -> String {
+    let ext = match mimetype {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/bmp" => "bmp",
+        "image/svg+xml" => "svg",
+        _ => "bin",
+    };
+    let stem = emote_name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let stem = if stem.is_empty() {
+        "emote".to_string()
+    } else {
+        stem
+    };
+
+    let mut file = format!("{}.{}", stem, ext);
+    let mut counter = 1;
+    while file == MANIFEST_NAME || taken.contains_key(&file) {
+        counter += 1;
+        file = format!("{}-{}.{}", stem, counter, ext);
+    }
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    fn emote(name: &str, image_id: &str) -> Emote {
+        Emote {
+            name: name.to_string(),
+            image_id: image_id.to_string(),
+        }
+    }
+
+    fn manifest_emote(name: &str, image_id: Option<&str>) -> ManifestEmote {
+        ManifestEmote {
+            name: name.to_string(),
+            image_id: image_id.map(str::to_string),
+            file: format!("{}.png", name),
+            sha256: image_id.map(|_| sha256_hex(PNG)),
+        }
+    }
+
+    fn pack(emotes: Vec<ManifestEmote>) -> LocalPack {
+        let images = emotes
+            .iter()
+            .map(|emote| (emote.file.clone(), PNG.to_vec()))
+            .collect();
+        LocalPack {
+            manifest: Manifest {
+                name: "pack".to_string(),
+                emotes,
+            },
+            images,
+        }
+    }
+
+    #[test]
+    fn diffs_packs() {
+        let local = pack(vec![
+            manifest_emote("same", Some("1")),
+            manifest_emote("new", None),
+            manifest_emote("replaced", Some("4")),
+            manifest_emote("reuploaded", None),
+        ]);
+        let remote = vec![
+            emote("same", "1"),
+            emote("gone", "2"),
+            emote("replaced", "3"),
+            emote("reuploaded", "5"),
+        ];
+
+        assert_eq!(
+            diff(&local, &remote),
+            PackDiff {
+                added: vec!["new".to_string()],
+                removed: vec!["gone".to_string()],
+                changed: vec!["replaced".to_string(), "reuploaded".to_string()],
+            }
+        );
+        assert!(diff(&local, &remote[..0]).removed.is_empty());
+    }
+
+    #[test]
+    fn diffs_image_contents() {
+        let mut local = pack(vec![
+            manifest_emote("edited", Some("1")),
+            manifest_emote("unhashed", Some("2")),
+        ]);
+        local.manifest.emotes[1].sha256 = None;
+        let remote = vec![emote("edited", "1"), emote("unhashed", "2")];
+        assert_eq!(diff(&local, &remote).changed, ["unhashed"]);
+
+        // The image was replaced locally, but kept its file name
+        local
+            .images
+            .insert("edited.png".to_string(), b"\x89PNG\r\n\x1a\nnew".to_vec());
+        assert_eq!(diff(&local, &remote).changed, ["edited", "unhashed"]);
+    }
+
+    #[test]
+    fn adds_unlisted_images() {
+        let mut files = HashMap::new();
+        files.insert("b.png".to_string(), PNG.to_vec());
+        files.insert("a.png".to_string(), PNG.to_vec());
+        files.insert("notes.txt".to_string(), b"hello".to_vec());
+
+        let pack = LocalPack::from_files(files, "dir").unwrap();
+        assert_eq!(pack.manifest.name, "dir");
+        assert_eq!(
+            pack.manifest.emotes,
+            vec![manifest_emote("a", None), manifest_emote("b", None)]
+        );
+        assert_eq!(pack.images.len(), 2);
+    }
+
+    #[test]
+    fn rejects_missing_images() {
+        let manifest = Manifest {
+            name: "pack".to_string(),
+            emotes: vec![manifest_emote("missing", Some("1"))],
+        };
+        let mut files = HashMap::new();
+        files.insert(
+            MANIFEST_NAME.to_string(),
+            serde_json::to_vec(&manifest).unwrap(),
+        );
+
+        let err = LocalPack::from_files(files, "dir").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn zip_roundtrip() {
+        let mut pack = LocalPack {
+            manifest: Manifest {
+                name: "pack".to_string(),
+                emotes: vec![manifest_emote("a", Some("1"))],
+            },
+            images: HashMap::new(),
+        };
+        pack.images.insert("a.png".to_string(), PNG.to_vec());
+
+        let read = LocalPack::from_zip(&pack.to_zip().unwrap(), "other").unwrap();
+        assert_eq!(read.manifest, pack.manifest);
+        assert_eq!(read.images, pack.images);
+    }
+
+    #[test]
+    fn rejects_oversized_zip_entries() {
+        let zip = |sizes: &[usize]| {
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+            let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+            for (index, size) in sizes.iter().enumerate() {
+                writer
+                    .start_file(format!("{}.png", index), options)
+                    .unwrap();
+                writer.write_all(&vec![0; *size]).unwrap();
+            }
+            writer.finish().unwrap().into_inner()
+        };
+        let read = |sizes: &[usize]| LocalPack::from_zip_with_limits(&zip(sizes), "pack", 100, 250);
+
+        assert_eq!(read(&[100, 100]).unwrap().images.len(), 2);
+        assert_eq!(read(&[101]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            read(&[100, 100, 100]).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn makes_unique_file_names() {
+        let mut taken = HashMap::new();
+        assert_eq!(
+            unique_file_name("pog champ", "image/png", &taken),
+            "pog_champ.png"
+        );
+        taken.insert("pog_champ.png".to_string(), Vec::new());
+        assert_eq!(
+            unique_file_name("pog champ", "image/png", &taken),
+            "pog_champ-2.png"
+        );
+        assert_eq!(
+            unique_file_name("manifest", "application/json", &taken),
+            "manifest.bin"
+        );
+    }
+}
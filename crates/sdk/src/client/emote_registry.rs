@@ -0,0 +1,385 @@
+use std::{collections::HashMap, ops::Range};
+
+use super::{error::ClientResult, Client};
+use crate::api::{
+    chat::Event,
+    emote::{
+        stream_event, Emote, EmotePack, EmotePackAdded, GetEmotePackEmotesRequest,
+        GetEmotePacksRequest,
+    },
+};
+
+/// An emote known to an [`EmoteRegistry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredEmote {
+    /// ID of the emote pack this emote is from.
+    pub pack_id: u64,
+    /// Name of the emote.
+    pub name: String,
+    /// File ID of the emote image.
+    pub image_id: String,
+}
+
+/// Keeps track of the current user's equipped emote packs and their emotes,
+/// for resolving emote names to images.
+///
+/// Call [`EmoteRegistry::load()`] to fetch the equipped emote packs, and pass
+/// all received events to [`EmoteRegistry::handle_event()`] to keep the
+/// registry up to date.
+///
+/// If multiple equipped packs have an emote with the same name, the emote
+/// from the pack that was equipped first is used.
+#[derive(Debug)]
+pub struct EmoteRegistry {
+    client: Client,
+    index: EmoteIndex,
+}
+
+impl EmoteRegistry {
+    /// Create a new, empty emote registry that uses the given client.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            index: EmoteIndex::default(),
+        }
+    }
+
+    /// Fetch all equipped emote packs and their emotes, replacing the
+    /// current contents of the registry.
+    pub async fn load(&mut self) -> ClientResult<()> {
+        let packs = self
+            .client
+            .call(GetEmotePacksRequest::default())
+            .await?
+            .packs;
+
+        let mut index = EmoteIndex::default();
+        for pack in packs {
+            let emotes = self.fetch_emotes(pack.pack_id).await?;
+            index.set_pack(pack, emotes);
+        }
+        self.index = index;
+
+        Ok(())
+    }
+
+    /// Update the registry using an event. Returns `true` if the registry
+    /// was changed.
+    ///
+    /// Emotes of newly added packs are fetched.
+    pub async fn handle_event(&mut self, event: &Event) -> ClientResult<bool> {
+        use stream_event::Event as EmoteEvent;
+
+        let event = match event {
+            Event::Emote(event) => event,
+            _ => return Ok(false),
+        };
+        let changed = match event {
+            EmoteEvent::EmotePackAdded(EmotePackAdded {
+                pack: Some(pack), ..
+            }) => {
+                let emotes = self.fetch_emotes(pack.pack_id).await?;
+                self.index.set_pack(pack.clone(), emotes);
+                true
+            }
+            EmoteEvent::EmotePackUpdated(update) => match &update.new_pack_name {
+                Some(name) => self.index.rename_pack(update.pack_id, name),
+                None => false,
+            },
+            EmoteEvent::EmotePackDeleted(deleted) => self.index.remove_pack(deleted.pack_id),
+            EmoteEvent::EmotePackEmotesUpdated(update) => self.index.update_emotes(
+                update.pack_id,
+                &update.added_emotes,
+                &update.deleted_emotes,
+            ),
+            _ => false,
+        };
+
+        Ok(changed)
+    }
+
+    /// Get an emote by its name.
+    pub fn get(&self, name: &str) -> Option<&RegisteredEmote> {
+        self.index.by_name.get(name)
+    }
+
+    /// Get the file ID of an emote's image by the emote's name.
+    pub fn image_id(&self, name: &str) -> Option<&str> {
+        self.get(name).map(|emote| emote.image_id.as_str())
+    }
+
+    /// Get the equipped emote packs, in the order they were equipped.
+    pub fn packs(&self) -> impl Iterator<Item = &EmotePack> + '_ {
+        self.index.packs.iter().map(|(pack, _)| pack)
+    }
+
+    /// Get the emotes in an emote pack.
+    pub fn pack_emotes(&self, pack_id: u64) -> Option<&[Emote]> {
+        self.index
+            .packs
+            .iter()
+            .find(|(pack, _)| pack.pack_id == pack_id)
+            .map(|(_, emotes)| emotes.as_slice())
+    }
+
+    /// Search emotes by name, for autocompletion. See [`fuzzy_score()`] for
+    /// how matches are ranked.
+    ///
+    /// Returns at most `limit` emotes, best matches first.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&RegisteredEmote> {
+        self.index.search(query, limit)
+    }
+
+    /// Find all `:name:` emote shortcodes in a text that refer to known emotes.
+    ///
+    /// Returns the byte range of every shortcode (including the colons) along
+    /// with the emote it refers to.
+    pub fn find_shortcodes<'a>(&'a self, text: &str) -> Vec<(Range<usize>, &'a RegisteredEmote)> {
+        self.index.find_shortcodes(text)
+    }
+
+    async fn fetch_emotes(&self, pack_id: u64) -> ClientResult<Vec<Emote>> {
+        let response = self
+            .client
+            .call(GetEmotePackEmotesRequest { pack_id })
+            .await?;
+        Ok(response.emotes)
+    }
+}
+
+#[derive(Debug, Default)]
+struct EmoteIndex {
+    packs: Vec<(EmotePack, Vec<Emote>)>,
+    by_name: HashMap<String, RegisteredEmote>,
+}
+
+impl EmoteIndex {
+    fn set_pack(&mut self, pack: EmotePack, emotes: Vec<Emote>) {
+        match self
+            .packs
+            .iter_mut()
+            .find(|(p, _)| p.pack_id == pack.pack_id)
+        {
+            Some(entry) => *entry = (pack, emotes),
+            None => self.packs.push((pack, emotes)),
+        }
+        self.rebuild();
+    }
+
+    fn rename_pack(&mut self, pack_id: u64, name: &str) -> bool {
+        match self.packs.iter_mut().find(|(p, _)| p.pack_id == pack_id) {
+            Some((pack, _)) => {
+                pack.pack_name = name.to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_pack(&mut self, pack_id: u64) -> bool {
+        let len = self.packs.len();
+        self.packs.retain(|(pack, _)| pack.pack_id != pack_id);
+        let changed = len != self.packs.len();
+        if changed {
+            self.rebuild();
+        }
+        changed
+    }
+
+    fn update_emotes(&mut self, pack_id: u64, added: &[Emote], deleted: &[String]) -> bool {
+        let emotes = match self.packs.iter_mut().find(|(p, _)| p.pack_id == pack_id) {
+            Some((_, emotes)) => emotes,
+            None => return false,
+        };
+        emotes.retain(|emote| !deleted.contains(&emote.name));
+        for emote in added {
+            match emotes.iter_mut().find(|e| e.name == emote.name) {
+                Some(existing) => *existing = emote.clone(),
+                None => emotes.push(emote.clone()),
+            }
+        }
+        self.rebuild();
+        true
+    }
+
+    fn rebuild(&mut self) {
+        self.by_name.clear();
+        for (pack, emotes) in &self.packs {
+            for emote in emotes {
+                self.by_name
+                    .entry(emote.name.clone())
+                    .or_insert_with(|| RegisteredEmote {
+                        pack_id: pack.pack_id,
+                        name: emote.name.clone(),
+                        image_id: emote.image_id.clone(),
+                    });
+            }
+        }
+    }
+
+    fn search(&self, query: &str, limit: usize) -> Vec<&RegisteredEmote> {
+        let query = query.trim_matches(':');
+        let mut matches = self
+            .by_name
+            .values()
+            .filter_map(|emote| Some((fuzzy_score(query, &emote.name)?, emote)))
+            .collect::<Vec<_>>();
+        matches.sort_by(|(a_score, a), (b_score, b)| {
+            a_score.cmp(b_score).then_with(|| a.name.cmp(&b.name))
+        });
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, emote)| emote)
+            .collect()
+    }
+
+    fn find_shortcodes<'a>(&'a self, text: &str) -> Vec<(Range<usize>, &'a RegisteredEmote)> {
+        let mut found = Vec::new();
+        let mut start = match text.find(':') {
+            Some(start) => start,
+            None => return found,
+        };
+        while let Some(len) = text[start + 1..].find(':') {
+            let end = start + 1 + len;
+            let name = &text[start + 1..end];
+            let emote = (!name.is_empty() && !name.contains(char::is_whitespace))
+                .then(|| self.by_name.get(name))
+                .flatten();
+            match emote {
+                Some(emote) => {
+                    found.push((start..end + 1, emote));
+                    match text[end + 1..].find(':') {
+                        Some(next) => start = end + 1 + next,
+                        None => break,
+                    }
+                }
+                // The closing colon might start another shortcode
+                None => start = end,
+            }
+        }
+        found
+    }
+}
+
+/// Scores how well an emote name matches a search query, ignoring case.
+/// Lower scores are better matches, and `None` means no match.
+///
+/// Exact matches rank first, then prefix matches, then substring matches,
+/// and finally names that contain all characters of the query in order.
+/// Within each group, shorter names and earlier matches rank higher.
+pub fn fuzzy_score(query: &str, name: &str) -> Option<u32> {
+    let query = query.to_lowercase();
+    let lower_name = name.to_lowercase();
+    let extra =
+        (lower_name.chars().count() - query.chars().count().min(lower_name.chars().count())) as u32;
+
+    if lower_name == query {
+        return Some(0);
+    }
+    if lower_name.starts_with(&query) {
+        return Some(1_000 + extra);
+    }
+    if let Some(pos) = lower_name.find(&query) {
+        let pos = lower_name[..pos].chars().count() as u32;
+        return Some(2_000 + pos * 10 + extra);
+    }
+
+    // Subsequence match, penalizing gaps between matched characters
+    let mut gaps = 0_u32;
+    let mut name_chars = lower_name.chars();
+    for query_char in query.chars() {
+        loop {
+            match name_chars.next() {
+                Some(c) if c == query_char => break,
+                Some(_) => gaps += 1,
+                None => return None,
+            }
+        }
+    }
+    Some(3_000 + gaps * 10 + extra)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack(pack_id: u64) -> EmotePack {
+        EmotePack {
+            pack_id,
+            ..Default::default()
+        }
+    }
+
+    fn emote(name: &str, image_id: &str) -> Emote {
+        Emote {
+            name: name.to_string(),
+            image_id: image_id.to_string(),
+        }
+    }
+
+    fn index() -> EmoteIndex {
+        let mut index = EmoteIndex::default();
+        index.set_pack(
+            pack(1),
+            vec![
+                emote("pog", "1"),
+                emote("pogchamp", "2"),
+                emote("kekw", "3"),
+            ],
+        );
+        index.set_pack(pack(2), vec![emote("pog", "4"), emote("sadge", "5")]);
+        index
+    }
+
+    #[test]
+    fn first_pack_wins() {
+        let mut index = index();
+        assert_eq!(index.by_name["pog"].image_id, "1");
+        index.remove_pack(1);
+        assert_eq!(index.by_name["pog"].image_id, "4");
+        assert!(!index.by_name.contains_key("kekw"));
+    }
+
+    #[test]
+    fn updates_emotes() {
+        let mut index = index();
+        assert!(index.update_emotes(
+            2,
+            &[emote("sadge", "6"), emote("hmm", "7")],
+            &["pog".to_string()]
+        ));
+        assert_eq!(index.by_name["sadge"].image_id, "6");
+        assert_eq!(index.by_name["hmm"].pack_id, 2);
+        assert_eq!(index.packs[1].1.len(), 2);
+        assert!(!index.update_emotes(3, &[], &[]));
+    }
+
+    #[test]
+    fn ranks_search_results() {
+        let index = index();
+        let names = |query| {
+            index
+                .search(query, 10)
+                .into_iter()
+                .map(|e| e.name.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("pog"), vec!["pog", "pogchamp"]);
+        assert_eq!(names(":CHAMP"), vec!["pogchamp"]);
+        assert_eq!(names("sg"), vec!["sadge"]);
+        assert_eq!(index.search("", 2).len(), 2);
+    }
+
+    #[test]
+    fn finds_shortcodes() {
+        let index = index();
+        let text = "hi :pog: :unknown:kekw: :sadge";
+        let found = index
+            .find_shortcodes(text)
+            .into_iter()
+            .map(|(range, emote)| (range, emote.name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![(3..8, "pog"), (17..23, "kekw")]);
+    }
+}
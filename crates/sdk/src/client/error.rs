@@ -23,7 +23,11 @@ pub type InternalClientError =
     hrpc::client::error::ClientError<hrpc::client::transport::http::HyperError>;
 
 /// Error type used by `Client`.
+///
+/// New variants may be added in minor releases, so matches on it need a
+/// wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ClientError {
     /// Returned if the internal hRPC client returns an error.
     Internal(InternalClientError),
@@ -39,12 +43,106 @@ pub enum ClientError {
     UnexpectedResponse(String),
     /// Returned if a socket returns an error.
     SocketError(SocketError),
+    /// Returned if an I/O error occurs, for example while writing a downloaded file.
+    Io(std::io::Error),
+    /// Returned if data the client uploads, encodes or decodes is invalid,
+    /// for example a non-image file uploaded as a photo, or app data stored
+    /// with an unknown schema version.
+    InvalidData {
+        /// What kind of data is invalid.
+        kind: InvalidDataKind,
+        /// Why the data is invalid.
+        message: String,
+    },
+    /// Returned if something outside of Harmony the client drives fails, for
+    /// example voice signaling or the remote network of a bridge.
+    External {
+        /// What failed.
+        kind: ExternalKind,
+        /// Why it failed.
+        message: String,
+    },
+}
+
+/// Kind of data in a [`ClientError::InvalidData`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InvalidDataKind {
+    /// A file to upload.
+    Upload,
+    /// App data stored with the profile service.
+    AppData,
+    /// A guild archive.
+    Archive,
+}
+
+impl Display for InvalidDataKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            InvalidDataKind::Upload => "upload",
+            InvalidDataKind::AppData => "app data",
+            InvalidDataKind::Archive => "guild archive",
+        })
+    }
+}
+
+/// What failed in a [`ClientError::External`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExternalKind {
+    /// Voice signaling, or the media backend of a voice session.
+    Voice,
+    /// The remote network of a bridge.
+    Bridge,
+}
+
+impl Display for ExternalKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ExternalKind::Voice => "Voice",
+            ExternalKind::Bridge => "Bridge",
+        })
+    }
 }
 
 impl ClientError {
     pub(crate) fn unexpected(msg: impl ToString) -> Self {
         ClientError::UnexpectedResponse(msg.to_string())
     }
+
+    // Only used by feature gated modules
+    #[allow(dead_code)]
+    pub(crate) fn invalid_data(kind: InvalidDataKind, msg: impl ToString) -> Self {
+        ClientError::InvalidData {
+            kind,
+            message: msg.to_string(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn external(kind: ExternalKind, msg: impl ToString) -> Self {
+        ClientError::External {
+            kind,
+            message: msg.to_string(),
+        }
+    }
+
+    /// Whether retrying the request that returned this error might succeed,
+    /// for example if the connection failed or the request was rate limited.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Internal(InternalClientError::Transport(_)) => true,
+            ClientError::Internal(InternalClientError::EndpointError { hrpc_error, .. }) => {
+                matches!(
+                    hrpc_error.identifier.as_str(),
+                    "hrpc.resource-exhausted" | "hrpc.unavailable"
+                )
+            }
+            ClientError::Reqwest(err) => err.is_timeout() || err.is_request(),
+            ClientError::SocketError(_) => true,
+            _ => false,
+        }
+    }
 }
 
 impl Display for ClientError {
@@ -57,6 +155,9 @@ impl Display for ClientError {
             ClientError::Unauthenticated => write!(f, "Client is not authenticated, but the API it tries to call requires authentication"),
             ClientError::UnexpectedResponse(msg) => write!(f, "Server responded with unexpected value: {}", msg),
             ClientError::SocketError(err) => write!(f, "socket error: {}", err),
+            ClientError::Io(err) => write!(f, "I/O error: {}", err),
+            ClientError::InvalidData { kind, message } => write!(f, "Invalid {}: {}", kind, message),
+            ClientError::External { kind, message } => write!(f, "{} error: {}", kind, message),
         }
     }
 }
@@ -99,12 +200,19 @@ impl From<SocketError> for ClientError {
     }
 }
 
+impl From<std::io::Error> for ClientError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
 impl StdError for ClientError {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match self {
             ClientError::Internal(err) => Some(err),
             ClientError::Reqwest(err) => Some(err),
             ClientError::UrlParse(err) => Some(err),
+            ClientError::Io(err) => Some(err),
             _ => None,
         }
     }
@@ -0,0 +1,1280 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hrpc::exports::futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{
+    error::{ClientError, ClientResult, InvalidDataKind},
+    Client,
+};
+use crate::api::{
+    chat::{
+        content, overrides, AddGuildRoleRequest, Channel, CreateChannelRequest, CreateGuildRequest,
+        GetChannelMessagesRequest, GetGuildChannelsRequest, GetGuildMembersRequest,
+        GetGuildRequest, GetGuildRolesRequest, GetPermissionsRequest, GetPinnedMessagesRequest,
+        GetUserRolesRequest, Guild, Message, Overrides, Permission, PinMessageRequest, Role,
+        SendMessageRequest, SetPermissionsRequest,
+    },
+    emote::{
+        AddEmoteToPackRequest, CreateEmotePackRequest, Emote, EmotePack, GetEmotePackEmotesRequest,
+        GetEmotePacksRequest,
+    },
+    harmonytypes::{Empty, ItemPosition},
+    profile::{GetProfileRequest, Profile},
+    rest::FileId,
+};
+
+/// Version of the archive format written by [`Client::export_guild`].
+pub const ARCHIVE_VERSION: u32 = 1;
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Permissions of a role in a channel.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RolePermissions {
+    /// ID of the role.
+    pub role_id: u64,
+    /// Permissions of the role in the channel.
+    pub permissions: Vec<Permission>,
+}
+
+/// A record in a guild archive.
+///
+/// An archive starts with a [`ArchiveRecord::Header`] and ends with an
+/// [`ArchiveRecord::End`], and [`ArchiveRecord::Media`] records come before
+/// the first record that references them. Channels are in the guild's order,
+/// and each channel's messages follow it, oldest first.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ArchiveRecord {
+    /// Information about the archive.
+    Header {
+        /// Version of the archive format.
+        version: u32,
+        /// ID of the exported guild on its homeserver.
+        guild_id: u64,
+        /// Homeserver the guild was exported from.
+        homeserver: String,
+        /// UNIX timestamp of when the guild was exported.
+        exported_at: u64,
+    },
+    /// The guild itself.
+    Guild {
+        /// The guild.
+        guild: Guild,
+    },
+    /// A role of the guild.
+    Role {
+        /// ID of the role.
+        role_id: u64,
+        /// The role.
+        role: Role,
+        /// Guild-wide permissions of the role.
+        permissions: Vec<Permission>,
+    },
+    /// A member of the guild.
+    Member {
+        /// ID of the user.
+        user_id: u64,
+        /// Profile of the user, if it could be fetched.
+        profile: Option<Profile>,
+        /// IDs of the roles the user has.
+        role_ids: Vec<u64>,
+    },
+    /// A channel of the guild.
+    Channel {
+        /// ID of the channel.
+        channel_id: u64,
+        /// The channel.
+        channel: Channel,
+        /// Permissions of roles in the channel.
+        permissions: Vec<RolePermissions>,
+        /// IDs of the pinned messages of the channel.
+        pinned_message_ids: Vec<u64>,
+    },
+    /// A message in a channel.
+    Message {
+        /// ID of the channel the message is in.
+        channel_id: u64,
+        /// ID of the message.
+        message_id: u64,
+        /// The message.
+        message: Message,
+    },
+    /// An emote pack of the exporting user.
+    EmotePack {
+        /// The emote pack.
+        pack: EmotePack,
+        /// Emotes in the pack.
+        emotes: Vec<Emote>,
+    },
+    /// A downloaded file referenced by other records.
+    Media {
+        /// File ID the file is referenced with.
+        file_id: String,
+        /// Name of the file.
+        name: String,
+        /// Mimetype of the file.
+        mimetype: String,
+        /// Contents of the file.
+        #[serde(with = "base64_data")]
+        data: Vec<u8>,
+    },
+    /// Marks the end of the archive, so truncated archives can be detected.
+    End,
+}
+
+mod base64_data {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let data = String::deserialize(deserializer)?;
+        base64::decode(data).map_err(D::Error::custom)
+    }
+}
+
+/// Destination of the records of a guild archive.
+pub trait ArchiveSink: Send {
+    /// Write a record.
+    fn write_record<'a>(&'a mut self, record: &'a ArchiveRecord)
+        -> BoxFuture<'a, ClientResult<()>>;
+}
+
+impl ArchiveSink for Vec<ArchiveRecord> {
+    fn write_record<'a>(
+        &'a mut self,
+        record: &'a ArchiveRecord,
+    ) -> BoxFuture<'a, ClientResult<()>> {
+        self.push(record.clone());
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// An [`ArchiveSink`] that writes records as JSON lines.
+#[derive(Debug)]
+pub struct JsonLinesWriter<W> {
+    inner: W,
+}
+
+impl<W: AsyncWrite + Unpin + Send> JsonLinesWriter<W> {
+    /// Create a new writer that writes to `inner`.
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Flush and return the inner writer.
+    pub async fn finish(mut self) -> ClientResult<W> {
+        self.inner.flush().await?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send> ArchiveSink for JsonLinesWriter<W> {
+    fn write_record<'a>(
+        &'a mut self,
+        record: &'a ArchiveRecord,
+    ) -> BoxFuture<'a, ClientResult<()>> {
+        Box::pin(async move {
+            let mut line = serde_json::to_vec(record)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            line.push(b'\n');
+            self.inner.write_all(&line).await?;
+            Ok(())
+        })
+    }
+}
+
+/// Source of the records of a guild archive.
+pub trait ArchiveSource: Send {
+    /// Read the next record, returning `None` at the end of the source.
+    fn next_record(&mut self) -> BoxFuture<'_, ClientResult<Option<ArchiveRecord>>>;
+}
+
+impl ArchiveSource for std::vec::IntoIter<ArchiveRecord> {
+    fn next_record(&mut self) -> BoxFuture<'_, ClientResult<Option<ArchiveRecord>>> {
+        let record = self.next();
+        Box::pin(async { Ok(record) })
+    }
+}
+
+/// An [`ArchiveSource`] that reads records written by a [`JsonLinesWriter`].
+#[derive(Debug)]
+pub struct JsonLinesReader<R> {
+    inner: R,
+    line: String,
+}
+
+impl<R: AsyncBufRead + Unpin + Send> JsonLinesReader<R> {
+    /// Create a new reader that reads from `inner`.
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            line: String::new(),
+        }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin + Send> ArchiveSource for JsonLinesReader<R> {
+    fn next_record(&mut self) -> BoxFuture<'_, ClientResult<Option<ArchiveRecord>>> {
+        Box::pin(async move {
+            loop {
+                self.line.clear();
+                if self.inner.read_line(&mut self.line).await? == 0 {
+                    return Ok(None);
+                }
+                if self.line.trim().is_empty() {
+                    continue;
+                }
+                return serde_json::from_str(&self.line)
+                    .map(Some)
+                    .map_err(|err| ClientError::invalid_data(InvalidDataKind::Archive, err));
+            }
+        })
+    }
+}
+
+/// Counts of what [`Client::export_guild`] wrote.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExportSummary {
+    /// Number of exported roles.
+    pub roles: usize,
+    /// Number of exported members.
+    pub members: usize,
+    /// Number of exported channels.
+    pub channels: usize,
+    /// Number of exported messages.
+    pub messages: usize,
+    /// Number of exported emote packs.
+    pub emote_packs: usize,
+    /// Number of downloaded files.
+    pub media: usize,
+    /// File IDs of referenced files that couldn't be downloaded.
+    pub missing_media: Vec<String>,
+}
+
+/// What [`Client::import_guild`] created, or would create for a dry run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// ID of the created guild, `None` for a dry run.
+    pub guild_id: Option<u64>,
+    /// Number of created roles.
+    pub roles: usize,
+    /// Number of members whose profiles were used for message overrides.
+    pub members: usize,
+    /// Number of created channels.
+    pub channels: usize,
+    /// Number of replayed messages.
+    pub messages: usize,
+    /// Number of pinned messages.
+    pub pins: usize,
+    /// Number of created emote packs.
+    pub emote_packs: usize,
+    /// Number of uploaded files.
+    pub media: usize,
+    /// Things that couldn't be restored, such as references to files missing
+    /// from the archive.
+    pub warnings: Vec<String>,
+}
+
+/// File IDs referenced by a message.
+fn message_media(message: &Message) -> Vec<&str> {
+    match message.get_content() {
+        Some(content::Content::AttachmentMessage(attachments)) => attachments
+            .files
+            .iter()
+            .map(|file| file.id.as_str())
+            .collect(),
+        Some(content::Content::PhotoMessage(photos)) => photos
+            .photos
+            .iter()
+            .map(|photo| photo.hmc.as_str())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+struct Exporter<'a, S> {
+    client: &'a Client,
+    sink: &'a mut S,
+    guild_id: u64,
+    downloaded: HashSet<String>,
+    summary: ExportSummary,
+}
+
+impl<'a, S: ArchiveSink> Exporter<'a, S> {
+    async fn write(&mut self, record: ArchiveRecord) -> ClientResult<()> {
+        self.sink.write_record(&record).await
+    }
+
+    /// Download a file and write it to the archive, unless it already was.
+    async fn media(&mut self, file_id: &str) -> ClientResult<()> {
+        if file_id.is_empty() || !self.downloaded.insert(file_id.to_string()) {
+            return Ok(());
+        }
+
+        let file = match FileId::from_str(file_id) {
+            Ok(id) => self.client.download_extract_file(id).await,
+            Err(err) => Err(ClientError::unexpected(err)),
+        };
+        match file {
+            Ok(file) => {
+                self.summary.media += 1;
+                self.write(ArchiveRecord::Media {
+                    file_id: file_id.to_string(),
+                    name: file.name,
+                    mimetype: file.mimetype,
+                    data: file.data.to_vec(),
+                })
+                .await
+            }
+            Err(err) => {
+                tracing::warn!("failed to download {} for guild export: {}", file_id, err);
+                self.summary.missing_media.push(file_id.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    async fn permissions(
+        &self,
+        channel_id: Option<u64>,
+        role_id: u64,
+    ) -> ClientResult<Vec<Permission>> {
+        let response = self
+            .client
+            .call(GetPermissionsRequest {
+                guild_id: self.guild_id,
+                channel_id,
+                role_id,
+            })
+            .await?;
+        Ok(response.perms)
+    }
+
+    async fn guild(&mut self) -> ClientResult<()> {
+        let guild = self
+            .client
+            .call(GetGuildRequest::new(self.guild_id))
+            .await?
+            .guild
+            .ok_or_else(|| ClientError::unexpected("guild is missing"))?;
+        if let Some(picture) = &guild.picture {
+            self.media(picture).await?;
+        }
+        self.write(ArchiveRecord::Guild { guild }).await
+    }
+
+    async fn roles(&mut self) -> ClientResult<Vec<u64>> {
+        let roles = self
+            .client
+            .call(GetGuildRolesRequest::new(self.guild_id))
+            .await?
+            .roles;
+
+        let mut role_ids = Vec::with_capacity(roles.len());
+        for role in roles {
+            let role_id = role.role_id;
+            let role = match role.role {
+                Some(role) => role,
+                None => continue,
+            };
+            let permissions = self.permissions(None, role_id).await?;
+            self.write(ArchiveRecord::Role {
+                role_id,
+                role,
+                permissions,
+            })
+            .await?;
+            self.summary.roles += 1;
+            role_ids.push(role_id);
+        }
+
+        Ok(role_ids)
+    }
+
+    async fn members(&mut self) -> ClientResult<()> {
+        let members = self
+            .client
+            .call(GetGuildMembersRequest::new(self.guild_id))
+            .await?
+            .members;
+
+        for user_id in members {
+            let request = GetProfileRequest { user_id };
+            // Profiles can be missing, for example for users of other
+            // homeservers, which shouldn't stop the export
+            let profile = match self.client.call(request).await {
+                Ok(response) => response.profile,
+                Err(err) => {
+                    tracing::warn!("failed to get profile of member {}: {}", user_id, err);
+                    None
+                }
+            };
+            if let Some(avatar) = profile.as_ref().and_then(|p| p.user_avatar.as_deref()) {
+                self.media(avatar).await?;
+            }
+            let role_ids = self
+                .client
+                .call(GetUserRolesRequest {
+                    guild_id: self.guild_id,
+                    user_id,
+                })
+                .await?
+                .roles;
+            self.write(ArchiveRecord::Member {
+                user_id,
+                profile,
+                role_ids,
+            })
+            .await?;
+            self.summary.members += 1;
+        }
+
+        Ok(())
+    }
+
+    async fn channels(&mut self, role_ids: &[u64]) -> ClientResult<()> {
+        let channels = self
+            .client
+            .call(GetGuildChannelsRequest::new(self.guild_id))
+            .await?
+            .channels;
+
+        for channel in channels {
+            let channel_id = channel.channel_id;
+            let channel = match channel.channel {
+                Some(channel) => channel,
+                None => continue,
+            };
+
+            let mut permissions = Vec::with_capacity(role_ids.len());
+            for role_id in role_ids {
+                permissions.push(RolePermissions {
+                    role_id: *role_id,
+                    permissions: self.permissions(Some(channel_id), *role_id).await?,
+                });
+            }
+            let pinned_message_ids = self
+                .client
+                .call(GetPinnedMessagesRequest {
+                    guild_id: self.guild_id,
+                    channel_id,
+                })
+                .await?
+                .pinned_message_ids;
+
+            self.write(ArchiveRecord::Channel {
+                channel_id,
+                channel,
+                permissions,
+                pinned_message_ids,
+            })
+            .await?;
+            self.summary.channels += 1;
+            self.messages(channel_id).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn messages(&mut self, channel_id: u64) -> ClientResult<()> {
+        // Pages come newest first, but the archive has messages oldest first
+        let mut messages = Vec::new();
+        let mut before = None;
+        loop {
+            let response = self
+                .client
+                .call(GetChannelMessagesRequest {
+                    guild_id: self.guild_id,
+                    channel_id,
+                    message_id: before,
+                    ..Default::default()
+                })
+                .await?;
+            before = response.messages.iter().map(|m| m.message_id).min();
+            messages.extend(response.messages);
+            if response.reached_top || before.is_none() {
+                break;
+            }
+        }
+        messages.sort_by_key(|m| m.message_id);
+
+        for message_with_id in messages {
+            let message = match message_with_id.message {
+                Some(message) => message,
+                None => continue,
+            };
+            for file_id in message_media(&message) {
+                self.media(file_id).await?;
+            }
+            self.write(ArchiveRecord::Message {
+                channel_id,
+                message_id: message_with_id.message_id,
+                message,
+            })
+            .await?;
+            self.summary.messages += 1;
+        }
+
+        Ok(())
+    }
+
+    async fn emote_packs(&mut self) -> ClientResult<()> {
+        let packs = self
+            .client
+            .call(GetEmotePacksRequest::default())
+            .await?
+            .packs;
+
+        for pack in packs {
+            let emotes = self
+                .client
+                .call(GetEmotePackEmotesRequest {
+                    pack_id: pack.pack_id,
+                })
+                .await?
+                .emotes;
+            for emote in &emotes {
+                self.media(&emote.image_id).await?;
+            }
+            self.write(ArchiveRecord::EmotePack { pack, emotes })
+                .await?;
+            self.summary.emote_packs += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Replace the file IDs in a message's content using `media`, dropping files
+/// that aren't in it. Returns the old IDs of dropped files.
+fn remap_message_media(message: &mut Message, media: &HashMap<String, String>) -> Vec<String> {
+    let mut missing = Vec::new();
+    let mut remap = |id: &mut String| match media.get(id.as_str()) {
+        Some(new_id) => {
+            *id = new_id.clone();
+            true
+        }
+        None => {
+            missing.push(id.clone());
+            false
+        }
+    };
+    match message.content.as_mut().and_then(|c| c.content.as_mut()) {
+        Some(content::Content::AttachmentMessage(attachments)) => {
+            attachments.files.retain_mut(|file| remap(&mut file.id))
+        }
+        Some(content::Content::PhotoMessage(photos)) => {
+            photos.photos.retain_mut(|photo| remap(&mut photo.hmc))
+        }
+        _ => {}
+    }
+    missing
+}
+
+struct Importer<'a, S> {
+    /// `None` for dry runs.
+    client: Option<&'a Client>,
+    source: &'a mut S,
+    guild_id: Option<u64>,
+    media: HashMap<String, String>,
+    roles: HashMap<u64, u64>,
+    channels: HashMap<u64, u64>,
+    messages: HashMap<(u64, u64), u64>,
+    profiles: HashMap<u64, Profile>,
+    pins: Vec<(u64, Vec<u64>)>,
+    last_channel_id: Option<u64>,
+    report: ImportReport,
+}
+
+impl<'a, S: ArchiveSource> Importer<'a, S> {
+    fn warn(&mut self, warning: String) {
+        tracing::warn!("guild import: {}", warning);
+        self.report.warnings.push(warning);
+    }
+
+    fn guild_id(&self, record: &str) -> ClientResult<u64> {
+        self.guild_id.ok_or_else(|| {
+            ClientError::invalid_data(
+                InvalidDataKind::Archive,
+                format!("{} record before the guild record", record),
+            )
+        })
+    }
+
+    /// Get the new ID of a file, warning if it isn't in the archive.
+    fn mapped_media(&mut self, file_id: &str, usage: &str) -> Option<String> {
+        let new_id = self.media.get(file_id).cloned();
+        if new_id.is_none() {
+            self.warn(format!("file {} used by {} is missing", file_id, usage));
+        }
+        new_id
+    }
+
+    async fn run(&mut self) -> ClientResult<()> {
+        match self.source.next_record().await? {
+            Some(ArchiveRecord::Header { version, .. }) if version <= ARCHIVE_VERSION => {}
+            Some(ArchiveRecord::Header { version, .. }) => {
+                return Err(ClientError::invalid_data(
+                    InvalidDataKind::Archive,
+                    format!("unsupported archive version {}", version),
+                ))
+            }
+            _ => {
+                return Err(ClientError::invalid_data(
+                    InvalidDataKind::Archive,
+                    "archive doesn't start with a header",
+                ))
+            }
+        }
+
+        let mut ended = false;
+        while let Some(record) = self.source.next_record().await? {
+            match record {
+                ArchiveRecord::Header { .. } => {
+                    return Err(ClientError::invalid_data(
+                        InvalidDataKind::Archive,
+                        "archive has more than one header",
+                    ))
+                }
+                ArchiveRecord::Guild { guild } => self.guild(guild).await?,
+                ArchiveRecord::Role {
+                    role_id,
+                    role,
+                    permissions,
+                } => self.role(role_id, role, permissions).await?,
+                ArchiveRecord::Member {
+                    user_id, profile, ..
+                } => {
+                    if let Some(profile) = profile {
+                        self.profiles.insert(user_id, profile);
+                    }
+                    self.report.members += 1;
+                }
+                ArchiveRecord::Channel {
+                    channel_id,
+                    channel,
+                    permissions,
+                    pinned_message_ids,
+                } => {
+                    self.channel(channel_id, channel, permissions).await?;
+                    self.pins.push((channel_id, pinned_message_ids));
+                }
+                ArchiveRecord::Message {
+                    channel_id,
+                    message_id,
+                    message,
+                } => self.message(channel_id, message_id, message).await?,
+                ArchiveRecord::EmotePack { pack, emotes } => self.emote_pack(pack, emotes).await?,
+                ArchiveRecord::Media {
+                    file_id,
+                    name,
+                    mimetype,
+                    data,
+                } => self.upload(file_id, name, mimetype, data).await?,
+                ArchiveRecord::End => {
+                    ended = true;
+                    break;
+                }
+            }
+        }
+        if !ended {
+            self.warn("archive is truncated".to_string());
+        }
+
+        self.pin_messages().await
+    }
+
+    async fn upload(
+        &mut self,
+        file_id: String,
+        name: String,
+        mimetype: String,
+        data: Vec<u8>,
+    ) -> ClientResult<()> {
+        let new_id = match self.client {
+            Some(client) => client.upload_extract_id(name, mimetype, data).await?,
+            None => file_id.clone(),
+        };
+        self.media.insert(file_id, new_id);
+        self.report.media += 1;
+        Ok(())
+    }
+
+    async fn guild(&mut self, guild: Guild) -> ClientResult<()> {
+        if self.guild_id.is_some() {
+            return Err(ClientError::invalid_data(
+                InvalidDataKind::Archive,
+                "archive has more than one guild record",
+            ));
+        }
+
+        let picture = match &guild.picture {
+            Some(picture) => self.mapped_media(picture, "the guild picture"),
+            None => None,
+        };
+        let guild_id = match self.client {
+            Some(client) => {
+                client
+                    .call(CreateGuildRequest {
+                        name: guild.name,
+                        picture,
+                        metadata: guild.metadata,
+                    })
+                    .await?
+                    .guild_id
+            }
+            None => 0,
+        };
+        self.guild_id = Some(guild_id);
+        Ok(())
+    }
+
+    async fn set_permissions(
+        &self,
+        channel_id: Option<u64>,
+        role_id: u64,
+        permissions: Vec<Permission>,
+    ) -> ClientResult<()> {
+        let client = match self.client {
+            Some(client) if !permissions.is_empty() => client,
+            _ => return Ok(()),
+        };
+        client
+            .call(SetPermissionsRequest {
+                guild_id: self.guild_id("permissions")?,
+                channel_id,
+                role_id,
+                perms_to_give: permissions,
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn role(
+        &mut self,
+        old_id: u64,
+        role: Role,
+        permissions: Vec<Permission>,
+    ) -> ClientResult<()> {
+        let guild_id = self.guild_id("role")?;
+        let role_id = match self.client {
+            Some(client) => {
+                client
+                    .call(AddGuildRoleRequest {
+                        guild_id,
+                        name: role.name,
+                        color: role.color,
+                        hoist: role.hoist,
+                        pingable: role.pingable,
+                    })
+                    .await?
+                    .role_id
+            }
+            None => old_id,
+        };
+        self.set_permissions(None, role_id, permissions).await?;
+        self.roles.insert(old_id, role_id);
+        self.report.roles += 1;
+        Ok(())
+    }
+
+    async fn channel(
+        &mut self,
+        old_id: u64,
+        channel: Channel,
+        permissions: Vec<RolePermissions>,
+    ) -> ClientResult<()> {
+        let guild_id = self.guild_id("channel")?;
+        let channel_id = match self.client {
+            Some(client) => {
+                client
+                    .call(CreateChannelRequest {
+                        guild_id,
+                        channel_name: channel.channel_name,
+                        kind: channel.kind,
+                        metadata: channel.metadata,
+                        position: self.last_channel_id.map(ItemPosition::new_after),
+                    })
+                    .await?
+                    .channel_id
+            }
+            None => old_id,
+        };
+        self.channels.insert(old_id, channel_id);
+        self.last_channel_id = Some(channel_id);
+        self.report.channels += 1;
+
+        for role_permissions in permissions {
+            match self.roles.get(&role_permissions.role_id) {
+                Some(role_id) => {
+                    self.set_permissions(Some(channel_id), *role_id, role_permissions.permissions)
+                        .await?
+                }
+                None => self.warn(format!(
+                    "channel {} has permissions for unknown role {}",
+                    old_id, role_permissions.role_id
+                )),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Overrides that make a message look like it was sent by its original
+    /// author.
+    fn author_overrides(&mut self, message: &mut Message) -> Overrides {
+        let mut overrides = message.overrides.take().unwrap_or_default();
+        let profile = self.profiles.get(&message.author_id);
+        if overrides.username.is_none() {
+            overrides.username = profile.map(|p| p.user_name.clone());
+        }
+        if overrides.avatar.is_none() {
+            overrides.avatar = profile.and_then(|p| p.user_avatar.clone());
+        }
+        if let Some(avatar) = overrides.avatar.take() {
+            let usage = format!("the avatar of user {}", message.author_id);
+            overrides.avatar = self.mapped_media(&avatar, &usage);
+        }
+        if overrides.reason.is_none() {
+            overrides.reason = Some(overrides::Reason::Bridge(Empty {}));
+        }
+        overrides
+    }
+
+    async fn message(
+        &mut self,
+        old_channel_id: u64,
+        old_id: u64,
+        mut message: Message,
+    ) -> ClientResult<()> {
+        let guild_id = self.guild_id("message")?;
+        let channel_id = *self.channels.get(&old_channel_id).ok_or_else(|| {
+            ClientError::invalid_data(
+                InvalidDataKind::Archive,
+                format!(
+                    "message {} is in unknown channel {}",
+                    old_id, old_channel_id
+                ),
+            )
+        })?;
+
+        for file_id in remap_message_media(&mut message, &self.media) {
+            self.warn(format!(
+                "file {} used by message {} is missing",
+                file_id, old_id
+            ));
+        }
+        let overrides = self.author_overrides(&mut message);
+        let in_reply_to = message
+            .in_reply_to
+            .and_then(|id| self.messages.get(&(old_channel_id, id)).copied());
+
+        let message_id = match self.client {
+            Some(client) => {
+                client
+                    .call(SendMessageRequest {
+                        guild_id,
+                        channel_id,
+                        content: message.content,
+                        overrides: Some(overrides),
+                        in_reply_to,
+                        metadata: message.metadata,
+                        ..Default::default()
+                    })
+                    .await?
+                    .message_id
+            }
+            None => old_id,
+        };
+        self.messages.insert((old_channel_id, old_id), message_id);
+        self.report.messages += 1;
+        Ok(())
+    }
+
+    async fn pin_messages(&mut self) -> ClientResult<()> {
+        let guild_id = match self.guild_id {
+            Some(guild_id) => guild_id,
+            None => return Ok(()),
+        };
+
+        for (old_channel_id, message_ids) in std::mem::take(&mut self.pins) {
+            let channel_id = self.channels[&old_channel_id];
+            for old_id in message_ids {
+                let message_id = match self.messages.get(&(old_channel_id, old_id)) {
+                    Some(message_id) => *message_id,
+                    None => {
+                        self.warn(format!("pinned message {} is missing", old_id));
+                        continue;
+                    }
+                };
+                if let Some(client) = self.client {
+                    client
+                        .call(PinMessageRequest {
+                            guild_id,
+                            channel_id,
+                            message_id,
+                        })
+                        .await?;
+                }
+                self.report.pins += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn emote_pack(&mut self, pack: EmotePack, emotes: Vec<Emote>) -> ClientResult<()> {
+        let pack_id = match self.client {
+            Some(client) => {
+                client
+                    .call(CreateEmotePackRequest {
+                        pack_name: pack.pack_name,
+                    })
+                    .await?
+                    .pack_id
+            }
+            None => pack.pack_id,
+        };
+
+        for mut emote in emotes {
+            let usage = format!("emote {}", emote.name);
+            emote.image_id = match self.mapped_media(&emote.image_id, &usage) {
+                Some(image_id) => image_id,
+                None => continue,
+            };
+            if let Some(client) = self.client {
+                client
+                    .call(AddEmoteToPackRequest {
+                        pack_id,
+                        emote: Some(emote),
+                    })
+                    .await?;
+            }
+        }
+        self.report.emote_packs += 1;
+
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Export a guild to an archive: the guild, its roles and their
+    /// permissions, members with their profiles and roles, channels with
+    /// their permissions, pins and message history, and the emote packs of
+    /// the current user. Files referenced by these are downloaded into the
+    /// archive.
+    ///
+    /// Files that can't be downloaded are skipped and listed in the returned
+    /// summary; any other error aborts the export.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use harmony_rust_sdk::client::{*, guild_archive::JsonLinesWriter};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> error::ClientResult<()> {
+    /// # let client = Client::new("chat.harmonyapp.io:2289".parse().unwrap(), None).await?;
+    /// let file = tokio::fs::File::create("guild.jsonl").await?;
+    /// let mut sink = JsonLinesWriter::new(file);
+    /// let summary = client.export_guild(1234, &mut sink).await?;
+    /// sink.finish().await?;
+    /// println!("exported {} messages", summary.messages);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn export_guild<S: ArchiveSink>(
+        &self,
+        guild_id: u64,
+        sink: &mut S,
+    ) -> ClientResult<ExportSummary> {
+        let mut exporter = Exporter {
+            client: self,
+            sink,
+            guild_id,
+            downloaded: HashSet::new(),
+            summary: ExportSummary::default(),
+        };
+
+        exporter
+            .write(ArchiveRecord::Header {
+                version: ARCHIVE_VERSION,
+                guild_id,
+                homeserver: self.homeserver_url().to_string(),
+                exported_at: unix_time(),
+            })
+            .await?;
+        exporter.guild().await?;
+        let role_ids = exporter.roles().await?;
+        exporter.members().await?;
+        exporter.channels(&role_ids).await?;
+        exporter.emote_packs().await?;
+        exporter.write(ArchiveRecord::End).await?;
+
+        Ok(exporter.summary)
+    }
+
+    /// Recreate a guild from an archive written by [`Client::export_guild`],
+    /// possibly on a different homeserver.
+    ///
+    /// The guild, its roles and their permissions, channels in order with
+    /// their permissions, and emote packs are created, and files are
+    /// re-uploaded. Messages are replayed with [`Overrides`] that carry their
+    /// original author's name and avatar, and pins are restored afterwards.
+    /// Members and their roles can't be restored, and replayed messages have
+    /// new timestamps.
+    ///
+    /// Use [`Client::dry_run_import_guild`] to check an archive first.
+    pub async fn import_guild<S: ArchiveSource>(
+        &self,
+        source: &mut S,
+    ) -> ClientResult<ImportReport> {
+        import_guild_with(Some(self), source).await
+    }
+
+    /// Read an archive like [`Client::import_guild`] without making any
+    /// requests, reporting what would be created and what can't be restored.
+    pub async fn dry_run_import_guild<S: ArchiveSource>(
+        &self,
+        source: &mut S,
+    ) -> ClientResult<ImportReport> {
+        import_guild_with(None, source).await
+    }
+}
+
+async fn import_guild_with<S: ArchiveSource>(
+    client: Option<&Client>,
+    source: &mut S,
+) -> ClientResult<ImportReport> {
+    let mut importer = Importer {
+        client,
+        source,
+        guild_id: None,
+        media: HashMap::new(),
+        roles: HashMap::new(),
+        channels: HashMap::new(),
+        messages: HashMap::new(),
+        profiles: HashMap::new(),
+        pins: Vec::new(),
+        last_channel_id: None,
+        report: ImportReport::default(),
+    };
+    importer.run().await?;
+
+    let mut report = importer.report;
+    if client.is_some() {
+        report.guild_id = importer.guild_id;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use hrpc::exports::futures_util::FutureExt;
+
+    use super::*;
+    use crate::api::chat::Attachment;
+
+    #[test]
+    fn records_roundtrip_as_json() {
+        let records = vec![
+            ArchiveRecord::Header {
+                version: ARCHIVE_VERSION,
+                guild_id: 1,
+                homeserver: "https://example.org:2289/".to_string(),
+                exported_at: 1000,
+            },
+            ArchiveRecord::Media {
+                file_id: "abc".to_string(),
+                name: "cat.png".to_string(),
+                mimetype: "image/png".to_string(),
+                data: vec![0, 1, 2, 255],
+            },
+            ArchiveRecord::End,
+        ];
+
+        for record in records {
+            let json = serde_json::to_string(&record).unwrap();
+            assert!(!json.contains('\n'));
+            assert_eq!(
+                serde_json::from_str::<ArchiveRecord>(&json).unwrap(),
+                record
+            );
+        }
+    }
+
+    #[test]
+    fn media_is_base64() {
+        let record = ArchiveRecord::Media {
+            file_id: "abc".to_string(),
+            name: "a".to_string(),
+            mimetype: "text/plain".to_string(),
+            data: b"hi".to_vec(),
+        };
+        let json = serde_json::to_value(&record).unwrap();
+
+        assert_eq!(json["type"], "media");
+        assert_eq!(json["data"], "aGk=");
+    }
+
+    #[test]
+    fn json_lines_roundtrip() {
+        let records = vec![
+            ArchiveRecord::Header {
+                version: ARCHIVE_VERSION,
+                guild_id: 1,
+                homeserver: "https://example.org:2289/".to_string(),
+                exported_at: 1000,
+            },
+            ArchiveRecord::End,
+        ];
+
+        let mut writer = JsonLinesWriter::new(Vec::new());
+        for record in &records {
+            writer.write_record(record).now_or_never().unwrap().unwrap();
+        }
+        let data = writer.finish().now_or_never().unwrap().unwrap();
+
+        let mut reader = JsonLinesReader::new(data.as_slice());
+        let mut read = Vec::new();
+        while let Some(record) = reader.next_record().now_or_never().unwrap().unwrap() {
+            read.push(record);
+        }
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn malformed_line_is_invalid_archive() {
+        let mut reader = JsonLinesReader::new(b"{\"type\":\"nope\"}\n".as_slice());
+        let result = reader.next_record().now_or_never().unwrap();
+
+        assert!(matches!(
+            result,
+            Err(ClientError::InvalidData {
+                kind: InvalidDataKind::Archive,
+                ..
+            })
+        ));
+    }
+
+    fn dry_run(records: Vec<ArchiveRecord>) -> ClientResult<ImportReport> {
+        import_guild_with(None, &mut records.into_iter())
+            .now_or_never()
+            .unwrap()
+    }
+
+    fn header(version: u32) -> ArchiveRecord {
+        ArchiveRecord::Header {
+            version,
+            guild_id: 1,
+            homeserver: "https://example.org:2289/".to_string(),
+            exported_at: 1000,
+        }
+    }
+
+    fn error_message(result: ClientResult<ImportReport>) -> String {
+        match result {
+            Err(ClientError::InvalidData {
+                kind: InvalidDataKind::Archive,
+                message,
+            }) => message,
+            other => panic!("expected an invalid archive error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dry_run_rejects_bad_archives() {
+        assert_eq!(
+            error_message(dry_run(vec![ArchiveRecord::End])),
+            "archive doesn't start with a header"
+        );
+        assert_eq!(
+            error_message(dry_run(vec![header(ARCHIVE_VERSION + 1)])),
+            format!("unsupported archive version {}", ARCHIVE_VERSION + 1)
+        );
+        let channel = ArchiveRecord::Channel {
+            channel_id: 2,
+            channel: Channel::default(),
+            permissions: Vec::new(),
+            pinned_message_ids: Vec::new(),
+        };
+        assert_eq!(
+            error_message(dry_run(vec![header(ARCHIVE_VERSION), channel])),
+            "channel record before the guild record"
+        );
+    }
+
+    #[test]
+    fn dry_run_reports_missing_media_and_remaps_pins() {
+        let records = vec![
+            header(ARCHIVE_VERSION),
+            ArchiveRecord::Guild {
+                guild: Guild {
+                    name: "guild".to_string(),
+                    picture: Some("picture".to_string()),
+                    ..Default::default()
+                },
+            },
+            ArchiveRecord::Channel {
+                channel_id: 2,
+                channel: Channel {
+                    channel_name: "general".to_string(),
+                    ..Default::default()
+                },
+                permissions: Vec::new(),
+                pinned_message_ids: vec![10, 11],
+            },
+            ArchiveRecord::Message {
+                channel_id: 2,
+                message_id: 10,
+                message: Message::default(),
+            },
+            ArchiveRecord::End,
+        ];
+
+        let report = dry_run(records).unwrap();
+        assert_eq!(report.guild_id, None);
+        assert_eq!((report.channels, report.messages), (1, 1));
+        assert_eq!(report.pins, 1);
+        assert_eq!(
+            report.warnings,
+            [
+                "file picture used by the guild picture is missing",
+                "pinned message 11 is missing",
+            ]
+        );
+    }
+
+    #[test]
+    fn message_media_is_remapped() {
+        let attachment = |id: &str| Attachment {
+            id: id.to_string(),
+            ..Default::default()
+        };
+        let mut message = Message {
+            content: SendMessageRequest::default()
+                .with_attachment_content(vec![attachment("old"), attachment("gone")])
+                .content,
+            ..Default::default()
+        };
+        let media = HashMap::from([("old".to_string(), "new".to_string())]);
+
+        let missing = remap_message_media(&mut message, &media);
+
+        assert_eq!(missing, vec!["gone".to_string()]);
+        assert_eq!(message_media(&message), vec!["new"]);
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{error::ClientResult, Client};
+use crate::api::{
+    chat::{embed, Embed, FormattedText},
+    mediaproxy::{fetch_link_metadata_response::Data, FetchLinkMetadataRequest, SiteMetadata},
+};
+
+/// Metadata of a link, as returned by the media proxy.
+pub type LinkMetadata = Data;
+
+/// Default amount of time fetched link metadata is cached for.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Fetches and caches link metadata through the media proxy service, and
+/// turns it into [`Embed`]s.
+#[derive(Debug)]
+pub struct LinkPreviewer {
+    client: Client,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, (Instant, Option<LinkMetadata>)>>,
+}
+
+impl LinkPreviewer {
+    /// Create a new link previewer that uses the given client.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            ttl: DEFAULT_TTL,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set how long fetched link metadata will be cached for.
+    ///
+    /// Links that failed to be fetched are also cached for this duration.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Fetch metadata of the given URLs, using cached metadata if possible.
+    ///
+    /// URLs that the media proxy failed to fetch are not included in the
+    /// returned map.
+    pub async fn fetch<Url: AsRef<str>>(
+        &self,
+        urls: impl IntoIterator<Item = Url>,
+    ) -> ClientResult<HashMap<String, LinkMetadata>> {
+        let mut fetched = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let cache = self.cache.lock().expect("poisoned");
+            for url in urls {
+                let url = url.as_ref();
+                match cache.get(url) {
+                    Some((fetched_at, metadata)) if fetched_at.elapsed() < self.ttl => {
+                        if let Some(metadata) = metadata {
+                            fetched.insert(url.to_string(), metadata.clone());
+                        }
+                    }
+                    _ => {
+                        if !missing.iter().any(|m| m == url) {
+                            missing.push(url.to_string());
+                        }
+                    }
+                }
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok(fetched);
+        }
+
+        let results = self.fetch_uncached(&missing).await;
+        let now = Instant::now();
+        let mut cache = self.cache.lock().expect("poisoned");
+        for (url, metadata) in missing.into_iter().zip(results) {
+            if let Some(metadata) = metadata.as_ref() {
+                fetched.insert(url.clone(), metadata.clone());
+            }
+            cache.insert(url, (now, metadata));
+        }
+
+        Ok(fetched)
+    }
+
+    /// Fetch metadata of all URLs in the given text, and create [`Embed`]s
+    /// for the ones that are websites.
+    ///
+    /// The embeds are in the same order the URLs appear in the text.
+    pub async fn preview(&self, text: &FormattedText) -> ClientResult<Vec<Embed>> {
+        let urls = extract_urls(text);
+        let mut metadata = self.fetch(&urls).await?;
+
+        Ok(urls
+            .into_iter()
+            .filter_map(|url| match metadata.remove(url)? {
+                Data::IsSite(site) => Some(site_to_embed(&site)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Remove all expired entries from the cache.
+    pub fn clear_expired(&self) {
+        let ttl = self.ttl;
+        self.cache
+            .lock()
+            .expect("poisoned")
+            .retain(|_, (fetched_at, _)| fetched_at.elapsed() < ttl);
+    }
+
+    /// Fetch metadata for all URLs, returning `None` for the ones that failed.
+    async fn fetch_uncached(&self, urls: &[String]) -> Vec<Option<LinkMetadata>> {
+        let requests = urls
+            .iter()
+            .cloned()
+            .map(FetchLinkMetadataRequest::new)
+            .collect::<Vec<_>>();
+
+        #[cfg(feature = "gen_batch")]
+        if requests.len() > 1 {
+            match self.client.batch_call(requests.clone()).await {
+                Ok(responses) => return responses.into_iter().map(|r| r.data).collect(),
+                // A single failing URL fails the whole batch, so retry one by one
+                Err(err) => tracing::debug!("batched link metadata fetch failed: {}", err),
+            }
+        }
+
+        let responses = requests.into_iter().map(|request| {
+            let fut = self.client.call(request);
+            async move {
+                match fut.await {
+                    Ok(response) => response.data,
+                    Err(err) => {
+                        tracing::debug!("failed to fetch link metadata: {}", err);
+                        None
+                    }
+                }
+            }
+        });
+        hrpc::exports::futures_util::future::join_all(responses).await
+    }
+}
+
+/// Create an [`Embed`] from site metadata, suitable to be used with
+/// [`SendMessageRequest::with_embed_content()`].
+///
+/// [`SendMessageRequest::with_embed_content()`]: crate::api::chat::SendMessageRequest::with_embed_content
+pub fn site_to_embed(site: &SiteMetadata) -> Embed {
+    let title = if site.page_title.is_empty() {
+        site.url.clone()
+    } else {
+        site.page_title.clone()
+    };
+
+    Embed {
+        title,
+        body: (!site.description.is_empty()).then(|| site.description.clone().into()),
+        header: (!site.site_title.is_empty()).then(|| embed::EmbedHeading {
+            text: site.site_title.clone(),
+            url: (!site.url.is_empty()).then(|| site.url.clone()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Extract all `http` and `https` URLs from a formatted text.
+pub fn extract_urls(text: &FormattedText) -> Vec<&str> {
+    find_urls(&text.text)
+}
+
+fn find_urls(text: &str) -> Vec<&str> {
+    const TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', '\'', '"', '*', '_', '~'];
+
+    let mut urls = Vec::new();
+    for word in text.split(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"')) {
+        let start = match (word.find("https://"), word.find("http://")) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => continue,
+        };
+        // Only accept URLs at the start of a word, or after an opening bracket
+        if start > 0 && !word[..start].ends_with(&['(', '[', '{', '\''][..]) {
+            continue;
+        }
+
+        let mut url = &word[start..];
+        loop {
+            let trimmed = url.trim_end_matches(TRAILING);
+            // Keep closing brackets that belong to the URL itself, like in wikipedia links
+            let trimmed = match trimmed.chars().last() {
+                Some(close @ (')' | ']' | '}')) => {
+                    let open = match close {
+                        ')' => '(',
+                        ']' => '[',
+                        _ => '{',
+                    };
+                    let opens = trimmed.matches(open).count();
+                    let closes = trimmed.matches(close).count();
+                    if closes > opens {
+                        &trimmed[..trimmed.len() - 1]
+                    } else {
+                        trimmed
+                    }
+                }
+                _ => trimmed,
+            };
+            if trimmed.len() == url.len() {
+                break;
+            }
+            url = trimmed;
+        }
+
+        if matches!(url.split_once("://"), Some((_, rest)) if !rest.is_empty()) {
+            urls.push(url);
+        }
+    }
+
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_urls;
+
+    #[test]
+    fn finds_urls() {
+        let text = "look at https://harmonyapp.io, and http://example.org/a?b=c!";
+        assert_eq!(
+            find_urls(text),
+            vec!["https://harmonyapp.io", "http://example.org/a?b=c"]
+        );
+    }
+
+    #[test]
+    fn handles_brackets() {
+        let text = "(see https://en.wikipedia.org/wiki/Rust_(programming_language))";
+        assert_eq!(
+            find_urls(text),
+            vec!["https://en.wikipedia.org/wiki/Rust_(programming_language)"]
+        );
+    }
+
+    #[test]
+    fn ignores_non_urls() {
+        assert!(find_urls("https:// is not a url, nor is foohttps://example.org").is_empty());
+    }
+}
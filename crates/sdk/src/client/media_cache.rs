@@ -0,0 +1,397 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use http::Uri;
+use prost::bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    error::ClientResult,
+    rest::{DownloadedFile, FileInfo},
+    Client,
+};
+use crate::api::rest::{FileId, FileKind};
+
+/// Extension used for metadata sidecar files.
+const META_EXT: &str = "meta.json";
+
+/// Creates a canonical cache key for a file ID.
+///
+/// [`FileId::Id`]s are resolved against the given homeserver URL, so that
+/// they produce the same key as a [`FileId::Hmc`] pointing to the same file.
+///
+/// # Example
+/// ```
+/// # use harmony_rust_sdk::{api::{rest::FileId, Hmc}, client::media_cache::cache_key};
+/// let homeserver = "https://chat.harmonyapp.io:2289".parse().unwrap();
+/// let id = FileId::Id("403cb46c".to_string());
+/// let hmc = FileId::Hmc(Hmc::new("chat.harmonyapp.io", "403cb46c").unwrap());
+/// assert_eq!(cache_key(&id, &homeserver), cache_key(&hmc, &homeserver));
+/// ```
+pub fn cache_key(file_id: &FileId, homeserver_url: &Uri) -> String {
+    match file_id {
+        FileId::Hmc(hmc) => format!("hmc://{}:{}/{}", hmc.server(), hmc.port(), hmc.id()),
+        FileId::Id(id) => format!(
+            "hmc://{}:{}/{}",
+            homeserver_url.host().unwrap_or_default(),
+            homeserver_url.port_u16().unwrap_or(2289),
+            id
+        ),
+        FileId::External(uri) => uri.to_string(),
+    }
+}
+
+/// Metadata stored next to every cached file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Sidecar {
+    key: String,
+    name: String,
+    mimetype: String,
+    inline: bool,
+    size: u64,
+    last_access: u64,
+}
+
+impl Sidecar {
+    fn info(&self) -> FileInfo {
+        FileInfo {
+            name: self.name.clone(),
+            mimetype: self.mimetype.clone(),
+            kind: if self.inline {
+                FileKind::Inline
+            } else {
+                FileKind::Attachment
+            },
+        }
+    }
+}
+
+/// Size-bounded least recently used index over cached entries.
+#[derive(Debug, Default)]
+struct LruIndex {
+    entries: HashMap<String, (u64, u64)>,
+    total_size: u64,
+    clock: u64,
+}
+
+impl LruIndex {
+    /// Returns a timestamp that is always bigger than the previous one.
+    fn tick(&mut self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        self.clock = now.max(self.clock + 1);
+        self.clock
+    }
+
+    fn insert(&mut self, hash: String, size: u64, last_access: u64) {
+        self.clock = self.clock.max(last_access);
+        if let Some((old_size, _)) = self.entries.insert(hash, (size, last_access)) {
+            self.total_size -= old_size;
+        }
+        self.total_size += size;
+    }
+
+    fn touch(&mut self, hash: &str) -> Option<u64> {
+        let now = self.tick();
+        let (_, last_access) = self.entries.get_mut(hash)?;
+        *last_access = now;
+        Some(now)
+    }
+
+    fn remove(&mut self, hash: &str) {
+        if let Some((size, _)) = self.entries.remove(hash) {
+            self.total_size -= size;
+        }
+    }
+
+    /// Removes least recently used entries until the total size fits in
+    /// `max_size`, returning the removed entries.
+    fn evict(&mut self, max_size: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.total_size > max_size {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_access))| *last_access)
+                .map(|(hash, _)| hash.clone());
+            match oldest {
+                Some(hash) => {
+                    self.remove(&hash);
+                    evicted.push(hash);
+                }
+                None => break,
+            }
+        }
+        evicted
+    }
+}
+
+/// An on-disk, content-addressed media cache with a size limit.
+///
+/// Files are stored under the SHA-256 hash of their [`cache_key`], along with
+/// a metadata sidecar file containing their name, mimetype and file kind.
+/// Least recently used files are evicted when the cache grows over its
+/// maximum size.
+#[derive(Debug)]
+pub struct MediaCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<LruIndex>,
+}
+
+impl MediaCache {
+    /// Open a media cache in the given directory, creating it if it doesn't
+    /// exist. `max_size` is the maximum total size of cached files in bytes.
+    pub async fn open(dir: impl Into<PathBuf>, max_size: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let mut index = LruIndex::default();
+        let mut read_dir = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let file_name = entry.file_name();
+            let hash = match file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(META_EXT))
+                .and_then(|name| name.strip_suffix('.'))
+            {
+                Some(hash) => hash.to_string(),
+                None => continue,
+            };
+            match read_sidecar(&entry.path()).await {
+                Some(sidecar) => index.insert(hash, sidecar.size, sidecar.last_access),
+                None => {
+                    tracing::warn!("removing invalid media cache entry {}", hash);
+                    remove_entry_files(&dir, &hash).await;
+                }
+            }
+        }
+
+        let cache = Self {
+            dir,
+            max_size,
+            index: Mutex::new(index),
+        };
+        cache.evict().await;
+
+        Ok(cache)
+    }
+
+    /// Get the total size of cached files in bytes.
+    pub fn size(&self) -> u64 {
+        self.index.lock().expect("poisoned").total_size
+    }
+
+    /// Get the maximum total size of cached files in bytes.
+    #[inline(always)]
+    pub fn max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    /// Get a cached file using its cache key.
+    pub async fn get(&self, key: &str) -> io::Result<Option<DownloadedFile>> {
+        let hash = hash_key(key);
+        let touched = self.index.lock().expect("poisoned").touch(&hash);
+        let last_access = match touched {
+            Some(last_access) => last_access,
+            None => return Ok(None),
+        };
+
+        let meta_path = self.meta_path(&hash);
+        let mut sidecar = match read_sidecar(&meta_path).await {
+            // Guard against hash collisions
+            Some(sidecar) if sidecar.key == key => sidecar,
+            _ => return Ok(None),
+        };
+        let data = match tokio::fs::read(self.data_path(&hash)).await {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.index.lock().expect("poisoned").remove(&hash);
+                remove_entry_files(&self.dir, &hash).await;
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        };
+
+        sidecar.last_access = last_access;
+        write_sidecar(&meta_path, &sidecar).await?;
+
+        let FileInfo {
+            name,
+            mimetype,
+            kind,
+        } = sidecar.info();
+        Ok(Some(DownloadedFile {
+            data: Bytes::from(data),
+            mimetype,
+            kind,
+            name,
+        }))
+    }
+
+    /// Insert a file into the cache with the given cache key.
+    ///
+    /// Files bigger than the maximum size of the cache won't be stored.
+    pub async fn insert(&self, key: &str, file: &DownloadedFile) -> io::Result<()> {
+        let size = file.data.len() as u64;
+        if size > self.max_size {
+            return Ok(());
+        }
+
+        let hash = hash_key(key);
+        let last_access = self.index.lock().expect("poisoned").tick();
+        let sidecar = Sidecar {
+            key: key.to_string(),
+            name: file.name.clone(),
+            mimetype: file.mimetype.clone(),
+            inline: matches!(file.kind, FileKind::Inline),
+            size,
+            last_access,
+        };
+
+        tokio::fs::write(self.data_path(&hash), &file.data).await?;
+        write_sidecar(&self.meta_path(&hash), &sidecar).await?;
+
+        self.index
+            .lock()
+            .expect("poisoned")
+            .insert(hash, size, last_access);
+        self.evict().await;
+
+        Ok(())
+    }
+
+    /// Remove a file from the cache using its cache key.
+    pub async fn remove(&self, key: &str) {
+        let hash = hash_key(key);
+        self.index.lock().expect("poisoned").remove(&hash);
+        remove_entry_files(&self.dir, &hash).await;
+    }
+
+    async fn evict(&self) {
+        let evicted = self.index.lock().expect("poisoned").evict(self.max_size);
+        for hash in evicted {
+            tracing::debug!("evicting media cache entry {}", hash);
+            remove_entry_files(&self.dir, &hash).await;
+        }
+    }
+
+    fn data_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(hash)
+    }
+
+    fn meta_path(&self, hash: &str) -> PathBuf {
+        meta_path(&self.dir, hash)
+    }
+}
+
+impl Client {
+    /// Downloads a file using the given [`MediaCache`], only fetching it from
+    /// the network if it isn't cached.
+    ///
+    /// Errors that occur while reading or writing the cache are logged and
+    /// otherwise ignored.
+    ///
+    /// Also see [`Client::download_extract_file()`].
+    pub async fn download_cached(
+        &self,
+        cache: &MediaCache,
+        file_id: impl Into<FileId>,
+    ) -> ClientResult<DownloadedFile> {
+        let file_id = file_id.into();
+        let key = cache_key(&file_id, self.homeserver_url());
+
+        match cache.get(&key).await {
+            Ok(Some(file)) => return Ok(file),
+            Ok(None) => {}
+            Err(err) => tracing::warn!("failed to read {} from media cache: {}", key, err),
+        }
+
+        let file = self.download_extract_file(file_id).await?;
+        if let Err(err) = cache.insert(&key, &file).await {
+            tracing::warn!("failed to write {} to media cache: {}", key, err);
+        }
+
+        Ok(file)
+    }
+}
+
+fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn meta_path(dir: &Path, hash: &str) -> PathBuf {
+    dir.join(format!("{}.{}", hash, META_EXT))
+}
+
+async fn read_sidecar(path: &Path) -> Option<Sidecar> {
+    let raw = tokio::fs::read(path).await.ok()?;
+    serde_json::from_slice(&raw).ok()
+}
+
+async fn write_sidecar(path: &Path, sidecar: &Sidecar) -> io::Result<()> {
+    let raw = serde_json::to_vec(sidecar).map_err(io::Error::from)?;
+    tokio::fs::write(path, raw).await
+}
+
+async fn remove_entry_files(dir: &Path, hash: &str) {
+    // Files might already be missing, which is fine
+    let _ = tokio::fs::remove_file(dir.join(hash)).await;
+    let _ = tokio::fs::remove_file(meta_path(dir, hash)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Hmc;
+
+    #[test]
+    fn key_normalizes_default_port() {
+        let homeserver = Uri::from_static("https://chat.harmonyapp.io:2289");
+        let with_port = FileId::Hmc(Hmc::new("chat.harmonyapp.io:2289", "abc").unwrap());
+        let without_port = FileId::Hmc(Hmc::new("chat.harmonyapp.io", "abc").unwrap());
+        assert_eq!(
+            cache_key(&with_port, &homeserver),
+            cache_key(&without_port, &homeserver)
+        );
+    }
+
+    #[test]
+    fn key_resolves_id_against_homeserver() {
+        let homeserver = Uri::from_static("https://example.org:2290");
+        let id = FileId::Id("abc".to_string());
+        assert_eq!(cache_key(&id, &homeserver), "hmc://example.org:2290/abc");
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut index = LruIndex::default();
+        index.insert("a".to_string(), 10, 1);
+        index.insert("b".to_string(), 10, 2);
+        index.insert("c".to_string(), 10, 3);
+        index.touch("a");
+
+        assert_eq!(index.evict(20), vec!["b".to_string()]);
+        assert_eq!(index.total_size, 20);
+        assert_eq!(index.evict(5), vec!["c".to_string(), "a".to_string()]);
+        assert_eq!(index.total_size, 0);
+    }
+
+    #[test]
+    fn reinsert_replaces_size() {
+        let mut index = LruIndex::default();
+        index.insert("a".to_string(), 10, 1);
+        index.insert("a".to_string(), 4, 2);
+        assert_eq!(index.total_size, 4);
+    }
+}
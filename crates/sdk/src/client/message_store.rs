@@ -0,0 +1,617 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use prost::{
+    encoding::{decode_varint, encode_varint},
+    Message as _,
+};
+
+use super::{
+    error::{ClientError, ClientResult},
+    Client,
+};
+use crate::api::chat::{
+    content, stream_event, Content, Event, FormattedText, GetChannelMessagesRequest, Message,
+    MessageWithId,
+};
+
+/// Version of the format [`MessageStore`] persists messages in.
+const FORMAT_VERSION: u8 = 1;
+
+/// Kind of content a message has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentKind {
+    /// Text content.
+    Text,
+    /// Photo content.
+    Photo,
+    /// Attachment content.
+    Attachment,
+    /// Embed content.
+    Embed,
+    /// Content describing a guild event, such as an invite being accepted.
+    Event,
+}
+
+impl ContentKind {
+    /// Get the kind of content a message has, or `None` if it has no content.
+    pub fn of(message: &Message) -> Option<Self> {
+        message.get_content().map(|content| match content {
+            content::Content::TextMessage(_) => ContentKind::Text,
+            content::Content::PhotoMessage(_) => ContentKind::Photo,
+            content::Content::AttachmentMessage(_) => ContentKind::Attachment,
+            content::Content::EmbedMessage(_) => ContentKind::Embed,
+            content::Content::InviteRejected(_)
+            | content::Content::InviteAccepted(_)
+            | content::Content::RoomUpgradedToGuild(_) => ContentKind::Event,
+        })
+    }
+}
+
+/// A message in a [`MessageStore`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredMessage {
+    /// Guild ID of the message.
+    pub guild_id: u64,
+    /// Channel ID of the message.
+    pub channel_id: u64,
+    /// ID of the message.
+    pub message_id: u64,
+    /// The message.
+    pub message: Message,
+}
+
+/// A search over the messages in a [`MessageStore`].
+///
+/// All words of the text must appear in a message for it to match, and the
+/// last word also matches words that start with it. Messages are matched
+/// against their text content, embed titles and bodies, and photo and
+/// attachment names.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    text: String,
+    guild_id: Option<u64>,
+    channel_id: Option<u64>,
+    author_id: Option<u64>,
+    after: Option<u64>,
+    before: Option<u64>,
+    kind: Option<ContentKind>,
+    limit: Option<usize>,
+}
+
+impl Query {
+    /// Create a new query that searches for the given text. If the text is
+    /// empty, all messages that pass the filters match.
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Only match messages in the given guild.
+    pub fn with_guild(mut self, guild_id: u64) -> Self {
+        self.guild_id = Some(guild_id);
+        self
+    }
+
+    /// Only match messages in the given channel.
+    pub fn with_channel(mut self, guild_id: u64, channel_id: u64) -> Self {
+        self.guild_id = Some(guild_id);
+        self.channel_id = Some(channel_id);
+        self
+    }
+
+    /// Only match messages sent by the given user.
+    pub fn with_author(mut self, author_id: u64) -> Self {
+        self.author_id = Some(author_id);
+        self
+    }
+
+    /// Only match messages created at or after the given UNIX timestamp.
+    pub fn with_after(mut self, after: u64) -> Self {
+        self.after = Some(after);
+        self
+    }
+
+    /// Only match messages created before the given UNIX timestamp.
+    pub fn with_before(mut self, before: u64) -> Self {
+        self.before = Some(before);
+        self
+    }
+
+    /// Only match messages with the given kind of content.
+    pub fn with_kind(mut self, kind: ContentKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Return at most this many messages.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    // `Option::is_none_or` is too new for the Rust versions this crate
+    // supports
+    #[allow(clippy::unnecessary_map_or)]
+    fn matches(&self, key: &Key, message: &Message) -> bool {
+        self.guild_id.map_or(true, |id| id == key.0)
+            && self.channel_id.map_or(true, |id| id == key.1)
+            && self.author_id.map_or(true, |id| id == message.author_id)
+            && self.after.map_or(true, |at| message.created_at >= at)
+            && self.before.map_or(true, |at| message.created_at < at)
+            && self
+                .kind
+                .map_or(true, |kind| ContentKind::of(message) == Some(kind))
+    }
+}
+
+/// Splits text into lowercase words.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Collects the searchable text of a message.
+fn searchable_text(message: &Message) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    match message.get_content() {
+        Some(content::Content::TextMessage(text)) => {
+            parts.extend(text.content.as_ref().map(|text| text.text.as_str()));
+        }
+        Some(content::Content::EmbedMessage(embed)) => {
+            for embed in &embed.embeds {
+                parts.push(&embed.title);
+                parts.extend(embed.body.as_ref().map(|body| body.text.as_str()));
+            }
+        }
+        Some(content::Content::PhotoMessage(photo)) => {
+            parts.extend(photo.photos.iter().map(|photo| photo.name.as_str()));
+        }
+        Some(content::Content::AttachmentMessage(attachment)) => {
+            parts.extend(attachment.files.iter().map(|file| file.name.as_str()));
+        }
+        _ => {}
+    }
+    parts.join(" ")
+}
+
+/// Guild ID, channel ID and message ID of a message.
+type Key = (u64, u64, u64);
+
+#[derive(Debug, Default)]
+struct Index {
+    messages: HashMap<Key, Message>,
+    words: BTreeMap<String, HashSet<Key>>,
+}
+
+impl Index {
+    fn insert(&mut self, key: Key, message: Message) {
+        self.remove(&key);
+        for word in words(&searchable_text(&message)) {
+            self.words.entry(word).or_default().insert(key);
+        }
+        self.messages.insert(key, message);
+    }
+
+    fn remove(&mut self, key: &Key) -> Option<Message> {
+        let message = self.messages.remove(key)?;
+        for word in words(&searchable_text(&message)) {
+            if let Some(keys) = self.words.get_mut(&word) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+        Some(message)
+    }
+
+    /// Get the messages containing a word, or a word starting with it.
+    fn matching(&self, word: &str, prefix: bool) -> HashSet<Key> {
+        if !prefix {
+            return self.words.get(word).cloned().unwrap_or_default();
+        }
+        self.words
+            .range(word.to_string()..)
+            .take_while(|(indexed, _)| indexed.starts_with(word))
+            .flat_map(|(_, keys)| keys.iter().copied())
+            .collect()
+    }
+
+    fn search(&self, query: &Query) -> Vec<StoredMessage> {
+        let words = words(&query.text).collect::<Vec<_>>();
+        let candidates = match words.split_last() {
+            None => self.messages.keys().copied().collect(),
+            Some((last, rest)) => rest.iter().fold(self.matching(last, true), |keys, word| {
+                let matching = self.matching(word, false);
+                keys.intersection(&matching).copied().collect()
+            }),
+        };
+
+        let mut results = candidates
+            .into_iter()
+            .filter_map(|key| Some((key, self.messages.get(&key)?)))
+            .filter(|(key, message)| query.matches(key, message))
+            .collect::<Vec<_>>();
+        results.sort_by_key(|(key, message)| Reverse((message.created_at, key.2)));
+        results
+            .into_iter()
+            .take(query.limit.unwrap_or(usize::MAX))
+            .map(
+                |((guild_id, channel_id, message_id), message)| StoredMessage {
+                    guild_id,
+                    channel_id,
+                    message_id,
+                    message: message.clone(),
+                },
+            )
+            .collect()
+    }
+}
+
+fn encode(messages: &HashMap<Key, Message>) -> Vec<u8> {
+    let mut buf = vec![FORMAT_VERSION];
+    for ((guild_id, channel_id, message_id), message) in messages {
+        encode_varint(*guild_id, &mut buf);
+        encode_varint(*channel_id, &mut buf);
+        encode_varint(*message_id, &mut buf);
+        message
+            .encode_length_delimited(&mut buf)
+            .expect("vec has enough capacity");
+    }
+    buf
+}
+
+fn decode(mut buf: &[u8]) -> ClientResult<Index> {
+    let mut index = Index::default();
+    match buf.split_first() {
+        None => return Ok(index),
+        Some((&FORMAT_VERSION, rest)) => buf = rest,
+        Some((version, _)) => {
+            return Err(ClientError::unexpected(format!(
+                "unknown message store format version {}",
+                version
+            )))
+        }
+    }
+
+    while !buf.is_empty() {
+        let key = (
+            decode_varint(&mut buf)?,
+            decode_varint(&mut buf)?,
+            decode_varint(&mut buf)?,
+        );
+        let message = Message::decode_length_delimited(&mut buf)?;
+        index.insert(key, message);
+    }
+
+    Ok(index)
+}
+
+/// A local store of messages with a full-text index, for searching message
+/// history without fetching it from the homeserver.
+///
+/// The store is filled from history fetched with
+/// [`MessageStore::fetch_history`] and kept up to date by passing events to
+/// [`MessageStore::handle_event`]. It's kept in memory, and written to its
+/// file by [`MessageStore::save`].
+#[derive(Debug)]
+pub struct MessageStore {
+    path: Option<PathBuf>,
+    index: Mutex<Index>,
+}
+
+impl Default for MessageStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageStore {
+    /// Create a new store that is only kept in memory.
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            index: Mutex::new(Index::default()),
+        }
+    }
+
+    /// Open a store kept in the file at `path`, loading the messages saved in
+    /// it if it exists.
+    pub async fn open(path: impl AsRef<Path>) -> ClientResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let index = match tokio::fs::read(&path).await {
+            Ok(data) => decode(&data)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Index::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            index: Mutex::new(index),
+        })
+    }
+
+    /// Write the messages to the store's file. Does nothing if the store is
+    /// only kept in memory.
+    ///
+    /// The whole store is serialized and written on every call, so prefer
+    /// saving after a batch of changes over saving after each one.
+    pub async fn save(&self) -> ClientResult<()> {
+        if let Some(path) = &self.path {
+            let data = encode(&self.index.lock().expect("poisoned").messages);
+            // Write to a temporary file first, so a crash can't leave a
+            // half written store behind
+            let temp = path.with_extension("tmp");
+            tokio::fs::write(&temp, data).await?;
+            tokio::fs::rename(&temp, path).await?;
+        }
+        Ok(())
+    }
+
+    /// Add a message to the store, replacing it if it was already stored.
+    pub fn insert(&self, guild_id: u64, channel_id: u64, message_id: u64, message: Message) {
+        self.index
+            .lock()
+            .expect("poisoned")
+            .insert((guild_id, channel_id, message_id), message);
+    }
+
+    /// Add messages of a channel, as returned by `GetChannelMessages`.
+    pub fn insert_history(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        messages: impl IntoIterator<Item = MessageWithId>,
+    ) {
+        let mut index = self.index.lock().expect("poisoned");
+        for MessageWithId {
+            message_id,
+            message,
+        } in messages
+        {
+            if let Some(message) = message {
+                index.insert((guild_id, channel_id, message_id), message);
+            }
+        }
+    }
+
+    /// Remove a message from the store, returning it if it was stored.
+    pub fn remove(&self, guild_id: u64, channel_id: u64, message_id: u64) -> Option<Message> {
+        self.index
+            .lock()
+            .expect("poisoned")
+            .remove(&(guild_id, channel_id, message_id))
+    }
+
+    /// Get a stored message.
+    pub fn get(&self, guild_id: u64, channel_id: u64, message_id: u64) -> Option<Message> {
+        let index = self.index.lock().expect("poisoned");
+        index
+            .messages
+            .get(&(guild_id, channel_id, message_id))
+            .cloned()
+    }
+
+    /// Get the number of stored messages.
+    pub fn len(&self) -> usize {
+        self.index.lock().expect("poisoned").messages.len()
+    }
+
+    /// Whether there are no stored messages.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Search for messages, returning the matching ones newest first.
+    pub fn search(&self, query: &Query) -> Vec<StoredMessage> {
+        self.index.lock().expect("poisoned").search(query)
+    }
+
+    /// Fetch a page of a channel's history and add it to the store.
+    ///
+    /// Fetches the messages before `before`, or the newest ones if it's
+    /// `None`. Returns the ID of the oldest fetched message, to fetch the next
+    /// page with, or `None` if the top of the channel was reached.
+    pub async fn fetch_history(
+        &self,
+        client: &Client,
+        guild_id: u64,
+        channel_id: u64,
+        before: Option<u64>,
+    ) -> ClientResult<Option<u64>> {
+        let response = client
+            .call(GetChannelMessagesRequest {
+                guild_id,
+                channel_id,
+                message_id: before,
+                ..Default::default()
+            })
+            .await?;
+        let oldest = response.messages.iter().map(|m| m.message_id).min();
+        self.insert_history(guild_id, channel_id, response.messages);
+        Ok(oldest.filter(|_| !response.reached_top))
+    }
+
+    /// Update the store from an event. Returns `true` if the store changed.
+    pub fn handle_event(&self, event: &Event) -> bool {
+        let mut index = self.index.lock().expect("poisoned");
+        match event {
+            Event::Chat(stream_event::Event::SentMessage(stream_event::MessageSent {
+                guild_id,
+                channel_id,
+                message_id,
+                message: Some(message),
+                ..
+            })) => {
+                index.insert((*guild_id, *channel_id, *message_id), message.clone());
+                true
+            }
+            Event::Chat(stream_event::Event::EditedMessage(stream_event::MessageUpdated {
+                guild_id,
+                channel_id,
+                message_id,
+                edited_at,
+                new_content,
+            })) => {
+                let key = (*guild_id, *channel_id, *message_id);
+                let mut message = match index.messages.get(&key) {
+                    Some(message) => message.clone(),
+                    None => return false,
+                };
+                message.edited_at = Some(*edited_at);
+                message.content = Some(text_content(new_content.clone()));
+                index.insert(key, message);
+                true
+            }
+            Event::Chat(stream_event::Event::DeletedMessage(stream_event::MessageDeleted {
+                guild_id,
+                channel_id,
+                message_id,
+            })) => index
+                .remove(&(*guild_id, *channel_id, *message_id))
+                .is_some(),
+            _ => false,
+        }
+    }
+}
+
+fn text_content(text: Option<FormattedText>) -> Content {
+    Content {
+        content: Some(content::Content::TextMessage(content::TextContent {
+            content: text,
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::chat::Attachment;
+
+    fn text(author_id: u64, created_at: u64, text: &str) -> Message {
+        Message {
+            author_id,
+            created_at,
+            content: Some(text_content(Some(text.to_string().into()))),
+            ..Default::default()
+        }
+    }
+
+    fn store() -> MessageStore {
+        let store = MessageStore::new();
+        store.insert(1, 2, 10, text(100, 1000, "Hello world!"));
+        store.insert(1, 2, 11, text(200, 2000, "hello there, World"));
+        store.insert(1, 3, 12, text(100, 3000, "Worldwide news"));
+        store.insert(
+            1,
+            3,
+            13,
+            Message {
+                author_id: 200,
+                created_at: 4000,
+                content: Some(Content {
+                    content: Some(content::Content::AttachmentMessage(
+                        content::AttachmentContent {
+                            files: vec![Attachment {
+                                name: "world-map.png".to_string(),
+                                ..Default::default()
+                            }],
+                        },
+                    )),
+                }),
+                ..Default::default()
+            },
+        );
+        store
+    }
+
+    fn ids(results: Vec<StoredMessage>) -> Vec<u64> {
+        results.into_iter().map(|m| m.message_id).collect()
+    }
+
+    #[test]
+    fn search_matches_words_and_prefixes() {
+        let store = store();
+
+        assert_eq!(ids(store.search(&Query::new("hello WORLD"))), [11, 10]);
+        assert_eq!(ids(store.search(&Query::new("world"))), [13, 12, 11, 10]);
+        assert_eq!(ids(store.search(&Query::new("world map"))), [13]);
+        assert!(store.search(&Query::new("hell world")).is_empty());
+        assert_eq!(store.search(&Query::default()).len(), 4);
+    }
+
+    #[test]
+    fn search_filters() {
+        let store = store();
+        let world = Query::new("world");
+
+        assert_eq!(ids(store.search(&world.clone().with_author(100))), [12, 10]);
+        assert_eq!(
+            ids(store.search(&world.clone().with_channel(1, 3))),
+            [13, 12]
+        );
+        let range = world.clone().with_after(2000).with_before(4000);
+        assert_eq!(ids(store.search(&range)), [12, 11]);
+        let attachments = world.clone().with_kind(ContentKind::Attachment);
+        assert_eq!(ids(store.search(&attachments)), [13]);
+        assert_eq!(ids(store.search(&world.with_limit(1))), [13]);
+    }
+
+    #[test]
+    fn events_update_index() {
+        let store = store();
+        let chat = |event| Event::Chat(event);
+
+        assert!(store.handle_event(&chat(stream_event::Event::EditedMessage(
+            stream_event::MessageUpdated {
+                guild_id: 1,
+                channel_id: 2,
+                message_id: 10,
+                edited_at: 5000,
+                new_content: Some("Goodbye".to_string().into()),
+            }
+        ))));
+        assert_eq!(ids(store.search(&Query::new("hello"))), [11]);
+        assert_eq!(ids(store.search(&Query::new("goodbye"))), [10]);
+
+        assert!(
+            store.handle_event(&chat(stream_event::Event::DeletedMessage(
+                stream_event::MessageDeleted {
+                    guild_id: 1,
+                    channel_id: 2,
+                    message_id: 11,
+                }
+            )))
+        );
+        assert!(store.search(&Query::new("hello")).is_empty());
+
+        assert!(store.handle_event(&chat(stream_event::Event::SentMessage(
+            stream_event::MessageSent {
+                guild_id: 1,
+                channel_id: 2,
+                message_id: 14,
+                message: Some(text(100, 6000, "hello again")),
+                ..Default::default()
+            }
+        ))));
+        assert_eq!(ids(store.search(&Query::new("hello"))), [14]);
+    }
+
+    #[test]
+    fn encoding_roundtrips() {
+        let store = store();
+        let data = encode(&store.index.lock().unwrap().messages);
+        let index = decode(&data).unwrap();
+
+        assert_eq!(index.messages, store.index.lock().unwrap().messages);
+        assert_eq!(index.search(&Query::new("world")).len(), 4);
+        assert!(decode(&[FORMAT_VERSION + 1]).is_err());
+    }
+}
@@ -2,11 +2,66 @@
 //!
 //! See the `examples` directory in the repository on how to use this.
 
+/// Messages with interactive actions, and routing performed actions to
+/// callbacks.
+#[cfg(feature = "gen_chat")]
+pub mod actions;
+/// Typed, versioned app data stored with the profile service.
+#[cfg(feature = "gen_profile")]
+pub mod app_data;
+/// Command framework for bots: argument parsing, permissions and help.
+#[cfg(feature = "gen_chat")]
+pub mod bot;
+/// Bridging other chat networks to Harmony using message overrides.
+#[cfg(feature = "gen_chat")]
+pub mod bridge;
+/// Creating and managing bots, and logging in as a bot.
+#[cfg(feature = "staging_gen_bots")]
+pub mod bots;
+/// Exporting, importing and syncing emote packs.
+#[cfg(feature = "client_emote_packs")]
+pub mod emote_packs;
+/// Resolving emote names to images using the equipped emote packs.
+#[cfg(feature = "gen_chat")]
+pub mod emote_registry;
 /// Error related code used by [`Client`].
 pub mod error;
+/// Exporting guilds to versioned archives and restoring them.
+#[cfg(feature = "client_guild_archive")]
+pub mod guild_archive;
+/// Link previews using the media proxy service.
+#[cfg(all(
+    feature = "gen_mediaproxy",
+    feature = "gen_chat",
+    feature = "client_native"
+))]
+pub mod link_preview;
+/// On-disk cache for downloaded media.
+#[cfg(feature = "client_media_cache")]
+pub mod media_cache;
+/// Offline message store with full-text search.
+#[cfg(feature = "client_message_store")]
+pub mod message_store;
+/// Queueing messages to send, with local echoes and retries.
+#[cfg(feature = "gen_chat")]
+pub mod outbox;
+/// Tracking user statuses and idle detection.
+#[cfg(all(feature = "gen_chat", feature = "client_native"))]
+pub mod presence;
 /// Implements the REST client API.
 #[cfg(feature = "rest")]
 pub mod rest;
+/// Federation helpers for the sync service: auth tokens, and pushing and
+/// pulling postbox events.
+#[cfg(feature = "client_sync")]
+pub mod sync;
+/// Typing notifications and indicators.
+#[cfg(all(feature = "gen_chat", feature = "client_native"))]
+pub mod typing;
+
+/// Joining voice channels: signaling, participants and media backend hooks.
+#[cfg(feature = "staging_gen_voice")]
+pub mod voice;
 
 /// Some crates exported for user convenience.
 pub mod exports {
@@ -17,6 +72,8 @@ use crate::api::{auth::*, Endpoint, Hmc, HmcFromStrError};
 use error::*;
 use tracing::Span;
 
+#[cfg(feature = "gen_profile")]
+use std::collections::HashMap;
 use std::{
     convert::TryFrom,
     fmt::{self, Debug, Formatter},
@@ -138,6 +195,10 @@ type ProfileService =
     crate::api::profile::profile_service_client::ProfileServiceClient<GenericClient>;
 #[cfg(feature = "gen_emote")]
 type EmoteService = crate::api::emote::emote_service_client::EmoteServiceClient<GenericClient>;
+#[cfg(feature = "staging_gen_voice")]
+type VoiceService = crate::api::voice::voice_service_client::VoiceServiceClient<GenericClient>;
+#[cfg(feature = "staging_gen_bots")]
+type BotsService = crate::api::bots::bots_service_client::BotsServiceClient<GenericClient>;
 #[cfg(feature = "gen_batch")]
 type BatchService = crate::api::batch::batch_service_client::BatchServiceClient<GenericClient>;
 
@@ -192,10 +253,16 @@ struct ClientData {
     mediaproxy: Mutex<MediaProxyService>,
     #[cfg(feature = "gen_profile")]
     profile: Mutex<ProfileService>,
+    #[cfg(feature = "gen_profile")]
+    app_data: Mutex<HashMap<String, app_data::Envelope>>,
     #[cfg(feature = "gen_emote")]
     emote: Mutex<EmoteService>,
     #[cfg(feature = "gen_batch")]
     batch: Mutex<BatchService>,
+    #[cfg(feature = "staging_gen_voice")]
+    voice: Mutex<VoiceService>,
+    #[cfg(feature = "staging_gen_bots")]
+    bots: Mutex<BotsService>,
     http: HttpClient,
 }
 
@@ -313,6 +380,10 @@ impl Client {
         let emote = EmoteService::new_inner(inner.clone());
         #[cfg(feature = "gen_batch")]
         let batch = BatchService::new_inner(inner.clone());
+        #[cfg(feature = "staging_gen_voice")]
+        let voice = VoiceService::new_inner(inner.clone());
+        #[cfg(feature = "staging_gen_bots")]
+        let bots = BotsService::new_inner(inner.clone());
         let auth = AuthService::new_inner(inner);
 
         let data = ClientData {
@@ -325,10 +396,16 @@ impl Client {
             mediaproxy: Mutex::new(mediaproxy),
             #[cfg(feature = "gen_profile")]
             profile: Mutex::new(profile),
+            #[cfg(feature = "gen_profile")]
+            app_data: Mutex::new(HashMap::new()),
             #[cfg(feature = "gen_emote")]
             emote: Mutex::new(emote),
             #[cfg(feature = "gen_batch")]
             batch: Mutex::new(batch),
+            #[cfg(feature = "staging_gen_voice")]
+            voice: Mutex::new(voice),
+            #[cfg(feature = "staging_gen_bots")]
+            bots: Mutex::new(bots),
             http,
         };
 
@@ -378,6 +455,20 @@ impl Client {
         self.data.batch.lock().expect("poisoned")
     }
 
+    /// Get a mutex guard to the voice service.
+    #[cfg(feature = "staging_gen_voice")]
+    #[inline(always)]
+    pub fn voice(&self) -> MutexGuard<'_, VoiceService> {
+        self.data.voice.lock().expect("poisoned")
+    }
+
+    /// Get a mutex guard to the bots service.
+    #[cfg(feature = "staging_gen_bots")]
+    #[inline(always)]
+    pub fn bots(&self) -> MutexGuard<'_, BotsService> {
+        self.data.bots.lock().expect("poisoned")
+    }
+
     /// Execute the given request, await the response and return the
     /// deserialized body type.
     pub fn call<Req>(
@@ -0,0 +1,694 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use hrpc::exports::futures_util::future::BoxFuture;
+use prost::Message;
+
+use super::{
+    error::{ClientError, ClientResult},
+    Client,
+};
+use crate::api::chat::{stream_event, Event, SendMessageRequest};
+
+/// Default number of times a message is sent before it's marked as failed.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+/// Version of the format [`Outbox`] persists messages in.
+const FORMAT_VERSION: u8 = 1;
+
+/// State of a message in an [`Outbox`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutboxState {
+    /// The message is waiting to be sent.
+    Pending,
+    /// The message was sent and has the given message ID.
+    Sent(u64),
+    /// Sending the message failed with the given error, and it won't be
+    /// retried unless [`Outbox::retry`] is called.
+    Failed(String),
+}
+
+/// A message in an [`Outbox`] that wasn't sent yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueuedMessage {
+    /// Local echo ID of the message, also set as the request's `echo_id`.
+    pub echo_id: u64,
+    /// The request that sends the message.
+    pub request: SendMessageRequest,
+    /// State of the message. Never [`OutboxState::Sent`], as sent messages
+    /// are removed from the outbox.
+    pub state: OutboxState,
+    /// How many times sending the message failed with a transient error.
+    pub attempts: u32,
+}
+
+impl QueuedMessage {
+    fn channel(&self) -> (u64, u64) {
+        (self.request.guild_id, self.request.channel_id)
+    }
+}
+
+/// Storage for the messages of an [`Outbox`], so that unsent messages
+/// survive restarts.
+pub trait OutboxStore: Send + Sync {
+    /// Load the persisted messages, or an empty buffer if there are none.
+    fn load(&self) -> BoxFuture<'_, ClientResult<Vec<u8>>>;
+    /// Persist messages, replacing the previously persisted ones.
+    fn save(&self, data: Vec<u8>) -> BoxFuture<'_, ClientResult<()>>;
+}
+
+/// An [`OutboxStore`] that keeps messages in memory.
+#[derive(Debug, Default)]
+pub struct MemoryOutboxStore {
+    data: Mutex<Vec<u8>>,
+}
+
+impl OutboxStore for MemoryOutboxStore {
+    fn load(&self) -> BoxFuture<'_, ClientResult<Vec<u8>>> {
+        let data = self.data.lock().expect("poisoned").clone();
+        Box::pin(async move { Ok(data) })
+    }
+
+    fn save(&self, data: Vec<u8>) -> BoxFuture<'_, ClientResult<()>> {
+        *self.data.lock().expect("poisoned") = data;
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// An [`OutboxStore`] that keeps messages in a file.
+#[cfg(feature = "client_native")]
+#[derive(Debug, Clone)]
+pub struct FileOutboxStore {
+    path: std::path::PathBuf,
+}
+
+#[cfg(feature = "client_native")]
+impl FileOutboxStore {
+    /// Create a new store that keeps messages in the file at `path`.
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(feature = "client_native")]
+impl OutboxStore for FileOutboxStore {
+    fn load(&self) -> BoxFuture<'_, ClientResult<Vec<u8>>> {
+        Box::pin(async move {
+            match tokio::fs::read(&self.path).await {
+                Ok(data) => Ok(data),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+                Err(err) => Err(err.into()),
+            }
+        })
+    }
+
+    fn save(&self, data: Vec<u8>) -> BoxFuture<'_, ClientResult<()>> {
+        Box::pin(async move {
+            // Write to a temporary file first, so a crash can't leave a
+            // half written outbox behind
+            let temp = self.path.with_extension("tmp");
+            tokio::fs::write(&temp, data).await?;
+            tokio::fs::rename(&temp, &self.path).await?;
+            Ok(())
+        })
+    }
+}
+
+fn encode(messages: &VecDeque<QueuedMessage>) -> Vec<u8> {
+    let mut buf = vec![FORMAT_VERSION];
+    for message in messages {
+        match &message.state {
+            OutboxState::Failed(reason) => {
+                buf.push(1);
+                prost::encode_length_delimiter(reason.len(), &mut buf)
+                    .expect("vec has enough capacity");
+                buf.extend_from_slice(reason.as_bytes());
+            }
+            _ => buf.push(0),
+        }
+        message
+            .request
+            .encode_length_delimited(&mut buf)
+            .expect("vec has enough capacity");
+    }
+    buf
+}
+
+fn decode(mut buf: &[u8]) -> ClientResult<VecDeque<QueuedMessage>> {
+    let mut messages = VecDeque::new();
+    match buf.split_first() {
+        None => return Ok(messages),
+        Some((&FORMAT_VERSION, rest)) => buf = rest,
+        Some((version, _)) => {
+            return Err(ClientError::unexpected(format!(
+                "unknown outbox format version {}",
+                version
+            )))
+        }
+    }
+
+    while let Some((&flag, rest)) = buf.split_first() {
+        buf = rest;
+        let state = if flag == 1 {
+            let len = prost::decode_length_delimiter(&mut buf)?;
+            if buf.len() < len {
+                return Err(ClientError::unexpected("truncated outbox"));
+            }
+            let (reason, rest) = buf.split_at(len);
+            buf = rest;
+            OutboxState::Failed(String::from_utf8_lossy(reason).into_owned())
+        } else {
+            OutboxState::Pending
+        };
+        let request = SendMessageRequest::decode_length_delimited(&mut buf)?;
+        messages.push_back(QueuedMessage {
+            echo_id: request.echo_id.unwrap_or_default(),
+            request,
+            state,
+            attempts: 0,
+        });
+    }
+
+    Ok(messages)
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    next_echo_id: u64,
+    messages: VecDeque<QueuedMessage>,
+}
+
+type Listener = Box<dyn Fn(u64, &OutboxState) + Send + Sync>;
+
+/// A queue of messages to send, for showing messages as soon as the user
+/// sends them.
+///
+/// Every queued message gets a local echo ID, which is sent as the message's
+/// `echo_id` so that the `SentMessage` event for it can be matched with the
+/// local echo. Listeners added with [`Outbox::with_listener`] are told when a
+/// message's [`OutboxState`] changes.
+///
+/// Messages are sent by [`Outbox::flush`]. Messages that fail with a
+/// transient error (see [`ClientError::is_transient`]) stay pending, and so
+/// do the ones queued after them in the same channel, to keep them in order.
+/// After [`Outbox::with_max_attempts`] attempts, or on any other error, a
+/// message is marked as failed.
+pub struct Outbox {
+    queue: Mutex<Queue>,
+    store: Option<Box<dyn OutboxStore>>,
+    listeners: Vec<Listener>,
+    max_attempts: u32,
+    flushing: AtomicBool,
+}
+
+impl Debug for Outbox {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Outbox")
+            .field("queue", &self.queue)
+            .field("max_attempts", &self.max_attempts)
+            .finish()
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Outbox {
+    /// Create a new outbox that doesn't persist messages.
+    pub fn new() -> Self {
+        Self {
+            queue: Mutex::new(Queue {
+                next_echo_id: 1,
+                messages: VecDeque::new(),
+            }),
+            store: None,
+            listeners: Vec::new(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            flushing: AtomicBool::new(false),
+        }
+    }
+
+    /// Create a new outbox that persists messages in the given store, loading
+    /// the messages that weren't sent before.
+    pub async fn open(store: impl OutboxStore + 'static) -> ClientResult<Self> {
+        let messages = decode(&store.load().await?)?;
+        let next_echo_id = messages.iter().map(|m| m.echo_id).max().unwrap_or(0) + 1;
+        let mut outbox = Self::new();
+        outbox.queue = Mutex::new(Queue {
+            next_echo_id,
+            messages,
+        });
+        outbox.store = Some(Box::new(store));
+        Ok(outbox)
+    }
+
+    /// Set how many times a message is sent before it's marked as failed.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Add a listener that is called with the echo ID and new state of a
+    /// message whenever its state changes.
+    pub fn with_listener(
+        mut self,
+        listener: impl Fn(u64, &OutboxState) + Send + Sync + 'static,
+    ) -> Self {
+        self.listeners.push(Box::new(listener));
+        self
+    }
+
+    fn notify(&self, echo_id: u64, state: &OutboxState) {
+        for listener in &self.listeners {
+            listener(echo_id, state);
+        }
+    }
+
+    async fn persist(&self) -> ClientResult<()> {
+        if let Some(store) = &self.store {
+            let data = encode(&self.queue.lock().expect("poisoned").messages);
+            store.save(data).await?;
+        }
+        Ok(())
+    }
+
+    /// Queue a message to be sent by [`Outbox::flush`], returning its echo ID.
+    ///
+    /// The request's `echo_id` is overwritten with the assigned one.
+    pub async fn queue(&self, mut request: SendMessageRequest) -> ClientResult<u64> {
+        let echo_id = {
+            let mut queue = self.queue.lock().expect("poisoned");
+            let echo_id = queue.next_echo_id;
+            queue.next_echo_id += 1;
+            request.echo_id = Some(echo_id);
+            queue.messages.push_back(QueuedMessage {
+                echo_id,
+                request,
+                state: OutboxState::Pending,
+                attempts: 0,
+            });
+            echo_id
+        };
+        self.notify(echo_id, &OutboxState::Pending);
+        self.persist().await?;
+        Ok(echo_id)
+    }
+
+    /// Get the messages that weren't sent yet, in the order they were queued.
+    pub fn messages(&self) -> Vec<QueuedMessage> {
+        let queue = self.queue.lock().expect("poisoned");
+        queue.messages.iter().cloned().collect()
+    }
+
+    /// Get the state of a message that wasn't sent yet.
+    pub fn state(&self, echo_id: u64) -> Option<OutboxState> {
+        let queue = self.queue.lock().expect("poisoned");
+        queue
+            .messages
+            .iter()
+            .find(|m| m.echo_id == echo_id)
+            .map(|m| m.state.clone())
+    }
+
+    /// Mark a failed message as pending again, so it's sent by the next
+    /// [`Outbox::flush`]. Returns `false` if there is no such failed message.
+    pub async fn retry(&self, echo_id: u64) -> ClientResult<bool> {
+        let found = {
+            let mut queue = self.queue.lock().expect("poisoned");
+            match queue.messages.iter_mut().find(|m| m.echo_id == echo_id) {
+                Some(message) if matches!(message.state, OutboxState::Failed(_)) => {
+                    message.state = OutboxState::Pending;
+                    message.attempts = 0;
+                    true
+                }
+                _ => false,
+            }
+        };
+        if found {
+            self.notify(echo_id, &OutboxState::Pending);
+            self.persist().await?;
+        }
+        Ok(found)
+    }
+
+    /// Remove a message that wasn't sent yet, returning its request.
+    pub async fn discard(&self, echo_id: u64) -> ClientResult<Option<SendMessageRequest>> {
+        let removed = {
+            let mut queue = self.queue.lock().expect("poisoned");
+            let index = queue.messages.iter().position(|m| m.echo_id == echo_id);
+            index.and_then(|index| queue.messages.remove(index))
+        };
+        if removed.is_some() {
+            self.persist().await?;
+        }
+        Ok(removed.map(|m| m.request))
+    }
+
+    /// Mark a message sent to `channel` as sent, removing it from the outbox.
+    async fn sent(&self, echo_id: u64, channel: (u64, u64), message_id: u64) -> ClientResult<bool> {
+        let removed = {
+            let mut queue = self.queue.lock().expect("poisoned");
+            let index = queue
+                .messages
+                .iter()
+                .position(|m| m.echo_id == echo_id && m.channel() == channel);
+            index.and_then(|index| queue.messages.remove(index))
+        };
+        if removed.is_some() {
+            self.notify(echo_id, &OutboxState::Sent(message_id));
+            self.persist().await?;
+        }
+        Ok(removed.is_some())
+    }
+
+    /// Send all pending messages, in the order they were queued.
+    ///
+    /// Returns the errors that occurred, with the echo ID of the message that
+    /// failed. Does nothing if another flush is already running.
+    pub async fn flush(&self, client: &Client) -> Vec<(u64, ClientError)> {
+        self.flush_with(|request| client.call(request)).await
+    }
+
+    async fn flush_with<F, Fut>(&self, send: F) -> Vec<(u64, ClientError)>
+    where
+        F: Fn(SendMessageRequest) -> Fut,
+        Fut: Future<Output = ClientResult<crate::api::chat::SendMessageResponse>>,
+    {
+        if self.flushing.swap(true, Ordering::AcqRel) {
+            return Vec::new();
+        }
+        // Also resets the flag if this future is dropped before it finishes
+        let _flushing = FlushGuard(&self.flushing);
+
+        let mut errors = Vec::new();
+        let mut blocked = HashSet::new();
+        let pending = self
+            .messages()
+            .into_iter()
+            .filter(|m| m.state == OutboxState::Pending);
+        for message in pending {
+            let channel = message.channel();
+            if blocked.contains(&channel) {
+                continue;
+            }
+
+            let result = match send(message.request.clone()).await {
+                Ok(response) => self
+                    .sent(message.echo_id, channel, response.message_id)
+                    .await
+                    .map(|_| ()),
+                Err(err) => {
+                    if self.failed(message.echo_id, &err) {
+                        blocked.insert(channel);
+                    }
+                    errors.push((message.echo_id, err));
+                    self.persist().await
+                }
+            };
+            if let Err(err) = result {
+                errors.push((message.echo_id, err));
+            }
+        }
+
+        errors
+    }
+
+    /// Record a failed attempt, returning whether the message is still
+    /// pending.
+    fn failed(&self, echo_id: u64, err: &ClientError) -> bool {
+        let state = {
+            let mut queue = self.queue.lock().expect("poisoned");
+            let message = match queue.messages.iter_mut().find(|m| m.echo_id == echo_id) {
+                Some(message) => message,
+                None => return false,
+            };
+            message.attempts += 1;
+            if err.is_transient() && message.attempts < self.max_attempts {
+                return true;
+            }
+            message.state = OutboxState::Failed(err.to_string());
+            message.state.clone()
+        };
+        self.notify(echo_id, &state);
+        false
+    }
+
+    /// Reconcile a message's local echo with its `SentMessage` event,
+    /// returning the echo ID if the event was for a message in the outbox.
+    ///
+    /// Messages are usually marked as sent as soon as the homeserver
+    /// responds, but the event can arrive first. Only events for messages
+    /// sent by the client's user, in the channel the message was queued for,
+    /// are matched, as echo IDs are picked by each client.
+    pub async fn handle_event(&self, client: &Client, event: &Event) -> ClientResult<Option<u64>> {
+        match client.user_id() {
+            Some(user_id) => self.handle_event_as(user_id, event).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn handle_event_as(&self, user_id: u64, event: &Event) -> ClientResult<Option<u64>> {
+        let sent = match event {
+            Event::Chat(stream_event::Event::SentMessage(sent)) => sent,
+            _ => return Ok(None),
+        };
+        let echo_id = match sent.echo_id {
+            Some(echo_id) => echo_id,
+            None => return Ok(None),
+        };
+        if sent.message.as_ref().map(|m| m.author_id) != Some(user_id) {
+            return Ok(None);
+        }
+        let channel = (sent.guild_id, sent.channel_id);
+        let found = self.sent(echo_id, channel, sent.message_id).await?;
+        Ok(found.then_some(echo_id))
+    }
+}
+
+/// Clears the flushing flag of an [`Outbox`] when dropped.
+struct FlushGuard<'a>(&'a AtomicBool);
+
+impl Drop for FlushGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::chat::SendMessageResponse;
+    use crate::client::error::InternalClientError;
+    use hrpc::exports::futures_util::FutureExt;
+    use std::sync::Arc;
+
+    fn request(channel_id: u64, text: &str) -> SendMessageRequest {
+        SendMessageRequest {
+            guild_id: 1,
+            channel_id,
+            ..Default::default()
+        }
+        .with_text_content(text.to_string())
+    }
+
+    fn ready<T>(fut: impl Future<Output = T>) -> T {
+        fut.now_or_never().expect("future is ready")
+    }
+
+    fn transient() -> ClientError {
+        ClientError::Internal(InternalClientError::EndpointError {
+            hrpc_error: hrpc::proto::Error::default().with_identifier("hrpc.resource-exhausted"),
+            endpoint: "/protocol.chat.v1.ChatService/SendMessage".into(),
+        })
+    }
+
+    #[test]
+    fn queue_assigns_echo_ids() {
+        let outbox = Outbox::new();
+        let first = ready(outbox.queue(request(2, "a"))).unwrap();
+        let second = ready(outbox.queue(request(2, "b"))).unwrap();
+
+        assert_eq!((first, second), (1, 2));
+        let messages = outbox.messages();
+        assert_eq!(messages[1].request.echo_id, Some(2));
+        assert_eq!(outbox.state(1), Some(OutboxState::Pending));
+    }
+
+    #[test]
+    fn flush_sends_and_notifies() {
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let outbox = Outbox::new().with_listener({
+            let states = states.clone();
+            move |echo_id, state| states.lock().unwrap().push((echo_id, state.clone()))
+        });
+        ready(outbox.queue(request(2, "a"))).unwrap();
+
+        let errors = ready(outbox.flush_with(|request| async move {
+            Ok(SendMessageResponse {
+                message_id: request.echo_id.unwrap() + 100,
+            })
+        }));
+
+        assert!(errors.is_empty());
+        assert!(outbox.messages().is_empty());
+        assert_eq!(
+            *states.lock().unwrap(),
+            [(1, OutboxState::Pending), (1, OutboxState::Sent(101))]
+        );
+    }
+
+    #[test]
+    fn transient_errors_block_channel_until_max_attempts() {
+        let outbox = Outbox::new().with_max_attempts(2);
+        ready(outbox.queue(request(2, "a"))).unwrap();
+        ready(outbox.queue(request(2, "b"))).unwrap();
+        ready(outbox.queue(request(3, "c"))).unwrap();
+
+        let sent = Mutex::new(Vec::new());
+        let send = |request: SendMessageRequest| {
+            let echo_id = request.echo_id.unwrap();
+            sent.lock().unwrap().push(echo_id);
+            async move {
+                match echo_id {
+                    1 => Err(transient()),
+                    _ => Ok(SendMessageResponse {
+                        message_id: echo_id,
+                    }),
+                }
+            }
+        };
+
+        // Message 2 waits behind message 1, but channel 3 isn't blocked
+        let errors = ready(outbox.flush_with(send));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(*sent.lock().unwrap(), [1, 3]);
+        assert_eq!(outbox.state(1), Some(OutboxState::Pending));
+
+        ready(outbox.flush_with(send));
+        assert!(matches!(outbox.state(1), Some(OutboxState::Failed(_))));
+        assert_eq!(*sent.lock().unwrap(), [1, 3, 1, 2]);
+        assert_eq!(outbox.messages().len(), 1);
+
+        assert!(ready(outbox.retry(1)).unwrap());
+        assert_eq!(outbox.state(1), Some(OutboxState::Pending));
+    }
+
+    #[test]
+    fn cancelled_flush_can_be_retried() {
+        let outbox = Outbox::new();
+        ready(outbox.queue(request(2, "a"))).unwrap();
+
+        let cancelled = outbox.flush_with(|_| std::future::pending()).now_or_never();
+        assert!(cancelled.is_none());
+        assert_eq!(outbox.state(1), Some(OutboxState::Pending));
+
+        let errors = ready(outbox.flush_with(|request| async move {
+            Ok(SendMessageResponse {
+                message_id: request.echo_id.unwrap(),
+            })
+        }));
+        assert!(errors.is_empty());
+        assert!(outbox.messages().is_empty());
+    }
+
+    #[test]
+    fn other_errors_fail_right_away() {
+        let outbox = Outbox::new();
+        ready(outbox.queue(request(2, "a"))).unwrap();
+
+        ready(outbox.flush_with(|_| async { Err(ClientError::Unauthenticated) }));
+        assert!(matches!(outbox.state(1), Some(OutboxState::Failed(_))));
+    }
+
+    #[test]
+    fn events_reconcile_echoes() {
+        let outbox = Outbox::new();
+        ready(outbox.queue(request(2, "a"))).unwrap();
+
+        let event = |echo_id, channel_id, author_id| {
+            Event::Chat(stream_event::Event::SentMessage(
+                stream_event::MessageSent {
+                    echo_id: Some(echo_id),
+                    guild_id: 1,
+                    channel_id,
+                    message_id: 50,
+                    message: Some(crate::api::chat::Message {
+                        author_id,
+                        ..Default::default()
+                    }),
+                },
+            ))
+        };
+        let handle = |event| ready(outbox.handle_event_as(7, &event)).unwrap();
+        assert_eq!(handle(event(9, 2, 7)), None);
+        assert_eq!(handle(event(1, 3, 7)), None);
+        assert_eq!(handle(event(1, 2, 7)), Some(1));
+        assert!(outbox.messages().is_empty());
+    }
+
+    #[test]
+    fn foreign_echoes_are_ignored() {
+        let outbox = Outbox::new();
+        ready(outbox.queue(request(2, "a"))).unwrap();
+
+        // Another user's client picked the same echo ID in the same channel
+        let event = Event::Chat(stream_event::Event::SentMessage(
+            stream_event::MessageSent {
+                echo_id: Some(1),
+                guild_id: 1,
+                channel_id: 2,
+                message_id: 50,
+                message: Some(crate::api::chat::Message {
+                    author_id: 8,
+                    ..Default::default()
+                }),
+            },
+        ));
+        assert_eq!(ready(outbox.handle_event_as(7, &event)).unwrap(), None);
+        assert_eq!(outbox.state(1), Some(OutboxState::Pending));
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedStore(Arc<MemoryOutboxStore>);
+
+    impl OutboxStore for SharedStore {
+        fn load(&self) -> BoxFuture<'_, ClientResult<Vec<u8>>> {
+            self.0.load()
+        }
+
+        fn save(&self, data: Vec<u8>) -> BoxFuture<'_, ClientResult<()>> {
+            self.0.save(data)
+        }
+    }
+
+    #[test]
+    fn unsent_messages_are_persisted() {
+        let store = SharedStore::default();
+        let outbox = ready(Outbox::open(store.clone())).unwrap();
+        ready(outbox.queue(request(2, "a"))).unwrap();
+        ready(outbox.queue(request(2, "b"))).unwrap();
+        ready(outbox.flush_with(|request| async move {
+            match request.echo_id {
+                Some(1) => Ok(SendMessageResponse { message_id: 10 }),
+                _ => Err(ClientError::Unauthenticated),
+            }
+        }));
+        drop(outbox);
+
+        let outbox = ready(Outbox::open(store)).unwrap();
+        let messages = outbox.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].request, request(2, "b").with_echo_id(Some(2)));
+        assert!(matches!(messages[0].state, OutboxState::Failed(_)));
+        assert_eq!(ready(outbox.queue(request(2, "c"))).unwrap(), 3);
+    }
+}
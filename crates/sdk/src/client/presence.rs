@@ -0,0 +1,305 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{error::ClientResult, Client};
+use crate::api::{
+    chat::Event,
+    profile::{stream_event, GetProfileRequest, UpdateProfileRequest, UserStatus},
+};
+
+/// Capacity of the channel used to send [`PresenceTransition`]s to
+/// subscribers.
+#[cfg(all(feature = "client_native", not(feature = "client_web")))]
+const TRANSITION_CHANNEL_CAPACITY: usize = 256;
+
+/// A change of a user's status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PresenceTransition {
+    /// ID of the user whose status changed.
+    pub user_id: u64,
+    /// The previous status of the user.
+    pub old: UserStatus,
+    /// The new status of the user.
+    pub new: UserStatus,
+}
+
+#[derive(Debug, Default)]
+struct Statuses {
+    by_user: HashMap<u64, UserStatus>,
+}
+
+impl Statuses {
+    /// Sets the status of a user, returning a transition if it changed.
+    /// Users that aren't known are considered offline.
+    fn set(&mut self, user_id: u64, status: UserStatus) -> Option<PresenceTransition> {
+        let old = self
+            .by_user
+            .insert(user_id, status)
+            .unwrap_or(UserStatus::OfflineUnspecified);
+        if old == status {
+            return None;
+        }
+        Some(PresenceTransition {
+            user_id,
+            old,
+            new: status,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct IdleState {
+    timeout: Option<Duration>,
+    last_activity: Instant,
+    /// Status to restore when there is activity, if the status was set to
+    /// idle automatically.
+    restore: Option<UserStatus>,
+}
+
+impl IdleState {
+    /// Returns `true` if the status should be set to idle automatically.
+    fn should_go_idle(&self, current: UserStatus, now: Instant) -> bool {
+        let inactive = now.saturating_duration_since(self.last_activity);
+        let timed_out = matches!(self.timeout, Some(timeout) if inactive >= timeout);
+        // Don't override statuses the user chose explicitly
+        timed_out
+            && self.restore.is_none()
+            && matches!(current, UserStatus::Online | UserStatus::Mobile)
+    }
+}
+
+/// Tracks the statuses of users over time.
+///
+/// Statuses are bootstrapped with [`PresenceTracker::bootstrap()`] and kept
+/// up to date by passing all received events to
+/// [`PresenceTracker::handle_event()`].
+///
+/// The tracker can also set the current user's status to idle after a period
+/// of inactivity, see [`PresenceTracker::with_idle_timeout()`].
+#[derive(Debug)]
+pub struct PresenceTracker {
+    client: Client,
+    statuses: Mutex<Statuses>,
+    idle: Mutex<IdleState>,
+    #[cfg(all(feature = "client_native", not(feature = "client_web")))]
+    transitions: tokio::sync::broadcast::Sender<PresenceTransition>,
+}
+
+impl PresenceTracker {
+    /// Create a new presence tracker that uses the given client.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            statuses: Mutex::new(Statuses::default()),
+            idle: Mutex::new(IdleState {
+                timeout: None,
+                last_activity: Instant::now(),
+                restore: None,
+            }),
+            #[cfg(all(feature = "client_native", not(feature = "client_web")))]
+            transitions: tokio::sync::broadcast::channel(TRANSITION_CHANNEL_CAPACITY).0,
+        }
+    }
+
+    /// Set the current user's status to idle automatically after `timeout`
+    /// of inactivity.
+    ///
+    /// Activity must be reported with [`PresenceTracker::record_activity()`],
+    /// and inactivity is checked with [`PresenceTracker::check_idle()`].
+    pub fn with_idle_timeout(self, timeout: Duration) -> Self {
+        self.idle.lock().expect("poisoned").timeout = Some(timeout);
+        self
+    }
+
+    /// Fetch the profiles of the given users and record their statuses.
+    pub async fn bootstrap(
+        &self,
+        user_ids: impl IntoIterator<Item = u64>,
+    ) -> ClientResult<Vec<PresenceTransition>> {
+        let user_ids = user_ids.into_iter().collect::<Vec<_>>();
+        let requests = user_ids
+            .iter()
+            .map(|user_id| GetProfileRequest { user_id: *user_id })
+            .collect::<Vec<_>>();
+
+        #[cfg(feature = "gen_batch")]
+        let responses = self.client.batch_call(requests).await?;
+        #[cfg(not(feature = "gen_batch"))]
+        let responses = hrpc::exports::futures_util::future::try_join_all(
+            requests
+                .into_iter()
+                .map(|request| self.client.call(request)),
+        )
+        .await?;
+
+        let statuses = user_ids
+            .into_iter()
+            .zip(responses)
+            .filter_map(|(user_id, response)| {
+                let status = UserStatus::from_i32(response.profile?.user_status)?;
+                Some((user_id, status))
+            });
+        Ok(self.set_statuses(statuses))
+    }
+
+    /// Update statuses using an event, returning a transition if a user's
+    /// status changed.
+    pub fn handle_event(&self, event: &Event) -> Option<PresenceTransition> {
+        let update = match event {
+            Event::Profile(stream_event::Event::ProfileUpdated(update)) => update,
+            _ => return None,
+        };
+        let status = update.new_status.and_then(UserStatus::from_i32)?;
+
+        if Some(update.user_id) == self.client.user_id() && status != UserStatus::Idle {
+            // The status was changed by the user, don't restore it later
+            self.idle.lock().expect("poisoned").restore = None;
+        }
+
+        self.set_statuses(std::iter::once((update.user_id, status)))
+            .pop()
+    }
+
+    /// Get the status of a user. Users whose status isn't known are
+    /// considered offline.
+    pub fn status(&self, user_id: u64) -> UserStatus {
+        self.statuses
+            .lock()
+            .expect("poisoned")
+            .by_user
+            .get(&user_id)
+            .copied()
+            .unwrap_or(UserStatus::OfflineUnspecified)
+    }
+
+    /// Get the statuses of all known users.
+    pub fn statuses(&self) -> HashMap<u64, UserStatus> {
+        self.statuses.lock().expect("poisoned").by_user.clone()
+    }
+
+    /// Subscribe to status transitions.
+    ///
+    /// Subscribers that fall behind will miss transitions, see
+    /// [`tokio::sync::broadcast`].
+    #[cfg(all(feature = "client_native", not(feature = "client_web")))]
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<PresenceTransition> {
+        self.transitions.subscribe()
+    }
+
+    /// Record user activity. If the current user's status was set to idle
+    /// automatically, it is restored.
+    pub async fn record_activity(&self) -> ClientResult<()> {
+        let restore = {
+            let mut idle = self.idle.lock().expect("poisoned");
+            idle.last_activity = Instant::now();
+            idle.restore.take()
+        };
+        match restore {
+            Some(status) => self.update_own_status(status).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Set the current user's status to idle if there was no activity for the
+    /// configured idle timeout. Returns `true` if the status was changed.
+    ///
+    /// Only online statuses are changed, so statuses like
+    /// [`UserStatus::DoNotDisturb`] aren't overridden. This should be called
+    /// periodically.
+    pub async fn check_idle(&self) -> ClientResult<bool> {
+        let user_id = match self.client.user_id() {
+            Some(user_id) => user_id,
+            None => return Ok(false),
+        };
+        let current = self.status(user_id);
+        {
+            let mut idle = self.idle.lock().expect("poisoned");
+            if !idle.should_go_idle(current, Instant::now()) {
+                return Ok(false);
+            }
+            idle.restore = Some(current);
+        }
+
+        if let Err(err) = self.update_own_status(UserStatus::Idle).await {
+            self.idle.lock().expect("poisoned").restore = None;
+            return Err(err);
+        }
+        Ok(true)
+    }
+
+    async fn update_own_status(&self, status: UserStatus) -> ClientResult<()> {
+        self.client
+            .call(UpdateProfileRequest::default().with_new_user_status(status))
+            .await?;
+        if let Some(user_id) = self.client.user_id() {
+            self.set_statuses(std::iter::once((user_id, status)));
+        }
+        Ok(())
+    }
+
+    fn set_statuses(
+        &self,
+        statuses: impl IntoIterator<Item = (u64, UserStatus)>,
+    ) -> Vec<PresenceTransition> {
+        let transitions = {
+            let mut current = self.statuses.lock().expect("poisoned");
+            statuses
+                .into_iter()
+                .filter_map(|(user_id, status)| current.set(user_id, status))
+                .collect::<Vec<_>>()
+        };
+
+        #[cfg(all(feature = "client_native", not(feature = "client_web")))]
+        for transition in &transitions {
+            // Sending only fails if there are no subscribers
+            let _ = self.transitions.send(*transition);
+        }
+
+        transitions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emits_transitions_on_change() {
+        let mut statuses = Statuses::default();
+        assert_eq!(
+            statuses.set(1, UserStatus::Online),
+            Some(PresenceTransition {
+                user_id: 1,
+                old: UserStatus::OfflineUnspecified,
+                new: UserStatus::Online,
+            })
+        );
+        assert_eq!(statuses.set(1, UserStatus::Online), None);
+        assert_eq!(statuses.set(2, UserStatus::OfflineUnspecified), None);
+    }
+
+    #[test]
+    fn idles_only_when_online_and_inactive() {
+        let start = Instant::now();
+        let later = start + Duration::from_secs(61);
+        let mut idle = IdleState {
+            timeout: Some(Duration::from_secs(60)),
+            last_activity: start,
+            restore: None,
+        };
+        assert!(!idle.should_go_idle(UserStatus::Online, start));
+
+        assert!(idle.should_go_idle(UserStatus::Online, later));
+        assert!(!idle.should_go_idle(UserStatus::DoNotDisturb, later));
+
+        idle.restore = Some(UserStatus::Online);
+        assert!(!idle.should_go_idle(UserStatus::Online, later));
+
+        idle.timeout = None;
+        idle.restore = None;
+        assert!(!idle.should_go_idle(UserStatus::Online, later));
+    }
+}
@@ -3,9 +3,11 @@ use crate::api::rest::*;
 use prost::bytes::Bytes;
 use reqwest::{multipart::*, Response};
 use serde::Deserialize;
+#[cfg(feature = "gen_chat")]
+use std::convert::TryFrom;
 
 use super::{
-    error::{ClientError, ClientResult},
+    error::{ClientError, ClientResult, InvalidDataKind},
     Client,
 };
 
@@ -69,7 +71,7 @@ impl Client {
         let response = self.data.http.execute(request).await?;
         tracing::debug!("Got HTTP response: {:?}", response);
 
-        response.error_for_status().map_err(Into::into)
+        Ok(response)
     }
 
     /// Downloads a file using a file ID.
@@ -77,15 +79,36 @@ impl Client {
     /// This endpoint does not require authentication.
     /// See [API documentation](https://github.com/harmony-development/protocol/blob/master/rest/rest.md#get-_harmonymediadownloadfile_id).
     pub async fn download(&self, file_id: impl Into<FileId>) -> ClientResult<Response> {
-        let uri = file_id.into().make_download_url(self.homeserver_url());
+        self.download_from(file_id.into(), 0).await
+    }
 
-        let request = self.data.http.get(uri.as_str()).build()?;
+    /// Downloads a file using a file ID, requesting the content starting from
+    /// the given byte offset via a `Range` header.
+    ///
+    /// No `Range` header is sent if `offset` is `0`.
+    async fn download_from(&self, file_id: FileId, offset: u64) -> ClientResult<Response> {
+        self.send_download(file_id, offset)
+            .await?
+            .error_for_status()
+            .map_err(Into::into)
+    }
+
+    /// Same as [`Client::download_from()`], but doesn't turn error statuses
+    /// into errors.
+    async fn send_download(&self, file_id: FileId, offset: u64) -> ClientResult<Response> {
+        let uri = file_id.make_download_url(self.homeserver_url());
+
+        let mut request = self.data.http.get(uri.as_str());
+        if offset > 0 {
+            request = request.header(http::header::RANGE, format!("bytes={}-", offset));
+        }
+        let request = request.build()?;
         tracing::debug!("Sending HTTP request: {:?}", request);
 
         let response = self.data.http.execute(request).await?;
         tracing::debug!("Got HTTP response: {:?}", response);
 
-        response.error_for_status().map_err(Into::into)
+        Ok(response)
     }
 
     /// Uploads a file and then extracts the file ID from the returned response.
@@ -108,6 +131,89 @@ impl Client {
         Ok(file_id.id)
     }
 
+    /// Uploads a file and returns an [`Attachment`] describing it, which can
+    /// be used with [`SendMessageRequest::with_attachment_content()`].
+    ///
+    /// The mimetype of the file is guessed using [`guess_mimetype()`].
+    ///
+    /// [`Attachment`]: crate::api::chat::Attachment
+    /// [`SendMessageRequest::with_attachment_content()`]: crate::api::chat::SendMessageRequest::with_attachment_content
+    #[cfg(feature = "gen_chat")]
+    pub async fn upload_attachment(
+        &self,
+        source: impl Into<UploadSource>,
+    ) -> ClientResult<crate::api::chat::Attachment> {
+        let (name, data) = source.into().read().await?;
+        let mimetype = guess_mimetype(&name, &data).to_string();
+        let size = file_size(&data);
+
+        let id = self
+            .upload_extract_id(name.clone(), mimetype.clone(), data)
+            .await?;
+
+        Ok(crate::api::chat::Attachment {
+            id,
+            name,
+            mimetype,
+            size,
+            ..Default::default()
+        })
+    }
+
+    /// Uploads an image and returns a [`Photo`] describing it, which can be
+    /// used with [`SendMessageRequest::with_photo_content()`].
+    ///
+    /// Width and height of the photo are read from the image header, see
+    /// [`image_dimensions()`]. If the `image` feature is enabled, the image
+    /// is decoded and a minithumbnail is generated as well, see
+    /// [`make_photo()`].
+    ///
+    /// [`Photo`]: crate::api::chat::Photo
+    /// [`SendMessageRequest::with_photo_content()`]: crate::api::chat::SendMessageRequest::with_photo_content
+    /// [`make_photo()`]: crate::api::chat::photo::make_photo
+    #[cfg(feature = "gen_chat")]
+    pub async fn upload_photo(
+        &self,
+        source: impl Into<UploadSource>,
+        caption: impl Into<crate::api::chat::FormattedText>,
+    ) -> ClientResult<crate::api::chat::Photo> {
+        let (name, data) = source.into().read().await?;
+        let mimetype = guess_mimetype(&name, &data);
+        if !mimetype.starts_with("image/") {
+            return Err(ClientError::invalid_data(
+                InvalidDataKind::Upload,
+                format!("{} is not an image", name),
+            ));
+        }
+
+        #[cfg(feature = "image")]
+        let photo = crate::api::chat::photo::make_photo(&data).map_err(|err| {
+            ClientError::invalid_data(InvalidDataKind::Upload, format!("{}: {}", name, err))
+        })?;
+        #[cfg(not(feature = "image"))]
+        let photo = {
+            let (width, height) = image_dimensions(&data).unwrap_or_default();
+            crate::api::chat::Photo {
+                file_size: file_size(&data),
+                width,
+                height,
+                ..Default::default()
+            }
+        };
+
+        let id = self
+            .upload_extract_id(name.clone(), mimetype.to_string(), data)
+            .await?;
+        let hmc = self.make_hmc(id).map_err(ClientError::unexpected)?;
+
+        Ok(crate::api::chat::Photo {
+            hmc: hmc.into(),
+            name,
+            caption: Some(caption.into()),
+            ..photo
+        })
+    }
+
     /// Downloads a file then extracts file information from it.
     ///
     /// Also see [`Client::download()`].
@@ -117,28 +223,122 @@ impl Client {
     ) -> ClientResult<DownloadedFile> {
         let resp = self.download(file_id).await?;
 
-        let (name, mimetype, kind) = extract_file_info_from_download_response(resp.headers())
-            .map(|(name, mimetype, kind)| {
-                (
-                    name.to_owned(),
-                    mimetype
-                        .to_str()
-                        .map(ToOwned::to_owned)
-                        .map_err(|_| ClientError::unexpected("Content-Type is not ASCII")),
-                    kind,
-                )
-            })
-            .map_err(ClientError::unexpected)?;
+        let FileInfo {
+            name,
+            mimetype,
+            kind,
+        } = extract_file_info(&resp)?;
 
         let data = resp.bytes().await?;
 
         Ok(DownloadedFile {
             data,
-            mimetype: mimetype?,
+            mimetype,
             kind,
             name,
         })
     }
+
+    /// Downloads a file as a stream of chunks, instead of collecting the whole
+    /// body into memory.
+    ///
+    /// Also see [`Client::download_stream_from()`].
+    #[cfg(all(feature = "client_native", not(feature = "client_web")))]
+    pub async fn download_stream(
+        &self,
+        file_id: impl Into<FileId>,
+    ) -> ClientResult<DownloadStream> {
+        self.download_stream_from(file_id, 0).await
+    }
+
+    /// Downloads a file as a stream of chunks, starting from the given byte
+    /// offset. This can be used to resume an interrupted download.
+    ///
+    /// Servers are not required to support ranges, in which case the whole
+    /// file will be sent. Use [`DownloadStream::offset()`] to check where
+    /// the returned stream actually starts from.
+    #[cfg(all(feature = "client_native", not(feature = "client_web")))]
+    pub async fn download_stream_from(
+        &self,
+        file_id: impl Into<FileId>,
+        offset: u64,
+    ) -> ClientResult<DownloadStream> {
+        let resp = self.download_from(file_id.into(), offset).await?;
+        DownloadStream::new(resp, offset)
+    }
+
+    /// Downloads a file directly to the given path, reporting progress after
+    /// each received chunk.
+    ///
+    /// If a file already exists at `path`, it is assumed to be a previously
+    /// interrupted download of the same file and the download will be resumed
+    /// from its end. If the file is already complete, only its last byte is
+    /// downloaded again. If the server can't resume it, the file will be
+    /// overwritten.
+    #[cfg(all(feature = "client_native", not(feature = "client_web")))]
+    pub async fn download_to_file(
+        &self,
+        file_id: impl Into<FileId>,
+        path: impl AsRef<std::path::Path>,
+        mut on_progress: impl FnMut(DownloadProgress),
+    ) -> ClientResult<FileInfo> {
+        use std::io::SeekFrom;
+        use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+        let file_id = file_id.into();
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .await?;
+        let existing_len = file.metadata().await?.len();
+
+        let response = self.send_download(file_id.clone(), existing_len).await?;
+        let mut stream = if response.status() == http::StatusCode::RANGE_NOT_SATISFIABLE {
+            let total = response
+                .headers()
+                .get(http::header::CONTENT_RANGE)
+                .and_then(|h| h.to_str().ok())
+                .and_then(parse_unsatisfied_range);
+            if existing_len > 0 && total == Some(existing_len) {
+                // The local file is already complete. Get its last byte
+                // again, since the file info comes with the response.
+                self.download_stream_from(file_id, existing_len - 1).await?
+            } else {
+                // The local file is not the same file, start over
+                self.download_stream(file_id).await?
+            }
+        } else {
+            DownloadStream::new(response.error_for_status()?, existing_len)?
+        };
+
+        file.set_len(stream.offset()).await?;
+        file.seek(SeekFrom::Start(stream.offset())).await?;
+        on_progress(stream.progress());
+
+        while let Some(chunk) = stream.next_chunk().await? {
+            file.write_all(&chunk).await?;
+            on_progress(stream.progress());
+        }
+        file.flush().await?;
+
+        Ok(stream.info)
+    }
+}
+
+fn extract_file_info(resp: &Response) -> ClientResult<FileInfo> {
+    let (name, mimetype, kind) = extract_file_info_from_download_response(resp.headers())
+        .map_err(ClientError::unexpected)?;
+    let mimetype = mimetype
+        .to_str()
+        .map_err(|_| ClientError::unexpected("Content-Type is not ASCII"))?;
+
+    Ok(FileInfo {
+        name: name.to_owned(),
+        mimetype: mimetype.to_owned(),
+        kind,
+    })
 }
 
 /// A downloaded file.
@@ -154,3 +354,232 @@ pub struct DownloadedFile {
     /// Name of the downloaded file.
     pub name: String,
 }
+
+/// A file to upload.
+#[derive(Debug, Clone)]
+pub enum UploadSource {
+    /// A file on the filesystem. The file name will be used as the name of
+    /// the upload.
+    #[cfg(all(feature = "client_native", not(feature = "client_web")))]
+    Path(std::path::PathBuf),
+    /// A file in memory.
+    Bytes {
+        /// Name of the file.
+        name: String,
+        /// Contents of the file.
+        data: Vec<u8>,
+    },
+}
+
+impl UploadSource {
+    /// Create an upload source from a file name and its contents.
+    pub fn bytes(name: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self::Bytes {
+            name: name.into(),
+            data: data.into(),
+        }
+    }
+
+    async fn read(self) -> ClientResult<(String, Vec<u8>)> {
+        match self {
+            #[cfg(all(feature = "client_native", not(feature = "client_web")))]
+            UploadSource::Path(path) => {
+                let name = path.file_name().map_or_else(
+                    || "unknown".to_string(),
+                    |n| n.to_string_lossy().into_owned(),
+                );
+                let data = tokio::fs::read(&path).await?;
+                Ok((name, data))
+            }
+            UploadSource::Bytes { name, data } => Ok((name, data)),
+        }
+    }
+}
+
+#[cfg(all(feature = "client_native", not(feature = "client_web")))]
+impl From<std::path::PathBuf> for UploadSource {
+    fn from(path: std::path::PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+#[cfg(all(feature = "client_native", not(feature = "client_web")))]
+impl From<&std::path::Path> for UploadSource {
+    fn from(path: &std::path::Path) -> Self {
+        Self::Path(path.to_path_buf())
+    }
+}
+
+impl<Name: Into<String>, Data: Into<Vec<u8>>> From<(Name, Data)> for UploadSource {
+    fn from((name, data): (Name, Data)) -> Self {
+        Self::bytes(name, data)
+    }
+}
+
+#[cfg(feature = "gen_chat")]
+fn file_size(data: &[u8]) -> u32 {
+    u32::try_from(data.len()).unwrap_or(u32::MAX)
+}
+
+/// Information about a file, extracted from a download response.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct FileInfo {
+    /// Name of the file.
+    pub name: String,
+    /// Mimetype of the file.
+    pub mimetype: String,
+    /// File kind of the file.
+    pub kind: FileKind,
+}
+
+/// Progress of a download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadProgress {
+    /// Amount of bytes downloaded so far, including the bytes skipped if the
+    /// download was resumed.
+    pub downloaded: u64,
+    /// Total size of the file in bytes, if the server reported it.
+    pub total: Option<u64>,
+}
+
+/// A file that is being downloaded as a stream of chunks.
+#[cfg(all(feature = "client_native", not(feature = "client_web")))]
+#[derive(Debug)]
+pub struct DownloadStream {
+    info: FileInfo,
+    offset: u64,
+    downloaded: u64,
+    total: Option<u64>,
+    response: Response,
+}
+
+#[cfg(all(feature = "client_native", not(feature = "client_web")))]
+impl DownloadStream {
+    /// Create a stream for the response to a request for the file starting
+    /// at `requested_offset`.
+    fn new(response: Response, requested_offset: u64) -> ClientResult<Self> {
+        let info = extract_file_info(&response)?;
+
+        // Only trust the offset if the server actually sent us a part of the file
+        let (offset, total) = if response.status() == http::StatusCode::PARTIAL_CONTENT {
+            let (offset, total) = response
+                .headers()
+                .get(http::header::CONTENT_RANGE)
+                .and_then(|h| h.to_str().ok())
+                .and_then(parse_content_range)
+                .ok_or_else(|| ClientError::unexpected("invalid or missing Content-Range"))?;
+            // Appending a part that starts elsewhere would corrupt the file
+            if offset != requested_offset {
+                return Err(ClientError::unexpected(format!(
+                    "Content-Range starts at {} instead of {}",
+                    offset, requested_offset
+                )));
+            }
+            (offset, total)
+        } else {
+            (0, response.content_length())
+        };
+
+        Ok(Self {
+            info,
+            offset,
+            downloaded: offset,
+            total,
+            response,
+        })
+    }
+
+    /// Information about the file being downloaded.
+    #[inline(always)]
+    pub fn info(&self) -> &FileInfo {
+        &self.info
+    }
+
+    /// The byte offset this stream starts from.
+    #[inline(always)]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Current progress of this download.
+    #[inline(always)]
+    pub fn progress(&self) -> DownloadProgress {
+        DownloadProgress {
+            downloaded: self.downloaded,
+            total: self.total,
+        }
+    }
+
+    /// Get the next chunk of the file.
+    ///
+    /// Returns `Ok(None)` if the download is complete.
+    pub async fn next_chunk(&mut self) -> ClientResult<Option<Bytes>> {
+        let chunk = self.response.chunk().await?;
+        if let Some(chunk) = chunk.as_ref() {
+            self.downloaded += chunk.len() as u64;
+        }
+        Ok(chunk)
+    }
+
+    /// Turn this download into a [`Stream`] of chunks.
+    ///
+    /// [`Stream`]: hrpc::exports::futures_util::Stream
+    pub fn into_stream(
+        self,
+    ) -> impl hrpc::exports::futures_util::Stream<Item = ClientResult<Bytes>> + Send + 'static {
+        hrpc::exports::futures_util::stream::try_unfold(self, |mut this| async move {
+            Ok(this.next_chunk().await?.map(|chunk| (chunk, this)))
+        })
+    }
+}
+
+/// Parses a `Content-Range` header value, returning the start offset and the
+/// total size if it is known.
+#[cfg(all(feature = "client_native", not(feature = "client_web")))]
+fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    let total = match total {
+        "*" => None,
+        total => Some(total.parse().ok()?),
+    };
+    Some((start.parse().ok()?, total))
+}
+
+/// Parses the `Content-Range` header value of a `416 Range Not Satisfiable`
+/// response, returning the total size of the file.
+#[cfg(all(feature = "client_native", not(feature = "client_web")))]
+fn parse_unsatisfied_range(value: &str) -> Option<u64> {
+    value.strip_prefix("bytes */")?.parse().ok()
+}
+
+#[cfg(all(test, feature = "client_native", not(feature = "client_web")))]
+mod tests {
+    use super::{parse_content_range, parse_unsatisfied_range};
+
+    #[test]
+    fn content_range_with_total() {
+        assert_eq!(
+            parse_content_range("bytes 200-1023/1024"),
+            Some((200, Some(1024)))
+        );
+    }
+
+    #[test]
+    fn content_range_unknown_total() {
+        assert_eq!(parse_content_range("bytes 0-99/*"), Some((0, None)));
+    }
+
+    #[test]
+    fn content_range_invalid() {
+        assert_eq!(parse_content_range("bytes */1024"), None);
+        assert_eq!(parse_content_range("items 0-1/2"), None);
+    }
+
+    #[test]
+    fn unsatisfied_range() {
+        assert_eq!(parse_unsatisfied_range("bytes */1024"), Some(1024));
+        assert_eq!(parse_unsatisfied_range("bytes 0-1/2"), None);
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use ed25519_dalek::Signer;
+use hrpc::client::transport::http::Hyper;
+use http::{header::AUTHORIZATION, HeaderValue, Uri};
+use prost::Message;
+
+use super::error::{ClientError, ClientResult, InternalClientError};
+use crate::api::{
+    auth::{auth_service_client::AuthServiceClient, KeyRequest, KeyResponse},
+    harmonytypes::Token,
+    sync::{postbox_service_client::PostboxServiceClient, *},
+};
+
+pub use ed25519_dalek::{Keypair, PublicKey};
+
+/// Maximum delay between two attempts to push queued events to a host.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+type PostboxService = PostboxServiceClient<Hyper>;
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn host_uri(host: &str) -> ClientResult<Uri> {
+    format!("https://{}", host).parse().map_err(Into::into)
+}
+
+fn transport(host: &str) -> ClientResult<hrpc::client::Client<Hyper>> {
+    let transport = Hyper::new(host_uri(host)?)
+        .map_err(|err| ClientError::Internal(InternalClientError::Transport(err)))?;
+    Ok(hrpc::client::Client::new(transport))
+}
+
+/// Create a sync auth token, to be sent in the `Authorization` header of
+/// requests to other homeservers' postbox service.
+///
+/// The token is a base64 encoded [`Token`], whose data is an [`AuthData`]
+/// for `server_id` (the host of the homeserver sending the request) signed
+/// with the homeserver's key.
+pub fn make_token(keypair: &Keypair, server_id: &str) -> String {
+    make_token_at(keypair, server_id, unix_time())
+}
+
+pub(crate) fn make_token_at(keypair: &Keypair, server_id: &str, time: u64) -> String {
+    let data = AuthData {
+        server_id: server_id.to_string(),
+        time,
+    }
+    .encode_to_vec();
+    let token = Token {
+        sig: keypair.sign(&data).to_bytes().to_vec(),
+        data,
+    };
+    base64::encode(token.encode_to_vec())
+}
+
+/// Create a response for the auth service's `Key` endpoint, which other
+/// homeservers use to verify sync auth tokens.
+pub fn key_response(keypair: &Keypair) -> KeyResponse {
+    KeyResponse {
+        key: keypair.public.to_bytes().to_vec(),
+    }
+}
+
+/// Fetch the public key of a homeserver using the auth service's `Key`
+/// endpoint.
+pub async fn fetch_key(host: &str) -> ClientResult<PublicKey> {
+    let mut auth = AuthServiceClient::new_inner(transport(host)?);
+    let response = auth
+        .key(KeyRequest::default())
+        .await?
+        .into_message()
+        .await?;
+    PublicKey::from_bytes(&response.key).map_err(ClientError::unexpected)
+}
+
+/// Returns how long to wait before trying to push to a host again after
+/// `failures` failed attempts.
+fn retry_delay(failures: u32) -> Duration {
+    Duration::from_secs(1_u64 << failures.min(12)).min(MAX_RETRY_DELAY)
+}
+
+#[derive(Debug, Default)]
+struct HostQueue {
+    events: VecDeque<Event>,
+    failures: u32,
+    next_attempt: Option<Instant>,
+}
+
+impl HostQueue {
+    fn is_due(&self, now: Instant) -> bool {
+        match self.next_attempt {
+            _ if self.events.is_empty() => false,
+            Some(at) => at <= now,
+            None => true,
+        }
+    }
+
+    fn failed(&mut self, now: Instant) {
+        self.failures += 1;
+        self.next_attempt = Some(now + retry_delay(self.failures));
+    }
+
+    fn succeeded(&mut self) {
+        self.failures = 0;
+        self.next_attempt = None;
+    }
+}
+
+/// Pushes [`Event`]s to and pulls them from other homeservers'
+/// postbox services.
+///
+/// Events can be queued per host with [`SyncClient::queue()`], and are sent
+/// in order by [`SyncClient::flush()`]. Hosts that fail are retried with
+/// exponential backoff.
+pub struct SyncClient {
+    keypair: Arc<Keypair>,
+    server_id: String,
+    services: Mutex<HashMap<String, PostboxService>>,
+    queues: Mutex<HashMap<String, HostQueue>>,
+}
+
+impl Debug for SyncClient {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncClient")
+            .field("server_id", &self.server_id)
+            .finish()
+    }
+}
+
+impl SyncClient {
+    /// Create a new sync client. `server_id` is the host of this homeserver,
+    /// and `keypair` is its key.
+    pub fn new(keypair: Arc<Keypair>, server_id: impl Into<String>) -> Self {
+        Self {
+            keypair,
+            server_id: server_id.into(),
+            services: Mutex::new(HashMap::new()),
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Push an event to a host right away.
+    pub async fn push(&self, host: &str, event: Event) -> ClientResult<()> {
+        let request = self.authorized(PushRequest { event: Some(event) });
+        let fut = self.service(host)?.push(request);
+        fut.await?.into_message().await?;
+        Ok(())
+    }
+
+    /// Pull the events a host has queued for this homeserver.
+    pub async fn pull(&self, host: &str) -> ClientResult<Vec<Event>> {
+        let request = self.authorized(PullRequest::default());
+        let fut = self.service(host)?.pull(request);
+        let response = fut.await?.into_message().await?;
+        Ok(response.event_queue)
+    }
+
+    /// Queue an event to be pushed to a host by [`SyncClient::flush()`].
+    pub fn queue(&self, host: impl Into<String>, event: Event) {
+        self.queues
+            .lock()
+            .expect("poisoned")
+            .entry(host.into())
+            .or_default()
+            .events
+            .push_back(event);
+    }
+
+    /// Get the number of events queued for a host.
+    pub fn queued(&self, host: &str) -> usize {
+        self.queues
+            .lock()
+            .expect("poisoned")
+            .get(host)
+            .map_or(0, |queue| queue.events.len())
+    }
+
+    /// Push queued events to all hosts that aren't waiting for a retry.
+    ///
+    /// Events are pushed in the order they were queued. If pushing to a host
+    /// fails, the remaining events for that host stay queued and the host is
+    /// retried later. Returns the errors that occurred, per host.
+    pub async fn flush(&self) -> Vec<(String, ClientError)> {
+        let now = Instant::now();
+        let due = self
+            .queues
+            .lock()
+            .expect("poisoned")
+            .iter()
+            .filter(|(_, queue)| queue.is_due(now))
+            .map(|(host, _)| host.clone())
+            .collect::<Vec<_>>();
+
+        let mut errors = Vec::new();
+        for host in due {
+            if let Err(err) = self.flush_host(&host).await {
+                tracing::warn!("failed to push sync events to {}: {}", host, err);
+                if let Some(queue) = self.queues.lock().expect("poisoned").get_mut(&host) {
+                    queue.failed(Instant::now());
+                }
+                errors.push((host, err));
+            }
+        }
+
+        errors
+    }
+
+    async fn flush_host(&self, host: &str) -> ClientResult<()> {
+        loop {
+            let event = {
+                let mut queues = self.queues.lock().expect("poisoned");
+                match queues.get_mut(host).and_then(|queue| queue.events.front()) {
+                    Some(event) => event.clone(),
+                    None => {
+                        queues.remove(host);
+                        return Ok(());
+                    }
+                }
+            };
+
+            self.push(host, event).await?;
+
+            if let Some(queue) = self.queues.lock().expect("poisoned").get_mut(host) {
+                queue.events.pop_front();
+                queue.succeeded();
+            }
+        }
+    }
+
+    fn authorized<T: Message>(&self, message: T) -> hrpc::Request<T> {
+        let token = make_token(&self.keypair, &self.server_id);
+        let mut request = hrpc::Request::new(&message);
+        request.get_or_insert_header_map().insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&token).expect("base64 is a valid header value"),
+        );
+        request
+    }
+
+    fn service(&self, host: &str) -> ClientResult<PostboxService> {
+        let mut services = self.services.lock().expect("poisoned");
+        if let Some(service) = services.get(host) {
+            return Ok(service.clone());
+        }
+        let service = PostboxService::new_inner(transport(host)?);
+        services.insert(host.to_string(), service.clone());
+        Ok(service)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially() {
+        assert_eq!(retry_delay(1), Duration::from_secs(2));
+        assert_eq!(retry_delay(5), Duration::from_secs(32));
+        assert_eq!(retry_delay(100), MAX_RETRY_DELAY);
+
+        let now = Instant::now();
+        let mut queue = HostQueue::default();
+        assert!(!queue.is_due(now));
+        queue.events.push_back(Event::default());
+        assert!(queue.is_due(now));
+        queue.failed(now);
+        assert!(!queue.is_due(now));
+        assert!(queue.is_due(now + Duration::from_secs(2)));
+    }
+}
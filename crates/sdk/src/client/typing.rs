@@ -0,0 +1,279 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::{error::ClientResult, Client};
+use crate::api::chat::{stream_event, Event, TypingRequest};
+
+/// Default minimum time between two typing notifications sent for the same
+/// channel.
+pub const DEFAULT_THROTTLE: Duration = Duration::from_secs(3);
+/// Default time after which a user that didn't send a typing notification
+/// is no longer considered typing.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+type ChannelKey = (u64, u64);
+
+/// Users typing in a channel, with the time they started typing and the time
+/// of their last typing notification.
+#[derive(Debug, Default)]
+struct Typists {
+    users: Vec<(u64, Instant, Instant)>,
+}
+
+impl Typists {
+    fn record(&mut self, user_id: u64, now: Instant, timeout: Duration) {
+        self.expire(now, timeout);
+        match self.users.iter_mut().find(|(id, _, _)| *id == user_id) {
+            Some((_, _, last_seen)) => *last_seen = now,
+            None => self.users.push((user_id, now, now)),
+        }
+    }
+
+    fn remove(&mut self, user_id: u64) -> bool {
+        let len = self.users.len();
+        self.users.retain(|(id, _, _)| *id != user_id);
+        len != self.users.len()
+    }
+
+    fn expire(&mut self, now: Instant, timeout: Duration) {
+        self.users
+            .retain(|(_, _, last_seen)| now.saturating_duration_since(*last_seen) < timeout);
+    }
+}
+
+/// Throttles outgoing typing notifications and keeps track of users typing
+/// in channels.
+///
+/// Call [`TypingManager::notify_typing()`] whenever the user types, and
+/// pass all received events to [`TypingManager::handle_event()`].
+#[derive(Debug)]
+pub struct TypingManager {
+    client: Client,
+    throttle: Duration,
+    timeout: Duration,
+    last_sent: Mutex<HashMap<ChannelKey, Instant>>,
+    typing: Mutex<HashMap<ChannelKey, Typists>>,
+}
+
+impl TypingManager {
+    /// Create a new typing manager that uses the given client.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            throttle: DEFAULT_THROTTLE,
+            timeout: DEFAULT_TIMEOUT,
+            last_sent: Mutex::new(HashMap::new()),
+            typing: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set the minimum time between two typing notifications sent for the
+    /// same channel. Defaults to [`DEFAULT_THROTTLE`].
+    pub fn with_throttle(mut self, throttle: Duration) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Set the time after which users are no longer considered typing if
+    /// they don't send another typing notification. Defaults to
+    /// [`DEFAULT_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Notify a channel that the current user is typing, unless a
+    /// notification was sent recently. Returns `true` if a notification was
+    /// sent.
+    pub async fn notify_typing(&self, guild_id: u64, channel_id: u64) -> ClientResult<bool> {
+        let key = (guild_id, channel_id);
+        let now = Instant::now();
+        let previous = {
+            let mut last_sent = self.last_sent.lock().expect("poisoned");
+            match last_sent.get(&key) {
+                Some(sent_at) if now.saturating_duration_since(*sent_at) < self.throttle => {
+                    return Ok(false)
+                }
+                _ => last_sent.insert(key, now),
+            }
+        };
+
+        let request = TypingRequest {
+            guild_id,
+            channel_id,
+        };
+        if let Err(err) = self.client.call(request).await {
+            // Allow retrying right away
+            let mut last_sent = self.last_sent.lock().expect("poisoned");
+            match previous {
+                Some(previous) => last_sent.insert(key, previous),
+                None => last_sent.remove(&key),
+            };
+            return Err(err);
+        }
+
+        Ok(true)
+    }
+
+    /// Reset the throttle for a channel, for example after the current user
+    /// sends a message, so that the next keystroke notifies the channel again.
+    pub fn reset_throttle(&self, guild_id: u64, channel_id: u64) {
+        self.last_sent
+            .lock()
+            .expect("poisoned")
+            .remove(&(guild_id, channel_id));
+    }
+
+    /// Update typing users using an event. Returns `true` if the typing
+    /// users of a channel changed.
+    ///
+    /// Typing events mark their author as typing, and sent messages mark
+    /// their author as no longer typing.
+    pub fn handle_event(&self, event: &Event) -> bool {
+        let mut typing = self.typing.lock().expect("poisoned");
+        match event {
+            Event::Chat(stream_event::Event::Typing(typing_event)) => {
+                typing
+                    .entry((typing_event.guild_id, typing_event.channel_id))
+                    .or_default()
+                    .record(typing_event.user_id, Instant::now(), self.timeout);
+                true
+            }
+            Event::Chat(stream_event::Event::SentMessage(sent)) => {
+                let author_id = match &sent.message {
+                    Some(message) => message.author_id,
+                    None => return false,
+                };
+                match typing.get_mut(&(sent.guild_id, sent.channel_id)) {
+                    Some(typists) => typists.remove(author_id),
+                    None => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    /// Get the users that are currently typing in a channel, ordered by when
+    /// they started typing. The current user is never included.
+    pub fn typing_users(&self, guild_id: u64, channel_id: u64) -> Vec<u64> {
+        let own_id = self.client.user_id();
+        let mut typing = self.typing.lock().expect("poisoned");
+        let typists = match typing.get_mut(&(guild_id, channel_id)) {
+            Some(typists) => typists,
+            None => return Vec::new(),
+        };
+        typists.expire(Instant::now(), self.timeout);
+
+        let mut users = typists
+            .users
+            .iter()
+            .filter(|(id, _, _)| Some(*id) != own_id)
+            .map(|(id, started, _)| (*id, *started))
+            .collect::<Vec<_>>();
+        users.sort_by_key(|(_, started)| *started);
+        users.into_iter().map(|(id, _)| id).collect()
+    }
+
+    /// Describe who is typing in a channel, like `"alice and bob are typing"`.
+    /// `name` is used to get the display name of users.
+    ///
+    /// Returns `None` if nobody is typing. See [`describe_typing()`].
+    pub fn typing_text(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        name: impl FnMut(u64) -> String,
+    ) -> Option<String> {
+        let names = self
+            .typing_users(guild_id, channel_id)
+            .into_iter()
+            .map(name)
+            .collect::<Vec<_>>();
+        describe_typing(&names)
+    }
+
+    /// Remove all expired typing users.
+    pub fn clear_expired(&self) {
+        let now = Instant::now();
+        let mut typing = self.typing.lock().expect("poisoned");
+        for typists in typing.values_mut() {
+            typists.expire(now, self.timeout);
+        }
+        typing.retain(|_, typists| !typists.users.is_empty());
+
+        let throttle = self.throttle;
+        self.last_sent
+            .lock()
+            .expect("poisoned")
+            .retain(|_, sent_at| now.saturating_duration_since(*sent_at) < throttle);
+    }
+}
+
+/// Describe who is typing, like `"alice and bob are typing"`.
+///
+/// At most three names are listed; more users are summarized, like
+/// `"alice, bob and 2 others are typing"`. Returns `None` if `names` is empty.
+///
+/// # Example
+/// ```
+/// # use harmony_rust_sdk::client::typing::describe_typing;
+/// assert_eq!(describe_typing(&["alice"]).unwrap(), "alice is typing");
+/// assert_eq!(describe_typing(&["alice", "bob"]).unwrap(), "alice and bob are typing");
+/// ```
+pub fn describe_typing<Name: AsRef<str>>(names: &[Name]) -> Option<String> {
+    let names = names.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+    let text = match names.as_slice() {
+        [] => return None,
+        [one] => format!("{} is typing", one),
+        [first, second] => format!("{} and {} are typing", first, second),
+        [first, second, third] => format!("{}, {} and {} are typing", first, second, third),
+        [first, second, rest @ ..] => {
+            format!("{}, {} and {} others are typing", first, second, rest.len())
+        }
+    };
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_typing_users() {
+        assert_eq!(describe_typing::<&str>(&[]), None);
+        assert_eq!(
+            describe_typing(&["a", "b", "c"]).unwrap(),
+            "a, b and c are typing"
+        );
+        assert_eq!(
+            describe_typing(&["a", "b", "c", "d"]).unwrap(),
+            "a, b and 2 others are typing"
+        );
+    }
+
+    #[test]
+    fn expires_typing_users() {
+        let timeout = Duration::from_secs(5);
+        let start = Instant::now();
+        let mut typists = Typists::default();
+        typists.record(1, start, timeout);
+        typists.record(2, start + Duration::from_secs(3), timeout);
+        typists.record(1, start + Duration::from_secs(4), timeout);
+
+        typists.expire(start + Duration::from_secs(7), timeout);
+        assert_eq!(typists.users.len(), 2);
+        // User 1 keeps their original start time
+        assert_eq!(typists.users[0].1, start);
+
+        typists.expire(start + Duration::from_millis(8500), timeout);
+        assert_eq!(
+            typists.users.iter().map(|u| u.0).collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert!(typists.remove(1));
+        assert!(!typists.remove(1));
+    }
+}
@@ -0,0 +1,748 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+};
+
+use hrpc::{client::socket::Socket, exports::futures_util::future::BoxFuture};
+
+use super::{
+    error::{ClientError, ClientResult, ExternalKind},
+    Client,
+};
+use crate::api::voice::{StreamMessageRequest, StreamMessageResponse};
+
+pub use crate::api::voice::{
+    stream_message_request as request, stream_message_response as response, TransportOptions,
+    UserConsumerOptions,
+};
+
+/// A two-way signaling channel to a voice server.
+///
+/// [`VoiceSocket`] implements this over the voice service stream, and
+/// [`LocalSignaling`] implements it in memory for tests.
+pub trait Signaling: Send {
+    /// Send a signaling message to the server.
+    fn send(&mut self, message: request::Message) -> BoxFuture<'_, ClientResult<()>>;
+    /// Wait for the next signaling message from the server.
+    fn receive(&mut self) -> BoxFuture<'_, ClientResult<response::Message>>;
+    /// Close the signaling channel.
+    fn close(&mut self) -> BoxFuture<'_, ClientResult<()>>;
+}
+
+/// Parameters the media backend answers the server's transport offer with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JoinParameters {
+    /// RTP parameters of the audio we produce.
+    pub rtp_parameters: String,
+    /// DTLS parameters of the producer (sending) transport.
+    pub producer_dtls_parameters: String,
+    /// DTLS parameters of the consumer (receiving) transport.
+    pub consumer_dtls_parameters: String,
+}
+
+/// Hooks for the media backend that sends and receives the actual audio.
+///
+/// The voice service negotiates media in these steps, each of which calls
+/// into the backend:
+/// 1. the server sends its RTP capabilities, and [`MediaBackend::load`]
+///    returns the ones the backend supports,
+/// 2. the server offers a producer and a consumer transport, with their ICE
+///    parameters, ICE candidates and DTLS parameters, and
+///    [`MediaBackend::connect`] answers them with [`JoinParameters`],
+/// 3. for every other user in the channel, [`MediaBackend::consume`] is
+///    called with the options for receiving their audio, and
+///    [`MediaBackend::remove`] is called once they leave.
+pub trait MediaBackend: Send {
+    /// Load the server's RTP capabilities, returning the backend's own.
+    fn load(&mut self, server_rtp_capabilities: &str) -> BoxFuture<'_, ClientResult<String>>;
+    /// Create the producer and consumer transports offered by the server.
+    fn connect(
+        &mut self,
+        producer: &TransportOptions,
+        consumer: &TransportOptions,
+    ) -> BoxFuture<'_, ClientResult<JoinParameters>>;
+    /// Start receiving a user's audio.
+    fn consume(&mut self, options: &UserConsumerOptions) -> BoxFuture<'_, ClientResult<()>>;
+    /// Stop receiving a user's audio.
+    fn remove(&mut self, user_id: u64) -> BoxFuture<'_, ClientResult<()>>;
+    /// Stop or resume sending our audio.
+    fn set_muted(&mut self, muted: bool) -> BoxFuture<'_, ClientResult<()>>;
+    /// Stop or resume playing received audio.
+    fn set_deafened(&mut self, deafened: bool) -> BoxFuture<'_, ClientResult<()>>;
+    /// Tear down all transports.
+    fn close(&mut self) -> BoxFuture<'_, ClientResult<()>>;
+}
+
+/// A user in a voice channel that we receive audio from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Participant {
+    /// ID of the user.
+    pub user_id: u64,
+    /// ID of the user's producer on the server.
+    pub producer_id: String,
+    /// ID of our consumer of the user's producer.
+    pub consumer_id: String,
+}
+
+impl From<&UserConsumerOptions> for Participant {
+    fn from(options: &UserConsumerOptions) -> Self {
+        Self {
+            user_id: options.user_id,
+            producer_id: options.producer_id.clone(),
+            consumer_id: options.consumer_id.clone(),
+        }
+    }
+}
+
+/// A change in a [`VoiceSession`]'s participants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoiceEvent {
+    /// A user joined the voice channel.
+    Joined(Participant),
+    /// A user left the voice channel.
+    Left(u64),
+}
+
+/// A joined voice channel.
+///
+/// Drive it by calling [`VoiceSession::next_event`] in a loop, which keeps
+/// the participants and the media backend up to date.
+pub struct VoiceSession<S, B> {
+    signaling: S,
+    backend: B,
+    guild_id: u64,
+    channel_id: u64,
+    participants: HashMap<u64, Participant>,
+    muted: bool,
+    deafened: bool,
+}
+
+impl<S: Signaling, B: MediaBackend> VoiceSession<S, B> {
+    /// Join a voice channel, negotiating media with the given backend.
+    pub async fn join(
+        mut signaling: S,
+        mut backend: B,
+        guild_id: u64,
+        channel_id: u64,
+    ) -> ClientResult<Self> {
+        signaling
+            .send(request::Message::Initialize(request::Initialize {
+                guild_id,
+                channel_id,
+            }))
+            .await?;
+        let initialized = match signaling.receive().await? {
+            response::Message::Initialized(initialized) => initialized,
+            other => return Err(unexpected("initialized", &other)),
+        };
+
+        let rtp_capabilities = backend.load(&initialized.rtp_capabilities).await?;
+        signaling
+            .send(request::Message::PrepareForJoinChannel(
+                request::PrepareForJoinChannel { rtp_capabilities },
+            ))
+            .await?;
+        let prepared = match signaling.receive().await? {
+            response::Message::PreparedForJoinChannel(prepared) => prepared,
+            other => return Err(unexpected("prepared for join channel", &other)),
+        };
+        let (producer, consumer) = prepared
+            .producer_transport_options
+            .zip(prepared.consumer_transport_options)
+            .ok_or_else(|| ClientError::unexpected("server didn't send transport options"))?;
+
+        let parameters = backend.connect(&producer, &consumer).await?;
+        // The field names are misspelled in voice.v1
+        signaling
+            .send(request::Message::JoinChannel(request::JoinChannel {
+                rtp_paramaters: parameters.rtp_parameters,
+                producer_dtls_paramaters: parameters.producer_dtls_parameters,
+                consumer_dtls_paramaters: parameters.consumer_dtls_parameters,
+            }))
+            .await?;
+        let joined = match signaling.receive().await? {
+            response::Message::JoinedChannel(joined) => joined,
+            other => return Err(unexpected("joined channel", &other)),
+        };
+
+        let mut session = Self {
+            signaling,
+            backend,
+            guild_id,
+            channel_id,
+            participants: HashMap::new(),
+            muted: false,
+            deafened: false,
+        };
+        for options in &joined.other_users {
+            session.add_participant(options).await?;
+        }
+
+        Ok(session)
+    }
+
+    async fn add_participant(
+        &mut self,
+        options: &UserConsumerOptions,
+    ) -> ClientResult<Participant> {
+        self.backend.consume(options).await?;
+        self.signaling
+            .send(request::Message::ResumeConsumer(request::ResumeConsumer {
+                consumer_id: options.consumer_id.clone(),
+            }))
+            .await?;
+        let participant = Participant::from(options);
+        self.participants
+            .insert(participant.user_id, participant.clone());
+        Ok(participant)
+    }
+
+    /// Wait for the next change in participants.
+    ///
+    /// Returns `Ok(None)` if the server sent a message that doesn't change
+    /// them, such as one for a user that already left.
+    pub async fn next_event(&mut self) -> ClientResult<Option<VoiceEvent>> {
+        match self.signaling.receive().await? {
+            response::Message::UserJoined(response::UserJoined {
+                data: Some(options),
+            }) => {
+                let participant = self.add_participant(&options).await?;
+                Ok(Some(VoiceEvent::Joined(participant)))
+            }
+            response::Message::UserLeft(response::UserLeft { user_id }) => {
+                if self.participants.remove(&user_id).is_none() {
+                    return Ok(None);
+                }
+                self.backend.remove(user_id).await?;
+                Ok(Some(VoiceEvent::Left(user_id)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Mute or unmute ourselves.
+    ///
+    /// This only stops or resumes sending audio in the media backend. The
+    /// `voice.v1` protocol has no signaling message for mute state, so other
+    /// participants aren't told about it.
+    pub async fn set_muted(&mut self, muted: bool) -> ClientResult<()> {
+        self.backend.set_muted(muted).await?;
+        self.muted = muted;
+        Ok(())
+    }
+
+    /// Deafen or undeafen ourselves.
+    ///
+    /// Like [`VoiceSession::set_muted()`], this is local to the media backend
+    /// and isn't signaled to the server.
+    pub async fn set_deafened(&mut self, deafened: bool) -> ClientResult<()> {
+        self.backend.set_deafened(deafened).await?;
+        self.deafened = deafened;
+        Ok(())
+    }
+
+    /// Leave the voice channel, closing the media backend and signaling.
+    pub async fn leave(mut self) -> ClientResult<()> {
+        self.backend.close().await?;
+        self.signaling.close().await
+    }
+}
+
+impl<S, B> VoiceSession<S, B> {
+    /// Guild ID of the voice channel.
+    pub fn guild_id(&self) -> u64 {
+        self.guild_id
+    }
+
+    /// ID of the voice channel.
+    pub fn channel_id(&self) -> u64 {
+        self.channel_id
+    }
+
+    /// Other users in the voice channel.
+    pub fn participants(&self) -> impl Iterator<Item = &Participant> + '_ {
+        self.participants.values()
+    }
+
+    /// Get a participant by their user ID.
+    pub fn participant(&self, user_id: u64) -> Option<&Participant> {
+        self.participants.get(&user_id)
+    }
+
+    /// Whether we are muted.
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Whether we are deafened.
+    pub fn is_deafened(&self) -> bool {
+        self.deafened
+    }
+
+    /// Get the media backend.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Get the media backend mutably.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+}
+
+impl<S, B> Debug for VoiceSession<S, B> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VoiceSession")
+            .field("guild_id", &self.guild_id)
+            .field("channel_id", &self.channel_id)
+            .field("participants", &self.participants)
+            .field("muted", &self.muted)
+            .field("deafened", &self.deafened)
+            .finish()
+    }
+}
+
+fn unexpected(expected: &str, got: &response::Message) -> ClientError {
+    ClientError::unexpected(format!(
+        "expected {} voice message, got {:?}",
+        expected, got
+    ))
+}
+
+/// Voice service stream socket.
+pub struct VoiceSocket {
+    inner: Option<Socket<StreamMessageRequest, StreamMessageResponse>>,
+}
+
+impl VoiceSocket {
+    fn socket(&mut self) -> ClientResult<&mut Socket<StreamMessageRequest, StreamMessageResponse>> {
+        self.inner
+            .as_mut()
+            .ok_or_else(|| ClientError::external(ExternalKind::Voice, "voice socket is closed"))
+    }
+}
+
+impl Signaling for VoiceSocket {
+    fn send(&mut self, message: request::Message) -> BoxFuture<'_, ClientResult<()>> {
+        Box::pin(async move {
+            let request = StreamMessageRequest {
+                message: Some(message),
+            };
+            self.socket()?
+                .send_message(request)
+                .await
+                .map_err(Into::into)
+        })
+    }
+
+    fn receive(&mut self) -> BoxFuture<'_, ClientResult<response::Message>> {
+        Box::pin(async move {
+            let socket = self.socket()?;
+            loop {
+                if let Some(message) = socket.receive_message().await?.message {
+                    return Ok(message);
+                }
+            }
+        })
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, ClientResult<()>> {
+        Box::pin(async move {
+            match self.inner.take() {
+                Some(socket) => socket.close().await.map_err(Into::into),
+                None => Ok(()),
+            }
+        })
+    }
+}
+
+impl Debug for VoiceSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("VoiceSocket")
+    }
+}
+
+impl Client {
+    /// Open a voice service stream.
+    pub async fn voice_stream(&self) -> ClientResult<VoiceSocket> {
+        let fut = self.voice().stream_message(());
+        let socket = fut.await?;
+        Ok(VoiceSocket {
+            inner: Some(socket),
+        })
+    }
+
+    /// Join a voice channel over a new voice service stream.
+    pub async fn join_voice<B: MediaBackend>(
+        &self,
+        guild_id: u64,
+        channel_id: u64,
+        backend: B,
+    ) -> ClientResult<VoiceSession<VoiceSocket, B>> {
+        let socket = self.voice_stream().await?;
+        VoiceSession::join(socket, backend, guild_id, channel_id).await
+    }
+}
+
+/// RTP capabilities the [`LocalVoiceServer`] sends.
+pub const LOCAL_RTP_CAPABILITIES: &str = "local";
+
+#[derive(Default)]
+struct Connection {
+    user_id: u64,
+    channel: Option<(u64, u64)>,
+    joined: bool,
+    resumed: HashSet<String>,
+    queue: VecDeque<response::Message>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+impl Connection {
+    fn push(&mut self, message: response::Message) {
+        self.queue.push_back(message);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+fn consumer_options(consumer: u64, producer: u64) -> UserConsumerOptions {
+    UserConsumerOptions {
+        user_id: producer,
+        producer_id: format!("producer-{}", producer),
+        consumer_id: format!("consumer-{}-{}", consumer, producer),
+        rtp_parameters: String::new(),
+    }
+}
+
+/// An in-memory stand-in for a voice server, for testing [`VoiceSession`]s
+/// without a homeserver.
+///
+/// Every connection is a user that can join a channel, and is told when other
+/// users join or leave the same channel. Transport options and consumer IDs
+/// are made up; nothing is checked about the media parameters.
+#[derive(Clone, Default)]
+pub struct LocalVoiceServer {
+    connections: Arc<Mutex<Vec<Connection>>>,
+}
+
+impl LocalVoiceServer {
+    /// Create a new local voice server.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connect to the server as a user.
+    pub fn connect(&self, user_id: u64) -> LocalSignaling {
+        let mut connections = self.connections.lock().expect("poisoned");
+        connections.push(Connection {
+            user_id,
+            ..Default::default()
+        });
+        LocalSignaling {
+            server: self.clone(),
+            index: connections.len() - 1,
+        }
+    }
+
+    /// Users that are in a voice channel.
+    pub fn users_in(&self, guild_id: u64, channel_id: u64) -> Vec<u64> {
+        let connections = self.connections.lock().expect("poisoned");
+        connections
+            .iter()
+            .filter(|conn| conn.joined && conn.channel == Some((guild_id, channel_id)))
+            .map(|conn| conn.user_id)
+            .collect()
+    }
+
+    /// Whether a user resumed a consumer, ie. is receiving audio from it.
+    pub fn is_resumed(&self, user_id: u64, consumer_id: &str) -> bool {
+        let connections = self.connections.lock().expect("poisoned");
+        connections
+            .iter()
+            .any(|conn| conn.user_id == user_id && conn.resumed.contains(consumer_id))
+    }
+
+    fn handle(&self, index: usize, message: request::Message) -> ClientResult<()> {
+        let mut connections = self.connections.lock().expect("poisoned");
+        let conn = &mut connections[index];
+        if conn.closed {
+            return Err(ClientError::external(
+                ExternalKind::Voice,
+                "signaling is closed",
+            ));
+        }
+        let user_id = conn.user_id;
+
+        match message {
+            request::Message::Initialize(request::Initialize {
+                guild_id,
+                channel_id,
+            }) => {
+                conn.channel = Some((guild_id, channel_id));
+                conn.push(response::Message::Initialized(response::Initialized {
+                    rtp_capabilities: LOCAL_RTP_CAPABILITIES.to_string(),
+                }));
+            }
+            request::Message::PrepareForJoinChannel(_) if conn.channel.is_some() => {
+                let transport = |kind: &str| TransportOptions {
+                    id: format!("{}-{}", kind, user_id),
+                    ..Default::default()
+                };
+                conn.push(response::Message::PreparedForJoinChannel(
+                    response::PreparedForJoinChannel {
+                        consumer_transport_options: Some(transport("consumer")),
+                        producer_transport_options: Some(transport("producer")),
+                    },
+                ));
+            }
+            request::Message::JoinChannel(_) if conn.channel.is_some() && !conn.joined => {
+                conn.joined = true;
+                let channel = conn.channel;
+                let mut other_users = Vec::new();
+                for other in connections.iter_mut().filter(|other| {
+                    other.joined && other.channel == channel && other.user_id != user_id
+                }) {
+                    other_users.push(consumer_options(user_id, other.user_id));
+                    other.push(response::Message::UserJoined(response::UserJoined {
+                        data: Some(consumer_options(other.user_id, user_id)),
+                    }));
+                }
+                connections[index].push(response::Message::JoinedChannel(
+                    response::JoinedChannel { other_users },
+                ));
+            }
+            request::Message::ResumeConsumer(request::ResumeConsumer { consumer_id })
+                if conn.joined =>
+            {
+                conn.resumed.insert(consumer_id);
+            }
+            message => {
+                return Err(ClientError::external(
+                    ExternalKind::Voice,
+                    format!("unexpected signaling message: {:?}", message),
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn close(&self, index: usize) {
+        let mut connections = self.connections.lock().expect("poisoned");
+        let conn = &mut connections[index];
+        if conn.closed {
+            return;
+        }
+        conn.closed = true;
+        let (user_id, channel, joined) = (conn.user_id, conn.channel, conn.joined);
+        conn.joined = false;
+        if let Some(waker) = conn.waker.take() {
+            waker.wake();
+        }
+
+        if joined {
+            for other in connections
+                .iter_mut()
+                .filter(|other| other.joined && other.channel == channel)
+            {
+                other.push(response::Message::UserLeft(response::UserLeft { user_id }));
+            }
+        }
+    }
+}
+
+impl Debug for LocalVoiceServer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("LocalVoiceServer")
+    }
+}
+
+/// A connection to a [`LocalVoiceServer`].
+///
+/// Dropping it leaves the channel, like closing it does.
+pub struct LocalSignaling {
+    server: LocalVoiceServer,
+    index: usize,
+}
+
+impl Signaling for LocalSignaling {
+    fn send(&mut self, message: request::Message) -> BoxFuture<'_, ClientResult<()>> {
+        let result = self.server.handle(self.index, message);
+        Box::pin(async move { result })
+    }
+
+    fn receive(&mut self) -> BoxFuture<'_, ClientResult<response::Message>> {
+        Box::pin(hrpc::exports::futures_util::future::poll_fn(move |cx| {
+            let mut connections = self.server.connections.lock().expect("poisoned");
+            let conn = &mut connections[self.index];
+            if let Some(message) = conn.queue.pop_front() {
+                Poll::Ready(Ok(message))
+            } else if conn.closed {
+                Poll::Ready(Err(ClientError::external(
+                    ExternalKind::Voice,
+                    "signaling is closed",
+                )))
+            } else {
+                conn.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }))
+    }
+
+    fn close(&mut self) -> BoxFuture<'_, ClientResult<()>> {
+        self.server.close(self.index);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl Drop for LocalSignaling {
+    fn drop(&mut self) {
+        self.server.close(self.index);
+    }
+}
+
+impl Debug for LocalSignaling {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("LocalSignaling")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hrpc::exports::futures_util::FutureExt;
+
+    #[derive(Debug, Default)]
+    struct Recorder {
+        calls: Vec<String>,
+    }
+
+    impl MediaBackend for Recorder {
+        fn load(&mut self, server_rtp_capabilities: &str) -> BoxFuture<'_, ClientResult<String>> {
+            self.calls.push(format!("load {}", server_rtp_capabilities));
+            Box::pin(async { Ok("client".to_string()) })
+        }
+
+        fn connect(
+            &mut self,
+            producer: &TransportOptions,
+            consumer: &TransportOptions,
+        ) -> BoxFuture<'_, ClientResult<JoinParameters>> {
+            self.calls
+                .push(format!("connect {} {}", producer.id, consumer.id));
+            Box::pin(async { Ok(JoinParameters::default()) })
+        }
+
+        fn consume(&mut self, options: &UserConsumerOptions) -> BoxFuture<'_, ClientResult<()>> {
+            self.calls.push(format!("consume {}", options.consumer_id));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn remove(&mut self, user_id: u64) -> BoxFuture<'_, ClientResult<()>> {
+            self.calls.push(format!("remove {}", user_id));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn set_muted(&mut self, muted: bool) -> BoxFuture<'_, ClientResult<()>> {
+            self.calls.push(format!("muted {}", muted));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn set_deafened(&mut self, deafened: bool) -> BoxFuture<'_, ClientResult<()>> {
+            self.calls.push(format!("deafened {}", deafened));
+            Box::pin(async { Ok(()) })
+        }
+
+        fn close(&mut self) -> BoxFuture<'_, ClientResult<()>> {
+            self.calls.push("close".to_string());
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    type Session = VoiceSession<LocalSignaling, Recorder>;
+
+    fn join(server: &LocalVoiceServer, user_id: u64) -> Session {
+        VoiceSession::join(server.connect(user_id), Recorder::default(), 1, 2)
+            .now_or_never()
+            .expect("local signaling is ready")
+            .unwrap()
+    }
+
+    fn next_event(session: &mut Session) -> Option<VoiceEvent> {
+        session.next_event().now_or_never().map(Result::unwrap)?
+    }
+
+    #[test]
+    fn join_negotiates_with_backend() {
+        let server = LocalVoiceServer::new();
+        let session = join(&server, 10);
+
+        assert_eq!(
+            session.backend().calls,
+            ["load local", "connect producer-10 consumer-10"]
+        );
+        assert_eq!(session.participants().count(), 0);
+        assert_eq!(server.users_in(1, 2), [10]);
+    }
+
+    #[test]
+    fn participants_are_tracked() {
+        let server = LocalVoiceServer::new();
+        let mut first = join(&server, 10);
+        let second = join(&server, 20);
+
+        assert_eq!(
+            second.participant(10).map(|p| p.consumer_id.as_str()),
+            Some("consumer-20-10")
+        );
+        assert!(server.is_resumed(20, "consumer-20-10"));
+
+        let joined = next_event(&mut first);
+        assert!(matches!(joined, Some(VoiceEvent::Joined(p)) if p.user_id == 20));
+        assert!(server.is_resumed(10, "consumer-10-20"));
+        assert!(next_event(&mut first).is_none());
+
+        second.leave().now_or_never().unwrap().unwrap();
+        assert_eq!(next_event(&mut first), Some(VoiceEvent::Left(20)));
+        assert_eq!(first.participants().count(), 0);
+        assert_eq!(first.backend().calls.last().unwrap(), "remove 20");
+        assert_eq!(server.users_in(1, 2), [10]);
+    }
+
+    #[test]
+    fn mute_and_deafen_reach_backend() {
+        let server = LocalVoiceServer::new();
+        let mut session = join(&server, 10);
+
+        session.set_muted(true).now_or_never().unwrap().unwrap();
+        session.set_deafened(true).now_or_never().unwrap().unwrap();
+
+        assert!(session.is_muted() && session.is_deafened());
+        assert_eq!(
+            session.backend().calls[2..],
+            ["muted true", "deafened true"]
+        );
+    }
+
+    #[test]
+    fn out_of_order_messages_are_rejected() {
+        let server = LocalVoiceServer::new();
+        let mut signaling = server.connect(10);
+
+        let result = signaling
+            .send(request::Message::JoinChannel(Default::default()))
+            .now_or_never()
+            .unwrap();
+        assert!(matches!(
+            result,
+            Err(ClientError::External {
+                kind: ExternalKind::Voice,
+                ..
+            })
+        ));
+    }
+}
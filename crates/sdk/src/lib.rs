@@ -26,6 +26,8 @@ env variable.
 
 - `message_log`: Showcases a simple message log bot that operates in a guild.
 It will log messages to the console whenever someone posts a message.
+- `homeserver`: A minimal in-memory homeserver, for running the other examples
+offline with `HOMESERVER=http://127.0.0.1:2289 GUILD_INVITE=lobby`.
 - Bot run instructions:
   - Run bots with `GUILD_INVITE=invite cargo run --example example_name`.
   - Make sure the bot has necessary permissions to view channels / send messages etc.
@@ -43,11 +45,19 @@ You can customize the crate to your needs by enabling feature(s) listed below:
   for all Harmony API types (except the `batch` service).
   - Enable `valuable` feature to derive `valuable::Valuable` for all Harmony
   API types (except the `batch` service).
+  - Enable `image` feature to generate photo dimensions, minithumbnails and
+  blurhashes from image data (see `api::chat::photo`).
   - customizing hRPC codegen:
     - Enable the `gen_client` feature to generate client service code for
     enabled protocols.
     - Enable the `gen_server` feature to generate server service code for
     enabled protocols.
+  - Server:
+    - Enable the `server` feature for helpers to implement servers with, like
+    session authentication, an event broadcaster and REST endpoint handlers
+    (see `server`).
+    - Enable the `server_sync` feature to verify the sync auth tokens of
+    incoming sync service requests with `server::SyncVerifier` (native only).
   - Client:
     - Enable the `client_native` feature for a lightweight client implementation
     that uses `hyper` and works on native platforms.
@@ -55,6 +65,17 @@ You can customize the crate to your needs by enabling feature(s) listed below:
     works on web platforms (WASM).
     - Enable the `client_backoff` feature to enable request retrying on ratelimited
     requests.
+    - Enable the `client_media_cache` feature for an on-disk media cache that
+    can be used with `Client::download_cached` (native only).
+    - Enable the `client_emote_packs` feature to export, import and sync emote
+    packs with `client::emote_packs::EmotePackManager` (native only).
+    - Enable the `client_guild_archive` feature to export guilds to archives
+    and restore them with `Client::export_guild` and `Client::import_guild`
+    (native only).
+    - Enable the `client_message_store` feature for an offline message store
+    with full-text search in `client::message_store` (native only).
+    - Enable the `client_sync` feature for federation helpers that sign, push
+    and pull sync service requests in `client::sync` (native only).
   - Stable protocols (enable `gen_stable_protocols` for all):
     - Enable the `gen_chat` feature to generate chat service code.
     - Enable the `gen_auth` feature to generate auth service code.
@@ -66,8 +87,10 @@ You can customize the crate to your needs by enabling feature(s) listed below:
     - Enable the `gen_batch` feature to generate batch service code.
     - Enable the `rest` feature to include REST API code.
   - Staging protocols (enable `gen_staging_protocols` for all):
-    - Enable the `staging_gen_voice` feature to generate voice service code.
-    - Enable the `staging_gen_bots` feature to generate bots service code.
+    - Enable the `staging_gen_voice` feature to generate voice service code, and the
+      `client::voice` module to join voice channels with a media backend.
+    - Enable the `staging_gen_bots` feature to generate bots service code, and the
+      `client::bots` module to create and manage bots and log in as one.
 
 ## MSRV
 
@@ -82,3 +105,10 @@ pub mod api;
 
 #[cfg(feature = "_client_common")]
 pub mod client;
+
+#[cfg(feature = "server")]
+pub mod server;
+
+// Lets tests use derive macros that refer to `::harmony_rust_sdk`
+#[cfg(test)]
+extern crate self as harmony_rust_sdk;
@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+
+use hrpc::{
+    exports::tower::{Layer, Service},
+    request::BoxRequest,
+};
+use http::{
+    header::{AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL},
+    HeaderMap,
+};
+
+use super::error::{ServerError, ServerResult};
+
+/// Looks up which user a session token belongs to.
+///
+/// Implemented for closures of type `Fn(&str) -> Option<u64>`.
+pub trait SessionStore: Send + Sync + 'static {
+    /// Get the ID of the user the session token belongs to, or `None` if the
+    /// token isn't valid.
+    fn user_id(&self, token: &str) -> Option<u64>;
+}
+
+impl<F> SessionStore for F
+where
+    F: Fn(&str) -> Option<u64> + Send + Sync + 'static,
+{
+    fn user_id(&self, token: &str) -> Option<u64> {
+        self(token)
+    }
+}
+
+/// A [`SessionStore`] that keeps sessions in memory.
+#[derive(Debug, Default)]
+pub struct MemorySessions {
+    sessions: RwLock<HashMap<String, u64>>,
+}
+
+impl MemorySessions {
+    /// Add a session for a user.
+    pub fn insert(&self, token: impl Into<String>, user_id: u64) {
+        self.sessions
+            .write()
+            .expect("poisoned")
+            .insert(token.into(), user_id);
+    }
+
+    /// Remove a session, returning the user it belonged to.
+    pub fn remove(&self, token: &str) -> Option<u64> {
+        self.sessions.write().expect("poisoned").remove(token)
+    }
+
+    /// Remove all sessions of a user.
+    pub fn remove_user(&self, user_id: u64) {
+        self.sessions
+            .write()
+            .expect("poisoned")
+            .retain(|_, id| *id != user_id);
+    }
+}
+
+impl SessionStore for MemorySessions {
+    fn user_id(&self, token: &str) -> Option<u64> {
+        self.sessions.read().expect("poisoned").get(token).copied()
+    }
+}
+
+/// An authenticated session, inserted into request extensions by
+/// [`SessionLayer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// ID of the user the session belongs to.
+    pub user_id: u64,
+    /// The session token.
+    pub token: String,
+}
+
+/// Extracts the session token from request headers.
+///
+/// The token is read from the `Authorization` header, or from the
+/// `Sec-WebSocket-Protocol` header for socket requests from web clients,
+/// which can't set other headers.
+pub fn extract_token(headers: &HeaderMap) -> Option<&str> {
+    if let Some(token) = headers.get(AUTHORIZATION) {
+        return token.to_str().ok().filter(|token| !token.is_empty());
+    }
+
+    headers
+        .get(SEC_WEBSOCKET_PROTOCOL)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .map(str::trim)
+        .find(|protocol| !protocol.is_empty() && !protocol.starts_with("hrpc"))
+}
+
+/// Validates the session token in request headers, returning the session.
+pub fn authenticate(headers: &HeaderMap, sessions: &impl SessionStore) -> ServerResult<Session> {
+    let token = extract_token(headers).ok_or(ServerError::BlankSession)?;
+    let user_id = sessions.user_id(token).ok_or(ServerError::InvalidSession)?;
+    Ok(Session {
+        user_id,
+        token: token.to_string(),
+    })
+}
+
+/// Gets the session of a request, which must have been inserted by
+/// [`SessionLayer`].
+///
+/// Returns [`ServerError::BlankSession`] if the request has no session token,
+/// and [`ServerError::InvalidSession`] if the token isn't valid.
+pub fn session<T>(request: &hrpc::Request<T>) -> ServerResult<&Session> {
+    if let Some(session) = request.extensions().get::<Session>() {
+        return Ok(session);
+    }
+    match request.header_map().and_then(extract_token) {
+        Some(_) => Err(ServerError::InvalidSession),
+        None => Err(ServerError::BlankSession),
+    }
+}
+
+/// Layer that authenticates requests using a [`SessionStore`].
+///
+/// Requests with a valid session token get a [`Session`] inserted into their
+/// extensions, which handlers can get with [`session()`]. Requests without
+/// one are passed through as is, so that endpoints that don't require
+/// authentication keep working.
+#[derive(Debug)]
+pub struct SessionLayer<Store> {
+    sessions: Arc<Store>,
+}
+
+impl<Store> SessionLayer<Store> {
+    /// Create a new session layer.
+    pub fn new(sessions: Arc<Store>) -> Self {
+        Self { sessions }
+    }
+}
+
+impl<Store> Clone for SessionLayer<Store> {
+    fn clone(&self) -> Self {
+        Self {
+            sessions: self.sessions.clone(),
+        }
+    }
+}
+
+impl<Store, S> Layer<S> for SessionLayer<Store> {
+    type Service = SessionService<Store, S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SessionService {
+            inner,
+            sessions: self.sessions.clone(),
+        }
+    }
+}
+
+/// Service created by [`SessionLayer`].
+#[derive(Debug)]
+pub struct SessionService<Store, S> {
+    inner: S,
+    sessions: Arc<Store>,
+}
+
+impl<Store, S: Clone> Clone for SessionService<Store, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            sessions: self.sessions.clone(),
+        }
+    }
+}
+
+impl<Store, S> Service<BoxRequest> for SessionService<Store, S>
+where
+    Store: SessionStore,
+    S: Service<BoxRequest>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::poll_ready(&mut self.inner, cx)
+    }
+
+    fn call(&mut self, mut req: BoxRequest) -> Self::Future {
+        let session = req
+            .header_map()
+            .and_then(|headers| authenticate(headers, self.sessions.as_ref()).ok());
+        if let Some(session) = session {
+            req.extensions_mut().insert(session);
+        }
+
+        Service::call(&mut self.inner, req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn extracts_token_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(extract_token(&headers), None);
+
+        headers.insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static("hrpc-ws-v1, socket-token"),
+        );
+        assert_eq!(extract_token(&headers), Some("socket-token"));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("token"));
+        assert_eq!(extract_token(&headers), Some("token"));
+    }
+
+    #[test]
+    fn authenticates_with_store() {
+        let sessions = MemorySessions::default();
+        sessions.insert("token", 42);
+
+        let mut headers = HeaderMap::new();
+        assert!(matches!(
+            authenticate(&headers, &sessions),
+            Err(ServerError::BlankSession)
+        ));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("other"));
+        assert!(matches!(
+            authenticate(&headers, &sessions),
+            Err(ServerError::InvalidSession)
+        ));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("token"));
+        assert_eq!(authenticate(&headers, &sessions).unwrap().user_id, 42);
+
+        sessions.remove_user(42);
+        assert!(authenticate(&headers, &sessions).is_err());
+    }
+}
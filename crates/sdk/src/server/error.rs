@@ -0,0 +1,101 @@
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+};
+
+use hrpc::proto::Error as HrpcError;
+use http::StatusCode;
+
+/// Result type used by server helpers.
+pub type ServerResult<T> = Result<T, ServerError>;
+
+/// Error type used by server helpers.
+///
+/// Can be converted to an hRPC error with an `h.*` identifier, or to an HTTP
+/// status code for REST endpoints.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ServerError {
+    /// Returned if a request requires authentication, but no session token
+    /// was sent.
+    BlankSession,
+    /// Returned if the session token sent with a request isn't valid.
+    InvalidSession,
+    /// Returned if a request is malformed.
+    BadRequest(String),
+    /// Returned if the requested resource doesn't exist.
+    NotFound,
+    /// Returned if an uploaded file is larger than the configured limit.
+    TooLarge,
+    /// Returned if an internal error occurs, for example in a storage backend.
+    Internal(Box<dyn StdError + Send + Sync>),
+}
+
+impl ServerError {
+    /// Create a [`ServerError::BadRequest`] error.
+    pub fn bad_request(msg: impl ToString) -> Self {
+        ServerError::BadRequest(msg.to_string())
+    }
+
+    /// Create a [`ServerError::Internal`] error.
+    pub fn internal(err: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        ServerError::Internal(err.into())
+    }
+
+    /// Get the hRPC error identifier of this error.
+    pub fn identifier(&self) -> &'static str {
+        match self {
+            ServerError::BlankSession => "h.blank-session",
+            ServerError::InvalidSession => "h.bad-session",
+            ServerError::BadRequest(_) => "h.bad-request",
+            ServerError::NotFound => "h.not-found",
+            ServerError::TooLarge => "h.too-large",
+            ServerError::Internal(_) => "h.internal-error",
+        }
+    }
+
+    /// Get the HTTP status code of this error, used by REST endpoints.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ServerError::BlankSession | ServerError::InvalidSession => StatusCode::UNAUTHORIZED,
+            ServerError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ServerError::NotFound => StatusCode::NOT_FOUND,
+            ServerError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ServerError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ServerError::BlankSession => write!(f, "no session token was sent"),
+            ServerError::InvalidSession => write!(f, "session token is invalid"),
+            ServerError::BadRequest(msg) => write!(f, "bad request: {}", msg),
+            ServerError::NotFound => write!(f, "not found"),
+            ServerError::TooLarge => write!(f, "file is too large"),
+            // Don't leak internal details to clients
+            ServerError::Internal(_) => write!(f, "internal server error"),
+        }
+    }
+}
+
+impl StdError for ServerError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            ServerError::Internal(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<ServerError> for HrpcError {
+    fn from(err: ServerError) -> Self {
+        if let ServerError::Internal(inner) = &err {
+            tracing::error!("internal server error: {}", inner);
+        }
+        HrpcError::default()
+            .with_identifier(err.identifier())
+            .with_message(err.to_string())
+    }
+}
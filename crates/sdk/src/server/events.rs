@@ -0,0 +1,400 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::api::chat::{Event, EventSource, StreamEventsRequest};
+
+/// Default number of events queued per subscriber.
+pub const DEFAULT_CAPACITY: usize = 512;
+
+/// Who an event is published to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventTarget {
+    /// Subscribers of a guild's events.
+    Guild(u64),
+    /// A user's subscribers of homeserver events.
+    Homeserver(u64),
+    /// A user's subscribers of action events.
+    Action(u64),
+}
+
+/// What to do when a subscriber's queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Drop the event for that subscriber. Dropped events are counted, see
+    /// [`EventSubscription::lagged()`].
+    DropEvents,
+    /// Disconnect the subscriber. It will receive the events that are already
+    /// queued, and then `None`.
+    Disconnect,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    user_id: u64,
+    sources: HashSet<EventSource>,
+    sender: mpsc::Sender<Event>,
+    lagged: Arc<AtomicU64>,
+}
+
+impl Subscriber {
+    fn wants(&self, target: EventTarget) -> bool {
+        match target {
+            EventTarget::Guild(guild_id) => self.sources.contains(&EventSource::Guild(guild_id)),
+            EventTarget::Homeserver(user_id) => {
+                self.user_id == user_id && self.sources.contains(&EventSource::Homeserver)
+            }
+            EventTarget::Action(user_id) => {
+                self.user_id == user_id && self.sources.contains(&EventSource::Action)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: u64,
+    subscribers: HashMap<u64, Subscriber>,
+    /// Subscriber IDs by the guild they are subscribed to.
+    by_guild: HashMap<u64, HashSet<u64>>,
+    /// Subscriber IDs by the user they belong to.
+    by_user: HashMap<u64, HashSet<u64>>,
+}
+
+impl State {
+    fn subscribe(&mut self, id: u64, source: EventSource) {
+        let subscriber = match self.subscribers.get_mut(&id) {
+            Some(subscriber) => subscriber,
+            None => return,
+        };
+        if source == EventSource::Unsubscribe {
+            for source in subscriber.sources.drain() {
+                if let EventSource::Guild(guild_id) = source {
+                    remove_index(&mut self.by_guild, guild_id, id);
+                }
+            }
+            return;
+        }
+        if subscriber.sources.insert(source) {
+            if let EventSource::Guild(guild_id) = source {
+                self.by_guild.entry(guild_id).or_default().insert(id);
+            }
+        }
+    }
+
+    fn unsubscribe(&mut self, id: u64, source: EventSource) {
+        let removed = match self.subscribers.get_mut(&id) {
+            Some(subscriber) => subscriber.sources.remove(&source),
+            None => false,
+        };
+        if let (true, EventSource::Guild(guild_id)) = (removed, source) {
+            remove_index(&mut self.by_guild, guild_id, id);
+        }
+    }
+
+    fn remove(&mut self, id: u64) {
+        if let Some(subscriber) = self.subscribers.remove(&id) {
+            for source in subscriber.sources {
+                if let EventSource::Guild(guild_id) = source {
+                    remove_index(&mut self.by_guild, guild_id, id);
+                }
+            }
+            remove_index(&mut self.by_user, subscriber.user_id, id);
+        }
+    }
+
+    fn targets(&self, target: EventTarget) -> Vec<u64> {
+        let candidates = match target {
+            EventTarget::Guild(guild_id) => self.by_guild.get(&guild_id),
+            EventTarget::Homeserver(user_id) | EventTarget::Action(user_id) => {
+                self.by_user.get(&user_id)
+            }
+        };
+        candidates
+            .into_iter()
+            .flatten()
+            .filter(|id| matches!(self.subscribers.get(id), Some(sub) if sub.wants(target)))
+            .copied()
+            .collect()
+    }
+}
+
+fn remove_index(index: &mut HashMap<u64, HashSet<u64>>, key: u64, id: u64) {
+    if let Some(ids) = index.get_mut(&key) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            index.remove(&key);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    capacity: usize,
+    policy: SlowConsumerPolicy,
+    state: Mutex<State>,
+}
+
+/// Routes stream events to the sockets subscribed to them.
+///
+/// Each socket (for example, a `StreamEvents` socket of the chat service)
+/// registers an [`EventSubscription`] with [`EventBroadcaster::register()`],
+/// and passes the [`StreamEventsRequest`]s it receives to
+/// [`EventSubscription::handle_request()`]. Events published with
+/// [`EventBroadcaster::publish()`] are then queued for every subscription
+/// whose [`EventSource`]s match the [`EventTarget`] of the event.
+///
+/// Every subscription has its own bounded queue, so a slow socket doesn't
+/// hold back the others. What happens when a queue is full is decided by the
+/// [`SlowConsumerPolicy`].
+#[derive(Debug, Clone)]
+pub struct EventBroadcaster {
+    inner: Arc<Inner>,
+}
+
+impl Default for EventBroadcaster {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, SlowConsumerPolicy::DropEvents)
+    }
+}
+
+impl EventBroadcaster {
+    /// Create a new broadcaster that queues up to `capacity` events per
+    /// subscription, and handles subscriptions that fall behind according to
+    /// `policy`.
+    pub fn new(capacity: usize, policy: SlowConsumerPolicy) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                capacity,
+                policy,
+                state: Mutex::new(State::default()),
+            }),
+        }
+    }
+
+    /// Register a subscription for a socket of a user. It isn't subscribed to
+    /// any source initially.
+    pub fn register(&self, user_id: u64) -> EventSubscription {
+        let (sender, receiver) = mpsc::channel(self.inner.capacity);
+        let lagged = Arc::new(AtomicU64::new(0));
+
+        let mut state = self.inner.state.lock().expect("poisoned");
+        state.next_id += 1;
+        let id = state.next_id;
+        state.subscribers.insert(
+            id,
+            Subscriber {
+                user_id,
+                sources: HashSet::new(),
+                sender,
+                lagged: lagged.clone(),
+            },
+        );
+        state.by_user.entry(user_id).or_default().insert(id);
+
+        EventSubscription {
+            id,
+            user_id,
+            receiver,
+            lagged,
+            broadcaster: self.clone(),
+        }
+    }
+
+    /// Publish an event. Returns the number of subscriptions the event was
+    /// queued for.
+    pub fn publish(&self, target: EventTarget, event: Event) -> usize {
+        self.publish_to_all(std::iter::once(target), event)
+    }
+
+    /// Publish an event to multiple targets. Subscriptions that match more
+    /// than one target receive the event only once. Returns the number of
+    /// subscriptions the event was queued for.
+    pub fn publish_to_all(
+        &self,
+        targets: impl IntoIterator<Item = EventTarget>,
+        event: Event,
+    ) -> usize {
+        let mut state = self.inner.state.lock().expect("poisoned");
+        let ids = targets
+            .into_iter()
+            .flat_map(|target| state.targets(target))
+            .collect::<HashSet<_>>();
+
+        let mut sent = 0;
+        let mut disconnect = Vec::new();
+        for id in ids {
+            let subscriber = &state.subscribers[&id];
+            match subscriber.sender.try_send(event.clone()) {
+                Ok(()) => sent += 1,
+                Err(TrySendError::Full(_)) => {
+                    subscriber.lagged.fetch_add(1, Ordering::Relaxed);
+                    if self.inner.policy == SlowConsumerPolicy::Disconnect {
+                        tracing::warn!(
+                            "disconnecting slow event subscriber of user {}",
+                            subscriber.user_id
+                        );
+                        disconnect.push(id);
+                    }
+                }
+                Err(TrySendError::Closed(_)) => disconnect.push(id),
+            }
+        }
+        for id in disconnect {
+            state.remove(id);
+        }
+
+        sent
+    }
+
+    /// Get the number of registered subscriptions.
+    pub fn subscription_count(&self) -> usize {
+        self.inner.state.lock().expect("poisoned").subscribers.len()
+    }
+}
+
+/// A socket's subscription to events, created by
+/// [`EventBroadcaster::register()`].
+///
+/// Dropping it unregisters it from the broadcaster.
+#[derive(Debug)]
+pub struct EventSubscription {
+    id: u64,
+    user_id: u64,
+    receiver: mpsc::Receiver<Event>,
+    lagged: Arc<AtomicU64>,
+    broadcaster: EventBroadcaster,
+}
+
+impl EventSubscription {
+    /// Get the ID of the user this subscription belongs to.
+    pub fn user_id(&self) -> u64 {
+        self.user_id
+    }
+
+    /// Subscribe to a source. [`EventSource::Unsubscribe`] removes all
+    /// subscribed sources.
+    pub fn subscribe(&self, source: EventSource) {
+        self.state().subscribe(self.id, source);
+    }
+
+    /// Unsubscribe from a source.
+    pub fn unsubscribe(&self, source: EventSource) {
+        self.state().unsubscribe(self.id, source);
+    }
+
+    /// Apply a [`StreamEventsRequest`] received from the socket. Returns the
+    /// source that was subscribed to, or `None` if the request was empty.
+    ///
+    /// Permission checks, such as whether the user is a member of a guild,
+    /// must be done before calling this.
+    pub fn handle_request(&self, request: &StreamEventsRequest) -> Option<EventSource> {
+        let source = EventSource::from_request(request)?;
+        self.subscribe(source);
+        Some(source)
+    }
+
+    /// Get the sources this subscription is subscribed to.
+    pub fn sources(&self) -> Vec<EventSource> {
+        self.state()
+            .subscribers
+            .get(&self.id)
+            .map_or_else(Vec::new, |sub| sub.sources.iter().copied().collect())
+    }
+
+    /// Get the number of events that were dropped because this
+    /// subscription's queue was full.
+    pub fn lagged(&self) -> u64 {
+        self.lagged.load(Ordering::Relaxed)
+    }
+
+    /// Receive the next event. Returns `None` if the subscription was
+    /// disconnected for being too slow, see [`SlowConsumerPolicy::Disconnect`].
+    pub async fn recv(&mut self) -> Option<Event> {
+        self.receiver.recv().await
+    }
+
+    /// Receive the next event if one is queued, without waiting.
+    pub fn try_recv(&mut self) -> Option<Event> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.broadcaster.inner.state.lock().expect("poisoned")
+    }
+}
+
+impl Drop for EventSubscription {
+    fn drop(&mut self) {
+        self.state().remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::chat::stream_event;
+
+    fn event(guild_id: u64) -> Event {
+        Event::Chat(stream_event::Event::Typing(stream_event::Typing {
+            guild_id,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn routes_events_by_source() {
+        let broadcaster = EventBroadcaster::default();
+        let mut alice = broadcaster.register(1);
+        let mut bob = broadcaster.register(2);
+        alice.subscribe(EventSource::Guild(10));
+        alice.subscribe(EventSource::Homeserver);
+        bob.subscribe(EventSource::Guild(20));
+        bob.subscribe(EventSource::Action);
+
+        assert_eq!(broadcaster.publish(EventTarget::Guild(10), event(10)), 1);
+        assert_eq!(alice.try_recv(), Some(event(10)));
+        assert_eq!(bob.try_recv(), None);
+
+        assert_eq!(broadcaster.publish(EventTarget::Homeserver(2), event(0)), 0);
+        assert_eq!(broadcaster.publish(EventTarget::Action(2), event(0)), 1);
+        assert_eq!(bob.try_recv(), Some(event(0)));
+
+        let targets = [EventTarget::Guild(10), EventTarget::Homeserver(1)];
+        assert_eq!(broadcaster.publish_to_all(targets, event(10)), 1);
+
+        alice.subscribe(EventSource::Unsubscribe);
+        assert!(alice.sources().is_empty());
+        assert_eq!(broadcaster.publish(EventTarget::Guild(10), event(10)), 0);
+
+        drop(bob);
+        assert_eq!(broadcaster.subscription_count(), 1);
+        assert_eq!(broadcaster.publish(EventTarget::Guild(20), event(20)), 0);
+    }
+
+    #[test]
+    fn handles_slow_consumers() {
+        let broadcaster = EventBroadcaster::new(1, SlowConsumerPolicy::DropEvents);
+        let mut sub = broadcaster.register(1);
+        sub.subscribe(EventSource::Guild(10));
+        assert_eq!(broadcaster.publish(EventTarget::Guild(10), event(1)), 1);
+        assert_eq!(broadcaster.publish(EventTarget::Guild(10), event(2)), 0);
+        assert_eq!(sub.lagged(), 1);
+        assert_eq!(sub.try_recv(), Some(event(1)));
+
+        let broadcaster = EventBroadcaster::new(1, SlowConsumerPolicy::Disconnect);
+        let mut sub = broadcaster.register(1);
+        sub.subscribe(EventSource::Guild(10));
+        broadcaster.publish(EventTarget::Guild(10), event(1));
+        broadcaster.publish(EventTarget::Guild(10), event(2));
+        assert_eq!(broadcaster.subscription_count(), 0);
+        assert_eq!(sub.try_recv(), Some(event(1)));
+        assert_eq!(sub.try_recv(), None);
+    }
+}
@@ -0,0 +1,24 @@
+//! Helpers for implementing Harmony servers on top of the services generated
+//! with the `gen_server` feature.
+//!
+//! This includes session authentication for hRPC requests, fan-out of stream
+//! events and handlers for the REST endpoints.
+
+/// Session authentication for requests.
+pub mod auth;
+/// Error related code used by server helpers.
+pub mod error;
+/// Fan-out of stream events to subscribers.
+pub mod events;
+/// Handlers for the REST endpoints.
+pub mod rest;
+/// Verification of incoming sync service requests.
+#[cfg(feature = "server_sync")]
+pub mod sync;
+
+pub use auth::{session, MemorySessions, Session, SessionLayer, SessionStore};
+pub use error::{ServerError, ServerResult};
+pub use events::{EventBroadcaster, EventSubscription, EventTarget, SlowConsumerPolicy};
+pub use rest::{MediaStore, MemoryMediaStore, RestHandler};
+#[cfg(feature = "server_sync")]
+pub use sync::{SyncVerifier, VerifyError};
@@ -0,0 +1,520 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use hrpc::exports::{bytes::Bytes, futures_util::future::BoxFuture};
+use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use serde::Serialize;
+
+use super::{
+    auth::{authenticate, SessionStore},
+    error::{ServerError, ServerResult},
+};
+use crate::api::rest::{guess_mimetype, About, FileId};
+
+/// Default maximum size of uploaded files, 50 MiB.
+pub const DEFAULT_MAX_UPLOAD_SIZE: usize = 50 * 1024 * 1024;
+
+const DOWNLOAD_PATH: &str = "/_harmony/media/download/";
+
+/// Mimetypes of downloads that browsers may display instead of saving. Other
+/// types, like SVG or HTML, can run scripts when displayed.
+const INLINE_MIMETYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// A file stored by a [`MediaStore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredFile {
+    /// Name of the file.
+    pub name: String,
+    /// Mimetype of the file.
+    pub mimetype: String,
+    /// Contents of the file.
+    pub data: Bytes,
+}
+
+/// Storage backend for uploaded media.
+pub trait MediaStore: Send + Sync + 'static {
+    /// Store a file, returning its ID.
+    fn put(&self, file: StoredFile) -> BoxFuture<'_, ServerResult<String>>;
+    /// Get a file by its ID, or `None` if it doesn't exist.
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, ServerResult<Option<StoredFile>>>;
+}
+
+/// A [`MediaStore`] that keeps files in memory.
+#[derive(Debug, Default)]
+pub struct MemoryMediaStore {
+    next_id: AtomicU64,
+    files: Mutex<HashMap<String, StoredFile>>,
+}
+
+impl MediaStore for MemoryMediaStore {
+    fn put(&self, file: StoredFile) -> BoxFuture<'_, ServerResult<String>> {
+        let id = (self.next_id.fetch_add(1, Ordering::Relaxed) + 1).to_string();
+        self.files
+            .lock()
+            .expect("poisoned")
+            .insert(id.clone(), file);
+        Box::pin(async move { Ok(id) })
+    }
+
+    fn get<'a>(&'a self, id: &'a str) -> BoxFuture<'a, ServerResult<Option<StoredFile>>> {
+        let file = self.files.lock().expect("poisoned").get(id).cloned();
+        Box::pin(async move { Ok(file) })
+    }
+}
+
+/// Response of the `/_harmony/server` endpoint, which tells clients which
+/// homeserver serves a domain.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServerInfo {
+    /// Host (and port) of the homeserver.
+    pub server: String,
+}
+
+/// Handles the REST endpoints of the protocol, matching the ones used by the
+/// client's `rest` module:
+///
+/// - `GET /_harmony/about`
+/// - `GET /_harmony/server`
+/// - `POST /_harmony/media/upload` (requires authentication)
+/// - `GET /_harmony/media/download/{file_id}` (supports `Range` requests)
+///
+/// Requests to other paths should be passed to the hRPC server.
+#[derive(Debug)]
+pub struct RestHandler<Media, Sessions> {
+    about: About,
+    server: ServerInfo,
+    media: Media,
+    sessions: Sessions,
+    max_upload_size: usize,
+}
+
+impl<Media: MediaStore, Sessions: SessionStore> RestHandler<Media, Sessions> {
+    /// Create a new REST handler. `server` is the host (and port) of this
+    /// homeserver, returned by `/_harmony/server`.
+    pub fn new(about: About, server: impl Into<String>, media: Media, sessions: Sessions) -> Self {
+        Self {
+            about,
+            server: ServerInfo {
+                server: server.into(),
+            },
+            media,
+            sessions,
+            max_upload_size: DEFAULT_MAX_UPLOAD_SIZE,
+        }
+    }
+
+    /// Set the maximum size of uploaded files. Defaults to
+    /// [`DEFAULT_MAX_UPLOAD_SIZE`].
+    pub fn with_max_upload_size(mut self, max_upload_size: usize) -> Self {
+        self.max_upload_size = max_upload_size;
+        self
+    }
+
+    /// Get the maximum size of uploaded files.
+    pub fn max_upload_size(&self) -> usize {
+        self.max_upload_size
+    }
+
+    /// Get the media store of this handler.
+    pub fn media(&self) -> &Media {
+        &self.media
+    }
+
+    /// Returns `true` if a request is for one of the REST endpoints.
+    pub fn handles<B>(&self, request: &Request<B>) -> bool {
+        let path = request.uri().path();
+        path == "/_harmony/about"
+            || path == "/_harmony/server"
+            || path == "/_harmony/media/upload"
+            || path.starts_with(DOWNLOAD_PATH)
+    }
+
+    /// Check the `Content-Length` header of a request before its body is
+    /// read, returning [`ServerError::TooLarge`] if it's larger than the
+    /// maximum upload size.
+    pub fn check_content_length(&self, headers: &HeaderMap) -> ServerResult<()> {
+        let length = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        match length {
+            Some(length) if length > self.max_upload_size as u64 => Err(ServerError::TooLarge),
+            _ => Ok(()),
+        }
+    }
+
+    /// Handle a request to one of the REST endpoints. Errors are turned into
+    /// responses with the status code of the error.
+    ///
+    /// The body must already be read. To reject large uploads before they're
+    /// buffered, check the request with [`RestHandler::check_content_length()`]
+    /// and stop reading its body after [`RestHandler::max_upload_size()`] bytes.
+    pub async fn handle(&self, request: Request<Bytes>) -> Response<Bytes> {
+        self.route(request).await.unwrap_or_else(|err| {
+            if let ServerError::Internal(inner) = &err {
+                tracing::error!("internal error in REST handler: {}", inner);
+            }
+            let mut response = Response::new(Bytes::from(err.to_string()));
+            *response.status_mut() = err.status();
+            response
+        })
+    }
+
+    async fn route(&self, request: Request<Bytes>) -> ServerResult<Response<Bytes>> {
+        let path = request.uri().path();
+        match (request.method(), path) {
+            (&Method::GET, "/_harmony/about") => json_response(&self.about),
+            (&Method::GET, "/_harmony/server") => json_response(&self.server),
+            (&Method::POST, "/_harmony/media/upload") => self.upload(&request).await,
+            (&Method::GET, _) if path.starts_with(DOWNLOAD_PATH) => {
+                let file_id = urlencoding::decode(&path[DOWNLOAD_PATH.len()..])
+                    .map_err(ServerError::bad_request)?
+                    .into_owned();
+                self.download(&file_id, request.headers().get(header::RANGE))
+                    .await
+            }
+            _ => Err(ServerError::NotFound),
+        }
+    }
+
+    async fn upload(&self, request: &Request<Bytes>) -> ServerResult<Response<Bytes>> {
+        authenticate(request.headers(), &self.sessions)?;
+        if request.body().len() > self.max_upload_size {
+            return Err(ServerError::TooLarge);
+        }
+
+        let content_type = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| ServerError::bad_request("missing content type"))?;
+        let part = parse_multipart(content_type, request.body())?
+            .into_iter()
+            .find(|part| part.name == "file")
+            .ok_or_else(|| ServerError::bad_request("missing file part"))?;
+
+        let name = part.filename.unwrap_or_else(|| "unknown".to_string());
+        let mimetype = part
+            .content_type
+            .unwrap_or_else(|| guess_mimetype(&name, &part.data).to_string());
+        let id = self
+            .media
+            .put(StoredFile {
+                name,
+                mimetype,
+                data: part.data,
+            })
+            .await?;
+
+        #[derive(Serialize)]
+        struct UploadResponse {
+            id: String,
+        }
+        json_response(&UploadResponse { id })
+    }
+
+    async fn download(
+        &self,
+        file_id: &str,
+        range: Option<&HeaderValue>,
+    ) -> ServerResult<Response<Bytes>> {
+        let id = match file_id.parse::<FileId>() {
+            Ok(FileId::Id(id)) => id,
+            Ok(FileId::Hmc(hmc)) => hmc.id().to_string(),
+            Ok(FileId::External(_)) => {
+                return Err(ServerError::bad_request("external files aren't proxied"))
+            }
+            Err(_) => return Err(ServerError::NotFound),
+        };
+        let file = self.media.get(&id).await?.ok_or(ServerError::NotFound)?;
+
+        let essence = file.mimetype.split(';').next().unwrap_or_default().trim();
+        let kind = if INLINE_MIMETYPES
+            .iter()
+            .any(|inline| inline.eq_ignore_ascii_case(essence))
+        {
+            "inline"
+        } else {
+            "attachment"
+        };
+        let disposition = format!(
+            "{}; filename=\"{}\"",
+            kind,
+            file.name.replace('"', "").replace(['\r', '\n'], "")
+        );
+
+        let total = file.data.len();
+        let start = range
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_range_start);
+        let (status, body) = match start {
+            Some(start) if start >= total => {
+                return Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", total))
+                    .body(Bytes::new())
+                    .map_err(ServerError::internal);
+            }
+            Some(start) => (StatusCode::PARTIAL_CONTENT, file.data.slice(start..)),
+            None => (StatusCode::OK, file.data),
+        };
+
+        let mut builder = Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, file.mimetype)
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .header(header::CONTENT_DISPOSITION, disposition)
+            .header(header::CONTENT_LENGTH, body.len())
+            .header(header::ACCEPT_RANGES, "bytes");
+        if let Some(start) = start {
+            builder = builder.header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, total - 1, total),
+            );
+        }
+        builder.body(body).map_err(ServerError::internal)
+    }
+}
+
+fn json_response(value: &impl Serialize) -> ServerResult<Response<Bytes>> {
+    let body = serde_json::to_vec(value).map_err(ServerError::internal)?;
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Bytes::from(body))
+        .map_err(ServerError::internal)
+}
+
+/// Parses the start of a `Range: bytes=N-` header, the only form the client
+/// sends.
+fn parse_range_start(range: &str) -> Option<usize> {
+    let range = range.trim().strip_prefix("bytes=")?;
+    let (start, end) = range.split_once('-')?;
+    if !end.is_empty() {
+        return None;
+    }
+    start.trim().parse().ok()
+}
+
+/// A part of a `multipart/form-data` body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultipartPart {
+    /// Name of the form field.
+    pub name: String,
+    /// File name of the part, if it's a file.
+    pub filename: Option<String>,
+    /// Content type of the part, if it was specified.
+    pub content_type: Option<String>,
+    /// Contents of the part.
+    pub data: Bytes,
+}
+
+/// Parses a `multipart/form-data` body, given the `Content-Type` header of the
+/// request.
+pub fn parse_multipart(content_type: &str, body: &Bytes) -> ServerResult<Vec<MultipartPart>> {
+    let mut params = content_type.split(';').map(str::trim);
+    if !params
+        .next()
+        .unwrap_or_default()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return Err(ServerError::bad_request("expected multipart/form-data"));
+    }
+    let boundary = params
+        .find_map(|param| param.strip_prefix("boundary="))
+        .map(|boundary| boundary.trim_matches('"'))
+        .filter(|boundary| !boundary.is_empty())
+        .ok_or_else(|| ServerError::bad_request("missing multipart boundary"))?;
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    let malformed = || ServerError::bad_request("malformed multipart body");
+    let mut at = find(body, delimiter, 0).ok_or_else(malformed)? + delimiter.len();
+    let mut parts = Vec::new();
+    loop {
+        // The closing delimiter is followed by `--`
+        if body[at..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        at += 2; // CRLF after the delimiter
+
+        let headers_end = find(body, b"\r\n\r\n", at).ok_or_else(malformed)?;
+        let headers = std::str::from_utf8(&body[at..headers_end]).map_err(|_| malformed())?;
+        let data_start = headers_end + 4;
+        let next = find(body, delimiter, data_start).ok_or_else(malformed)?;
+        // The CRLF before the delimiter belongs to it
+        let data_end = next.checked_sub(2).filter(|end| *end >= data_start);
+        let data = data_end.map_or_else(Bytes::new, |end| body.slice(data_start..end));
+
+        let mut part = MultipartPart {
+            name: String::new(),
+            filename: None,
+            content_type: None,
+            data,
+        };
+        for line in headers.split("\r\n") {
+            let (name, value) = match line.split_once(':') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("content-disposition") {
+                for param in value.split(';').map(str::trim) {
+                    if let Some(field) = param.strip_prefix("name=") {
+                        part.name = field.trim_matches('"').to_string();
+                    } else if let Some(filename) = param.strip_prefix("filename=") {
+                        part.filename = Some(filename.trim_matches('"').to_string());
+                    }
+                }
+            }
+        }
+        parts.push(part);
+
+        at = next + delimiter.len();
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| pos + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hrpc::exports::futures_util::FutureExt;
+
+    fn handler() -> RestHandler<MemoryMediaStore, fn(&str) -> Option<u64>> {
+        let about = About {
+            server_name: "test".to_string(),
+            version: "0.1.0".to_string(),
+            about_server: String::new(),
+            message_of_the_day: String::new(),
+        };
+        RestHandler::new(about, "localhost:2289", MemoryMediaStore::default(), |_| {
+            None
+        })
+    }
+
+    #[test]
+    fn parses_multipart_body() {
+        let body = Bytes::from_static(
+            b"--abc\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            hello\r\nworld\r\n\
+            --abc\r\n\
+            Content-Disposition: form-data; name=\"empty\"\r\n\
+            \r\n\
+            \r\n\
+            --abc--\r\n",
+        );
+        let parts = parse_multipart("multipart/form-data; boundary=abc", &body).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "file");
+        assert_eq!(parts[0].filename.as_deref(), Some("a.txt"));
+        assert_eq!(parts[0].content_type.as_deref(), Some("text/plain"));
+        assert_eq!(parts[0].data, Bytes::from_static(b"hello\r\nworld"));
+        assert_eq!(parts[1].name, "empty");
+        assert!(parts[1].data.is_empty());
+
+        assert!(parse_multipart("text/plain", &body).is_err());
+        assert!(parse_multipart("multipart/form-data; boundary=xyz", &body).is_err());
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_range() {
+        let handler = handler();
+        let file = StoredFile {
+            name: "a.txt".to_string(),
+            mimetype: "text/plain".to_string(),
+            data: Bytes::from_static(b"hello"),
+        };
+        let id = handler.media().put(file).now_or_never().unwrap().unwrap();
+
+        let request = |range: &str| {
+            Request::get(format!("{}{}", DOWNLOAD_PATH, id))
+                .header(header::RANGE, range)
+                .body(Bytes::new())
+                .unwrap()
+        };
+        let response = handler.handle(request("bytes=2-")).now_or_never().unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.body(), &Bytes::from_static(b"llo"));
+
+        let response = handler.handle(request("bytes=5-")).now_or_never().unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */5");
+    }
+
+    #[test]
+    fn downloads_scriptable_images_as_attachments() {
+        let handler = handler();
+        let download = |name: &str, mimetype: &str| {
+            let file = StoredFile {
+                name: name.to_string(),
+                mimetype: mimetype.to_string(),
+                data: Bytes::from_static(b"<svg></svg>"),
+            };
+            let id = handler.media().put(file).now_or_never().unwrap().unwrap();
+            let request = Request::get(format!("{}{}", DOWNLOAD_PATH, id))
+                .body(Bytes::new())
+                .unwrap();
+            handler.handle(request).now_or_never().unwrap()
+        };
+
+        let response = download("a.svg", "image/svg+xml");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"a.svg\""
+        );
+        assert_eq!(
+            response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+            "nosniff"
+        );
+
+        let response = download("a.png", "image/PNG");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "inline; filename=\"a.png\""
+        );
+        assert_eq!(
+            response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+            "nosniff"
+        );
+    }
+
+    #[test]
+    fn checks_content_length() {
+        let handler = handler().with_max_upload_size(4);
+        let headers = |length: usize| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_LENGTH, length.into());
+            headers
+        };
+        assert!(handler.check_content_length(&headers(4)).is_ok());
+        assert!(handler.check_content_length(&HeaderMap::new()).is_ok());
+        assert!(matches!(
+            handler.check_content_length(&headers(5)),
+            Err(ServerError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn parses_range_start() {
+        assert_eq!(parse_range_start("bytes=100-"), Some(100));
+        assert_eq!(parse_range_start("bytes=0-10"), None);
+        assert_eq!(parse_range_start("items=1-"), None);
+    }
+}
@@ -0,0 +1,277 @@
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use ed25519_dalek::{Signature, Verifier};
+use http::header::AUTHORIZATION;
+use prost::Message;
+
+use crate::{
+    api::{harmonytypes::Token, sync::AuthData},
+    client::{error::ClientError, sync::fetch_key},
+};
+
+pub use ed25519_dalek::PublicKey;
+
+/// Default maximum age of a sync auth token before it's rejected.
+pub const DEFAULT_MAX_TOKEN_AGE: Duration = Duration::from_secs(60);
+/// Default minimum time between two fetches of the same homeserver's key.
+pub const DEFAULT_KEY_REFETCH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Decode a sync auth token without verifying it. Use [`SyncVerifier`] to
+/// verify tokens.
+pub fn decode_token(token: &str) -> Result<(Token, AuthData), VerifyError> {
+    let raw = base64::decode(token.trim()).map_err(|_| VerifyError::MalformedToken)?;
+    let token = Token::decode(raw.as_slice()).map_err(|_| VerifyError::MalformedToken)?;
+    let data = AuthData::decode(token.data.as_slice()).map_err(|_| VerifyError::MalformedToken)?;
+    Ok((token, data))
+}
+
+fn verify_signature(token: &Token, key: &PublicKey) -> Result<(), VerifyError> {
+    let signature =
+        Signature::try_from(token.sig.as_slice()).map_err(|_| VerifyError::MalformedToken)?;
+    key.verify(&token.data, &signature)
+        .map_err(|_| VerifyError::InvalidSignature)
+}
+
+/// Error returned when verifying a sync auth token fails.
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The request doesn't have an `Authorization` header.
+    MissingToken,
+    /// The token couldn't be decoded.
+    MalformedToken,
+    /// The token is too old, or from the future.
+    Expired,
+    /// The token wasn't signed by the key of the homeserver it claims to be from.
+    InvalidSignature,
+    /// The key of the homeserver the token claims to be from couldn't be fetched.
+    KeyUnavailable(ClientError),
+    /// The key of the homeserver the token claims to be from isn't known, and
+    /// it was fetched too recently to be fetched again.
+    KeyRefetchThrottled,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            VerifyError::MissingToken => write!(f, "missing sync auth token"),
+            VerifyError::MalformedToken => write!(f, "malformed sync auth token"),
+            VerifyError::Expired => write!(f, "sync auth token expired"),
+            VerifyError::InvalidSignature => write!(f, "sync auth token has invalid signature"),
+            VerifyError::KeyUnavailable(err) => write!(f, "couldn't fetch homeserver key: {}", err),
+            VerifyError::KeyRefetchThrottled => {
+                write!(f, "homeserver key unknown and was fetched too recently")
+            }
+        }
+    }
+}
+
+impl StdError for VerifyError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            VerifyError::KeyUnavailable(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct HostKey {
+    key: Option<PublicKey>,
+    last_fetch: Option<Instant>,
+}
+
+/// Verifies the sync auth tokens of incoming postbox service requests.
+///
+/// Homeserver keys are fetched with [`fetch_key()`] and cached. If a token's
+/// signature doesn't match a cached key, the key is fetched again in case it
+/// was rotated. A homeserver's key is fetched at most once per refetch
+/// interval, whether the fetch succeeded or not, so invalid tokens can't be
+/// used to make the verifier flood a homeserver with requests.
+#[derive(Debug)]
+pub struct SyncVerifier {
+    max_age: Duration,
+    refetch_interval: Duration,
+    keys: Mutex<HashMap<String, HostKey>>,
+}
+
+impl Default for SyncVerifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SyncVerifier {
+    /// Create a new verifier.
+    pub fn new() -> Self {
+        Self {
+            max_age: DEFAULT_MAX_TOKEN_AGE,
+            refetch_interval: DEFAULT_KEY_REFETCH_INTERVAL,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set the maximum age of tokens. Defaults to [`DEFAULT_MAX_TOKEN_AGE`].
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Set the minimum time between two fetches of the same homeserver's
+    /// key. Defaults to [`DEFAULT_KEY_REFETCH_INTERVAL`].
+    pub fn with_refetch_interval(mut self, interval: Duration) -> Self {
+        self.refetch_interval = interval;
+        self
+    }
+
+    /// Set the key of a homeserver, instead of fetching it.
+    pub fn add_key(&self, host: impl Into<String>, key: PublicKey) {
+        self.keys
+            .lock()
+            .expect("poisoned")
+            .entry(host.into())
+            .or_default()
+            .key = Some(key);
+    }
+
+    /// Verify the `Authorization` header of a request, returning the
+    /// verified [`AuthData`].
+    pub async fn verify_request<T>(
+        &self,
+        request: &hrpc::Request<T>,
+    ) -> Result<AuthData, VerifyError> {
+        let token = request
+            .header_map()
+            .and_then(|headers| headers.get(AUTHORIZATION))
+            .and_then(|value| value.to_str().ok())
+            .ok_or(VerifyError::MissingToken)?;
+        self.verify(token).await
+    }
+
+    /// Verify a sync auth token, returning the verified [`AuthData`].
+    pub async fn verify(&self, token: &str) -> Result<AuthData, VerifyError> {
+        let (token, data) = decode_token(token)?;
+        let (now, max_age) = (unix_time(), self.max_age.as_secs());
+        if data.time.saturating_add(max_age) < now || data.time > now.saturating_add(max_age) {
+            return Err(VerifyError::Expired);
+        }
+
+        let cached = self
+            .keys
+            .lock()
+            .expect("poisoned")
+            .get(&data.server_id)
+            .and_then(|host| host.key);
+        if let Some(key) = cached {
+            if verify_signature(&token, &key).is_ok() {
+                return Ok(data);
+            }
+        }
+
+        if !self.reserve_fetch(&data.server_id, Instant::now()) {
+            return Err(match cached {
+                Some(_) => VerifyError::InvalidSignature,
+                None => VerifyError::KeyRefetchThrottled,
+            });
+        }
+
+        let key = fetch_key(&data.server_id)
+            .await
+            .map_err(VerifyError::KeyUnavailable)?;
+        self.add_key(data.server_id.clone(), key);
+        verify_signature(&token, &key)?;
+
+        Ok(data)
+    }
+
+    /// Returns whether a host's key may be fetched at `now`, recording the
+    /// fetch if so. The fetch is recorded before it's made so concurrent
+    /// requests don't fetch the key too.
+    fn reserve_fetch(&self, host: &str, now: Instant) -> bool {
+        let mut keys = self.keys.lock().expect("poisoned");
+        let entry = keys.entry(host.to_string()).or_default();
+        let throttled = matches!(
+            entry.last_fetch,
+            Some(last) if now.saturating_duration_since(last) < self.refetch_interval
+        );
+        if !throttled {
+            entry.last_fetch = Some(now);
+        }
+        !throttled
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::sync::{make_token, make_token_at, Keypair};
+    use ed25519_dalek::SecretKey;
+    use hrpc::exports::futures_util::FutureExt;
+
+    fn test_keypair(seed: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    #[test]
+    fn token_roundtrip() {
+        let keypair = test_keypair(1);
+        let token = make_token_at(&keypair, "chat.harmonyapp.io", 1234);
+
+        let (token, data) = decode_token(&token).unwrap();
+        assert_eq!(data.server_id, "chat.harmonyapp.io");
+        assert_eq!(data.time, 1234);
+        assert!(verify_signature(&token, &keypair.public).is_ok());
+        assert!(matches!(
+            verify_signature(&token, &test_keypair(2).public),
+            Err(VerifyError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        assert!(matches!(
+            decode_token("not base64!"),
+            Err(VerifyError::MalformedToken)
+        ));
+    }
+
+    #[test]
+    fn throttles_key_fetches() {
+        let verifier = SyncVerifier::new();
+        let host = "chat.harmonyapp.io";
+        let now = Instant::now();
+
+        assert!(verifier.reserve_fetch(host, now));
+        assert!(!verifier.reserve_fetch(host, now));
+        let later = now + DEFAULT_KEY_REFETCH_INTERVAL;
+        assert!(verifier.reserve_fetch(host, later));
+
+        // While throttled, tokens are only checked against the cached key
+        verifier.add_key(host, test_keypair(1).public);
+        let valid = make_token(&test_keypair(1), host);
+        let forged = make_token(&test_keypair(2), host);
+        assert!(verifier.verify(&valid).now_or_never().unwrap().is_ok());
+        assert!(matches!(
+            verifier.verify(&forged).now_or_never().unwrap(),
+            Err(VerifyError::InvalidSignature)
+        ));
+        verifier.keys.lock().unwrap().get_mut(host).unwrap().key = None;
+        assert!(matches!(
+            verifier.verify(&forged).now_or_never().unwrap(),
+            Err(VerifyError::KeyRefetchThrottled)
+        ));
+    }
+}
@@ -0,0 +1,236 @@
+//! Auth service, with a login / register flow using forms.
+use harmony_rust_sdk::{
+    api::auth::{
+        auth_service_server::AuthService, auth_step, next_step_request,
+        next_step_request::form_fields::Field, *,
+    },
+    api::profile::{Profile, UserStatus},
+    server::ServerError,
+};
+use hrpc::server::prelude::{
+    handler, make_handler, IntoResponse, Request, Response, ServerResult, Socket,
+};
+
+use crate::state::{random_token, AuthFlow, Homeserver, User};
+
+fn step(can_go_back: bool, step: auth_step::Step) -> AuthStep {
+    AuthStep {
+        can_go_back,
+        step: Some(step),
+        ..Default::default()
+    }
+}
+
+fn choice_step() -> AuthStep {
+    step(
+        false,
+        auth_step::Step::Choice(auth_step::Choice {
+            title: "initial".to_string(),
+            options: vec!["login".to_string(), "register".to_string()],
+        }),
+    )
+}
+
+fn form_step(title: &str, fields: &[(&str, &str)]) -> AuthStep {
+    let fields = fields
+        .iter()
+        .map(|(name, kind)| auth_step::form::FormField {
+            name: name.to_string(),
+            r#type: kind.to_string(),
+        })
+        .collect();
+    step(
+        true,
+        auth_step::Step::Form(auth_step::Form {
+            title: title.to_string(),
+            fields,
+        }),
+    )
+}
+
+fn login_step() -> AuthStep {
+    form_step("login", &[("email", "email"), ("password", "password")])
+}
+
+fn register_step() -> AuthStep {
+    form_step(
+        "register",
+        &[
+            ("email", "email"),
+            ("username", "username"),
+            ("password", "new-password"),
+        ],
+    )
+}
+
+fn form_fields(request: NextStepRequest) -> Vec<Field> {
+    match request.step {
+        Some(next_step_request::Step::Form(form)) => {
+            form.fields.into_iter().filter_map(|f| f.field).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+impl Homeserver {
+    fn session_step(&self, user_id: u64) -> AuthStep {
+        let session_token = random_token(32);
+        self.sessions.insert(session_token.clone(), user_id);
+        step(
+            false,
+            auth_step::Step::Session(Session {
+                user_id,
+                session_token,
+            }),
+        )
+    }
+
+    fn login(&self, fields: Vec<Field>) -> Option<u64> {
+        let (email, password) = match fields.as_slice() {
+            [Field::String(email), Field::Bytes(password)] => (email, password),
+            _ => return None,
+        };
+        self.store()
+            .users
+            .iter()
+            .find(|(_, user)| &user.email == email && &user.password == password)
+            .map(|(id, _)| *id)
+    }
+
+    fn register(&self, fields: Vec<Field>) -> Result<u64, ServerError> {
+        let (email, username, password) = match fields.as_slice() {
+            [Field::String(email), Field::String(username), Field::Bytes(password)] => {
+                (email, username, password)
+            }
+            _ => {
+                return Err(ServerError::bad_request(
+                    "expected email, username and password",
+                ))
+            }
+        };
+
+        let mut store = self.store();
+        if store.users.values().any(|user| &user.email == email) {
+            return Err(ServerError::bad_request("email is already registered"));
+        }
+        let user_id = store.next_id();
+        store.users.insert(
+            user_id,
+            User {
+                email: email.clone(),
+                password: password.clone(),
+                profile: Profile {
+                    user_name: username.clone(),
+                    user_status: UserStatus::OfflineUnspecified.into(),
+                    ..Default::default()
+                },
+                app_data: Default::default(),
+                guilds: Vec::new(),
+                equipped_packs: Vec::new(),
+            },
+        );
+        Ok(user_id)
+    }
+
+    fn auth_flow(&self, auth_id: &str) -> Result<AuthFlow, ServerError> {
+        self.store()
+            .auth
+            .get(auth_id)
+            .copied()
+            .ok_or_else(|| ServerError::bad_request("unknown auth ID"))
+    }
+
+    fn set_auth_flow(&self, auth_id: &str, flow: Option<AuthFlow>) {
+        let mut store = self.store();
+        match flow {
+            Some(flow) => store.auth.insert(auth_id.to_string(), flow),
+            None => store.auth.remove(auth_id),
+        };
+    }
+}
+
+impl AuthService for Homeserver {
+    #[handler]
+    async fn begin_auth(
+        &self,
+        _: Request<BeginAuthRequest>,
+    ) -> ServerResult<Response<BeginAuthResponse>> {
+        let auth_id = random_token(16);
+        self.set_auth_flow(&auth_id, Some(AuthFlow::Initial));
+        Ok(BeginAuthResponse { auth_id }.into_response())
+    }
+
+    #[handler]
+    async fn next_step(
+        &self,
+        request: Request<NextStepRequest>,
+    ) -> ServerResult<Response<NextStepResponse>> {
+        let request = request.into_message().await?;
+        let auth_id = request.auth_id.clone();
+
+        let (next, flow) = match self.auth_flow(&auth_id)? {
+            AuthFlow::Initial => (choice_step(), Some(AuthFlow::Choice)),
+            AuthFlow::Choice => match &request.step {
+                Some(next_step_request::Step::Choice(choice)) if choice.choice == "login" => {
+                    (login_step(), Some(AuthFlow::Login))
+                }
+                Some(next_step_request::Step::Choice(choice)) if choice.choice == "register" => {
+                    (register_step(), Some(AuthFlow::Register))
+                }
+                _ => return Err(ServerError::bad_request("expected login or register").into()),
+            },
+            // Failed logins get the form again, so that the client can retry
+            // or go back
+            AuthFlow::Login => match self.login(form_fields(request)) {
+                Some(user_id) => (self.session_step(user_id), None),
+                None => (login_step(), Some(AuthFlow::Login)),
+            },
+            AuthFlow::Register => {
+                let user_id = self.register(form_fields(request))?;
+                (self.session_step(user_id), None)
+            }
+        };
+        self.set_auth_flow(&auth_id, flow);
+
+        Ok(NextStepResponse { step: Some(next) }.into_response())
+    }
+
+    #[handler]
+    async fn step_back(
+        &self,
+        request: Request<StepBackRequest>,
+    ) -> ServerResult<Response<StepBackResponse>> {
+        let request = request.into_message().await?;
+        self.auth_flow(&request.auth_id)?;
+        self.set_auth_flow(&request.auth_id, Some(AuthFlow::Choice));
+        Ok(StepBackResponse {
+            step: Some(choice_step()),
+        }
+        .into_response())
+    }
+
+    #[handler]
+    async fn stream_steps(
+        &self,
+        _: Request<()>,
+        _: Socket<StreamStepsResponse, StreamStepsRequest>,
+    ) -> ServerResult<()> {
+        // Steps never change without the client asking for them here
+        Err(crate::not_implemented())
+    }
+
+    #[handler]
+    async fn check_logged_in(
+        &self,
+        request: Request<CheckLoggedInRequest>,
+    ) -> ServerResult<Response<CheckLoggedInResponse>> {
+        crate::user_id(self, &request)?;
+        Ok(CheckLoggedInResponse {}.into_response())
+    }
+
+    not_implemented! {
+        federate(FederateRequest) -> FederateResponse;
+        login_federated(LoginFederatedRequest) -> LoginFederatedResponse;
+        key(KeyRequest) -> KeyResponse;
+    }
+}
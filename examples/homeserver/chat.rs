@@ -0,0 +1,863 @@
+//! Chat service: guilds, channels, messages, invites, roles and events.
+use harmony_rust_sdk::{
+    api::chat::{
+        chat_service_server::ChatService, stream_event, Channel, ChannelWithId, Event, EventSource,
+        Invite, InviteWithId, Message, MessageWithId, Role, RoleWithId, *,
+    },
+    server::{events::EventTarget, ServerError},
+};
+use hrpc::server::prelude::{
+    handler, make_handler, IntoResponse, Request, Response, ServerResult, Socket,
+};
+
+use crate::state::{random_token, unix_time, ChannelData, Homeserver};
+
+/// Default number of messages returned by `GetChannelMessages`.
+const DEFAULT_MESSAGE_COUNT: usize = 25;
+
+fn chat_event(event: stream_event::Event) -> Event {
+    Event::Chat(event)
+}
+
+impl Homeserver {
+    fn publish_to_guild(&self, guild_id: u64, event: stream_event::Event) {
+        self.publish(EventTarget::Guild(guild_id), chat_event(event));
+    }
+
+    fn add_member(&self, guild_id: u64, user_id: u64) -> Result<(), ServerError> {
+        {
+            let mut store = self.store();
+            let guild = store
+                .guilds
+                .get_mut(&guild_id)
+                .ok_or(ServerError::NotFound)?;
+            if guild.members.contains(&user_id) {
+                return Err(ServerError::bad_request("already a member of this guild"));
+            }
+            guild.members.push(user_id);
+            store.user_mut(user_id)?.guilds.push(guild_id);
+        }
+
+        self.publish_to_guild(
+            guild_id,
+            stream_event::Event::JoinedMember(stream_event::MemberJoined {
+                guild_id,
+                member_id: user_id,
+            }),
+        );
+        self.publish(
+            EventTarget::Homeserver(user_id),
+            chat_event(stream_event::Event::GuildAddedToList(
+                stream_event::GuildAddedToList {
+                    guild_id,
+                    homeserver: self.host.to_string(),
+                },
+            )),
+        );
+        Ok(())
+    }
+}
+
+impl ChatService for Homeserver {
+    #[handler]
+    async fn create_guild(
+        &self,
+        request: Request<CreateGuildRequest>,
+    ) -> ServerResult<Response<CreateGuildResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+
+        let guild_id = self
+            .store()
+            .create_guild(request.name, request.picture, vec![user_id]);
+        self.add_member(guild_id, user_id)?;
+
+        Ok(CreateGuildResponse { guild_id }.into_response())
+    }
+
+    #[handler]
+    async fn get_guild_list(
+        &self,
+        request: Request<GetGuildListRequest>,
+    ) -> ServerResult<Response<GetGuildListResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let guilds = self
+            .store()
+            .user(user_id)?
+            .guilds
+            .iter()
+            .map(|guild_id| GuildListEntry {
+                guild_id: *guild_id,
+                server_id: String::new(),
+            })
+            .collect();
+        Ok(GetGuildListResponse { guilds }.into_response())
+    }
+
+    #[handler]
+    async fn get_guild(
+        &self,
+        request: Request<GetGuildRequest>,
+    ) -> ServerResult<Response<GetGuildResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let mut store = self.store();
+        let guild = store.guild_as(request.guild_id, user_id)?;
+        Ok(GetGuildResponse {
+            guild: Some(Guild {
+                name: guild.name.clone(),
+                picture: guild.picture.clone(),
+                owner_ids: guild.owner_ids.clone(),
+                ..Default::default()
+            }),
+        }
+        .into_response())
+    }
+
+    #[handler]
+    async fn get_guild_members(
+        &self,
+        request: Request<GetGuildMembersRequest>,
+    ) -> ServerResult<Response<GetGuildMembersResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let members = self
+            .store()
+            .guild_as(request.guild_id, user_id)?
+            .members
+            .clone();
+        Ok(GetGuildMembersResponse { members }.into_response())
+    }
+
+    #[handler]
+    async fn delete_guild(
+        &self,
+        request: Request<DeleteGuildRequest>,
+    ) -> ServerResult<Response<DeleteGuildResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let guild_id = request.into_message().await?.guild_id;
+        let members = {
+            let mut store = self.store();
+            store.owned_guild(guild_id, user_id)?;
+            let guild = store
+                .guilds
+                .remove(&guild_id)
+                .ok_or(ServerError::NotFound)?;
+            store.invites.retain(|_, id| *id != guild_id);
+            for member in &guild.members {
+                if let Some(user) = store.users.get_mut(member) {
+                    user.guilds.retain(|id| *id != guild_id);
+                }
+            }
+            guild.members
+        };
+
+        self.publish_to_guild(
+            guild_id,
+            stream_event::Event::DeletedGuild(stream_event::GuildDeleted { guild_id }),
+        );
+        for member in members {
+            self.publish(
+                EventTarget::Homeserver(member),
+                chat_event(stream_event::Event::GuildRemovedFromList(
+                    stream_event::GuildRemovedFromList {
+                        guild_id,
+                        homeserver: self.host.to_string(),
+                    },
+                )),
+            );
+        }
+
+        Ok(DeleteGuildResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn create_channel(
+        &self,
+        request: Request<CreateChannelRequest>,
+    ) -> ServerResult<Response<CreateChannelResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let channel_id = {
+            let mut store = self.store();
+            let channel_id = store.next_id();
+            store
+                .owned_guild(request.guild_id, user_id)?
+                .channels
+                .insert(
+                    channel_id,
+                    ChannelData {
+                        channel: Channel {
+                            channel_name: request.channel_name.clone(),
+                            kind: request.kind,
+                            metadata: request.metadata.clone(),
+                        },
+                        ..Default::default()
+                    },
+                );
+            channel_id
+        };
+
+        self.publish_to_guild(
+            request.guild_id,
+            stream_event::Event::CreatedChannel(stream_event::ChannelCreated {
+                guild_id: request.guild_id,
+                channel_id,
+                name: request.channel_name,
+                kind: request.kind,
+                ..Default::default()
+            }),
+        );
+
+        Ok(CreateChannelResponse { channel_id }.into_response())
+    }
+
+    #[handler]
+    async fn get_guild_channels(
+        &self,
+        request: Request<GetGuildChannelsRequest>,
+    ) -> ServerResult<Response<GetGuildChannelsResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let channels = self
+            .store()
+            .guild_as(request.guild_id, user_id)?
+            .channels
+            .iter()
+            .map(|(channel_id, data)| ChannelWithId {
+                channel_id: *channel_id,
+                channel: Some(data.channel.clone()),
+            })
+            .collect();
+        Ok(GetGuildChannelsResponse { channels }.into_response())
+    }
+
+    #[handler]
+    async fn delete_channel(
+        &self,
+        request: Request<DeleteChannelRequest>,
+    ) -> ServerResult<Response<DeleteChannelResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        self.store()
+            .owned_guild(request.guild_id, user_id)?
+            .channels
+            .remove(&request.channel_id)
+            .ok_or(ServerError::NotFound)?;
+
+        self.publish_to_guild(
+            request.guild_id,
+            stream_event::Event::DeletedChannel(stream_event::ChannelDeleted {
+                guild_id: request.guild_id,
+                channel_id: request.channel_id,
+            }),
+        );
+
+        Ok(DeleteChannelResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn send_message(
+        &self,
+        request: Request<SendMessageRequest>,
+    ) -> ServerResult<Response<SendMessageResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let message = Message {
+            author_id: user_id,
+            created_at: unix_time(),
+            content: request.content,
+            in_reply_to: request.in_reply_to,
+            overrides: request.overrides,
+            metadata: request.metadata,
+            ..Default::default()
+        };
+
+        let message_id = {
+            let mut store = self.store();
+            let message_id = store.next_id();
+            store
+                .guild_as(request.guild_id, user_id)?
+                .channel_mut(request.channel_id)?
+                .messages
+                .insert(message_id, message.clone());
+            message_id
+        };
+
+        self.publish_to_guild(
+            request.guild_id,
+            stream_event::Event::SentMessage(stream_event::MessageSent {
+                echo_id: request.echo_id,
+                guild_id: request.guild_id,
+                channel_id: request.channel_id,
+                message_id,
+                message: Some(message),
+            }),
+        );
+
+        Ok(SendMessageResponse { message_id }.into_response())
+    }
+
+    #[handler]
+    async fn get_channel_messages(
+        &self,
+        request: Request<GetChannelMessagesRequest>,
+    ) -> ServerResult<Response<GetChannelMessagesResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let count = request
+            .count
+            .map_or(DEFAULT_MESSAGE_COUNT, |count| count as usize);
+
+        let mut store = self.store();
+        let channel = store
+            .guild_as(request.guild_id, user_id)?
+            .channel_mut(request.channel_id)?;
+        // Newest messages first, before the given message if there is one
+        let before = request.message_id.unwrap_or(u64::MAX);
+        let messages = channel
+            .messages
+            .range(..before)
+            .rev()
+            .take(count)
+            .map(|(message_id, message)| MessageWithId {
+                message_id: *message_id,
+                message: Some(message.clone()),
+            })
+            .collect::<Vec<_>>();
+
+        Ok(GetChannelMessagesResponse {
+            reached_top: messages.len() < count,
+            messages,
+            ..Default::default()
+        }
+        .into_response())
+    }
+
+    #[handler]
+    async fn get_message(
+        &self,
+        request: Request<GetMessageRequest>,
+    ) -> ServerResult<Response<GetMessageResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let message = self
+            .store()
+            .guild_as(request.guild_id, user_id)?
+            .channel_mut(request.channel_id)?
+            .messages
+            .get(&request.message_id)
+            .cloned()
+            .ok_or(ServerError::NotFound)?;
+        Ok(GetMessageResponse {
+            message: Some(message),
+        }
+        .into_response())
+    }
+
+    #[handler]
+    async fn update_message_text(
+        &self,
+        request: Request<UpdateMessageTextRequest>,
+    ) -> ServerResult<Response<UpdateMessageTextResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let edited_at = unix_time();
+        {
+            let mut store = self.store();
+            let message = store
+                .guild_as(request.guild_id, user_id)?
+                .channel_mut(request.channel_id)?
+                .messages
+                .get_mut(&request.message_id)
+                .ok_or(ServerError::NotFound)?;
+            if message.author_id != user_id {
+                return Err(ServerError::bad_request("can't edit others' messages").into());
+            }
+            message.edited_at = Some(edited_at);
+            message.content = Some(Content {
+                content: Some(content::Content::TextMessage(content::TextContent {
+                    content: request.new_content.clone(),
+                })),
+            });
+        }
+
+        self.publish_to_guild(
+            request.guild_id,
+            stream_event::Event::EditedMessage(stream_event::MessageUpdated {
+                guild_id: request.guild_id,
+                channel_id: request.channel_id,
+                message_id: request.message_id,
+                edited_at,
+                new_content: request.new_content,
+            }),
+        );
+
+        Ok(UpdateMessageTextResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn delete_message(
+        &self,
+        request: Request<DeleteMessageRequest>,
+    ) -> ServerResult<Response<DeleteMessageResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        {
+            let mut store = self.store();
+            let guild = store.guild_as(request.guild_id, user_id)?;
+            let is_owner = guild.owner_ids.contains(&user_id);
+            let messages = &mut guild.channel_mut(request.channel_id)?.messages;
+            match messages.get(&request.message_id) {
+                Some(message) if message.author_id == user_id || is_owner => {
+                    messages.remove(&request.message_id);
+                }
+                Some(_) => {
+                    return Err(ServerError::bad_request("can't delete others' messages").into())
+                }
+                None => return Err(ServerError::NotFound.into()),
+            }
+        }
+
+        self.publish_to_guild(
+            request.guild_id,
+            stream_event::Event::DeletedMessage(stream_event::MessageDeleted {
+                guild_id: request.guild_id,
+                channel_id: request.channel_id,
+                message_id: request.message_id,
+            }),
+        );
+
+        Ok(DeleteMessageResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn create_invite(
+        &self,
+        request: Request<CreateInviteRequest>,
+    ) -> ServerResult<Response<CreateInviteResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let invite_id = if request.name.is_empty() {
+            random_token(8)
+        } else {
+            request.name
+        };
+
+        let mut store = self.store();
+        if store.invites.contains_key(&invite_id) {
+            return Err(ServerError::bad_request("invite already exists").into());
+        }
+        store
+            .owned_guild(request.guild_id, user_id)?
+            .invites
+            .insert(
+                invite_id.clone(),
+                Invite {
+                    possible_uses: request.possible_uses,
+                    use_count: 0,
+                },
+            );
+        store.invites.insert(invite_id.clone(), request.guild_id);
+
+        Ok(CreateInviteResponse { invite_id }.into_response())
+    }
+
+    #[handler]
+    async fn get_guild_invites(
+        &self,
+        request: Request<GetGuildInvitesRequest>,
+    ) -> ServerResult<Response<GetGuildInvitesResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let invites = self
+            .store()
+            .owned_guild(request.guild_id, user_id)?
+            .invites
+            .iter()
+            .map(|(invite_id, invite)| InviteWithId {
+                invite_id: invite_id.clone(),
+                invite: Some(invite.clone()),
+            })
+            .collect();
+        Ok(GetGuildInvitesResponse { invites }.into_response())
+    }
+
+    #[handler]
+    async fn delete_invite(
+        &self,
+        request: Request<DeleteInviteRequest>,
+    ) -> ServerResult<Response<DeleteInviteResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let mut store = self.store();
+        store
+            .owned_guild(request.guild_id, user_id)?
+            .invites
+            .remove(&request.invite_id)
+            .ok_or(ServerError::NotFound)?;
+        store.invites.remove(&request.invite_id);
+        Ok(DeleteInviteResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn preview_guild(
+        &self,
+        request: Request<PreviewGuildRequest>,
+    ) -> ServerResult<Response<PreviewGuildResponse>> {
+        let request = request.into_message().await?;
+        let store = self.store();
+        let guild = store
+            .invites
+            .get(&request.invite_id)
+            .and_then(|guild_id| store.guilds.get(guild_id))
+            .ok_or(ServerError::NotFound)?;
+        Ok(PreviewGuildResponse {
+            name: guild.name.clone(),
+            picture: guild.picture.clone(),
+            member_count: guild.members.len() as u64,
+        }
+        .into_response())
+    }
+
+    #[handler]
+    async fn join_guild(
+        &self,
+        request: Request<JoinGuildRequest>,
+    ) -> ServerResult<Response<JoinGuildResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let invite_id = request.into_message().await?.invite_id;
+        let guild_id = {
+            let mut store = self.store();
+            let guild_id = *store.invites.get(&invite_id).ok_or(ServerError::NotFound)?;
+            let guild = store
+                .guilds
+                .get_mut(&guild_id)
+                .ok_or(ServerError::NotFound)?;
+            // Joining a guild again doesn't use up the invite
+            if guild.members.contains(&user_id) {
+                return Ok(JoinGuildResponse { guild_id }.into_response());
+            }
+            let invite = guild
+                .invites
+                .get_mut(&invite_id)
+                .ok_or(ServerError::NotFound)?;
+            // An invite with no possible uses can be used infinitely
+            if invite.possible_uses != 0 && invite.use_count >= invite.possible_uses {
+                return Err(ServerError::bad_request("invite has no uses left").into());
+            }
+            invite.use_count += 1;
+            guild_id
+        };
+        self.add_member(guild_id, user_id)?;
+
+        Ok(JoinGuildResponse { guild_id }.into_response())
+    }
+
+    #[handler]
+    async fn leave_guild(
+        &self,
+        request: Request<LeaveGuildRequest>,
+    ) -> ServerResult<Response<LeaveGuildResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let guild_id = request.into_message().await?.guild_id;
+        {
+            let mut store = self.store();
+            let guild = store.guild_as(guild_id, user_id)?;
+            guild.members.retain(|id| *id != user_id);
+            guild.owner_ids.retain(|id| *id != user_id);
+            guild.user_roles.remove(&user_id);
+            store.user_mut(user_id)?.guilds.retain(|id| *id != guild_id);
+        }
+
+        self.publish_to_guild(
+            guild_id,
+            stream_event::Event::LeftMember(stream_event::MemberLeft {
+                guild_id,
+                member_id: user_id,
+                leave_reason: LeaveReason::WillinglyUnspecified.into(),
+            }),
+        );
+        self.publish(
+            EventTarget::Homeserver(user_id),
+            chat_event(stream_event::Event::GuildRemovedFromList(
+                stream_event::GuildRemovedFromList {
+                    guild_id,
+                    homeserver: self.host.to_string(),
+                },
+            )),
+        );
+
+        Ok(LeaveGuildResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn add_guild_role(
+        &self,
+        request: Request<AddGuildRoleRequest>,
+    ) -> ServerResult<Response<AddGuildRoleResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let role = Role {
+            name: request.name,
+            color: request.color,
+            hoist: request.hoist,
+            pingable: request.pingable,
+        };
+        let role_id = {
+            let mut store = self.store();
+            let role_id = store.next_id();
+            store
+                .owned_guild(request.guild_id, user_id)?
+                .roles
+                .insert(role_id, role.clone());
+            role_id
+        };
+
+        self.publish_to_guild(
+            request.guild_id,
+            stream_event::Event::RoleCreated(stream_event::RoleCreated {
+                guild_id: request.guild_id,
+                role_id,
+                name: role.name,
+                color: role.color,
+                hoist: role.hoist,
+                pingable: role.pingable,
+            }),
+        );
+
+        Ok(AddGuildRoleResponse { role_id }.into_response())
+    }
+
+    #[handler]
+    async fn get_guild_roles(
+        &self,
+        request: Request<GetGuildRolesRequest>,
+    ) -> ServerResult<Response<GetGuildRolesResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let roles = self
+            .store()
+            .guild_as(request.guild_id, user_id)?
+            .roles
+            .iter()
+            .map(|(role_id, role)| RoleWithId {
+                role_id: *role_id,
+                role: Some(role.clone()),
+            })
+            .collect();
+        Ok(GetGuildRolesResponse { roles }.into_response())
+    }
+
+    #[handler]
+    async fn delete_guild_role(
+        &self,
+        request: Request<DeleteGuildRoleRequest>,
+    ) -> ServerResult<Response<DeleteGuildRoleResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        {
+            let mut store = self.store();
+            let guild = store.owned_guild(request.guild_id, user_id)?;
+            guild
+                .roles
+                .remove(&request.role_id)
+                .ok_or(ServerError::NotFound)?;
+            for roles in guild.user_roles.values_mut() {
+                roles.retain(|id| *id != request.role_id);
+            }
+        }
+
+        self.publish_to_guild(
+            request.guild_id,
+            stream_event::Event::RoleDeleted(stream_event::RoleDeleted {
+                guild_id: request.guild_id,
+                role_id: request.role_id,
+            }),
+        );
+
+        Ok(DeleteGuildRoleResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn manage_user_roles(
+        &self,
+        request: Request<ManageUserRolesRequest>,
+    ) -> ServerResult<Response<ManageUserRolesResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let new_role_ids = {
+            let mut store = self.store();
+            let guild = store.owned_guild(request.guild_id, user_id)?;
+            if !guild.members.contains(&request.user_id) {
+                return Err(ServerError::NotFound.into());
+            }
+            if let Some(role_id) = request
+                .give_role_ids
+                .iter()
+                .find(|role_id| !guild.roles.contains_key(role_id))
+            {
+                return Err(
+                    ServerError::bad_request(format!("no role with ID {}", role_id)).into(),
+                );
+            }
+            let roles = guild.user_roles.entry(request.user_id).or_default();
+            roles.retain(|id| !request.take_role_ids.contains(id));
+            for role_id in &request.give_role_ids {
+                if !roles.contains(role_id) {
+                    roles.push(*role_id);
+                }
+            }
+            roles.clone()
+        };
+
+        self.publish_to_guild(
+            request.guild_id,
+            stream_event::Event::UserRolesUpdated(stream_event::UserRolesUpdated {
+                guild_id: request.guild_id,
+                user_id: request.user_id,
+                new_role_ids,
+            }),
+        );
+
+        Ok(ManageUserRolesResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn get_user_roles(
+        &self,
+        request: Request<GetUserRolesRequest>,
+    ) -> ServerResult<Response<GetUserRolesResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let roles = self
+            .store()
+            .guild_as(request.guild_id, user_id)?
+            .user_roles
+            .get(&request.user_id)
+            .cloned()
+            .unwrap_or_default();
+        Ok(GetUserRolesResponse { roles }.into_response())
+    }
+
+    /// Permissions aren't implemented (`SetPermissions` and `GetPermissions`
+    /// return "not implemented"), so every member of a guild has every
+    /// permission. Endpoints that need more check for guild ownership
+    /// themselves.
+    #[handler]
+    async fn query_has_permission(
+        &self,
+        request: Request<QueryHasPermissionRequest>,
+    ) -> ServerResult<Response<QueryHasPermissionResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let target = request.r#as.unwrap_or(user_id);
+        let mut store = self.store();
+        let guild = store.guild_as(request.guild_id, user_id)?;
+        let ok = guild.members.contains(&target);
+        Ok(QueryHasPermissionResponse { ok }.into_response())
+    }
+
+    #[handler]
+    async fn typing(
+        &self,
+        request: Request<TypingRequest>,
+    ) -> ServerResult<Response<TypingResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        self.store()
+            .guild_as(request.guild_id, user_id)?
+            .channel_mut(request.channel_id)?;
+
+        self.publish_to_guild(
+            request.guild_id,
+            stream_event::Event::Typing(stream_event::Typing {
+                user_id,
+                guild_id: request.guild_id,
+                channel_id: request.channel_id,
+            }),
+        );
+
+        Ok(TypingResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn stream_events(
+        &self,
+        request: Request<()>,
+        socket: Socket<StreamEventsResponse, StreamEventsRequest>,
+    ) -> ServerResult<()> {
+        let user_id = crate::user_id(self, &request)?;
+        let mut subscription = self.events.register(user_id);
+
+        // Sockets are subscribed to everything they can see by default,
+        // clients opt out by sending `EventSource::Unsubscribe`
+        subscription.subscribe(EventSource::Homeserver);
+        subscription.subscribe(EventSource::Action);
+        for guild_id in self.store().user(user_id)?.guilds.clone() {
+            subscription.subscribe(EventSource::Guild(guild_id));
+        }
+
+        let (mut tx, mut rx) = socket.split();
+        loop {
+            tokio::select! {
+                request = rx.receive_message() => {
+                    let request = match request {
+                        Ok(request) => request,
+                        Err(_) => break,
+                    };
+                    if let Some(EventSource::Guild(guild_id)) = EventSource::from_request(&request) {
+                        if self.store().guild_as(guild_id, user_id).is_err() {
+                            continue;
+                        }
+                    }
+                    subscription.handle_request(&request);
+                }
+                event = subscription.recv() => {
+                    let event = match event {
+                        Some(event) => event,
+                        None => break,
+                    };
+                    let response = StreamEventsResponse {
+                        event: Some(event.into()),
+                    };
+                    if tx.send_message(response).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    not_implemented! {
+        create_room(CreateRoomRequest) -> CreateRoomResponse;
+        create_direct_message(CreateDirectMessageRequest) -> CreateDirectMessageResponse;
+        upgrade_room_to_guild(UpgradeRoomToGuildRequest) -> UpgradeRoomToGuildResponse;
+        invite_user_to_guild(InviteUserToGuildRequest) -> InviteUserToGuildResponse;
+        get_pending_invites(GetPendingInvitesRequest) -> GetPendingInvitesResponse;
+        reject_pending_invite(RejectPendingInviteRequest) -> RejectPendingInviteResponse;
+        ignore_pending_invite(IgnorePendingInviteRequest) -> IgnorePendingInviteResponse;
+        update_guild_information(UpdateGuildInformationRequest) -> UpdateGuildInformationResponse;
+        update_channel_information(UpdateChannelInformationRequest) -> UpdateChannelInformationResponse;
+        update_channel_order(UpdateChannelOrderRequest) -> UpdateChannelOrderResponse;
+        update_all_channel_order(UpdateAllChannelOrderRequest) -> UpdateAllChannelOrderResponse;
+        trigger_action(TriggerActionRequest) -> TriggerActionResponse;
+        set_permissions(SetPermissionsRequest) -> SetPermissionsResponse;
+        get_permissions(GetPermissionsRequest) -> GetPermissionsResponse;
+        move_role(MoveRoleRequest) -> MoveRoleResponse;
+        modify_guild_role(ModifyGuildRoleRequest) -> ModifyGuildRoleResponse;
+        get_banned_users(GetBannedUsersRequest) -> GetBannedUsersResponse;
+        ban_user(BanUserRequest) -> BanUserResponse;
+        kick_user(KickUserRequest) -> KickUserResponse;
+        unban_user(UnbanUserRequest) -> UnbanUserResponse;
+        get_pinned_messages(GetPinnedMessagesRequest) -> GetPinnedMessagesResponse;
+        pin_message(PinMessageRequest) -> PinMessageResponse;
+        unpin_message(UnpinMessageRequest) -> UnpinMessageResponse;
+        add_reaction(AddReactionRequest) -> AddReactionResponse;
+        remove_reaction(RemoveReactionRequest) -> RemoveReactionResponse;
+        grant_ownership(GrantOwnershipRequest) -> GrantOwnershipResponse;
+        give_up_ownership(GiveUpOwnershipRequest) -> GiveUpOwnershipResponse;
+    }
+}
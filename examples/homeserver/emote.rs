@@ -0,0 +1,251 @@
+//! Emote service: emote packs and equipping them.
+use harmony_rust_sdk::{
+    api::{
+        chat::Event,
+        emote::{emote_service_server::EmoteService, stream_event, *},
+    },
+    server::{events::EventTarget, ServerError, ServerResult as StoreResult},
+};
+use hrpc::server::prelude::{handler, make_handler, IntoResponse, Request, Response, ServerResult};
+
+use crate::state::{Homeserver, PackData, Store};
+
+impl Store {
+    /// Get an emote pack, checking that the user owns it.
+    fn owned_pack(&mut self, pack_id: u64, user_id: u64) -> StoreResult<&mut PackData> {
+        match self.packs.get_mut(&pack_id) {
+            Some(data) if data.pack.pack_owner == user_id => Ok(data),
+            Some(_) => Err(ServerError::bad_request("not the owner of this emote pack")),
+            None => Err(ServerError::NotFound),
+        }
+    }
+
+    /// Get the users that have an emote pack equipped.
+    fn pack_users(&self, pack_id: u64) -> Vec<EventTarget> {
+        self.users
+            .iter()
+            .filter(|(_, user)| user.equipped_packs.contains(&pack_id))
+            .map(|(user_id, _)| EventTarget::Homeserver(*user_id))
+            .collect()
+    }
+}
+
+impl Homeserver {
+    fn publish_emote_event(&self, targets: Vec<EventTarget>, event: stream_event::Event) {
+        self.events.publish_to_all(targets, Event::Emote(event));
+    }
+}
+
+impl EmoteService for Homeserver {
+    #[handler]
+    async fn create_emote_pack(
+        &self,
+        request: Request<CreateEmotePackRequest>,
+    ) -> ServerResult<Response<CreateEmotePackResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let pack = {
+            let mut store = self.store();
+            let pack = EmotePack {
+                pack_id: store.next_id(),
+                pack_owner: user_id,
+                pack_name: request.pack_name,
+            };
+            store.packs.insert(
+                pack.pack_id,
+                PackData {
+                    pack: pack.clone(),
+                    emotes: Vec::new(),
+                },
+            );
+            // Packs are equipped by their owner when they are created
+            store.user_mut(user_id)?.equipped_packs.push(pack.pack_id);
+            pack
+        };
+
+        let pack_id = pack.pack_id;
+        self.publish_emote_event(
+            vec![EventTarget::Homeserver(user_id)],
+            stream_event::Event::EmotePackAdded(EmotePackAdded { pack: Some(pack) }),
+        );
+
+        Ok(CreateEmotePackResponse { pack_id }.into_response())
+    }
+
+    #[handler]
+    async fn get_emote_packs(
+        &self,
+        request: Request<GetEmotePacksRequest>,
+    ) -> ServerResult<Response<GetEmotePacksResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let store = self.store();
+        let packs = store
+            .user(user_id)?
+            .equipped_packs
+            .iter()
+            .filter_map(|pack_id| store.packs.get(pack_id))
+            .map(|data| data.pack.clone())
+            .collect();
+        Ok(GetEmotePacksResponse { packs }.into_response())
+    }
+
+    #[handler]
+    async fn get_emote_pack_emotes(
+        &self,
+        request: Request<GetEmotePackEmotesRequest>,
+    ) -> ServerResult<Response<GetEmotePackEmotesResponse>> {
+        crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let emotes = self
+            .store()
+            .packs
+            .get(&request.pack_id)
+            .ok_or(ServerError::NotFound)?
+            .emotes
+            .clone();
+        Ok(GetEmotePackEmotesResponse { emotes }.into_response())
+    }
+
+    #[handler]
+    async fn add_emote_to_pack(
+        &self,
+        request: Request<AddEmoteToPackRequest>,
+    ) -> ServerResult<Response<AddEmoteToPackResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let emote = request
+            .emote
+            .ok_or_else(|| ServerError::bad_request("missing emote"))?;
+        let targets = {
+            let mut store = self.store();
+            let data = store.owned_pack(request.pack_id, user_id)?;
+            if data.emotes.iter().any(|e| e.name == emote.name) {
+                return Err(ServerError::bad_request("emote name is already used").into());
+            }
+            data.emotes.push(emote.clone());
+            store.pack_users(request.pack_id)
+        };
+
+        self.publish_emote_event(
+            targets,
+            stream_event::Event::EmotePackEmotesUpdated(EmotePackEmotesUpdated {
+                pack_id: request.pack_id,
+                added_emotes: vec![emote],
+                deleted_emotes: Vec::new(),
+            }),
+        );
+
+        Ok(AddEmoteToPackResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn delete_emote_from_pack(
+        &self,
+        request: Request<DeleteEmoteFromPackRequest>,
+    ) -> ServerResult<Response<DeleteEmoteFromPackResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let targets = {
+            let mut store = self.store();
+            let data = store.owned_pack(request.pack_id, user_id)?;
+            let count = data.emotes.len();
+            data.emotes.retain(|emote| emote.name != request.name);
+            if data.emotes.len() == count {
+                return Err(ServerError::NotFound.into());
+            }
+            store.pack_users(request.pack_id)
+        };
+
+        self.publish_emote_event(
+            targets,
+            stream_event::Event::EmotePackEmotesUpdated(EmotePackEmotesUpdated {
+                pack_id: request.pack_id,
+                added_emotes: Vec::new(),
+                deleted_emotes: vec![request.name],
+            }),
+        );
+
+        Ok(DeleteEmoteFromPackResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn delete_emote_pack(
+        &self,
+        request: Request<DeleteEmotePackRequest>,
+    ) -> ServerResult<Response<DeleteEmotePackResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let pack_id = request.into_message().await?.pack_id;
+        let targets = {
+            let mut store = self.store();
+            store.owned_pack(pack_id, user_id)?;
+            let targets = store.pack_users(pack_id);
+            store.packs.remove(&pack_id);
+            for user in store.users.values_mut() {
+                user.equipped_packs.retain(|id| *id != pack_id);
+            }
+            targets
+        };
+
+        self.publish_emote_event(
+            targets,
+            stream_event::Event::EmotePackDeleted(EmotePackDeleted { pack_id }),
+        );
+
+        Ok(DeleteEmotePackResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn dequip_emote_pack(
+        &self,
+        request: Request<DequipEmotePackRequest>,
+    ) -> ServerResult<Response<DequipEmotePackResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let pack_id = request.into_message().await?.pack_id;
+        {
+            let mut store = self.store();
+            let packs = &mut store.user_mut(user_id)?.equipped_packs;
+            if !packs.contains(&pack_id) {
+                return Err(ServerError::NotFound.into());
+            }
+            packs.retain(|id| *id != pack_id);
+        }
+
+        self.publish_emote_event(
+            vec![EventTarget::Homeserver(user_id)],
+            stream_event::Event::EmotePackDeleted(EmotePackDeleted { pack_id }),
+        );
+
+        Ok(DequipEmotePackResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn equip_emote_pack(
+        &self,
+        request: Request<EquipEmotePackRequest>,
+    ) -> ServerResult<Response<EquipEmotePackResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let pack_id = request.into_message().await?.pack_id;
+        let pack = {
+            let mut store = self.store();
+            let pack = store
+                .packs
+                .get(&pack_id)
+                .ok_or(ServerError::NotFound)?
+                .pack
+                .clone();
+            let packs = &mut store.user_mut(user_id)?.equipped_packs;
+            if packs.contains(&pack_id) {
+                return Err(ServerError::bad_request("emote pack is already equipped").into());
+            }
+            packs.push(pack_id);
+            pack
+        };
+
+        self.publish_emote_event(
+            vec![EventTarget::Homeserver(user_id)],
+            stream_event::Event::EmotePackAdded(EmotePackAdded { pack: Some(pack) }),
+        );
+
+        Ok(EquipEmotePackResponse {}.into_response())
+    }
+}
@@ -0,0 +1,198 @@
+//! Minimal in-memory homeserver, useful for running the other examples and
+//! integration tests offline. Nothing is persisted: all data is lost when the
+//! server stops.
+//!
+//! A "Lobby" guild without owners exists on startup, which any user can join
+//! with the `lobby` invite. Permissions aren't implemented: guild members can
+//! do anything except what requires owning the guild.
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use harmony_rust_sdk::{
+    api::{
+        auth::auth_service_server::AuthServiceServer, chat::chat_service_server::ChatServiceServer,
+        emote::emote_service_server::EmoteServiceServer,
+        profile::profile_service_server::ProfileServiceServer, rest::About,
+    },
+    server::{auth::authenticate, MemoryMediaStore, RestHandler, ServerError, SessionStore},
+};
+use hrpc::{
+    exports::futures_util::future::BoxFuture,
+    proto::Error as HrpcError,
+    server::{
+        prelude::*,
+        transport::{
+            http::{box_body, HttpRequest, HttpResponse, Hyper},
+            Transport,
+        },
+    },
+};
+use hyper::body::{Bytes, HttpBody};
+use tower::{Layer, Service};
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+/// Generates handlers that return a "not implemented" error.
+macro_rules! not_implemented {
+    ($($name:ident($req:ty) -> $resp:ty;)*) => {
+        $(
+            #[handler]
+            async fn $name(&self, _: Request<$req>) -> ServerResult<Response<$resp>> {
+                Err(crate::not_implemented())
+            }
+        )*
+    };
+}
+
+mod auth;
+mod chat;
+mod emote;
+mod profile;
+mod state;
+
+use state::{Homeserver, DEFAULT_INVITE};
+
+const DEFAULT_ADDR: &str = "127.0.0.1:2289";
+
+pub fn not_implemented() -> HrpcError {
+    HrpcError::default()
+        .with_identifier("h.not-implemented")
+        .with_message("this endpoint is not implemented by this homeserver")
+}
+
+/// Get the ID of the user that made a request.
+pub fn user_id<T>(homeserver: &Homeserver, request: &Request<T>) -> Result<u64, HrpcError> {
+    let headers = request.header_map().ok_or(ServerError::BlankSession)?;
+    let session = authenticate(headers, &*homeserver.sessions)?;
+    Ok(session.user_id)
+}
+
+type Rest = RestHandler<MemoryMediaStore, Box<dyn Fn(&str) -> Option<u64> + Send + Sync>>;
+
+/// Layer that serves the REST endpoints, passing other requests to hRPC.
+#[derive(Clone)]
+struct RestLayer {
+    rest: Arc<Rest>,
+}
+
+impl<S> Layer<S> for RestLayer {
+    type Service = RestService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RestService {
+            rest: self.rest.clone(),
+            inner,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct RestService<S> {
+    rest: Arc<Rest>,
+    inner: S,
+}
+
+impl<S> Service<HttpRequest> for RestService<S>
+where
+    S: Service<HttpRequest, Response = HttpResponse>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = HttpResponse;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<HttpResponse, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: HttpRequest) -> Self::Future {
+        if !self.rest.handles(&request) {
+            return Box::pin(self.inner.call(request));
+        }
+
+        let rest = self.rest.clone();
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = match rest.check_content_length(&parts.headers) {
+                Ok(()) => read_body(body, rest.max_upload_size()).await,
+                Err(err) => Err(err),
+            };
+            let response = match body {
+                Ok(body) => rest.handle(http::Request::from_parts(parts, body)).await,
+                Err(err) => {
+                    let mut response = http::Response::new(err.to_string().into());
+                    *response.status_mut() = err.status();
+                    response
+                }
+            };
+            Ok(response.map(|body| box_body(hyper::Body::from(body))))
+        })
+    }
+}
+
+/// Read a request body, failing as soon as it's larger than `limit` instead
+/// of buffering all of it.
+async fn read_body<B>(mut body: B, limit: usize) -> Result<Bytes, ServerError>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+    B::Error: std::fmt::Display,
+{
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(ServerError::bad_request)?;
+        if data.len() + chunk.len() > limit {
+            return Err(ServerError::TooLarge);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(Bytes::from(data))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::from("info")),
+        )
+        .init();
+
+    let addr = std::env::var("HOMESERVER_ADDR").unwrap_or_else(|_| DEFAULT_ADDR.to_string());
+    let addr: SocketAddr = addr.parse()?;
+    let homeserver = Homeserver::new(addr.to_string());
+    let guild_id = homeserver.store().seed_default_guild();
+    info!(
+        "default guild {} can be joined with invite {:?}",
+        guild_id, DEFAULT_INVITE
+    );
+
+    let sessions = homeserver.sessions.clone();
+    let rest: Rest = RestHandler::new(
+        About {
+            server_name: "homeserver example".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            about_server: "In-memory homeserver of harmony_rust_sdk".to_string(),
+            message_of_the_day: String::new(),
+        },
+        addr.to_string(),
+        MemoryMediaStore::default(),
+        Box::new(move |token: &str| sessions.user_id(token)),
+    );
+
+    let service = AuthServiceServer::new(homeserver.clone())
+        .combine_with(ChatServiceServer::new(homeserver.clone()))
+        .combine_with(ProfileServiceServer::new(homeserver.clone()))
+        .combine_with(EmoteServiceServer::new(homeserver));
+
+    let transport = Hyper::new(addr)?.layer(RestLayer {
+        rest: Arc::new(rest),
+    });
+
+    info!("listening on {}", addr);
+    transport.serve(service).await?;
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Profile service: profiles and app data.
+use harmony_rust_sdk::{
+    api::{
+        chat::Event,
+        profile::{profile_service_server::ProfileService, stream_event, *},
+    },
+    server::ServerError,
+};
+use hrpc::server::prelude::{handler, make_handler, IntoResponse, Request, Response, ServerResult};
+
+use crate::state::Homeserver;
+
+impl ProfileService for Homeserver {
+    #[handler]
+    async fn get_profile(
+        &self,
+        request: Request<GetProfileRequest>,
+    ) -> ServerResult<Response<GetProfileResponse>> {
+        crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let profile = self.store().user(request.user_id)?.profile.clone();
+        Ok(GetProfileResponse {
+            profile: Some(profile),
+        }
+        .into_response())
+    }
+
+    #[handler]
+    async fn update_profile(
+        &self,
+        request: Request<UpdateProfileRequest>,
+    ) -> ServerResult<Response<UpdateProfileResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let targets = {
+            let mut store = self.store();
+            let profile = &mut store.user_mut(user_id)?.profile;
+            if let Some(name) = &request.new_user_name {
+                if name.is_empty() {
+                    return Err(ServerError::bad_request("user name can't be empty").into());
+                }
+                profile.user_name = name.clone();
+            }
+            if let Some(avatar) = &request.new_user_avatar {
+                profile.user_avatar = Some(avatar.clone()).filter(|avatar| !avatar.is_empty());
+            }
+            if let Some(status) = request.new_user_status {
+                profile.user_status = status;
+            }
+            if let Some(is_bot) = request.new_is_bot {
+                profile.is_bot = is_bot;
+            }
+            store.user_targets(user_id)
+        };
+
+        let event = Event::Profile(stream_event::Event::ProfileUpdated(ProfileUpdated {
+            user_id,
+            new_username: request.new_user_name,
+            new_avatar: request.new_user_avatar,
+            new_status: request.new_user_status,
+            new_is_bot: request.new_is_bot,
+        }));
+        self.events.publish_to_all(targets, event);
+
+        Ok(UpdateProfileResponse {}.into_response())
+    }
+
+    #[handler]
+    async fn get_app_data(
+        &self,
+        request: Request<GetAppDataRequest>,
+    ) -> ServerResult<Response<GetAppDataResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        let app_data = self
+            .store()
+            .user(user_id)?
+            .app_data
+            .get(&request.app_id)
+            .cloned()
+            .unwrap_or_default();
+        Ok(GetAppDataResponse { app_data }.into_response())
+    }
+
+    #[handler]
+    async fn set_app_data(
+        &self,
+        request: Request<SetAppDataRequest>,
+    ) -> ServerResult<Response<SetAppDataResponse>> {
+        let user_id = crate::user_id(self, &request)?;
+        let request = request.into_message().await?;
+        self.store()
+            .user_mut(user_id)?
+            .app_data
+            .insert(request.app_id, request.app_data);
+        Ok(SetAppDataResponse {}.into_response())
+    }
+}
@@ -0,0 +1,226 @@
+//! In-memory storage of the homeserver.
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use harmony_rust_sdk::{
+    api::{
+        chat::{Channel, Event, Invite, Message, Role},
+        emote::{Emote, EmotePack},
+        profile::Profile,
+    },
+    server::{events::EventTarget, EventBroadcaster, MemorySessions, ServerError, ServerResult},
+};
+use rand::{distributions::Alphanumeric, Rng};
+
+/// Name of the guild that exists when the homeserver starts.
+pub const DEFAULT_GUILD_NAME: &str = "Lobby";
+/// Invite to the guild that exists when the homeserver starts.
+pub const DEFAULT_INVITE: &str = "lobby";
+
+/// A registered user.
+#[derive(Debug)]
+pub struct User {
+    pub email: String,
+    pub password: Vec<u8>,
+    pub profile: Profile,
+    pub app_data: HashMap<String, Vec<u8>>,
+    /// Guilds the user is a member of, in the order they joined them.
+    pub guilds: Vec<u64>,
+    pub equipped_packs: Vec<u64>,
+}
+
+/// A channel and its messages.
+#[derive(Debug, Default)]
+pub struct ChannelData {
+    pub channel: Channel,
+    pub messages: BTreeMap<u64, Message>,
+}
+
+/// A guild and everything in it.
+#[derive(Debug, Default)]
+pub struct GuildData {
+    pub name: String,
+    pub picture: Option<String>,
+    pub owner_ids: Vec<u64>,
+    pub members: Vec<u64>,
+    pub channels: BTreeMap<u64, ChannelData>,
+    pub invites: HashMap<String, Invite>,
+    pub roles: BTreeMap<u64, Role>,
+    pub user_roles: HashMap<u64, Vec<u64>>,
+}
+
+impl GuildData {
+    pub fn channel_mut(&mut self, channel_id: u64) -> ServerResult<&mut ChannelData> {
+        self.channels
+            .get_mut(&channel_id)
+            .ok_or(ServerError::NotFound)
+    }
+}
+
+/// An emote pack and its emotes.
+#[derive(Debug)]
+pub struct PackData {
+    pub pack: EmotePack,
+    pub emotes: Vec<Emote>,
+}
+
+/// Step of an authentication session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthFlow {
+    Initial,
+    Choice,
+    Login,
+    Register,
+}
+
+#[derive(Debug, Default)]
+pub struct Store {
+    next_id: u64,
+    pub users: HashMap<u64, User>,
+    pub guilds: HashMap<u64, GuildData>,
+    /// Guild IDs by invite ID.
+    pub invites: HashMap<String, u64>,
+    pub packs: HashMap<u64, PackData>,
+    /// Current step of authentication sessions, by auth ID.
+    pub auth: HashMap<String, AuthFlow>,
+}
+
+impl Store {
+    pub fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Create a guild with a "general" channel, returning its ID. The owners
+    /// must be added as members separately.
+    pub fn create_guild(
+        &mut self,
+        name: String,
+        picture: Option<String>,
+        owner_ids: Vec<u64>,
+    ) -> u64 {
+        let guild_id = self.next_id();
+        let channel_id = self.next_id();
+        let mut guild = GuildData {
+            name,
+            picture,
+            owner_ids,
+            ..Default::default()
+        };
+        guild.channels.insert(
+            channel_id,
+            ChannelData {
+                channel: Channel {
+                    channel_name: "general".to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        self.guilds.insert(guild_id, guild);
+        guild_id
+    }
+
+    /// Create the default guild, which has no owners and can be joined with
+    /// the [`DEFAULT_INVITE`] invite any number of times. Returns its ID.
+    pub fn seed_default_guild(&mut self) -> u64 {
+        let guild_id = self.create_guild(DEFAULT_GUILD_NAME.to_string(), None, Vec::new());
+        let invite = Invite {
+            possible_uses: 0,
+            use_count: 0,
+        };
+        self.guilds
+            .get_mut(&guild_id)
+            .expect("guild was just created")
+            .invites
+            .insert(DEFAULT_INVITE.to_string(), invite);
+        self.invites.insert(DEFAULT_INVITE.to_string(), guild_id);
+        guild_id
+    }
+
+    pub fn user(&self, user_id: u64) -> ServerResult<&User> {
+        self.users.get(&user_id).ok_or(ServerError::NotFound)
+    }
+
+    pub fn user_mut(&mut self, user_id: u64) -> ServerResult<&mut User> {
+        self.users.get_mut(&user_id).ok_or(ServerError::NotFound)
+    }
+
+    /// Get a guild, checking that the user is a member of it.
+    pub fn guild_as(&mut self, guild_id: u64, user_id: u64) -> ServerResult<&mut GuildData> {
+        match self.guilds.get_mut(&guild_id) {
+            Some(guild) if guild.members.contains(&user_id) => Ok(guild),
+            Some(_) => Err(ServerError::bad_request("not a member of this guild")),
+            None => Err(ServerError::NotFound),
+        }
+    }
+
+    /// Get a guild, checking that the user owns it.
+    pub fn owned_guild(&mut self, guild_id: u64, user_id: u64) -> ServerResult<&mut GuildData> {
+        let guild = self.guild_as(guild_id, user_id)?;
+        if !guild.owner_ids.contains(&user_id) {
+            return Err(ServerError::bad_request("not an owner of this guild"));
+        }
+        Ok(guild)
+    }
+
+    /// Get the targets of events about a user: the guilds they are in, and
+    /// their own homeserver events.
+    pub fn user_targets(&self, user_id: u64) -> Vec<EventTarget> {
+        let guilds = self
+            .users
+            .get(&user_id)
+            .map(|user| user.guilds.clone())
+            .unwrap_or_default();
+        guilds
+            .into_iter()
+            .map(EventTarget::Guild)
+            .chain(std::iter::once(EventTarget::Homeserver(user_id)))
+            .collect()
+    }
+}
+
+/// Shared state of the homeserver, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Homeserver {
+    pub host: Arc<str>,
+    pub store: Arc<Mutex<Store>>,
+    pub sessions: Arc<MemorySessions>,
+    pub events: EventBroadcaster,
+}
+
+impl Homeserver {
+    pub fn new(host: impl Into<Arc<str>>) -> Self {
+        Self {
+            host: host.into(),
+            store: Arc::default(),
+            sessions: Arc::default(),
+            events: EventBroadcaster::default(),
+        }
+    }
+
+    pub fn store(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().expect("poisoned")
+    }
+
+    pub fn publish(&self, target: EventTarget, event: Event) {
+        self.events.publish(target, event);
+    }
+}
+
+pub fn random_token(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
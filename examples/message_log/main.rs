@@ -14,7 +14,7 @@ use tracing_subscriber::EnvFilter;
 const EMAIL: &str = "rust_sdk_test@example.org";
 const USERNAME: &str = "rust_sdk_test";
 const PASSWORD: &str = "123456789Ab";
-const HOMESERVER: &str = "https://chat.harmonyapp.io:2289";
+const DEFAULT_HOMESERVER: &str = "https://chat.harmonyapp.io:2289";
 
 const GUILD_ID_FILE: &str = "guild_id";
 
@@ -47,9 +47,12 @@ async fn main() -> ClientResult<()> {
     }
 
     let guild_invite = std::env::var("GUILD_INVITE");
+    // Use `HOMESERVER=http://127.0.0.1:2289 GUILD_INVITE=lobby` with the
+    // `homeserver` example to run this example offline
+    let homeserver = std::env::var("HOMESERVER").unwrap_or_else(|_| DEFAULT_HOMESERVER.to_string());
 
     // Let's create our client first
-    let client = Client::new(HOMESERVER.parse().unwrap(), None).await?;
+    let client = Client::new(homeserver.parse().unwrap(), None).await?;
     info!("Successfully created client.");
 
     // We try to login, if it fails we register (which also authenticates)